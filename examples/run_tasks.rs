@@ -86,8 +86,8 @@ async fn main() -> anyhow::Result<()> {
             ]
         }
     }))
-    .on_completed(|activity_id, output| {
-        println!("{} => {:?}", activity_id, output);
+    .on_completed(|context, output| {
+        println!("task {} => {:?}", context.task_id, output);
     })
     .run()
     .await
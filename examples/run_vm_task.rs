@@ -65,8 +65,8 @@ async fn main() -> anyhow::Result<()> {
                 download("/golem/output/input-file", format!("output-{}", i))
             }
         }))
-        .on_completed(|activity_id, output| {
-            println!("{} => {:?}", activity_id, output);
+        .on_completed(|context, output| {
+            println!("{} => {:?}", context.attempt_id(), output);
         })
         .run()
         .await
@@ -3,3 +3,121 @@ pub use ya_agreement_utils::{
     ConstraintOperator, ConstraintValue, Constraints, CpuInfo, InfNodeInfo, NodeInfo, OfferBuilder,
     OfferDefinition, ServiceInfo,
 };
+
+use std::fmt;
+
+/// LDAP filter combining constraints with AND, OR and NOT.
+///
+/// `constraints!` only expresses a conjunction of comparisons; wrap it with
+/// the `all!`, `any!` and `not!` macros (which nest) for anything else.
+/// Strings are taken as raw LDAP filters, e.g. to match a pattern.
+///
+/// ## Example:
+/// ```
+/// use yarapi::{any, not, agreement::{constraints, ConstraintKey, Constraints}};
+///
+/// let filter = any![
+///     constraints!["golem.runtime.name" == "vm"],
+///     constraints!["golem.runtime.name" == "wasmtime"],
+/// ]
+/// .and(not!("(golem.node.id.name=*test*)"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter(String);
+
+impl Filter {
+    /// Matches if all `filters` match; `(&)` matches everything.
+    pub fn all(filters: impl IntoIterator<Item = Filter>) -> Self {
+        Self::clause('&', filters)
+    }
+
+    /// Matches if any of `filters` matches; `(|)` matches nothing.
+    pub fn any(filters: impl IntoIterator<Item = Filter>) -> Self {
+        Self::clause('|', filters)
+    }
+
+    pub fn not(filter: Filter) -> Self {
+        Filter(format!("(!{})", filter))
+    }
+
+    pub fn and(self, filter: Filter) -> Self {
+        Self::all(vec![self, filter])
+    }
+
+    pub fn or(self, filter: Filter) -> Self {
+        Self::any(vec![self, filter])
+    }
+
+    fn clause(operator: char, filters: impl IntoIterator<Item = Filter>) -> Self {
+        let filters: Vec<Filter> = filters.into_iter().collect();
+        match filters.len() {
+            1 => filters.into_iter().next().unwrap(),
+            _ => Filter(format!(
+                "({}{})",
+                operator,
+                filters.iter().map(|f| f.0.as_str()).collect::<String>()
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<Constraints> for Filter {
+    fn from(constraints: Constraints) -> Self {
+        Filter(constraints.to_string())
+    }
+}
+
+impl From<&str> for Filter {
+    fn from(filter: &str) -> Self {
+        Filter(filter.to_string())
+    }
+}
+
+impl From<String> for Filter {
+    fn from(filter: String) -> Self {
+        Filter(filter)
+    }
+}
+
+/// Conjunction of `Filter`s (or anything convertible into one).
+#[macro_export]
+macro_rules! all {
+    ($($filter:expr),* $(,)?) => {
+        $crate::agreement::Filter::all(vec![$($crate::agreement::Filter::from($filter)),*])
+    };
+}
+
+/// Alternative of `Filter`s (or anything convertible into one).
+#[macro_export]
+macro_rules! any {
+    ($($filter:expr),* $(,)?) => {
+        $crate::agreement::Filter::any(vec![$($crate::agreement::Filter::from($filter)),*])
+    };
+}
+
+/// Negation of a `Filter` (or anything convertible into one).
+#[macro_export]
+macro_rules! not {
+    ($filter:expr) => {
+        $crate::agreement::Filter::not($crate::agreement::Filter::from($filter))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let filter = any!["(a=1)", all!["(b=2)", not!("(c=*3*)")]];
+        assert_eq!(filter.to_string(), "(|(a=1)(&(b=2)(!(c=*3*))))");
+        assert_eq!(any!["(a=1)"].to_string(), "(a=1)");
+        assert_eq!(Filter::any(vec![]).to_string(), "(|)");
+    }
+}
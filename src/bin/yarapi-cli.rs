@@ -0,0 +1,224 @@
+//! Command line interface for common requestor flows, enabled with the `cli` feature.
+//!
+//! ```sh
+//! export YAGNA_APPKEY=$(yagna app-key list --json | jq -r .values[0][1])
+//! cargo run --features cli --bin yarapi-cli -- run --package-path image.gvmi tasks.json
+//! ```
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use std::path::PathBuf;
+use std::time::Duration;
+use structopt::StructOpt;
+
+use yarapi::glm::Glm;
+use yarapi::properties::{node, srv};
+use yarapi::requestor::{
+    CommandList, Image, Package, ProviderInfo, Requestor, StateSnapshot, TaskState,
+};
+use yarapi::rest::{self, WebClient};
+
+#[derive(StructOpt)]
+#[structopt(name = "yarapi-cli", about = "Runs tasks on the Golem network")]
+struct Args {
+    #[structopt(long, env = "YAGNA_APPKEY", hide_env_values = true)]
+    appkey: String,
+    #[structopt(subcommand)]
+    command: Subcommand,
+}
+
+#[derive(StructOpt)]
+enum Subcommand {
+    /// Runs tasks from a JSON file with a list of command lists.
+    Run(RunArgs),
+    /// Shows task states recorded in a state file.
+    Status { state_file: PathBuf },
+    /// Releases allocations left behind by interrupted runs.
+    Cleanup {
+        /// Only list the allocations.
+        #[structopt(long)]
+        dry_run: bool,
+    },
+    /// Estimates the cost of running tasks, based on current offers.
+    Estimate(EstimateArgs),
+}
+
+#[derive(StructOpt)]
+struct RunArgs {
+    /// JSON file with a list of tasks, each a list of commands.
+    tasks: PathBuf,
+    #[structopt(long, conflicts_with_all = &["package-url", "package-digest"])]
+    package_path: Option<PathBuf>,
+    #[structopt(long, requires = "package-digest")]
+    package_url: Option<String>,
+    #[structopt(long)]
+    package_digest: Option<String>,
+    #[structopt(long, default_value = "vm")]
+    runtime: String,
+    #[structopt(long, default_value = "0.2.4")]
+    runtime_version: semver::Version,
+    #[structopt(long, env, default_value = "community.4")]
+    subnet: String,
+    /// Max budget in GLM.
+    #[structopt(long, default_value = "5")]
+    budget: Glm,
+    #[structopt(long, default_value = "720")]
+    timeout_secs: u64,
+    /// Resumes the run recorded in this file, see `Requestor::with_state_file`.
+    #[structopt(long)]
+    state_file: Option<PathBuf>,
+}
+
+#[derive(StructOpt)]
+struct EstimateArgs {
+    #[structopt(long, default_value = "vm")]
+    runtime: String,
+    #[structopt(long, env, default_value = "community.4")]
+    subnet: String,
+    #[structopt(long, default_value = "1")]
+    tasks: u64,
+    /// Expected duration of a single task.
+    #[structopt(long, default_value = "60")]
+    task_secs: f64,
+    /// How long to collect offers for.
+    #[structopt(long, default_value = "20")]
+    collect_secs: u64,
+}
+
+async fn run(args: RunArgs) -> Result<()> {
+    let package = match (args.package_path, args.package_url, args.package_digest) {
+        (Some(path), _, _) => Package::Archive(path),
+        (None, Some(url), Some(digest)) => Package::Url { digest, url },
+        _ => {
+            return Err(anyhow!(
+                "either --package-path or --package-url is required"
+            ))
+        }
+    };
+    let image = Image::from_runtime_name(&args.runtime, args.runtime_version)
+        .ok_or_else(|| anyhow!("unsupported runtime: {}", args.runtime))?;
+    let contents = std::fs::read(&args.tasks)
+        .with_context(|| format!("unable to read tasks {}", args.tasks.display()))?;
+    let tasks: Vec<CommandList> = serde_json::from_slice(&contents)
+        .with_context(|| format!("invalid tasks {}", args.tasks.display()))?;
+
+    let mut requestor = Requestor::new("yarapi-cli", image, package)
+        .with_subnet(args.subnet)
+        .with_max_budget_glm(args.budget)
+        .with_timeout(Duration::from_secs(args.timeout_secs))
+        .with_tasks(tasks)
+        .on_completed(|context, output| {
+            println!("{} => {:?}", context.attempt_id(), output);
+        });
+    if let Some(state_file) = args.state_file {
+        requestor = requestor.with_state_file(state_file);
+    }
+    requestor.run().await
+}
+
+fn status(state_file: PathBuf) -> Result<()> {
+    let snapshot = StateSnapshot::load(&state_file)?;
+    for (task_id, state) in &snapshot.tasks {
+        println!("{:>6}  {:?}", task_id, state);
+    }
+    let states = [
+        TaskState::Pending,
+        TaskState::Negotiating,
+        TaskState::Dispatched,
+        TaskState::Running,
+        TaskState::Downloading,
+        TaskState::Done,
+        TaskState::Failed,
+    ];
+    let summary: Vec<String> = states
+        .iter()
+        .filter(|state| snapshot.count(**state) > 0)
+        .map(|state| format!("{:?}: {}", state, snapshot.count(*state)))
+        .collect();
+    println!("{} tasks ({})", snapshot.tasks.len(), summary.join(", "));
+    Ok(())
+}
+
+async fn cleanup(session: &rest::Session, dry_run: bool) -> Result<()> {
+    let payment = session.payment()?;
+    let allocations = payment.allocations().await?;
+    if allocations.is_empty() {
+        println!("no allocations");
+    }
+    for allocation in allocations {
+        println!(
+            "{}  {} GLM reserved, {} GLM spent",
+            allocation.allocation_id,
+            Glm::from(allocation.total_amount),
+            Glm::from(allocation.spent_amount)
+        );
+        if !dry_run {
+            payment
+                .release_allocation(&allocation.allocation_id)
+                .await
+                .with_context(|| format!("releasing allocation {}", allocation.allocation_id))?;
+        }
+    }
+    Ok(())
+}
+
+async fn estimate(session: &rest::Session, args: EstimateArgs) -> Result<()> {
+    let props = serde_json::json!({
+        node::NAME: "yarapi-cli",
+        node::SUBNET: args.subnet,
+        srv::EXPIRATION: (Utc::now() + chrono::Duration::minutes(10)).timestamp_millis(),
+    });
+    let constraints = format!(
+        "(&(golem.runtime.name={})({}={}))",
+        args.runtime,
+        node::SUBNET,
+        args.subnet
+    );
+    let offers = session
+        .market()?
+        .scan(&props, &constraints, Duration::from_secs(args.collect_secs))
+        .await?;
+
+    let mut costs = vec![];
+    for offer in &offers {
+        // Usage vector is [duration, cpu time]; the last coefficient is the start price.
+        if let Some(coeffs) = ProviderInfo::from_offer(offer).linear_coeffs() {
+            if let Some((start, usage)) = coeffs.split_last() {
+                costs.push(start + usage.iter().sum::<f64>() * args.task_secs);
+            }
+        }
+    }
+    if costs.is_empty() {
+        return Err(anyhow!("no offers within {}s", args.collect_secs));
+    }
+
+    costs.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let tasks = args.tasks as f64;
+    println!("{} offers, cost of {} tasks:", costs.len(), args.tasks);
+    println!("  cheapest: {:.6} GLM", costs[0] * tasks);
+    println!("  median:   {:.6} GLM", costs[costs.len() / 2] * tasks);
+    println!("  highest:  {:.6} GLM", costs[costs.len() - 1] * tasks);
+    Ok(())
+}
+
+#[actix_rt::main]
+async fn main() -> Result<()> {
+    dotenv::dotenv().ok();
+    env_logger::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args = Args::from_args();
+    // `Requestor::run` takes the app key from the environment.
+    std::env::set_var("YAGNA_APPKEY", &args.appkey);
+    let session = rest::Session::with_client(WebClient::with_token(&args.appkey));
+
+    let result = session
+        .with(async {
+            match args.command {
+                Subcommand::Run(run_args) => run(run_args).await,
+                Subcommand::Status { state_file } => status(state_file),
+                Subcommand::Cleanup { dry_run } => cleanup(&session, dry_run).await,
+                Subcommand::Estimate(estimate_args) => estimate(&session, estimate_args).await,
+            }
+        })
+        .await;
+    result.unwrap_or_else(|| Err(anyhow!("ctrl-c caught")))
+}
@@ -0,0 +1,333 @@
+//! C ABI for embedding yarapi in non-Rust applications.
+//!
+//! Enabled with the `ffi` feature. Build a shared library with
+//! `cargo rustc --release --features ffi --crate-type cdylib` and generate
+//! the header with `cbindgen --config cbindgen.toml`.
+//!
+//! A job is described by a JSON `Manifest` (see `requestor::Manifest`);
+//! additional tasks can be added as JSON command lists. The job runs on
+//! a background thread, while the caller polls its status and fetches
+//! outputs of completed tasks:
+//!
+//! ```c
+//! YarapiJob *job = yarapi_job_from_manifest(manifest_json);
+//! yarapi_job_add_task(job, "[{\"run\": [\"/bin/ls\", \"-la\"]}]");
+//! yarapi_job_start(job);
+//! while (yarapi_job_status(job) == YARAPI_STATUS_RUNNING) {
+//!     char *output;
+//!     while ((output = yarapi_job_next_output(job)) != NULL) {
+//!         puts(output);
+//!         yarapi_string_free(output);
+//!     }
+//!     sleep(1);
+//! }
+//! yarapi_job_free(job);
+//! ```
+//!
+//! Strings returned by the library must be released with `yarapi_string_free`.
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+
+use crate::requestor::{CommandList, Manifest, Requestor};
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YarapiStatus {
+    Created = 0,
+    Running = 1,
+    Finished = 2,
+    Failed = 3,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TaskOutput {
+    activity_id: String,
+    output: Vec<String>,
+}
+
+struct JobState {
+    status: YarapiStatus,
+    error: Option<String>,
+    outputs: VecDeque<TaskOutput>,
+}
+
+/// Opaque handle to a job.
+pub struct YarapiJob {
+    manifest: Manifest,
+    state: Arc<Mutex<JobState>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+/// Runs the body of an exported function; a panic must not unwind into
+/// the caller, so it is logged and `fallback` returned instead.
+fn guard<T>(fallback: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        log::error!("panic in yarapi call");
+        fallback
+    })
+}
+
+/// Locks the job state; the state stays usable if a thread panicked while
+/// holding the lock.
+fn lock(state: &Mutex<JobState>) -> MutexGuard<'_, JobState> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+unsafe fn read_str<'a>(s: *const c_char) -> Option<&'a str> {
+    match s.is_null() {
+        true => None,
+        false => CStr::from_ptr(s).to_str().ok(),
+    }
+}
+
+fn into_c_string(s: String) -> *mut c_char {
+    CString::new(s)
+        .map(CString::into_raw)
+        .unwrap_or(std::ptr::null_mut())
+}
+
+/// Creates a job from a JSON manifest. Returns NULL if the manifest is invalid.
+#[no_mangle]
+pub unsafe extern "C" fn yarapi_job_from_manifest(manifest_json: *const c_char) -> *mut YarapiJob {
+    guard(std::ptr::null_mut(), || job_from_manifest(manifest_json))
+}
+
+unsafe fn job_from_manifest(manifest_json: *const c_char) -> *mut YarapiJob {
+    let manifest = match read_str(manifest_json).map(serde_json::from_str::<Manifest>) {
+        Some(Ok(manifest)) => manifest,
+        Some(Err(e)) => {
+            log::error!("invalid manifest: {}", e);
+            return std::ptr::null_mut();
+        }
+        None => return std::ptr::null_mut(),
+    };
+    let job = YarapiJob {
+        manifest,
+        state: Arc::new(Mutex::new(JobState {
+            status: YarapiStatus::Created,
+            error: None,
+            outputs: VecDeque::new(),
+        })),
+        thread: None,
+    };
+    Box::into_raw(Box::new(job))
+}
+
+/// Adds a task given as a JSON command list. Returns 0 on success.
+#[no_mangle]
+pub unsafe extern "C" fn yarapi_job_add_task(
+    job: *mut YarapiJob,
+    commands_json: *const c_char,
+) -> c_int {
+    guard(-1, || job_add_task(job, commands_json))
+}
+
+unsafe fn job_add_task(job: *mut YarapiJob, commands_json: *const c_char) -> c_int {
+    let job = match job.as_mut() {
+        Some(job) if job.thread.is_none() => job,
+        _ => return -1,
+    };
+    match read_str(commands_json).map(serde_json::from_str::<CommandList>) {
+        Some(Ok(commands)) => {
+            job.manifest.tasks.push(commands);
+            0
+        }
+        Some(Err(e)) => {
+            log::error!("invalid command list: {}", e);
+            -1
+        }
+        None => -1,
+    }
+}
+
+/// Starts the job on a background thread. Returns 0 on success.
+#[no_mangle]
+pub unsafe extern "C" fn yarapi_job_start(job: *mut YarapiJob) -> c_int {
+    guard(-1, || job_start(job))
+}
+
+unsafe fn job_start(job: *mut YarapiJob) -> c_int {
+    let job = match job.as_mut() {
+        Some(job) if job.thread.is_none() => job,
+        _ => return -1,
+    };
+    let manifest = job.manifest.clone();
+    let state = job.state.clone();
+    lock(&state).status = YarapiStatus::Running;
+
+    job.thread = Some(thread::spawn(move || {
+        let outputs = state.clone();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let requestor =
+                Requestor::from_manifest(manifest)?.on_completed(move |context, output| {
+                    lock(&outputs).outputs.push_back(TaskOutput {
+                        activity_id: context.attempt_id().to_string(),
+                        output,
+                    })
+                });
+            actix_rt::System::new("yarapi-ffi").block_on(requestor.run())
+        }))
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("job panicked")));
+
+        let mut state = lock(&state);
+        match result {
+            Ok(()) => state.status = YarapiStatus::Finished,
+            Err(e) => {
+                state.status = YarapiStatus::Failed;
+                state.error = Some(format!("{:?}", e));
+            }
+        }
+    }));
+    0
+}
+
+/// Returns the current status of the job.
+#[no_mangle]
+pub unsafe extern "C" fn yarapi_job_status(job: *const YarapiJob) -> YarapiStatus {
+    guard(YarapiStatus::Failed, || match job.as_ref() {
+        Some(job) => lock(&job.state).status,
+        None => YarapiStatus::Failed,
+    })
+}
+
+/// Returns the output of the next completed task as JSON
+/// (`{"activityId": "...", "output": ["..."]}`), or NULL if there is none.
+#[no_mangle]
+pub unsafe extern "C" fn yarapi_job_next_output(job: *mut YarapiJob) -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        let output = match job.as_ref() {
+            Some(job) => lock(&job.state).outputs.pop_front(),
+            None => None,
+        };
+        match output.map(|output| serde_json::to_string(&output)) {
+            Some(Ok(json)) => into_c_string(json),
+            _ => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// Returns the error of a failed job, or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn yarapi_job_error(job: *const YarapiJob) -> *mut c_char {
+    guard(std::ptr::null_mut(), || {
+        match job.as_ref().and_then(|job| lock(&job.state).error.clone()) {
+            Some(error) => into_c_string(error),
+            None => std::ptr::null_mut(),
+        }
+    })
+}
+
+/// Releases the job. Blocks until a running job is finished.
+#[no_mangle]
+pub unsafe extern "C" fn yarapi_job_free(job: *mut YarapiJob) {
+    guard((), || {
+        if job.is_null() {
+            return;
+        }
+        let mut job = Box::from_raw(job);
+        if let Some(thread) = job.thread.take() {
+            let _ = thread.join();
+        }
+    })
+}
+
+/// Releases a string returned by the library.
+#[no_mangle]
+pub unsafe extern "C" fn yarapi_string_free(s: *mut c_char) {
+    guard((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn manifest(budget: &str) -> CString {
+        let manifest = serde_json::json!({
+            "version": "0.1.0",
+            "name": "ffi-test",
+            "payload": {
+                "runtime": "vm",
+                "runtimeVersion": "0.2.4",
+                "image": { "type": "url", "hash": "beefdead", "url": "http://example.com/image.gvmi" }
+            },
+            "market": { "subnet": "devnet-beta", "budget": budget, "timeoutSecs": 60 },
+            "tasks": []
+        });
+        CString::new(manifest.to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_create_free() {
+        unsafe {
+            let job = yarapi_job_from_manifest(manifest("5").as_ptr());
+            assert!(!job.is_null());
+            let task = CString::new(r#"[{"run": ["/bin/ls", "-la"]}]"#).unwrap();
+            assert_eq!(yarapi_job_add_task(job, task.as_ptr()), 0);
+            assert_eq!((*job).manifest.tasks.len(), 1);
+            assert_eq!(yarapi_job_status(job), YarapiStatus::Created);
+            assert!(yarapi_job_next_output(job).is_null());
+            assert!(yarapi_job_error(job).is_null());
+            yarapi_job_free(job);
+        }
+    }
+
+    #[test]
+    fn test_null_and_invalid_input() {
+        unsafe {
+            assert!(yarapi_job_from_manifest(std::ptr::null()).is_null());
+            let invalid = CString::new("{").unwrap();
+            assert!(yarapi_job_from_manifest(invalid.as_ptr()).is_null());
+
+            assert_eq!(
+                yarapi_job_add_task(std::ptr::null_mut(), invalid.as_ptr()),
+                -1
+            );
+            assert_eq!(yarapi_job_start(std::ptr::null_mut()), -1);
+            assert_eq!(yarapi_job_status(std::ptr::null()), YarapiStatus::Failed);
+            assert!(yarapi_job_next_output(std::ptr::null_mut()).is_null());
+            assert!(yarapi_job_error(std::ptr::null()).is_null());
+            yarapi_job_free(std::ptr::null_mut());
+            yarapi_string_free(std::ptr::null_mut());
+
+            let job = yarapi_job_from_manifest(manifest("5").as_ptr());
+            assert_eq!(yarapi_job_add_task(job, std::ptr::null()), -1);
+            assert_eq!(yarapi_job_add_task(job, invalid.as_ptr()), -1);
+            assert!((*job).manifest.tasks.is_empty());
+            yarapi_job_free(job);
+        }
+    }
+
+    #[test]
+    fn test_error_propagation() {
+        unsafe {
+            // The budget is only parsed once the job starts.
+            let job = yarapi_job_from_manifest(manifest("lots").as_ptr());
+            assert!(!job.is_null());
+            assert_eq!(yarapi_job_start(job), 0);
+            assert_eq!(yarapi_job_start(job), -1);
+            while yarapi_job_status(job) == YarapiStatus::Running {
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(yarapi_job_status(job), YarapiStatus::Failed);
+
+            let error = yarapi_job_error(job);
+            assert!(!error.is_null());
+            assert!(CStr::from_ptr(error)
+                .to_str()
+                .unwrap()
+                .contains("invalid budget"));
+            yarapi_string_free(error);
+            yarapi_job_free(job);
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! GLM amounts.
+//!
+//! ## Example:
+//! ```
+//! use yarapi::glm::Glm;
+//!
+//! let amount: Glm = "1.5".parse().unwrap();
+//! assert_eq!(format!("{:.2} GLM", amount), "1.50 GLM");
+//! assert_eq!(amount.to_wei(), Some(1_500_000_000_000_000_000));
+//! ```
+use anyhow::{anyhow, Result};
+use bigdecimal::BigDecimal;
+use std::fmt;
+use std::str::FromStr;
+
+const WEI_PER_GLM: u64 = 1_000_000_000_000_000_000;
+/// GLM is an ERC-20 token with 18 decimals.
+const DECIMALS: i64 = 18;
+
+/// Amount of GLM (or tGLM on test networks), kept as an exact decimal.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd)]
+pub struct Glm(BigDecimal);
+
+impl Glm {
+    pub fn zero() -> Self {
+        Glm(0.into())
+    }
+
+    pub fn from_wei(wei: u128) -> Self {
+        // `BigDecimal` doesn't convert from `u128` directly.
+        let wei = BigDecimal::from_str(&wei.to_string()).unwrap_or_default();
+        Glm(wei / BigDecimal::from(WEI_PER_GLM))
+    }
+
+    /// Amount in wei; `None` for negative or too precise amounts.
+    pub fn to_wei(&self) -> Option<u128> {
+        let wei = &self.0 * BigDecimal::from(WEI_PER_GLM);
+        if wei != wei.with_scale(0) {
+            return None;
+        }
+        wei.with_scale(0).to_string().parse().ok()
+    }
+
+    pub fn as_big_decimal(&self) -> &BigDecimal {
+        &self.0
+    }
+}
+
+impl FromStr for Glm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let s = s
+            .strip_suffix("tGLM")
+            .or_else(|| s.strip_suffix("GLM"))
+            .unwrap_or(s)
+            .trim();
+        let amount =
+            BigDecimal::from_str(s).map_err(|e| anyhow!("invalid GLM amount {:?}: {}", s, e))?;
+        if amount < 0.into() {
+            return Err(anyhow!("negative GLM amount: {}", s));
+        }
+        if amount != amount.with_scale(DECIMALS) {
+            return Err(anyhow!(
+                "GLM amount {} has more than {} decimals",
+                s,
+                DECIMALS
+            ));
+        }
+        Ok(Glm(amount))
+    }
+}
+
+/// Without precision, trailing zeros are dropped; with precision (`{:.3}`)
+/// the amount is truncated or padded to that many decimals.
+impl fmt::Display for Glm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match f.precision() {
+            Some(precision) => write!(f, "{}", self.0.with_scale(precision as i64)),
+            None => {
+                let s = self.0.to_string();
+                match s.contains('.') {
+                    true => write!(f, "{}", s.trim_end_matches('0').trim_end_matches('.')),
+                    false => write!(f, "{}", s),
+                }
+            }
+        }
+    }
+}
+
+impl From<BigDecimal> for Glm {
+    fn from(amount: BigDecimal) -> Self {
+        Glm(amount)
+    }
+}
+
+impl From<u64> for Glm {
+    fn from(amount: u64) -> Self {
+        Glm(amount.into())
+    }
+}
+
+impl From<Glm> for BigDecimal {
+    fn from(amount: Glm) -> Self {
+        amount.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        let amount: Glm = "1.500 GLM".parse().unwrap();
+        assert_eq!(amount.to_string(), "1.5");
+        assert_eq!(format!("{:.3}", amount), "1.500");
+        assert_eq!(format!("{:.0}", amount), "1");
+        assert_eq!(Glm::from(5).to_string(), "5");
+        assert!("-1".parse::<Glm>().is_err());
+        assert!("0.0000000000000000001".parse::<Glm>().is_err());
+    }
+
+    #[test]
+    fn test_wei() {
+        let amount = Glm::from_wei(1_250_000_000_000_000_000);
+        assert_eq!(amount.to_string(), "1.25");
+        assert_eq!(amount.to_wei(), Some(1_250_000_000_000_000_000));
+    }
+}
@@ -1,5 +1,10 @@
 pub mod agreement;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod glm;
+pub mod properties;
 pub mod requestor;
 pub mod rest;
+pub mod util;
 
 pub use ya_agreement_utils;
@@ -0,0 +1,134 @@
+//! Names of the Golem properties used in demands, offers and constraints.
+//!
+//! ## Example:
+//! ```
+//! use yarapi::properties::{inf, node};
+//!
+//! let demand = serde_json::json!({ node::NAME: "requestor" });
+//! let filter = format!("({}>=4)", inf::CPU_THREADS);
+//! ```
+use serde_json::Value;
+
+/// `golem.node.*`
+pub mod node {
+    pub const NAME: &str = "golem.node.id.name";
+    /// Human-readable description of the requestor's job, for market
+    /// visibility and debugging.
+    pub const DESCRIPTION: &str = "golem.node.id.description";
+    pub const SUBNET: &str = "golem.node.debug.subnet";
+    /// Region reported by the provider, e.g. `"EU"`.
+    pub const GEO_REGION: &str = "golem.node.geo.region";
+}
+
+/// `golem.srv.comp.*`
+pub mod srv {
+    /// Url of the image, as `hash:sha3:<digest>:<url>`.
+    pub const TASK_PACKAGE: &str = "golem.srv.comp.task_package";
+    /// Unix timestamp in milliseconds.
+    pub const EXPIRATION: &str = "golem.srv.comp.expiration";
+    /// OpenTelemetry trace id of the requestor's run, as hex; a custom
+    /// property, see `Requestor::with_telemetry`.
+    pub const TRACE_ID: &str = "golem.srv.comp.trace_id";
+}
+
+/// `golem.inf.*`
+pub mod inf {
+    pub const CPU_THREADS: &str = "golem.inf.cpu.threads";
+    pub const CPU_CORES: &str = "golem.inf.cpu.cores";
+    pub const CPU_ARCHITECTURE: &str = "golem.inf.cpu.architecture";
+    pub const CPU_BRAND: &str = "golem.inf.cpu.brand";
+    pub const MEM_GIB: &str = "golem.inf.mem.gib";
+    pub const STORAGE_GIB: &str = "golem.inf.storage.gib";
+}
+
+/// `golem.runtime.*`
+pub mod runtime {
+    pub const NAME: &str = "golem.runtime.name";
+    pub const VERSION: &str = "golem.runtime.version";
+    /// Capabilities of the runtime, e.g. `["vpn", "inet"]`.
+    pub const CAPABILITIES: &str = "golem.runtime.capabilities";
+}
+
+/// `golem.activity.*`
+pub mod activity {
+    /// Transfer protocols the exe-unit supports, e.g. `["gftp", "http", "https"]`.
+    pub const CAPS_TRANSFER_PROTOCOL: &str = "golem.activity.caps.transfer.protocol";
+}
+
+/// Looks up a property, e.g. `"golem.inf.cpu.threads"`.
+///
+/// Both flat (`{"golem.inf.cpu.threads": 4}`) and nested
+/// (`{"golem": {"inf": {"cpu": {"threads": 4}}}}`) layouts are supported.
+pub fn get<'a>(properties: &'a Value, key: &str) -> Option<&'a Value> {
+    properties
+        .get(key)
+        .or_else(|| properties.pointer(&format!("/{}", key.replace('.', "/"))))
+}
+
+/// `golem.usage.*`, names of the usage counters.
+pub mod usage {
+    pub const DURATION_SEC: &str = "golem.usage.duration_sec";
+    pub const CPU_SEC: &str = "golem.usage.cpu_sec";
+}
+
+/// Sets a property unless it is already there, in either layout.
+///
+/// Does nothing if `properties` is not an object.
+pub fn set_default(properties: &mut Value, key: &str, value: impl Into<Value>) {
+    if get(properties, key).is_some() {
+        return;
+    }
+    if let Some(properties) = properties.as_object_mut() {
+        properties.insert(key.to_string(), value.into());
+    }
+}
+
+/// Sets a property, replacing its value in either layout.
+pub fn set(properties: &mut Value, key: &str, value: impl Into<Value>) {
+    let value = value.into();
+    if properties.get(key).is_none() {
+        if let Some(slot) = properties.pointer_mut(&format!("/{}", key.replace('.', "/"))) {
+            *slot = value;
+            return;
+        }
+    }
+    if let Some(properties) = properties.as_object_mut() {
+        properties.insert(key.to_string(), value);
+    }
+}
+
+/// `golem.com.*`
+pub mod com {
+    pub const PAYMENT_CHOSEN_PLATFORM: &str = "golem.com.payment.chosen-platform";
+    /// Prefix of `golem.com.payment.platform.<platform>.address`.
+    pub const PAYMENT_PLATFORM: &str = "golem.com.payment.platform";
+    pub const PRICING_MODEL: &str = "golem.com.pricing.model";
+    pub const PRICING_LINEAR_COEFFS: &str = "golem.com.pricing.model.linear.coeffs";
+    /// Names of the usage counters, e.g. `["golem.usage.duration_sec", "golem.usage.cpu_sec"]`.
+    pub const USAGE_VECTOR: &str = "golem.com.usage.vector";
+
+    /// `golem.com.payment.platform.<platform>.address`
+    pub fn payment_platform_address(platform: &str) -> String {
+        format!("golem.com.payment.platform.{}.address", platform)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_default() {
+        let mut props = serde_json::json!({ "golem": { "node": { "id": { "name": "custom" } } } });
+        set_default(&mut props, node::NAME, "default");
+        set_default(&mut props, node::DESCRIPTION, "rendering");
+        assert_eq!(get(&props, node::NAME), Some(&Value::from("custom")));
+        assert_eq!(
+            get(&props, node::DESCRIPTION),
+            Some(&Value::from("rendering"))
+        );
+
+        set(&mut props, node::NAME, "renamed");
+        assert_eq!(props["golem"]["node"]["id"]["name"], "renamed");
+    }
+}
@@ -1,7 +1,40 @@
 mod activity;
+mod budget;
+mod checkpoint;
 mod command;
+mod config;
+mod debit_note;
+mod deterministic;
+mod digest_cache;
+mod event_log;
+mod glob;
+mod manifest;
+mod metrics;
+mod negotiation;
 mod package;
 mod payment_manager;
+mod pipeline;
+mod policy;
+mod pool;
+#[cfg(feature = "progress-server")]
+mod progress;
+mod provider;
+mod provider_cache;
+#[cfg(feature = "render")]
+pub mod render;
+mod requirements;
+mod scheduler;
+mod split;
+mod summary;
+mod task;
+mod task_state;
+#[cfg(feature = "telemetry")]
+mod telemetry;
+mod termination;
+mod throttle;
+mod transfer;
+mod verify;
+mod wasm;
 
 #[macro_use]
 mod macros;
@@ -9,13 +42,19 @@ mod macros;
 use actix::prelude::*;
 use anyhow::{anyhow, Context, Error, Result};
 use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
 use futures::channel::mpsc;
-use futures::future::{select, Either};
+use futures::future::{select, Either, LocalBoxFuture};
 use futures::prelude::*;
 use payment_manager::PaymentManager;
 use std::{
+    cell::RefCell,
+    collections::{BTreeSet, HashMap, HashSet},
     iter::FromIterator,
-    sync::Arc,
+    path::PathBuf,
+    rc::Rc,
+    str::FromStr,
+    sync::{Arc, Mutex},
     time::{Duration, Instant},
 };
 use tokio::time;
@@ -28,21 +67,82 @@ use ya_client::{
         activity::CommandResult,
         market::{
             proposal::{Proposal, State},
-            AgreementProposal, NewDemand, RequestorEvent,
+            AgreementProposal, NewDemand, Reason, RequestorEvent,
         },
     },
     payment::PaymentApi,
     web::WebClient,
 };
 
-use crate::requestor::{activity::Activity, payment_manager::ReleaseAllocation};
+use crate::agreement::Filter;
+use crate::glm::Glm;
+use crate::properties;
+use crate::rest::{is_subscription_expired, reject_reason, Workdir};
+
+#[cfg(feature = "progress-server")]
+pub use crate::requestor::progress::ProgressEvent;
+#[cfg(feature = "progress-server")]
+use crate::requestor::progress::{ProgressHub, ProgressServer};
+#[cfg(feature = "telemetry")]
+use crate::requestor::telemetry::Telemetry;
+use crate::requestor::{
+    activity::Activity,
+    budget::{BudgetCallback, CostCap},
+    checkpoint::Checkpointing,
+    deterministic::Clock,
+    event_log::EventLog,
+    payment_manager::{
+        BudgetThresholdCrossed, GetAgreementCosts, RecordUsage, ReleaseAllocation, TopUp,
+        WatchBudget,
+    },
+    policy::ProviderCosts,
+    provider_cache::PreferredProviders,
+    requirements::Requirements,
+    scheduler::{SchedulerEngine, SlotKind},
+    task::{RunningTask, Task, TaskFactory},
+    task_state::TaskLog,
+    termination::Terminations,
+    verify::{Candidate, Verdict, Verifier},
+};
 pub use crate::requestor::{
+    budget::{BudgetDecision, BudgetThreshold},
+    checkpoint::CHECKPOINT_PATH,
     command::{Command, CommandList},
+    config::RequestorConfig,
+    debit_note::{DebitNoteCheck, DebitNoteVerifier, DeviationTolerance},
+    deterministic::VirtualClock,
+    event_log::{LifecycleEvent, EVENT_LOG_VERSION},
+    manifest::{ImageLocation, Manifest, MarketManifest, PayloadManifest, MANIFEST_VERSION},
+    metrics::SchedulerMetrics,
+    negotiation::{FailureReason, NegotiationReport},
     package::{Image, Package},
+    pipeline::{Pipeline, Stage, StageOutput},
+    policy::SchedulingPolicy,
+    pool::ActivityPool,
+    provider::ProviderInfo,
+    provider_cache::{CachedProvider, ProviderCache},
+    scheduler::SharedScheduler,
+    split::{join_outputs, split_input, SplitJob},
+    summary::{RunSummary, TaskStatus, TaskSummary},
+    task::{Decision, TaskContext},
+    task_state::{StateSnapshot, TaskEvent, TaskState},
+    termination::ProviderTerminated,
+    throttle::TransferLimits,
+    transfer::UnsupportedTransfer,
+    verify::{TaskOutput, Verification},
+    wasm::{WasmEntryPoint, WasmManifest},
 };
-use ya_client::model::payment::Account;
+use ya_client::model::{payment::Account, NodeId};
 
 const MAX_CONCURRENT_JOBS: usize = 64;
+const STRAGGLER_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+/// Clock difference to yagna worth a warning.
+const MAX_CLOCK_SKEW_SECS: i64 = 5;
+/// How many providers may fail to start an activity for a task before the
+/// task is considered failed.
+const DEFAULT_ACTIVITY_RETRIES: usize = 3;
+const PREFERRED_PROVIDERS_WINDOW: Duration = Duration::from_secs(30);
 
 #[derive(Clone, Debug, MessageResponse)]
 enum ComputationState {
@@ -55,6 +155,8 @@ enum ComputationState {
 struct ComputationTracker {
     initial: usize,
     completed: usize,
+    failed: usize,
+    durations: Vec<Duration>,
 }
 
 impl Default for ComputationTracker {
@@ -62,8 +164,55 @@ impl Default for ComputationTracker {
         ComputationTracker {
             initial: 0,
             completed: 0,
+            failed: 0,
+            durations: vec![],
+        }
+    }
+}
+
+impl ComputationTracker {
+    fn is_done(&self) -> bool {
+        self.completed + self.failed >= self.initial
+    }
+
+    /// Average duration of the tasks completed so far.
+    fn mean_duration(&self) -> Option<Duration> {
+        match self.durations.len() {
+            0 => None,
+            n => Some(self.durations.iter().sum::<Duration>() / n as u32),
         }
     }
+
+    /// Whether `remaining` tasks, computed by `active` providers at the mean
+    /// duration, would finish later than `time_left` from now.
+    fn misses_deadline(
+        &self,
+        remaining: usize,
+        active: usize,
+        time_left: Duration,
+    ) -> Option<bool> {
+        let projected = self
+            .mean_duration()?
+            .mul_f64(remaining as f64 / active.max(1) as f64);
+        Some(projected > time_left)
+    }
+
+    /// Median duration of the tasks completed so far.
+    fn median_duration(&self) -> Option<Duration> {
+        let mut durations = self.durations.clone();
+        durations.sort();
+        match durations.len() {
+            0 => None,
+            n if n % 2 == 0 => Some((durations[n / 2 - 1] + durations[n / 2]) / 2),
+            n => Some(durations[n / 2]),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct SpeculativeExecution {
+    min_done_ratio: f64,
+    slowdown_factor: f64,
 }
 
 #[derive(Clone)]
@@ -72,22 +221,153 @@ struct ProposalCtx {
     payment_manager: Addr<PaymentManager>,
     activity_api: ActivityRequestorApi,
     market_api: MarketRequestorApi,
+    health_check: Option<HealthCheck>,
+    report: NegotiationReport,
+    activity_retries: usize,
+    upload_packing: Option<u64>,
+    agreement_cap: Option<BigDecimal>,
+    verify_debit_notes: bool,
+    checkpointing: Option<Checkpointing>,
+    event_log: Option<EventLog>,
+    verifying: bool,
+    scheduler: Option<SchedulerEngine>,
+    on_output_downloaded: Option<OutputHook>,
+    workdir: Option<Workdir>,
+    transfer_limits: Option<TransferLimits>,
+    terminations: Terminations,
+    #[cfg(feature = "telemetry")]
+    telemetry: Option<Telemetry>,
+}
+
+type OutputHook = Arc<dyn Fn(TaskContext, PathBuf) -> LocalBoxFuture<'static, Result<()>>>;
+
+/// Actors of a running requestor, see `Requestor::add_budget`.
+#[derive(Clone)]
+struct Engine {
+    requestor: Addr<Requestor>,
+    payment_manager: Addr<PaymentManager>,
+}
+
+/// Controls a running requestor, see `Requestor::handle`.
+#[derive(Clone)]
+pub struct RequestorHandle {
+    engine: Arc<Mutex<Option<Engine>>>,
+}
+
+impl RequestorHandle {
+    fn engine(&self) -> Result<Engine> {
+        self.engine
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow!("requestor is not running"))
+    }
+
+    /// Stops the run: negotiations end, no more tasks are dispatched and
+    /// the running activities are destroyed, so their batches stop.
+    ///
+    /// The run then settles the payments for the work done and returns as
+    /// usual; the tasks cut short are `TaskStatus::Aborted` in its
+    /// `RunSummary`.
+    pub async fn abort(&self) -> Result<()> {
+        self.engine()?.requestor.send(AbortRun).await?;
+        Ok(())
+    }
+
+    /// Stops dispatching tasks, e.g. to inspect the outputs so far before
+    /// continuing a costly run.
+    ///
+    /// The running tasks go on and new proposals are ignored until
+    /// `resume`. The run doesn't end while paused, but its timeout
+    /// (see `Requestor::with_timeout`) still counts.
+    pub async fn pause(&self) -> Result<()> {
+        self.engine()?.requestor.send(HoldDispatching(true)).await?;
+        Ok(())
+    }
+
+    /// Resumes dispatching paused with `pause`.
+    pub async fn resume(&self) -> Result<()> {
+        self.engine()?
+            .requestor
+            .send(HoldDispatching(false))
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+struct HealthCheck {
+    commands: CommandList,
+    timeout: Duration,
 }
 
 #[derive(Clone)]
 pub struct Requestor {
     name: String,
+    /// Overrides `name` in the demand.
+    node_name: Option<String>,
+    description: Option<String>,
     subnet: String,
     image_type: Image,
     task_package: Package,
+    force_rehash: bool,
     constraints: Constraints,
+    /// Constraints in LDAP filter notation, e.g. imported from a `Manifest`.
+    raw_constraints: Vec<String>,
+    demand_decorator: Option<Arc<dyn Fn(&mut serde_json::Value, &mut String)>>,
+    requirements: Requirements,
     secure: bool,
-    tasks: Vec<CommandList>,
+    tasks: Vec<Task>,
+    running: HashMap<usize, RunningTask>,
+    task_log: TaskLog,
+    negotiation_report: NegotiationReport,
+    run_summary: RunSummary,
+    metrics: SchedulerMetrics,
     timeout: Duration,
+    expiration_margin: Duration,
+    deadline: Option<DateTime<Utc>>,
+    scheduling_policy: SchedulingPolicy,
+    provider_costs: ProviderCosts,
+    /// Orders the proposals, see `with_deterministic_mode`.
+    seed: Option<u64>,
+    clock: Clock,
+    health_check: Option<HealthCheck>,
+    activity_retries: usize,
+    max_rounds: u32,
+    upload_packing: Option<u64>,
+    speculative: Option<SpeculativeExecution>,
+    preferred_providers: ProviderCache,
+    provider_cache_path: Option<PathBuf>,
+    completed_providers: ProviderCache,
     budget: BigDecimal,
+    payment_network: Option<String>,
+    app_key: Option<String>,
     state: ComputationState,
     tracker: ComputationTracker,
-    on_completed: Option<Arc<dyn Fn(String, Vec<String>)>>,
+    on_completed: Option<Arc<dyn Fn(&TaskContext, Vec<String>)>>,
+    on_task_failed: Option<Arc<dyn Fn(&TaskContext, &Error, usize) -> Decision>>,
+    /// Factories of the tasks added by `with_task_factory`, by task id.
+    task_factories: HashMap<usize, TaskFactory>,
+    on_output_downloaded: Option<OutputHook>,
+    workdir: Option<Workdir>,
+    transfer_limits: Option<TransferLimits>,
+    budget_thresholds: Vec<(f64, BudgetCallback)>,
+    agreement_cap: Option<BigDecimal>,
+    debit_note_verifier: Option<Arc<dyn DebitNoteVerifier>>,
+    checkpointing: Option<Checkpointing>,
+    event_log: Option<EventLog>,
+    verifier: Option<Verifier>,
+    scheduler: Option<SchedulerEngine>,
+    /// Shared by the clones of the requestor while it runs.
+    engine: Arc<Mutex<Option<Engine>>>,
+    /// Set when a budget callback has paused dispatching.
+    paused: bool,
+    /// Set while dispatching is paused with `RequestorHandle::pause`.
+    held: bool,
+    #[cfg(feature = "progress-server")]
+    progress: Option<ProgressServer>,
+    #[cfg(feature = "telemetry")]
+    telemetry: bool,
 }
 
 impl Requestor {
@@ -95,17 +375,81 @@ impl Requestor {
     pub fn new(name: impl Into<String>, image_type: Image, task_package: Package) -> Self {
         Self {
             name: name.into(),
+            node_name: None,
+            description: None,
             subnet: "community.4".into(),
             image_type,
             task_package,
-            constraints: constraints!["golem.com.pricing.model" == "linear"], /* TODO: other models */
+            force_rehash: false,
+            constraints: constraints![(properties::com::PRICING_MODEL) == "linear"], /* TODO: other models */
+            raw_constraints: vec![],
+            demand_decorator: None,
+            requirements: Requirements::default(),
             secure: false,
             tasks: vec![],
+            running: HashMap::new(),
+            task_log: TaskLog::default(),
+            negotiation_report: NegotiationReport::default(),
+            run_summary: RunSummary::default(),
+            metrics: SchedulerMetrics::default(),
             timeout: Duration::from_secs(300),
+            expiration_margin: Duration::from_secs(0),
+            deadline: None,
+            scheduling_policy: SchedulingPolicy::FirstAvailable,
+            provider_costs: ProviderCosts::default(),
+            seed: None,
+            clock: Clock::System,
+            health_check: None,
+            activity_retries: DEFAULT_ACTIVITY_RETRIES,
+            max_rounds: 1,
+            upload_packing: None,
+            speculative: None,
+            preferred_providers: ProviderCache::default(),
+            provider_cache_path: None,
+            completed_providers: ProviderCache::default(),
             budget: 0.into(),
+            payment_network: None,
+            app_key: None,
             state: ComputationState::AwaitingProviders,
             tracker: ComputationTracker::default(),
             on_completed: None,
+            on_task_failed: None,
+            task_factories: HashMap::new(),
+            on_output_downloaded: None,
+            workdir: None,
+            transfer_limits: None,
+            budget_thresholds: vec![],
+            agreement_cap: None,
+            debit_note_verifier: None,
+            checkpointing: None,
+            event_log: None,
+            verifier: None,
+            scheduler: None,
+            engine: Default::default(),
+            paused: false,
+            held: false,
+            #[cfg(feature = "progress-server")]
+            progress: None,
+            #[cfg(feature = "telemetry")]
+            telemetry: false,
+        }
+    }
+
+    /// Sets the node name the providers see (`golem.node.id.name`); by
+    /// default it's the requestor's name.
+    pub fn with_node_name(self, node_name: impl Into<String>) -> Self {
+        Self {
+            node_name: Some(node_name.into()),
+            ..self
+        }
+    }
+
+    /// Describes the job in the demand (`golem.node.id.description`), e.g.
+    /// to tell the runs apart on the market.
+    pub fn with_description(self, description: impl Into<String>) -> Self {
+        Self {
+            description: Some(description.into()),
+            ..self
         }
     }
 
@@ -117,6 +461,15 @@ impl Requestor {
         }
     }
 
+    /// Computes the digest of the package archive even if it is cached
+    /// from a previous run, see `Package::publish_with`.
+    pub fn with_force_rehash(self) -> Self {
+        Self {
+            force_rehash: true,
+            ..self
+        }
+    }
+
     /// `Demand`s will be handled only by providers in this subnetwork.
     pub fn with_subnet(self, subnet: impl Into<String>) -> Self {
         Self {
@@ -133,12 +486,441 @@ impl Requestor {
         }
     }
 
+    /// Adds a `Filter`, for constraints which `Constraints` can't express,
+    /// e.g. alternatives built with `any!`.
+    pub fn with_filter(mut self, filter: impl Into<Filter>) -> Self {
+        self.raw_constraints.push(filter.into().to_string());
+        self
+    }
+
+    /// Sets hook to invoke on the properties and constraints of the demand
+    /// before it is published, e.g. to add experimental properties or
+    /// clauses this crate doesn't support yet.
+    ///
+    /// The constraints are an LDAP filter, so clauses are added by joining
+    /// it with `&`.
+    ///
+    /// ## Example:
+    /// ```no_run
+    /// # fn run(requestor: yarapi::requestor::Requestor) {
+    /// let requestor = requestor.with_demand_decorator(|properties, constraints| {
+    ///     properties["golem.srv.comp.experimental"] = true.into();
+    ///     *constraints = format!("(&{}(golem.inf.mem.gib>=4))", constraints);
+    /// });
+    /// # }
+    /// ```
+    pub fn with_demand_decorator<F: Fn(&mut serde_json::Value, &mut String) + 'static>(
+        self,
+        f: F,
+    ) -> Self {
+        Self {
+            demand_decorator: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
+    /// Accepts only providers with at least `threads` CPU threads.
+    pub fn with_min_cpu_threads(self, threads: u64) -> Self {
+        Self {
+            requirements: Requirements {
+                min_cpu_threads: Some(threads),
+                ..self.requirements
+            },
+            ..self
+        }
+    }
+
+    /// Accepts only providers with at least `cores` CPU cores.
+    pub fn with_min_cpu_cores(self, cores: u64) -> Self {
+        Self {
+            requirements: Requirements {
+                min_cpu_cores: Some(cores),
+                ..self.requirements
+            },
+            ..self
+        }
+    }
+
+    /// Accepts only providers with the given CPU architecture, e.g. `"x86_64"`.
+    pub fn with_cpu_architecture(self, architecture: impl Into<String>) -> Self {
+        Self {
+            requirements: Requirements {
+                cpu_architecture: Some(architecture.into()),
+                ..self.requirements
+            },
+            ..self
+        }
+    }
+
+    /// Accepts only providers whose CPU brand contains `brand` (case-insensitive),
+    /// e.g. `"AMD"`.
+    ///
+    /// This can't be expressed as a market constraint, so it is checked
+    /// against proposals only.
+    pub fn with_cpu_brand(self, brand: impl Into<String>) -> Self {
+        Self {
+            requirements: Requirements {
+                cpu_brand: Some(brand.into()),
+                ..self.requirements
+            },
+            ..self
+        }
+    }
+
+    /// Accepts only providers reporting one of the given regions
+    /// (`golem.node.geo.region`), e.g. `["EU"]`.
+    ///
+    /// Providers which don't report their region are rejected.
+    pub fn with_regions<I, S>(self, regions: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            requirements: Requirements {
+                regions: regions.into_iter().map(Into::into).collect(),
+                ..self.requirements
+            },
+            ..self
+        }
+    }
+
+    /// Requests runtime capabilities (`golem.runtime.capabilities`), e.g.
+    /// `["vpn", "inet"]`.
+    ///
+    /// The capabilities are put in the demand and required from the offers;
+    /// proposals whose offer doesn't list all of them are rejected before an
+    /// agreement is created.
+    pub fn with_runtime_capabilities<I, S>(self, capabilities: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            requirements: Requirements {
+                capabilities: capabilities.into_iter().map(Into::into).collect(),
+                ..self.requirements
+            },
+            ..self
+        }
+    }
+
+    /// Runs all the tasks on the given provider only.
+    ///
+    /// Useful to reproduce issues with an image or a runtime on a provider
+    /// one operates. Other providers' proposals are ignored.
+    pub fn with_provider(self, node_id: NodeId) -> Self {
+        Self {
+            requirements: Requirements {
+                node_id: Some(node_id),
+                ..self.requirements
+            },
+            ..self
+        }
+    }
+
     /// Adds some `timeout` value for the tasks.
     pub fn with_timeout(self, timeout: Duration) -> Self {
         Self { timeout, ..self }
     }
 
-    /// Sets the max budget in GLM.
+    /// Makes the demand expire `margin` earlier than the `timeout`.
+    ///
+    /// Expiration is already computed using yagna's clock; the margin covers
+    /// providers whose clocks are ahead and which would reject a demand
+    /// expiring too far in the future.
+    pub fn with_expiration_margin(self, margin: Duration) -> Self {
+        Self {
+            expiration_margin: margin,
+            ..self
+        }
+    }
+
+    /// Sets the time by which all tasks should be finished.
+    ///
+    /// Once some tasks have completed, their average duration is used to spot
+    /// tasks which run late. Such tasks are duplicated onto additional providers
+    /// as long as a fresh copy can still finish before the deadline; the first
+    /// result wins and the redundant activities are cancelled.
+    ///
+    /// Note that the deadline does not extend the computation `timeout`.
+    pub fn with_deadline(self, deadline: DateTime<Utc>) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    /// Sets which providers get the tasks; `FirstAvailable` by default.
+    ///
+    /// ## Example:
+    /// ```no_run
+    /// # fn run(requestor: yarapi::requestor::Requestor) {
+    /// use yarapi::requestor::SchedulingPolicy;
+    ///
+    /// let requestor = requestor
+    ///     .with_deadline(chrono::Utc::now() + chrono::Duration::hours(2))
+    ///     .with_scheduling_policy(SchedulingPolicy::CheapestWithinDeadline);
+    /// # }
+    /// ```
+    pub fn with_scheduling_policy(self, scheduling_policy: SchedulingPolicy) -> Self {
+        Self {
+            scheduling_policy,
+            ..self
+        }
+    }
+
+    /// Enables straggler mitigation.
+    ///
+    /// Once at least `min_done_ratio` (0.0 - 1.0) of the tasks have completed,
+    /// every task running longer than `slowdown_factor` times the median task
+    /// duration is re-dispatched to the next available provider. The first
+    /// result wins; the other activity is terminated.
+    pub fn with_speculative_execution(self, min_done_ratio: f64, slowdown_factor: f64) -> Self {
+        Self {
+            speculative: Some(SpeculativeExecution {
+                min_done_ratio,
+                slowdown_factor,
+            }),
+            ..self
+        }
+    }
+
+    /// Computes every task on several distinct providers and accepts the
+    /// result returned by the majority of them, for untrusted providers.
+    ///
+    /// Providers whose results disagree are flagged in the provider cache
+    /// (see `with_provider_cache`) and get no more tasks. When there is no
+    /// majority, all the copies are computed again.
+    ///
+    /// ## Example:
+    /// ```no_run
+    /// # fn run(requestor: yarapi::requestor::Requestor) {
+    /// use yarapi::requestor::Verification;
+    ///
+    /// let requestor = requestor.with_verification(
+    ///     Verification::new(3).with_comparator(|a, b| a.output == b.output),
+    /// );
+    /// # }
+    /// ```
+    pub fn with_verification(self, verification: Verification) -> Self {
+        Self {
+            verifier: Some(Verifier::new(verification)),
+            ..self
+        }
+    }
+
+    /// Shares agreement negotiation and activity slots with the other
+    /// requestors using `scheduler`, in proportion to `priority` (at least 1).
+    ///
+    /// Without a scheduler every requestor negotiates and dispatches as much
+    /// as it can, competing with the others for the daemon.
+    pub fn with_scheduler(self, scheduler: &SharedScheduler, priority: u32) -> Self {
+        Self {
+            scheduler: Some(scheduler.register(priority)),
+            ..self
+        }
+    }
+
+    /// Counters a provider's draft proposal which doesn't meet the
+    /// requirements up to `max_rounds` times in total (1 by default), for
+    /// providers which adjust their offers over several rounds.
+    ///
+    /// Proposals still not meeting them are rejected.
+    pub fn with_max_rounds(self, max_rounds: u32) -> Self {
+        Self {
+            max_rounds: max_rounds.max(1),
+            ..self
+        }
+    }
+
+    /// Negotiates only with the given providers for the first 30 seconds;
+    /// proposals from other providers are considered afterwards.
+    pub fn with_preferred_providers(self, providers: ProviderCache) -> Self {
+        Self {
+            preferred_providers: providers,
+            ..self
+        }
+    }
+
+    /// Keeps a `ProviderCache` at `path` between runs.
+    ///
+    /// If the file exists and is not older than `max_age`, its providers are
+    /// preferred (see `with_preferred_providers`). At the end of the run the
+    /// file is overwritten with the providers which completed tasks.
+    pub fn with_provider_cache(self, path: impl Into<PathBuf>, max_age: Duration) -> Self {
+        let path = path.into();
+        let preferred_providers = match path.exists() {
+            true => match ProviderCache::load(&path) {
+                Ok(cache) if cache.is_fresh(max_age) => cache,
+                Ok(_) => {
+                    log::info!("provider cache {} is stale; ignoring", path.display());
+                    self.preferred_providers
+                }
+                Err(e) => {
+                    log::warn!("{:?}", e);
+                    self.preferred_providers
+                }
+            },
+            false => self.preferred_providers,
+        };
+        Self {
+            preferred_providers,
+            provider_cache_path: Some(path),
+            ..self
+        }
+    }
+
+    /// Checks every provider before dispatching a task to it.
+    ///
+    /// Right after an agreement is made, `commands` (preceded by deploy and
+    /// start) are run on a separate activity. If they fail or do not finish
+    /// within `timeout`, the agreement is terminated and the task goes to
+    /// the next provider. Pass an empty `CommandList` to check deploy and
+    /// start only.
+    pub fn with_health_check(self, commands: CommandList, timeout: Duration) -> Self {
+        Self {
+            health_check: Some(HealthCheck { commands, timeout }),
+            ..self
+        }
+    }
+
+    /// Sets how many times a task is moved to another provider when an
+    /// activity can't be created (e.g. the provider went offline after
+    /// approving the agreement). Once exceeded, the task fails as if its
+    /// commands had failed (see `on_task_failed`).
+    pub fn with_activity_retries(self, activity_retries: usize) -> Self {
+        Self {
+            activity_retries,
+            ..self
+        }
+    }
+
+    /// Packs uploads of files not larger than `threshold` bytes into
+    /// archives before running a task, see `CommandList::pack_uploads`.
+    pub fn with_upload_packing(self, threshold: u64) -> Self {
+        Self {
+            upload_packing: Some(threshold),
+            ..self
+        }
+    }
+
+    /// Limits the uploads to providers, see `TransferLimits`; the limits can
+    /// be shared by several requestors.
+    pub fn with_transfer_limits(self, limits: &TransferLimits) -> Self {
+        Self {
+            transfer_limits: Some(limits.clone()),
+            ..self
+        }
+    }
+
+    /// Keeps packed uploads in `workdir` and resolves a relative
+    /// checkpoints directory against it. The directory is cleaned up at
+    /// the end of the run; the run has failed if it was interrupted or any
+    /// task failed.
+    pub fn with_workdir(self, workdir: Workdir) -> Self {
+        Self {
+            workdir: Some(workdir),
+            ..self
+        }
+    }
+
+    /// Persists task state changes to `path` (as JSON lines).
+    ///
+    /// If the file already exists, tasks which are `Done` according to it
+    /// are not run again, which allows resuming an interrupted run with
+    /// the same task list.
+    pub fn with_state_file(self, path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let task_log = match TaskLog::load(&path) {
+            Ok(task_log) => task_log,
+            Err(e) => {
+                log::warn!("{:?}", e);
+                self.task_log
+            }
+        };
+        Self { task_log, ..self }
+    }
+
+    /// Returns the current state of all the tasks.
+    pub fn state_snapshot(&self) -> StateSnapshot {
+        self.task_log.snapshot()
+    }
+
+    /// Returns a handle to the summary of negotiations with providers,
+    /// filled in while the requestor runs.
+    pub fn negotiation_report(&self) -> NegotiationReport {
+        self.negotiation_report.clone()
+    }
+
+    /// Handle to the per-task outcomes of the run, see `RunSummary`.
+    pub fn run_summary(&self) -> RunSummary {
+        self.run_summary.clone()
+    }
+
+    /// Handle to the progress and ETA of the run, see `SchedulerMetrics`.
+    pub fn scheduler_metrics(&self) -> SchedulerMetrics {
+        self.metrics.clone()
+    }
+
+    /// Handle to control the run from outside, e.g. to pause or abort it.
+    ///
+    /// ## Example:
+    /// ```no_run
+    /// # async fn run(requestor: yarapi::requestor::Requestor) -> anyhow::Result<()> {
+    /// let handle = requestor.handle();
+    /// let summary = requestor.run_summary();
+    /// actix_rt::spawn(async move {
+    ///     tokio::time::delay_for(std::time::Duration::from_secs(600)).await;
+    ///     let _ = handle.abort().await;
+    /// });
+    /// requestor.run().await?;
+    /// println!("{}", summary.to_csv());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn handle(&self) -> RequestorHandle {
+        RequestorHandle {
+            engine: self.engine.clone(),
+        }
+    }
+
+    /// Adds `amount` GLM to the budget of the running requestor, e.g. when
+    /// providers turn out more expensive than estimated.
+    ///
+    /// Like `with_max_budget_glm`, accepts `Glm` as well as plain numbers:
+    /// `Glm` converts into `BigDecimal`, while integer literals don't convert
+    /// into `Glm`.
+    ///
+    /// Call it on a clone of the requestor taken before `run`; the allocation
+    /// is recreated with the increased amount and dispatching paused by a
+    /// budget callback resumes.
+    ///
+    /// ## Example:
+    /// ```no_run
+    /// # async fn run(requestor: yarapi::requestor::Requestor) -> anyhow::Result<()> {
+    /// let control = requestor.clone();
+    /// actix_rt::spawn(async move {
+    ///     let _ = requestor.run().await;
+    /// });
+    /// // ...
+    /// control.add_budget(5).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn add_budget<T: Into<BigDecimal>>(&self, amount: T) -> Result<()> {
+        let engine = self
+            .engine
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| anyhow!("requestor is not running"))?;
+        engine.payment_manager.send(TopUp(amount.into())).await??;
+        engine.requestor.send(ResumeDispatching).await?;
+        Ok(())
+    }
+
+    /// Sets the max budget in GLM; accepts `Glm` as well as plain numbers.
     pub fn with_max_budget_glm<T: Into<BigDecimal>>(self, budget: T) -> Self {
         Self {
             budget: budget.into(),
@@ -146,24 +928,377 @@ impl Requestor {
         }
     }
 
+    /// Pays with the requestor account on the given network, e.g. `"rinkeby"`
+    /// or `"mainnet"`. By default the first account configured in yagna is used.
+    pub fn with_payment_network(self, network: impl Into<String>) -> Self {
+        Self {
+            payment_network: Some(network.into()),
+            ..self
+        }
+    }
+
+    /// Authenticates with `app_key` instead of `YAGNA_APPKEY`, e.g. to run
+    /// requestors of several identities in one process.
+    pub fn with_app_key(self, app_key: impl Into<String>) -> Self {
+        Self {
+            app_key: Some(app_key.into()),
+            ..self
+        }
+    }
+
     /// Adds tasks from the specified iterator.
     pub fn with_tasks(mut self, tasks: impl IntoIterator<Item = CommandList>) -> Self {
-        let tasks = Vec::from_iter(tasks);
+        let tasks = Vec::from_iter(
+            tasks
+                .into_iter()
+                .enumerate()
+                .map(|(id, commands)| Task::new(id, commands)),
+        );
         self.tracker.initial = tasks.len();
         Self { tasks, ..self }
     }
 
-    /// Sets callback to invoke upon completion of the tasks.
-    pub fn on_completed<T: Fn(String, Vec<String>) + 'static>(self, f: T) -> Self {
+    /// Adds tasks whose commands are built only once the provider is known.
+    ///
+    /// For each item of `tasks`, `factory` is called with the properties of the
+    /// negotiated provider, which allows e.g. matching the number of worker
+    /// threads to `golem.inf.cpu.threads`. The factory is called again when
+    /// a failed task is retried on another provider.
+    pub fn with_task_factory<T, F>(mut self, tasks: impl IntoIterator<Item = T>, factory: F) -> Self
+    where
+        T: 'static,
+        F: Fn(&ProviderInfo, &T) -> CommandList + 'static,
+    {
+        let factory = Arc::new(factory);
+        let mut task_factories = HashMap::new();
+        let tasks = Vec::from_iter(tasks.into_iter().enumerate().map(|(id, item)| {
+            let factory = factory.clone();
+            let task_factory: TaskFactory = Arc::new(move |provider| factory(provider, &item));
+            task_factories.insert(id, task_factory);
+            Task::with_factory(id)
+        }));
+        self.tracker.initial = tasks.len();
+        Self {
+            tasks,
+            task_factories,
+            ..self
+        }
+    }
+
+    /// Adds the stages of a pipeline as tasks.
+    ///
+    /// A stage is dispatched only once all the stages it depends on are
+    /// done; outputs passed between stages are kept in `dir`. When a stage
+    /// is skipped by `on_task_failed`, the stages depending on it fail too.
+    pub fn with_pipeline(mut self, stages: Vec<Stage>, dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        let tasks = pipeline::into_tasks(stages, &dir);
+        for output in tasks
+            .iter()
+            .filter_map(Task::fixed_commands)
+            .flat_map(CommandList::outputs)
+        {
+            if let Some(parent) = output.parent() {
+                if let Err(e) = std::fs::create_dir_all(parent) {
+                    log::warn!("unable to create {}: {}", parent.display(), e);
+                }
+            }
+        }
+        self.tracker.initial = tasks.len();
+        Self { tasks, ..self }
+    }
+
+    /// Makes the scheduling decisions reproducible, e.g. for tests of an
+    /// application's scheduling logic.
+    ///
+    /// Proposals collected together are handled in an order given by `seed`
+    /// instead of the order of the market events, and task durations,
+    /// straggler duplication and the deadline are measured with `clock`.
+    pub fn with_deterministic_mode(self, seed: u64, clock: VirtualClock) -> Self {
+        Self {
+            seed: Some(seed),
+            clock: Clock::Virtual(clock),
+            ..self
+        }
+    }
+
+    /// Sets callback to invoke upon completion of the tasks, with the
+    /// context of the attempt which completed the task and the outputs of
+    /// its `run` commands.
+    pub fn on_completed<T: Fn(&TaskContext, Vec<String>) + 'static>(self, f: T) -> Self {
         Self {
             on_completed: Some(Arc::new(f)),
             ..self
         }
     }
 
+    /// Sets callback to invoke when a task fails on a provider.
+    ///
+    /// The callback receives the context of the failed attempt, the error and
+    /// the number of the attempt (starting from 1), and decides whether the
+    /// task should be retried, skipped or the whole computation aborted.
+    /// Without this callback failed tasks are always retried.
+    pub fn on_task_failed<T: Fn(&TaskContext, &Error, usize) -> Decision + 'static>(
+        self,
+        f: T,
+    ) -> Self {
+        Self {
+            on_task_failed: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
+    /// Sets hook to invoke on every file downloaded by a task, before the
+    /// task is considered done, e.g. to check the integrity of an image.
+    ///
+    /// An error fails the attempt, as if the provider had failed the task,
+    /// so that it is retried or handled by `on_task_failed`.
+    ///
+    /// ## Example:
+    /// ```no_run
+    /// # fn run(requestor: yarapi::requestor::Requestor) {
+    /// let requestor = requestor.on_output_downloaded(|task, path| async move {
+    ///     let contents = std::fs::read(&path)?;
+    ///     if !contents.starts_with(b"\x89PNG") {
+    ///         anyhow::bail!("task {}: {} is not a PNG", task.task_id, path.display());
+    ///     }
+    ///     Ok(())
+    /// });
+    /// # }
+    /// ```
+    pub fn on_output_downloaded<F, Fut>(self, f: F) -> Self
+    where
+        F: Fn(TaskContext, PathBuf) -> Fut + 'static,
+        Fut: Future<Output = Result<()>> + 'static,
+    {
+        Self {
+            on_output_downloaded: Some(Arc::new(move |task, path| f(task, path).boxed_local())),
+            ..self
+        }
+    }
+
+    /// Limits the cost of a single agreement to `cap`.
+    ///
+    /// While a task runs, its cost is estimated from the activity usage and
+    /// the pricing coefficients negotiated with the provider. Once it exceeds
+    /// the cap, the activity is destroyed, the agreement terminated and the
+    /// task fails; invoices above the cap are rejected.
+    pub fn with_agreement_cap(self, cap: Glm) -> Self {
+        Self {
+            agreement_cap: Some(cap.into()),
+            ..self
+        }
+    }
+
+    /// Downloads checkpoints of running tasks into `dir`.
+    ///
+    /// Guests write their checkpoints to `CHECKPOINT_PATH` at the end of
+    /// every `run` step, and the checkpoint is downloaded after each step but
+    /// the last, as a part of the task's batch. When a task is retried, e.g.
+    /// after its provider has disappeared, its latest checkpoint is uploaded
+    /// back there before the task's commands, so that the computation resumes
+    /// instead of starting over. Checkpoints of completed tasks are removed.
+    pub fn with_checkpointing(self, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            checkpointing: Some(Checkpointing { dir: dir.into() }),
+            ..self
+        }
+    }
+
+    /// Writes lifecycle events to `writer` as JSON lines, see `LifecycleEvent`.
+    ///
+    /// Covers negotiations, task dispatch and state changes, results of the
+    /// exe-script commands and payments, e.g. to ship them to a log
+    /// aggregator without parsing the human-readable logs.
+    pub fn with_event_log(self, writer: impl std::io::Write + Send + 'static) -> Self {
+        Self {
+            event_log: Some(EventLog::new(writer)),
+            ..self
+        }
+    }
+
+    /// Verifies debit notes against the usage of the activities, sampled
+    /// every few seconds, priced with the negotiated coefficients.
+    ///
+    /// Debit notes which `verifier` doesn't accept are rejected, e.g.
+    /// `with_debit_note_verifier(DeviationTolerance(0.1))` rejects notes
+    /// claiming over 10% more than expected.
+    pub fn with_debit_note_verifier(self, verifier: impl DebitNoteVerifier + 'static) -> Self {
+        Self {
+            debit_note_verifier: Some(Arc::new(verifier)),
+            ..self
+        }
+    }
+
+    /// Sets callback to invoke when the amount of accepted invoices crosses
+    /// `fraction` (0.0 - 1.0) of the budget; may be called for several fractions.
+    ///
+    /// The callback decides whether to keep dispatching tasks; a paused run
+    /// waits for `add_budget` (or `RequestorHandle::abort`). Note that
+    /// invoices are only accepted for completed tasks, so spending lags
+    /// behind the running ones.
+    ///
+    /// ## Example:
+    /// ```no_run
+    /// # fn budget(requestor: yarapi::requestor::Requestor) -> yarapi::requestor::Requestor {
+    /// use yarapi::requestor::BudgetDecision;
+    ///
+    /// requestor
+    ///     .on_budget_threshold(0.5, |status| {
+    ///         println!("spent {} GLM", status.spent);
+    ///         BudgetDecision::Continue
+    ///     })
+    ///     .on_budget_threshold(0.8, |_| BudgetDecision::Pause)
+    /// # }
+    /// ```
+    pub fn on_budget_threshold<T: Fn(&BudgetThreshold) -> BudgetDecision + 'static>(
+        mut self,
+        fraction: f64,
+        f: T,
+    ) -> Self {
+        self.budget_thresholds.push((fraction, Arc::new(f)));
+        self
+    }
+
+    /// Serves live progress of the run over HTTP on `addr`.
+    ///
+    /// `GET /status` returns states of the tasks, providers which computed
+    /// them, recent failures and payments as JSON, and `GET /events` streams
+    /// `ProgressEvent`s as server-sent events.
+    #[cfg(feature = "progress-server")]
+    pub fn with_progress_server(self, addr: std::net::SocketAddr) -> Self {
+        Self {
+            progress: Some(ProgressServer {
+                addr,
+                hub: ProgressHub::default(),
+            }),
+            ..self
+        }
+    }
+
+    /// Records the run as OpenTelemetry spans, with the global tracer
+    /// provider (install an exporter before `run`).
+    ///
+    /// Spans of agreements and activities are tagged with their ids, and
+    /// the trace id goes to the demand as `golem.srv.comp.trace_id`, so that
+    /// providers which log it can be correlated with the trace.
+    #[cfg(feature = "telemetry")]
+    pub fn with_telemetry(self) -> Self {
+        Self {
+            telemetry: true,
+            ..self
+        }
+    }
+
+    /// Exports configuration and tasks to a `Manifest`.
+    ///
+    /// Fails if some tasks are built by a task factory, as these
+    /// cannot be serialized.
+    pub fn to_manifest(&self) -> Result<Manifest> {
+        let image = match &self.task_package {
+            Package::Archive(path) => ImageLocation::File { path: path.clone() },
+            Package::Url { digest, url } => ImageLocation::Url {
+                hash: digest.clone(),
+                url: url.clone(),
+            },
+        };
+        let mut tasks = self.tasks.clone();
+        tasks.sort_by_key(|task| task.id);
+        let tasks = tasks
+            .iter()
+            .map(|task| {
+                task.fixed_commands()
+                    .cloned()
+                    .ok_or_else(|| anyhow!("task {} is built by a task factory", task.id))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Manifest {
+            version: MANIFEST_VERSION.to_string(),
+            name: self.name.clone(),
+            payload: PayloadManifest {
+                runtime: self.image_type.runtime_name().to_string(),
+                runtime_version: self.image_type.runtime_version().to_string(),
+                image,
+            },
+            market: MarketManifest {
+                subnet: self.subnet.clone(),
+                budget: Glm::from(self.budget.clone()).to_string(),
+                timeout_secs: self.timeout.as_secs(),
+                secure: self.secure,
+                constraints: Some(self.constraints_filter(self.constraints.clone())),
+            },
+            tasks,
+        })
+    }
+
+    /// Creates a requestor from a `Manifest`.
+    pub fn from_manifest(manifest: Manifest) -> Result<Self> {
+        let version = semver::Version::parse(&manifest.payload.runtime_version)
+            .context("invalid runtime version in manifest")?;
+        let image = Image::from_runtime_name(&manifest.payload.runtime, version)
+            .ok_or_else(|| anyhow!("unsupported runtime: {}", manifest.payload.runtime))?;
+        let package = match manifest.payload.image {
+            ImageLocation::File { path } => Package::Archive(path),
+            ImageLocation::Url { hash, url } => Package::Url { digest: hash, url },
+        };
+        let budget = Glm::from_str(&manifest.market.budget)
+            .with_context(|| format!("invalid budget: {}", manifest.market.budget))?;
+
+        let mut requestor = Requestor::new(manifest.name, image, package)
+            .with_subnet(manifest.market.subnet)
+            .with_max_budget_glm(budget)
+            .with_timeout(Duration::from_secs(manifest.market.timeout_secs))
+            .with_tasks(manifest.tasks);
+        // The exported filter includes the constraints of every `Requestor`.
+        let base = requestor.constraints.to_string();
+        if let Some(constraints) = manifest
+            .market
+            .constraints
+            .as_deref()
+            .and_then(|filter| strip_base_filter(filter, &base))
+        {
+            requestor.raw_constraints.push(constraints);
+        }
+        if manifest.market.secure {
+            requestor = requestor.secure();
+        }
+        Ok(requestor)
+    }
+
     /// Runs all tasks asynchronously.
-    pub async fn run(self) -> Result<()> {
-        let app_key = std::env::var("YAGNA_APPKEY")?;
+    pub async fn run(mut self) -> Result<()> {
+        #[cfg(feature = "progress-server")]
+        if let Some(progress) = &self.progress {
+            let hub = progress.hub.clone();
+            hub.seed(self.task_log.snapshot());
+            self.task_log
+                .add_listener(move |event| hub.publish(ProgressEvent::Task(event.clone())));
+        }
+        if let Some(event_log) = self.event_log.clone() {
+            self.negotiation_report.set_event_log(event_log.clone());
+            self.task_log.add_listener(move |event| {
+                event_log.emit(LifecycleEvent::TaskState {
+                    task_id: event.task_id,
+                    state: event.state,
+                })
+            });
+        }
+        self.resume();
+        self.validate_entry_points()?;
+        self.validate_dependencies()?;
+        if let (Some(workdir), Some(checkpointing)) = (&self.workdir, &mut self.checkpointing) {
+            checkpointing.dir = workdir.resolve(&checkpointing.dir);
+        }
+        self.completed_providers.flagged = self.preferred_providers.flagged.clone();
+        if let Some(verifier) = &self.verifier {
+            self.tasks = verifier.expand(std::mem::take(&mut self.tasks));
+        }
+
+        let app_key = match self.app_key.clone() {
+            Some(app_key) => app_key,
+            None => std::env::var("YAGNA_APPKEY").context("YAGNA_APPKEY is not set")?,
+        };
 
         let client = WebClient::builder().auth_token(&app_key).build();
         let market_api: MarketRequestorApi = client.interface()?;
@@ -176,72 +1311,323 @@ impl Requestor {
                 "No Requestor accounts initialized. Please run `yagna payment init --sender`."
             )
         }
-
-        let demand = self.create_demand(&accounts[0]).await?;
-        log::debug!("demand: {}", serde_json::to_string_pretty(&demand)?);
+        // Platforms are named `<driver>-<network>-<token>`, e.g. `erc20-rinkeby-tglm`.
+        let (account, platform) = match &self.payment_network {
+            Some(network) => {
+                let account = accounts
+                    .iter()
+                    .find(|account| account.platform.split('-').nth(1) == Some(network.as_str()))
+                    .ok_or_else(|| anyhow!("No Requestor account on {} network.", network))?;
+                (account, Some(account))
+            }
+            None => (&accounts[0], None),
+        };
 
         let allocation = payment_api
             .create_allocation(&model::payment::NewAllocation {
-                address: None,
-                payment_platform: None,
+                address: platform.map(|account| account.address.clone()),
+                payment_platform: platform.map(|account| account.platform.clone()),
                 total_amount: self.budget.clone(),
                 timeout: None,
                 make_deposit: false,
             })
             .await?;
-        log::info!("allocated {} GLM", &allocation.total_amount);
+        log::info!(
+            "allocated {} GLM",
+            Glm::from(allocation.total_amount.clone())
+        );
+
+        // The allocation is timestamped by yagna, so it tells the server time.
+        let clock_skew = allocation.timestamp - Utc::now();
+        if clock_skew.num_seconds().abs() >= MAX_CLOCK_SKEW_SECS {
+            log::warn!(
+                "local clock differs from yagna by {}s; using yagna time for demand expiration",
+                clock_skew.num_seconds()
+            );
+        }
+
+        let (digest, package_url) = self
+            .task_package
+            .publish_with(self.force_rehash, |_, _| ())
+            .await?;
+        // Packages given as urls are published by someone else.
+        let package_published = matches!(self.task_package, Package::Archive(_));
+        #[allow(unused_mut)]
+        let mut demand = self
+            .create_demand(account, &digest, &package_url, clock_skew)
+            .await?;
+        #[cfg(feature = "telemetry")]
+        let (telemetry, _run_span) = match self.telemetry {
+            true => {
+                let (telemetry, span) = Telemetry::start(&self.name);
+                demand.properties[properties::srv::TRACE_ID] = telemetry.trace_id().into();
+                (Some(telemetry), Some(span))
+            }
+            false => (None, None),
+        };
+        log::debug!("demand: {}", serde_json::to_string_pretty(&demand)?);
 
         let subscription_id = market_api.subscribe(&demand).await?;
         log::info!("subscribed to market (id: [{}])", subscription_id);
+        // Replaced by `process_market_events` when the subscription expires.
+        let subscription_id = Arc::new(Mutex::new(subscription_id));
 
         let secure = self.secure;
         let timeout = self.timeout;
-        let payment_manager = PaymentManager::new(payment_api.clone(), allocation).start();
-        let requestor = self.start();
+        let max_rounds = self.max_rounds;
+        let seed = self.seed;
+        let health_check = self.health_check.clone();
+        let report = self.negotiation_report.clone();
+        let summary = self.run_summary.clone();
+        let requirements = self.requirements.clone();
+        let provider_cache_path = self.provider_cache_path.clone();
+        let preferred =
+            PreferredProviders::new(&self.preferred_providers, PREFERRED_PROVIDERS_WINDOW);
+        let mut budget_thresholds: Vec<f64> =
+            self.budget_thresholds.iter().map(|(f, _)| *f).collect();
+        budget_thresholds.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        budget_thresholds.dedup();
+        let payment_manager = PaymentManager::new(payment_api.clone(), allocation)
+            .with_debit_note_verifier(self.debit_note_verifier.clone())
+            .with_event_log(self.event_log.clone())
+            .start();
+        #[cfg(feature = "progress-server")]
+        let progress_server = match &self.progress {
+            Some(progress) => Some(progress::serve(progress, payment_manager.clone())?),
+            None => None,
+        };
+        let engine = self.engine.clone();
+        let requestor = self.clone().start();
+        *engine.lock().unwrap() = Some(Engine {
+            requestor: requestor.clone(),
+            payment_manager: payment_manager.clone(),
+        });
+        if !budget_thresholds.is_empty() {
+            payment_manager.do_send(WatchBudget {
+                thresholds: budget_thresholds,
+                recipient: requestor.clone().recipient(),
+            });
+        }
 
         let (proposal_tx, proposal_rx) = mpsc::channel::<Proposal>(MAX_CONCURRENT_JOBS);
+        let terminations = Terminations::default();
         let proposal_ctx = ProposalCtx {
             requestor: requestor.clone(),
             payment_manager: payment_manager.clone(),
             activity_api,
             market_api: market_api.clone(),
+            health_check,
+            report: report.clone(),
+            activity_retries: self.activity_retries,
+            upload_packing: self.upload_packing,
+            agreement_cap: self.agreement_cap.clone(),
+            verify_debit_notes: self.debit_note_verifier.is_some(),
+            checkpointing: self.checkpointing.clone(),
+            event_log: self.event_log.clone(),
+            verifying: self.verifier.is_some(),
+            scheduler: self.scheduler.clone(),
+            on_output_downloaded: self.on_output_downloaded.clone(),
+            workdir: self.workdir.clone(),
+            transfer_limits: self.transfer_limits.clone(),
+            terminations: terminations.clone(),
+            #[cfg(feature = "telemetry")]
+            telemetry,
         };
 
         let compute = proposal_rx.for_each_concurrent(MAX_CONCURRENT_JOBS, move |proposal| {
             let ctx = proposal_ctx.clone();
             async move {
                 let proposal_id = proposal.proposal_id.clone();
-                let agreement_id = create_agreement(ctx.market_api.clone(), proposal)
-                    .await
-                    .with_context(|| {
-                        format!("cannot create agreement for proposal [{:?}]", proposal_id)
-                    })?;
+                let provider = ProviderInfo::from_proposal(&proposal);
+                // Held until the activity finishes.
+                let dispatch_slot = match &ctx.scheduler {
+                    Some(scheduler) => Some(scheduler.acquire(SlotKind::Dispatch).await),
+                    None => None,
+                };
+                let task = async {
+                    Ok::<_, Error>(ctx.requestor.send(TakeTask(provider.clone())).await??)
+                }
+                .await
+                .with_context(|| format!("no tasks for proposal [{:?}]", proposal_id))?;
+                let mut commands = match task
+                    .commands()
+                    .and_then(|commands| commands.resolve(&provider))
+                {
+                    Ok(commands) => commands,
+                    Err(e) => {
+                        ctx.requestor.do_send(ReturnTask(task));
+                        ctx.report
+                            .failure(provider.node_id, FailureReason::Other(e.to_string()));
+                        let context = format!("proposal [{:?}] rejected", proposal_id);
+                        return Err(e.context(context));
+                    }
+                };
+                if ctx.verifying {
+                    commands = verify::redirect_downloads(commands, &provider.node_id);
+                }
+                if let Err(e) = transfer::check(&commands, &provider) {
+                    ctx.requestor.do_send(ReturnTask(task));
+                    ctx.report
+                        .failure(provider.node_id, FailureReason::UnsupportedTransfer);
+                    let context = format!("proposal [{:?}] rejected", proposal_id);
+                    return Err(Error::new(e).context(context));
+                }
 
-                let task = async { Ok::<_, Error>(ctx.requestor.send(TakeTask).await??) }
-                    .await
-                    .with_context(|| format!("no tasks for agreement [{:?}]", agreement_id))?;
+                let negotiation_slot = match &ctx.scheduler {
+                    Some(scheduler) => Some(scheduler.acquire(SlotKind::Negotiation).await),
+                    None => None,
+                };
+                let agreement =
+                    create_agreement(ctx.market_api.clone(), proposal, &ctx.report).await;
+                drop(negotiation_slot);
+                let agreement_id = match agreement {
+                    Ok(agreement_id) => agreement_id,
+                    Err(e) => {
+                        ctx.requestor.do_send(ReturnTask(task));
+                        return Err(e.context(format!(
+                            "cannot create agreement for proposal [{:?}]",
+                            proposal_id
+                        )));
+                    }
+                };
+                #[cfg(feature = "telemetry")]
+                let agreement_span = ctx
+                    .telemetry
+                    .as_ref()
+                    .map(|telemetry| telemetry.agreement(&agreement_id, &provider.node_id));
+
+                if let Some(check) = &ctx.health_check {
+                    let result = health_check(
+                        ctx.activity_api.clone(),
+                        agreement_id.clone(),
+                        check.clone(),
+                        secure,
+                    )
+                    .await;
+                    if let Err(e) = result {
+                        if let Err(e) = ctx
+                            .market_api
+                            .terminate_agreement(&agreement_id, &None)
+                            .await
+                        {
+                            log::warn!("unable to terminate agreement [{}]: {}", agreement_id, e);
+                        }
+                        ctx.requestor.do_send(ReturnTask(task));
+                        ctx.report
+                            .failure(provider.node_id, FailureReason::HealthCheckFailed);
+                        return Err(e.context(format!(
+                            "provider [{:?}] failed the health check",
+                            provider.node_id
+                        )));
+                    }
+                }
 
-                let activity = Activity::create(
+                ctx.requestor
+                    .do_send(SetTaskState(task.id, TaskState::Dispatched));
+                let outputs = commands.outputs();
+                if let Some(checkpointing) = &ctx.checkpointing {
+                    commands = checkpointing.seed(task.id, commands);
+                }
+                if let Some(threshold) = ctx.upload_packing {
+                    let packed = match &ctx.workdir {
+                        Some(workdir) => workdir
+                            .dir("uploads")
+                            .and_then(|dir| commands.clone().pack_uploads_in(threshold, &dir)),
+                        None => commands.clone().pack_uploads(threshold),
+                    };
+                    commands = match packed {
+                        Ok(packed) => packed,
+                        Err(e) => {
+                            log::warn!("unable to pack uploads of task {}: {:#}", task.id, e);
+                            commands
+                        }
+                    };
+                }
+                let activity = match Activity::create(
                     ctx.activity_api.clone(),
                     agreement_id.clone(),
-                    task.clone(),
+                    commands,
                     secure,
                 )
                 .await
-                .with_context(|| {
-                    format!("can't create activity for agreement [{:?}]", agreement_id)
-                })?;
+                {
+                    Ok(activity) => activity,
+                    Err(e) => {
+                        if let Err(e) = ctx
+                            .market_api
+                            .terminate_agreement(&agreement_id, &None)
+                            .await
+                        {
+                            log::warn!("unable to terminate agreement [{}]: {}", agreement_id, e);
+                        }
+                        ctx.report.failure(
+                            provider.node_id,
+                            FailureReason::Other("activity not created".to_string()),
+                        );
+                        let e = e.context(format!(
+                            "can't create activity for agreement [{:?}]",
+                            agreement_id
+                        ));
+                        let mut task = task;
+                        task.activity_failures += 1;
+                        if task.activity_failures > ctx.activity_retries {
+                            let context = TaskContext::new(task.id, agreement_id, None, &provider);
+                            ctx.requestor.do_send(TaskFailed(context, task, e));
+                            return Ok(());
+                        }
+                        // The next proposal picks the task up.
+                        ctx.requestor.do_send(ReturnTask(task));
+                        return Err(e);
+                    }
+                };
                 let activity_id = activity.activity_id.clone();
-                let task = activity.task.clone();
-                let fut = monitor_activity(activity, ctx.payment_manager.clone()).then(
+                let agreement_id = activity.agreement_id.clone();
+                let task_id = task.id;
+                #[cfg(feature = "telemetry")]
+                let spans = (
+                    agreement_span,
+                    ctx.telemetry
+                        .as_ref()
+                        .map(|telemetry| telemetry.activity(&activity_id, &agreement_id, task_id)),
+                );
+                let fut = monitor_activity(activity, task_id, provider.clone(), ctx.clone()).then(
                     |result| async move {
+                        drop(dispatch_slot);
+                        #[cfg(feature = "telemetry")]
+                        let _spans = spans;
+                        let context = TaskContext::new(
+                            task_id,
+                            agreement_id.clone(),
+                            Some(activity_id.clone()),
+                            &provider,
+                        );
+                        let result = match (result, &ctx.on_output_downloaded) {
+                            (Ok(Some(o)), Some(hook)) => post_process(hook, &context, &outputs)
+                                .await
+                                .map(|_| Some(o)),
+                            (result, _) => result,
+                        };
                         match result {
-                            Ok(o) => {
-                                ctx.requestor.do_send(FinishTask(activity_id, o));
+                            Ok(Some(o)) => {
+                                ctx.requestor.do_send(FinishTask {
+                                    activity_id,
+                                    agreement_id,
+                                    task_id,
+                                    provider,
+                                    output: o,
+                                    outputs,
+                                });
+                            }
+                            Ok(None) => {
+                                log::info!(
+                                    "activity [{}] cancelled; task {} computed elsewhere",
+                                    activity_id,
+                                    task_id
+                                );
                             }
                             Err(e) => {
                                 log::error!("activity [{}] error: {}", activity_id, e);
-                                ctx.requestor.do_send(ReturnTask(task));
+                                ctx.requestor.do_send(TaskFailed(context, task, e));
                             }
                         }
                     },
@@ -260,25 +1646,57 @@ impl Requestor {
             market_api.clone(),
             subscription_id.clone(),
             demand,
+            preferred,
+            requirements,
+            max_rounds,
+            seed,
+            report.clone(),
             proposal_tx,
         ));
+        Arbiter::spawn(termination::watch(
+            terminations,
+            market_api.clone(),
+            self.event_log.clone(),
+            requestor.clone(),
+        ));
 
-        match select(
-            await_activity(requestor, timeout).boxed_local(),
+        let interrupted = match select(
+            await_activity(requestor.clone(), timeout).boxed_local(),
             actix_rt::signal::ctrl_c().boxed_local(),
         )
         .await
         {
-            Either::Left(_) => (),
+            Either::Left(_) => false,
             Either::Right((result, fut)) => match result {
-                Ok(_) => log::warn!("interrupted with ctrl-c"),
+                Ok(_) => {
+                    log::warn!("interrupted with ctrl-c");
+                    true
+                }
                 Err(_) => {
                     log::warn!("unable to bind a ctrl-c handler; waiting for computation");
                     fut.await;
+                    false
                 }
             },
+        };
+
+        if let Some(path) = provider_cache_path {
+            let cache = ProviderCache {
+                created: Utc::now(),
+                ..requestor.send(GetCompletedProviders).await?
+            };
+            match cache.save(&path) {
+                Ok(()) => log::info!(
+                    "saved {} providers to {}",
+                    cache.providers.len(),
+                    path.display()
+                ),
+                Err(e) => log::warn!("{:?}", e),
+            }
         }
 
+        log::info!("{}", report);
+
         log::info!("waiting for payments");
         loop {
             let r = payment_manager.send(payment_manager::GetPending).await?;
@@ -288,65 +1706,444 @@ impl Requestor {
             log::info!("pending payments: {}", r);
             tokio::time::delay_for(Duration::from_secs(1)).await;
         }
+        summary.set_costs(&payment_manager.send(GetAgreementCosts).await?);
 
         log::info!("unsubscribing from the market");
+        let subscription_id = subscription_id.lock().unwrap().clone();
         if let Err(e) = market_api.unsubscribe(&subscription_id).await {
             log::warn!("unable to unsubscribe from the market: {}", e);
         }
 
+        *engine.lock().unwrap() = None;
         log::info!("releasing allocation");
         if let Err(e) = payment_manager.send(ReleaseAllocation).await {
             log::warn!("unable to release allocation: {:?}", e);
         }
 
+        if package_published {
+            if let Err(e) = gftp::close(&package_url).await {
+                log::warn!("unable to unpublish the package: {}", e);
+            }
+        }
+
+        #[cfg(feature = "progress-server")]
+        if let Some(server) = progress_server {
+            server.stop(true).await;
+        }
+
+        if let Some(workdir) = &self.workdir {
+            let failed = summary
+                .tasks()
+                .iter()
+                .any(|task| task.status != TaskStatus::Done);
+            workdir.cleanup(!interrupted && !failed);
+        }
+
         Ok(())
     }
 
-    async fn create_demand(&self, account: &Account) -> Result<NewDemand> {
+    #[cfg(feature = "progress-server")]
+    fn publish(&self, event: ProgressEvent) {
+        if let Some(progress) = &self.progress {
+            progress.hub.publish(event);
+        }
+    }
+
+    /// Skips tasks already done according to the task log.
+    fn resume(&mut self) {
+        let task_log = &self.task_log;
+        let (done, pending): (Vec<_>, Vec<_>) = self
+            .tasks
+            .drain(..)
+            .partition(|task| task_log.state(task.id) == Some(TaskState::Done));
+        if !done.is_empty() {
+            log::info!("resuming; {} tasks already done", done.len());
+        }
+        self.tracker.completed += done.len();
+        self.tasks = pending;
+        for task in &self.tasks {
+            self.task_log.record(task.id, TaskState::Pending);
+        }
+    }
+
+    /// Fails the queued tasks which depend, directly or not, on a failed one.
+    fn fail_dependents(&mut self, task_id: usize) {
+        let (dependents, tasks): (Vec<_>, Vec<_>) = self
+            .tasks
+            .drain(..)
+            .partition(|task| task.after.contains(&task_id));
+        self.tasks = tasks;
+        for task in dependents {
+            log::warn!(
+                "task {} failed, dropping dependent task {}",
+                task_id,
+                task.id
+            );
+            self.task_log.record(task.id, TaskState::Failed);
+            self.run_summary.record(TaskSummary::failed(
+                task.id,
+                format!("dependency {} failed", task_id),
+            ));
+            self.tracker.failed += 1;
+            self.fail_dependents(task.id);
+        }
+        if self.tasks.is_empty() && matches!(self.state, ComputationState::AwaitingProviders) {
+            self.state = ComputationState::AwaitingCompletion;
+        }
+    }
+
+    /// Checks that the tasks depend only on tasks of this computation.
+    fn validate_dependencies(&self) -> Result<()> {
+        for task in &self.tasks {
+            for id in &task.after {
+                let known = self.tasks.iter().any(|task| task.id == *id)
+                    || self.task_log.state(*id) == Some(TaskState::Done);
+                if !known {
+                    anyhow::bail!("task {} depends on unknown task {}", task.id, id);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the `run` commands of a WASM package's tasks against the
+    /// entry points in its manifest. Tasks built by a factory and published
+    /// packages (`Package::Url`) can't be checked up front.
+    fn validate_entry_points(&self) -> Result<()> {
+        let path = match (&self.image_type, &self.task_package) {
+            (Image::Wasm(_), Package::Archive(path)) => path,
+            _ => return Ok(()),
+        };
+        let manifest = WasmManifest::read(path)?;
+        log::info!(
+            "package {} entry points: {}",
+            manifest.name,
+            manifest
+                .entry_points
+                .iter()
+                .map(|entry_point| entry_point.id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        for task in &self.tasks {
+            if let Some(commands) = task.fixed_commands() {
+                manifest
+                    .validate(commands)
+                    .with_context(|| format!("invalid task {}", task.id))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn update_metrics(&self) {
+        let track = &self.tracker;
+        self.metrics.update(
+            track.initial,
+            track.completed + track.failed,
+            track.mean_duration(),
+            self.running
+                .values()
+                .map(|running| running.started)
+                .collect(),
+        );
+        #[cfg(feature = "progress-server")]
+        self.publish(ProgressEvent::Progress {
+            percent: self.metrics.progress(),
+            eta_secs: self.metrics.eta().map(|eta| eta.as_secs_f64()),
+        });
+    }
+
+    /// Returns how long a task may run before it gets duplicated.
+    fn straggler_threshold(&self) -> Option<Duration> {
+        let deadline_threshold = self.deadline.and_then(|deadline| {
+            let estimate = self.tracker.mean_duration()?;
+            let time_left = (deadline - self.clock.now_utc()).to_std().ok()?;
+            let remaining = self.tasks.len() + self.running.len();
+            let active = self.running.values().map(|running| running.copies).sum();
+            // Duplicates are only worth it if the deadline is at risk, and
+            // a fresh copy has to make it before the deadline.
+            let at_risk = self.tracker.misses_deadline(remaining, active, time_left)?;
+            match at_risk && estimate <= time_left {
+                true => Some(estimate),
+                false => None,
+            }
+        });
+        let speculative_threshold = self.speculative.as_ref().and_then(|speculative| {
+            let done = self.tracker.completed as f64 / self.tracker.initial.max(1) as f64;
+            if done < speculative.min_done_ratio {
+                return None;
+            }
+            let median = self.tracker.median_duration()?;
+            Some(median.mul_f64(speculative.slowdown_factor))
+        });
+
+        match (deadline_threshold, speculative_threshold) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Schedules duplicates of the tasks which run far longer than expected.
+    fn check_stragglers(&mut self) {
+        let threshold = match self.straggler_threshold() {
+            Some(threshold) => threshold,
+            None => return,
+        };
+
+        let mut duplicates = vec![];
+        for running in self.running.values_mut() {
+            let elapsed = self.clock.elapsed(running.started);
+            if !running.duplicated && elapsed > threshold {
+                log::info!(
+                    "task {} runs for {:?}, threshold {:?}; scheduling a duplicate",
+                    running.task.id,
+                    elapsed,
+                    threshold
+                );
+                running.duplicated = true;
+                duplicates.push(running.task.clone());
+            }
+        }
+
+        if !duplicates.is_empty() {
+            self.tasks.extend(duplicates);
+            self.state = ComputationState::AwaitingProviders;
+        }
+    }
+
+    /// Compares a copy of a task under verification with the other copies;
+    /// returns the accepted result once all of them have finished.
+    fn verify(&mut self, task_id: usize, candidate: Candidate) -> Option<Candidate> {
+        let verifier = match &mut self.verifier {
+            Some(verifier) => verifier,
+            None => return Some(candidate),
+        };
+        match self.running.get_mut(&task_id) {
+            Some(running) if running.copies > 1 => running.copies -= 1,
+            Some(_) => (),
+            None => {
+                log::debug!("ignoring redundant result of task {}", task_id);
+                return None;
+            }
+        }
+        match verifier.add(task_id, candidate) {
+            Verdict::Pending => None,
+            Verdict::Accepted {
+                winner,
+                disagreeing,
+            } => {
+                for provider in disagreeing {
+                    log::warn!(
+                        "task {}: result of provider [{:?}] disagrees with the majority",
+                        task_id,
+                        provider.node_id
+                    );
+                    self.completed_providers.flag(&provider);
+                }
+                Some(winner)
+            }
+            Verdict::Inconclusive => {
+                if let Some(running) = self.running.remove(&task_id) {
+                    let copies = verifier.expand(vec![running.task]);
+                    self.tasks.extend(copies);
+                    self.state = ComputationState::AwaitingProviders;
+                }
+                None
+            }
+        }
+    }
+
+    /// While dispatching is paused, proposals are ignored; the run goes on
+    /// until it is resumed or aborted, or its timeout passes.
+    fn settle_paused(&mut self) {
+        if self.paused || self.held {
+            if let ComputationState::AwaitingProviders = self.state {
+                self.state = ComputationState::AwaitingCompletion;
+            }
+        }
+    }
+
+    /// Drops the queued tasks and cancels the running ones, whose monitors
+    /// then destroy their activities; the run finishes.
+    fn abort(&mut self) {
+        log::warn!(
+            "run aborted with {} tasks queued and {} running",
+            self.tasks.len(),
+            self.running.len()
+        );
+        let task_ids: BTreeSet<usize> = self
+            .tasks
+            .drain(..)
+            .map(|task| task.id)
+            .chain(self.running.drain().map(|(task_id, _)| task_id))
+            .collect();
+        for task_id in task_ids {
+            self.task_log.record(task_id, TaskState::Failed);
+            self.run_summary.record(TaskSummary::aborted(task_id));
+        }
+        self.state = ComputationState::Finished;
+    }
+
+    /// Marks one copy of the task as no longer running. Returns `true`
+    /// if it was the last running copy.
+    fn release_copy(&mut self, task_id: usize) -> bool {
+        match self.running.get_mut(&task_id) {
+            Some(running) if running.copies > 1 => {
+                running.copies -= 1;
+                false
+            }
+            Some(_) => {
+                self.running.remove(&task_id);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Builds the demand; its expiration is computed in yagna time,
+    /// i.e. local time shifted by `clock_skew`.
+    async fn create_demand(
+        &self,
+        account: &Account,
+        digest: &str,
+        url: &url::Url,
+        clock_skew: chrono::Duration,
+    ) -> Result<NewDemand> {
+        use crate::properties::{com, node, runtime, srv};
+
         // "golem.node.debug.subnet" == "mysubnet", TODO
-        let (digest, url) = self.task_package.publish().await?;
         let url_with_hash = format!("hash:sha3:{}:{}", digest, url);
-        let constraints = self.constraints.clone().and(constraints![
-            "golem.runtime.name" == self.image_type.runtime_name(),
-            // "golem.runtime.version" == self.image_type.runtime_version().to_string(),
-            "golem.node.debug.subnet" == self.subnet.clone(),
-        ]);
+        let mut constraints = self.constraints_filter(self.constraints.clone().and(constraints![
+            (runtime::NAME) == self.image_type.runtime_name(),
+            // (runtime::VERSION) == self.image_type.runtime_version().to_string(),
+            (node::SUBNET) == self.subnet.clone(),
+        ]));
 
         log::debug!("srv.comp.task_package: {}", url_with_hash);
 
-        let deadline = chrono::Utc::now() + chrono::Duration::from_std(self.timeout.clone())?;
+        let deadline =
+            chrono::Utc::now() + clock_skew + chrono::Duration::from_std(self.timeout.clone())?
+                - chrono::Duration::from_std(self.expiration_margin)?;
+
+        let mut properties = serde_json::json!({
+            node::NAME: self.node_name.as_ref().unwrap_or(&self.name),
+            node::SUBNET: self.subnet.clone(),
+            srv::TASK_PACKAGE: url_with_hash,
+            srv::EXPIRATION: deadline.timestamp_millis(),
+            com::PAYMENT_CHOSEN_PLATFORM: account.platform.clone(),
+            com::payment_platform_address(&account.platform): account.address.clone(),
+        });
+        if let Some(description) = &self.description {
+            properties[node::DESCRIPTION] = description.clone().into();
+        }
+        if !self.requirements.capabilities.is_empty() {
+            properties[runtime::CAPABILITIES] = self.requirements.capabilities.clone().into();
+        }
+        if let Some(decorator) = &self.demand_decorator {
+            decorator(&mut properties, &mut constraints);
+        }
 
-        let demand = NewDemand::new(
-            serde_json::json!({
-                "golem.node.id.name": self.name,
-                "golem.node.debug.subnet": self.subnet.clone(),
-                "golem.srv.comp.task_package": url_with_hash,
-                "golem.srv.comp.expiration": deadline.timestamp_millis(),
-                "golem.com.payment.chosen-platform": account.platform.clone(),
-                format!("golem.com.payment.platform.{}.address", account.platform): account.address.clone(),
-            }),
-            constraints.to_string(),
-        );
+        Ok(NewDemand::new(properties, constraints))
+    }
 
-        Ok(demand)
+    /// Joins `constraints` with the raw constraints.
+    fn constraints_filter(&self, constraints: Constraints) -> String {
+        let mut raw_constraints = self.raw_constraints.clone();
+        raw_constraints.extend(self.requirements.constraints());
+        match raw_constraints.is_empty() {
+            true => constraints.to_string(),
+            false => format!("(&{}{})", constraints, raw_constraints.join("")),
+        }
+    }
+}
+
+/// Removes the `base` constraints from an exported `filter`, returning
+/// the remaining clauses, if any.
+fn strip_base_filter(filter: &str, base: &str) -> Option<String> {
+    let filter = filter.lines().map(str::trim).collect::<String>();
+    let base = base.lines().map(str::trim).collect::<String>();
+    if filter.is_empty() || filter == base {
+        return None;
+    }
+    match filter
+        .strip_prefix(&format!("(&{}", base))
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        Some("") => None,
+        Some(rest) => Some(rest.to_string()),
+        None => Some(filter),
     }
 }
 
 async fn process_market_events(
     requestor: Addr<Requestor>,
     market_api: MarketRequestorApi,
-    subscription_id: String,
+    subscription_id: Arc<Mutex<String>>,
     demand: NewDemand,
+    mut preferred: PreferredProviders,
+    requirements: Requirements,
+    max_rounds: u32,
+    seed: Option<u64>,
+    report: NegotiationReport,
     mut tx: mpsc::Sender<Proposal>,
 ) {
     log::info!("processing market events");
+    // Negotiation round of our counter-proposals, by their ids.
+    let rounds = Rc::new(RefCell::new(HashMap::new()));
     'outer: loop {
-        let events = market_api
-            .collect(&subscription_id, Some(2.0), Some(5))
-            .await
-            .map_err(|e| log::error!("error collecting market events: {}", e))
-            .unwrap_or_else(|_| Vec::new());
+        let current_id = subscription_id.lock().unwrap().clone();
+        let events = match market_api.collect(&current_id, Some(2.0), Some(5)).await {
+            Ok(events) => events,
+            Err(e) if is_subscription_expired(&e) => {
+                if let Ok(ComputationState::Finished) = requestor.send(GetState).await {
+                    break 'outer;
+                }
+                match market_api.subscribe(&demand).await {
+                    Ok(new_id) => {
+                        log::info!(
+                            "subscription [{}] expired; resubscribed (id: [{}])",
+                            current_id,
+                            new_id
+                        );
+                        *subscription_id.lock().unwrap() = new_id;
+                    }
+                    Err(e) => log::error!("unable to resubscribe to market: {}", e),
+                }
+                Vec::new()
+            }
+            Err(e) => {
+                log::error!("error collecting market events: {}", e);
+                Vec::new()
+            }
+        };
         log::debug!("collected {} market events", events.len());
+        let mut events = events;
+        if let Some(seed) = seed {
+            events.sort_by_cached_key(|event| match event {
+                RequestorEvent::ProposalEvent { proposal, .. } => {
+                    Some(deterministic::rank(seed, &proposal.issuer_id))
+                }
+                _ => None,
+            });
+        }
+
+        let postponed = preferred.release();
+        if !postponed.is_empty() {
+            if let Ok(ComputationState::AwaitingProviders) = requestor.send(GetState).await {
+                log::debug!("countering {} postponed proposals", postponed.len());
+                for proposal in postponed {
+                    spawn_counter(
+                        market_api.clone(),
+                        current_id.clone(),
+                        demand.clone(),
+                        proposal,
+                        1,
+                        rounds.clone(),
+                        report.clone(),
+                    );
+                }
+            }
+        }
 
         for event in events {
             match requestor.send(GetState).await {
@@ -365,31 +2162,86 @@ async fn process_market_events(
                     proposal,
                 } => match proposal.state {
                     State::Initial => {
+                        if !requirements.allows_node(&proposal.issuer_id) {
+                            spawn_reject(
+                                market_api.clone(),
+                                current_id.clone(),
+                                proposal.proposal_id,
+                                &FailureReason::ConstraintMismatch("provider".to_string()),
+                            );
+                            continue;
+                        }
+                        if !preferred.allows(&proposal.issuer_id) {
+                            log::debug!(
+                                "postponing proposal from non-preferred provider [{:?}]",
+                                proposal.issuer_id
+                            );
+                            preferred.postpone(proposal);
+                            continue;
+                        }
                         log::debug!("answering with counter proposal");
-
-                        let market_api_clone = market_api.clone();
-                        let subscription_id_clone = subscription_id.clone();
-                        let counter_proposal = demand.clone();
-
-                        Arbiter::spawn(async move {
-                            if let Err(e) = market_api_clone
-                                .counter_proposal(
-                                    &counter_proposal,
-                                    &subscription_id_clone,
-                                    &proposal.proposal_id,
-                                )
-                                .await
-                            {
-                                log::error!("unable to counter proposal: {}", e);
-                            }
-                        });
+                        spawn_counter(
+                            market_api.clone(),
+                            current_id.clone(),
+                            demand.clone(),
+                            proposal,
+                            1,
+                            rounds.clone(),
+                            report.clone(),
+                        );
                     }
                     State::Draft => {
                         log::debug!("draft proposal from [{:?}]", proposal.issuer_id);
+                        report.proposal(proposal.issuer_id, &proposal.proposal_id);
+                        let round = proposal
+                            .prev_proposal_id
+                            .as_ref()
+                            .and_then(|id| rounds.borrow_mut().remove(id))
+                            .unwrap_or(1);
+                        let provider = ProviderInfo::from_proposal(&proposal);
+                        if let Err(requirement) = requirements.check(&provider) {
+                            if round < max_rounds {
+                                log::debug!(
+                                    "countering draft proposal from [{:?}] (round {})",
+                                    proposal.issuer_id,
+                                    round + 1
+                                );
+                                spawn_counter(
+                                    market_api.clone(),
+                                    current_id.clone(),
+                                    demand.clone(),
+                                    proposal,
+                                    round + 1,
+                                    rounds.clone(),
+                                    report.clone(),
+                                );
+                                continue;
+                            }
+                            let reason = match round {
+                                1 => FailureReason::ConstraintMismatch(requirement.to_string()),
+                                _ => FailureReason::TooManyRounds,
+                            };
+                            spawn_reject(
+                                market_api.clone(),
+                                current_id.clone(),
+                                proposal.proposal_id,
+                                &reason,
+                            );
+                            report.failure(provider.node_id, reason);
+                            continue;
+                        }
                         if let Err(e) = tx.send(proposal).await {
                             log::error!("unable to process proposal: {:?}", e);
                         }
                     }
+                    State::Rejected => {
+                        log::debug!(
+                            "proposal [{:?}] rejected by [{:?}]",
+                            proposal.proposal_id,
+                            proposal.issuer_id
+                        );
+                        report.failure(proposal.issuer_id, FailureReason::RejectedByProvider);
+                    }
                     state => {
                         log::debug!(
                             "ignoring proposal [{:?}] from [{:?}] with state {:?}",
@@ -406,51 +2258,236 @@ async fn process_market_events(
     log::info!("stopped processing market events");
 }
 
-async fn create_agreement(market_api: MarketRequestorApi, proposal: Proposal) -> Result<String> {
+/// Rejects a proposal, telling the provider the `reason`.
+fn spawn_reject(
+    market_api: MarketRequestorApi,
+    subscription_id: String,
+    proposal_id: String,
+    reason: &FailureReason,
+) {
+    let reason = Some(reject_reason(reason.code(), &reason.to_string()));
+    Arbiter::spawn(async move {
+        if let Err(e) = market_api
+            .reject_proposal(&subscription_id, &proposal_id, &reason)
+            .await
+        {
+            log::debug!("unable to reject proposal: {}", e);
+        }
+    });
+}
+
+/// Counters `proposal` with `demand`, recording the `round` of the
+/// counter-proposal in `rounds`.
+fn spawn_counter(
+    market_api: MarketRequestorApi,
+    subscription_id: String,
+    demand: NewDemand,
+    proposal: Proposal,
+    round: u32,
+    rounds: Rc<RefCell<HashMap<String, u32>>>,
+    report: NegotiationReport,
+) {
+    Arbiter::spawn(async move {
+        match market_api
+            .counter_proposal(&demand, &subscription_id, &proposal.proposal_id)
+            .await
+        {
+            Ok(counter_id) => {
+                rounds.borrow_mut().insert(counter_id, round);
+            }
+            Err(e) => {
+                log::error!("unable to counter proposal: {}", e);
+                report.failure(
+                    proposal.issuer_id,
+                    FailureReason::Other(format!("counter proposal failed: {}", e)),
+                );
+            }
+        }
+    });
+}
+
+async fn create_agreement(
+    market_api: MarketRequestorApi,
+    proposal: Proposal,
+    report: &NegotiationReport,
+) -> Result<String> {
     let id = proposal.proposal_id;
+    let issuer_id = proposal.issuer_id;
     let agreement = AgreementProposal::new(
         id.clone(),
         chrono::Utc::now() + chrono::Duration::minutes(10), /* TODO */
     );
 
-    let agreement_id = market_api.create_agreement(&agreement).await?;
+    let agreement_id = match market_api.create_agreement(&agreement).await {
+        Ok(agreement_id) => agreement_id,
+        Err(e) => {
+            report.failure(
+                issuer_id,
+                FailureReason::Other(format!("agreement not created: {}", e)),
+            );
+            return Err(e.into());
+        }
+    };
     log::info!(
         "created agreement [{}] with [{:?}]; confirming",
         agreement_id,
-        &proposal.issuer_id
+        &issuer_id
     );
-    let _ = market_api.confirm_agreement(&agreement_id, None).await?;
+    if let Err(e) = market_api.confirm_agreement(&agreement_id, None).await {
+        report.failure(
+            issuer_id,
+            FailureReason::Other(format!("agreement not confirmed: {}", e)),
+        );
+        return Err(e.into());
+    }
     log::info!("waiting for approval of agreement [{}]", agreement_id);
 
     match market_api
         .wait_for_approval(&agreement_id, Some(10.0))
         .await
     {
-        Ok(()) => Ok(agreement_id),
-        Err(e) => Err(anyhow!("Agreement not approved; got: `{}`", e)),
+        Ok(()) => {
+            report.agreement(issuer_id, &agreement_id);
+            Ok(agreement_id)
+        }
+        Err(e) => {
+            let reason = match e.to_string().as_str() {
+                "Timeout" => FailureReason::Timeout,
+                _ => FailureReason::ApprovalRejected,
+            };
+            report.failure(issuer_id, reason);
+            Err(anyhow!("Agreement not approved; got: `{}`", e))
+        }
+    }
+}
+
+/// Runs the health check commands on a fresh activity.
+async fn health_check(
+    activity_api: ActivityRequestorApi,
+    agreement_id: String,
+    check: HealthCheck,
+    secure: bool,
+) -> Result<()> {
+    let activity = Activity::create(activity_api, agreement_id, check.commands, secure).await?;
+    let result = time::timeout(check.timeout, async {
+        let batch_id = activity.exec().await?;
+        loop {
+            time::delay_for(Duration::from_secs(1)).await;
+            let results = match activity.get_exec_batch_results(&batch_id).await {
+                Ok(results) => results,
+                Err(e) if e.to_string() == "Timeout" => continue,
+                Err(e) => return Err(e),
+            };
+            if let Some(failed) = results.iter().find(|r| r.result != CommandResult::Ok) {
+                return Err(anyhow!(
+                    "command {} failed: {}",
+                    failed.index,
+                    failed.message.clone().unwrap_or_default()
+                ));
+            }
+            if results.last().map(|r| r.is_batch_finished).unwrap_or(false) {
+                return Ok(());
+            }
+        }
+    })
+    .await
+    .unwrap_or_else(|_| Err(anyhow!("timed out after {:?}", check.timeout)));
+
+    if let Err(e) = activity.destroy().await {
+        log::warn!(
+            "unable to destroy activity [{}]: {}",
+            activity.activity_id,
+            e
+        );
     }
+    log::info!(
+        "health check on agreement [{}]: {}",
+        activity.agreement_id,
+        match &result {
+            Ok(()) => "passed".to_string(),
+            Err(e) => format!("failed: {}", e),
+        }
+    );
+    result
 }
 
+/// Returns `None` when the activity has been cancelled because its task
+/// has already been computed by another provider.
 async fn monitor_activity(
     activity: Activity,
-    payment_manager: Addr<PaymentManager>,
-) -> Result<Vec<String>> {
+    task_id: usize,
+    provider: ProviderInfo,
+    ctx: ProposalCtx,
+) -> Result<Option<Vec<String>>> {
+    let ProposalCtx {
+        requestor,
+        payment_manager,
+        market_api,
+        agreement_cap,
+        verify_debit_notes: record_usage,
+        checkpointing,
+        event_log,
+        transfer_limits,
+        terminations,
+        ..
+    } = ctx;
+    let coeffs = provider.linear_coeffs();
     let _ = payment_manager
         .send(payment_manager::AcceptAgreement {
             agreement_id: activity.agreement_id.clone(),
+            cap: agreement_cap.clone(),
+            coeffs: coeffs.clone(),
+            platforms: provider.payment_platforms(),
         })
         .await?;
+    let cost_cap = agreement_cap.map(|limit| CostCap { limit, coeffs });
+    // Dropped before the requestor terminates the agreement itself.
+    let tracked = terminations.track(&activity.agreement_id);
 
     let activity_id = activity.activity_id.clone();
+    let last_upload = activity.script.upload_indices.iter().max().copied();
+    // Held until the provider has fetched the inputs.
+    let mut transfer_permit = match (&transfer_limits, last_upload) {
+        (Some(limits), Some(_)) => Some(limits.acquire(activity.script.upload_bytes).await),
+        _ => None,
+    };
     let batch_id = activity
         .exec()
         .await
         .map_err(|e| anyhow::anyhow!("exec failed: {}", e))?;
+    requestor.do_send(SetTaskState(task_id, TaskState::Running));
+    let mut downloading = false;
+    // Checkpoint downloads within the batch, see `Checkpointing::seed`.
+    let mut checkpoints: HashMap<usize, PathBuf> = match &checkpointing {
+        Some(checkpointing) => activity
+            .script
+            .downloads
+            .iter()
+            .filter(|(_, path)| checkpointing.is_partial(task_id, path))
+            .map(|(index, path)| (*index, path.clone()))
+            .collect(),
+        None => HashMap::new(),
+    };
+    let checkpoint_steps: HashSet<usize> = checkpoints.keys().copied().collect();
+    let mut logged_steps = 0;
 
     let delay = Duration::from_secs(3);
     let mut results = vec![];
     loop {
         time::delay_for(delay).await;
+        if requestor.send(IsTaskDone(task_id)).await? {
+            activity
+                .destroy()
+                .await
+                .map_err(|e| anyhow::anyhow!("destroy failed: {}", e))?;
+            return Ok(None);
+        }
+        if let Some(termination) = terminations.get(&activity.agreement_id) {
+            if let Err(e) = activity.destroy().await {
+                log::debug!("unable to destroy activity [{}]: {}", activity_id, e);
+            }
+            return Err(termination.into());
+        }
         if !activity
             .get_state()
             .await
@@ -460,6 +2497,50 @@ async fn monitor_activity(
             log::warn!("activity [{}] is no longer alive", activity_id);
             break;
         };
+        if cost_cap.is_some() || record_usage {
+            let usage = activity
+                .get_usage()
+                .await
+                .map_err(|e| anyhow::anyhow!("get_usage failed: {}", e))?
+                .current_usage
+                .unwrap_or_default();
+            if record_usage {
+                payment_manager.do_send(RecordUsage {
+                    agreement_id: activity.agreement_id.clone(),
+                    usage: usage.clone(),
+                });
+            }
+            let exceeded = cost_cap
+                .as_ref()
+                .and_then(|cap| Some((cap, cap.exceeded_by(&usage)?)));
+            if let Some((cap, cost)) = exceeded {
+                log::warn!(
+                    "activity [{}] cost {:.6} GLM exceeds the cap; terminating agreement [{}]",
+                    activity_id,
+                    cost,
+                    activity.agreement_id
+                );
+                if let Err(e) = activity.destroy().await {
+                    log::warn!("unable to destroy activity [{}]: {}", activity_id, e);
+                }
+                drop(tracked);
+                let reason = Some(Reason::new("cap exceeded"));
+                if let Err(e) = market_api
+                    .terminate_agreement(&activity.agreement_id, &reason)
+                    .await
+                {
+                    log::warn!(
+                        "unable to terminate agreement [{}]: {}",
+                        activity.agreement_id,
+                        e
+                    );
+                }
+                return Err(anyhow!(
+                    "agreement cap of {} GLM exceeded",
+                    Glm::from(cap.limit.clone())
+                ));
+            }
+        }
         results = match activity.get_exec_batch_results(&batch_id).await {
             Ok(results) => results,
             Err(e) => match e.to_string().as_str() {
@@ -467,6 +2548,44 @@ async fn monitor_activity(
                 _ => return Err(anyhow::anyhow!("get results error: {}", e)),
             },
         };
+        if let Some(event_log) = &event_log {
+            for result in results.iter().filter(|r| r.index as usize >= logged_steps) {
+                event_log.emit(LifecycleEvent::StepResult {
+                    activity_id: activity_id.clone(),
+                    task_id,
+                    index: result.index as usize,
+                    success: result.result == CommandResult::Ok,
+                    message: result.message.clone(),
+                });
+            }
+            if let Some(last) = results.last() {
+                logged_steps = logged_steps.max(last.index as usize + 1);
+            }
+        }
+        if let Some(last_upload) = last_upload {
+            if transfer_permit.is_some() && results.iter().any(|r| r.index as usize >= last_upload)
+            {
+                transfer_permit = None;
+            }
+        }
+        if let Some(checkpointing) = &checkpointing {
+            for result in results.iter().filter(|r| r.result == CommandResult::Ok) {
+                if let Some(partial) = checkpoints.remove(&(result.index as usize)) {
+                    if let Err(e) = checkpointing.store(task_id, &partial) {
+                        log::warn!("checkpoint of task {} not stored: {:#}", task_id, e);
+                    }
+                }
+            }
+        }
+        if !downloading
+            && results.iter().any(|r| {
+                let index = r.index as usize;
+                activity.script.downloads.contains_key(&index) && !checkpoint_steps.contains(&index)
+            })
+        {
+            downloading = true;
+            requestor.do_send(SetTaskState(task_id, TaskState::Downloading));
+        }
         if results.last().map(|r| r.is_batch_finished).unwrap_or(false) {
             log::info!("activity [{}] finished", activity_id);
             break;
@@ -483,6 +2602,18 @@ async fn monitor_activity(
         log::warn!("activity [{}] failed", activity_id);
     }
 
+    if results.len() == activity.script.num_cmds
+        && results.iter().all(|r| r.result == CommandResult::Ok)
+    {
+        for (pattern, to) in activity.task.glob_downloads() {
+            requestor.do_send(SetTaskState(task_id, TaskState::Downloading));
+            if let Err(e) = glob::download(&activity, &pattern, &to).await {
+                let _ = activity.destroy().await;
+                return Err(e.context(format!("downloading {} failed", pattern)));
+            }
+        }
+    }
+
     activity
         .destroy()
         .await
@@ -497,7 +2628,17 @@ async fn monitor_activity(
         })
         .collect::<Vec<_>>();
 
-    Ok(output)
+    Ok(Some(output))
+}
+
+/// Runs the `on_output_downloaded` hook on the downloaded files.
+async fn post_process(hook: &OutputHook, context: &TaskContext, outputs: &[PathBuf]) -> Result<()> {
+    for path in outputs {
+        hook(context.clone(), path.clone())
+            .await
+            .with_context(|| format!("output {} rejected", path.display()))?;
+    }
+    Ok(())
 }
 
 async fn await_activity(requestor: Addr<Requestor>, timeout: Duration) {
@@ -527,6 +2668,15 @@ async fn await_activity(requestor: Addr<Requestor>, timeout: Duration) {
 
 impl Actor for Requestor {
     type Context = actix::Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(METRICS_INTERVAL, |actor, _| actor.update_metrics());
+        if self.deadline.is_some() || self.speculative.is_some() {
+            ctx.run_interval(STRAGGLER_CHECK_INTERVAL, |actor, _| {
+                actor.check_stragglers()
+            });
+        }
+    }
 }
 
 #[derive(Message)]
@@ -548,41 +2698,365 @@ actix_handler!(
 );
 
 #[derive(Message)]
-#[rtype(result = "Result<CommandList>")]
-struct TakeTask;
-actix_handler!(Requestor, TakeTask, |actor: &mut Requestor, _, _| {
-    match actor.tasks.pop() {
-        Some(task) => {
-            if actor.tasks.len() == 0 {
-                actor.state = ComputationState::AwaitingCompletion;
+#[rtype(result = "Result<Task>")]
+struct TakeTask(ProviderInfo);
+actix_handler!(
+    Requestor,
+    TakeTask,
+    |actor: &mut Requestor, msg: TakeTask, _| {
+        if actor.paused || actor.held {
+            return Err(anyhow::anyhow!("dispatching paused"));
+        }
+        let provider = msg.0;
+        let node_id = provider.node_id;
+        if actor.completed_providers.is_flagged(&node_id) {
+            return Err(anyhow::anyhow!("provider [{:?}] is flagged", node_id));
+        }
+        if let SchedulingPolicy::CheapestWithinDeadline = actor.scheduling_policy {
+            let time_left = actor
+                .deadline
+                .and_then(|deadline| (deadline - actor.clock.now_utc()).to_std().ok());
+            let remaining = actor.tasks.len() + actor.running.len();
+            let mean_duration = actor.tracker.mean_duration();
+            let now = actor.clock.now();
+            if !actor
+                .provider_costs
+                .admits(&provider, remaining, time_left, mean_duration, now)
+            {
+                return Err(anyhow::anyhow!(
+                    "provider [{:?}] is too expensive for the deadline",
+                    node_id
+                ));
+            }
+        }
+        // Copies of a task under verification go to distinct providers.
+        let task_log = &actor.task_log;
+        let verifier = &actor.verifier;
+        let index = actor.tasks.iter().rposition(|task| {
+            task.after
+                .iter()
+                .all(|id| task_log.state(*id) == Some(TaskState::Done))
+                && verifier
+                    .as_ref()
+                    .map(|verifier| verifier.allows(task.id, &node_id))
+                    .unwrap_or(true)
+        });
+        match index.map(|index| actor.tasks.remove(index)) {
+            Some(mut task) => {
+                if task.fixed_commands().is_none() {
+                    if let Some(factory) = actor.task_factories.get(&task.id) {
+                        task.build(factory(&provider));
+                    }
+                }
+                if let Some(verifier) = &mut actor.verifier {
+                    verifier.assign(task.id, &node_id);
+                }
+                if actor.tasks.len() == 0 {
+                    actor.state = ComputationState::AwaitingCompletion;
+                }
+                // The state of the task follows its first copy; duplicates
+                // and speculative copies don't move it back.
+                match actor.running.get_mut(&task.id) {
+                    Some(running) => running.copies += 1,
+                    None => {
+                        actor.task_log.record(task.id, TaskState::Negotiating);
+                        actor
+                            .running
+                            .insert(task.id, RunningTask::new(task.clone(), actor.clock.now()));
+                    }
+                }
+                Ok(task)
             }
-            Ok(task)
+            None => Err(anyhow::anyhow!("no more tasks")),
         }
-        None => Err(anyhow::anyhow!("no more tasks")),
     }
+);
+
+/// Ends the run, see `RequestorHandle::abort`.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct AbortRun;
+actix_handler!(Requestor, AbortRun, |actor: &mut Requestor, _, _| {
+    if let ComputationState::Finished = actor.state {
+        return;
+    }
+    actor.abort();
 });
 
+/// Resumes dispatching paused by a budget callback, after a top-up.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct ResumeDispatching;
+actix_handler!(
+    Requestor,
+    ResumeDispatching,
+    |actor: &mut Requestor, _, _| {
+        if !actor.paused {
+            return;
+        }
+        log::info!("resuming dispatching");
+        actor.paused = false;
+        if !actor.tasks.is_empty() && !actor.held {
+            actor.state = ComputationState::AwaitingProviders;
+        }
+    }
+);
+
+/// Pauses or resumes dispatching, see `RequestorHandle::pause`.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct HoldDispatching(bool);
+actix_handler!(
+    Requestor,
+    HoldDispatching,
+    |actor: &mut Requestor, msg: HoldDispatching, _| {
+        if actor.held == msg.0 {
+            return;
+        }
+        actor.held = msg.0;
+        match actor.held {
+            true => {
+                log::info!(
+                    "dispatching paused with {} tasks running",
+                    actor.running.len()
+                );
+                actor.settle_paused();
+            }
+            false => {
+                log::info!("dispatching resumed");
+                let finished = matches!(actor.state, ComputationState::Finished);
+                if !finished && !actor.paused && !actor.tasks.is_empty() {
+                    actor.state = ComputationState::AwaitingProviders;
+                }
+            }
+        }
+    }
+);
+
 #[derive(Message)]
 #[rtype(result = "()")]
-struct ReturnTask(CommandList);
+struct ReturnTask(Task);
 actix_handler!(
     Requestor,
     ReturnTask,
     |actor: &mut Requestor, msg: ReturnTask, _| {
-        actor.tasks.push(msg.0);
-        actor.state = ComputationState::AwaitingProviders;
+        let released = actor.release_copy(msg.0.id);
+        // Every copy of a task under verification has to be computed.
+        let verifying = actor.verifier.is_some() && actor.running.contains_key(&msg.0.id);
+        if released || verifying {
+            if released {
+                actor.task_log.record(msg.0.id, TaskState::Pending);
+            }
+            actor.tasks.push(msg.0);
+            actor.state = ComputationState::AwaitingProviders;
+        }
+        actor.settle_paused();
+    }
+);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct TaskFailed(TaskContext, Task, Error);
+actix_handler!(
+    Requestor,
+    TaskFailed,
+    |actor: &mut Requestor, msg: TaskFailed, _| {
+        let TaskFailed(context, mut task, error) = msg;
+        // Every copy of a task under verification has to be computed, so a
+        // failed copy is retried or fails the task, like the task itself.
+        let verifying = actor.verifier.is_some() && actor.running.contains_key(&task.id);
+        if !actor.release_copy(task.id) && !verifying {
+            log::debug!(
+                "task {} failed on activity [{}]; other copies still running",
+                task.id,
+                context.attempt_id()
+            );
+            return;
+        }
+        let copies_running = actor.running.contains_key(&task.id);
+        task.attempt += 1;
+
+        let decision = match &actor.on_task_failed {
+            Some(f) => f(&context, &error, task.attempt),
+            None => Decision::Retry,
+        };
+        log::info!(
+            "task {} failed on activity [{}] (attempt {}): {:?}",
+            task.id,
+            context.attempt_id(),
+            task.attempt,
+            decision
+        );
+        #[cfg(feature = "progress-server")]
+        actor.publish(ProgressEvent::Log {
+            message: format!(
+                "task {} failed (attempt {}): {}; {:?}",
+                task.id, task.attempt, error, decision
+            ),
+            timestamp: Utc::now(),
+        });
+
+        match decision {
+            Decision::Retry => {
+                if !copies_running {
+                    actor.task_log.record(task.id, TaskState::Pending);
+                }
+                actor.tasks.push(task);
+                actor.state = ComputationState::AwaitingProviders;
+                actor.settle_paused();
+            }
+            Decision::Skip => {
+                // Results of the other copies are ignored from now on.
+                actor.running.remove(&task.id);
+                actor.tasks.retain(|queued| queued.id != task.id);
+                actor.task_log.record(task.id, TaskState::Failed);
+                actor
+                    .run_summary
+                    .record(TaskSummary::failed(task.id, format!("{:#}", error)));
+                actor.tracker.failed += 1;
+                actor.fail_dependents(task.id);
+                if actor.tracker.is_done() {
+                    actor.state = ComputationState::Finished;
+                }
+                actor.settle_paused();
+            }
+            Decision::Abort => {
+                actor.task_log.record(task.id, TaskState::Failed);
+                actor
+                    .run_summary
+                    .record(TaskSummary::failed(task.id, format!("{:#}", error)));
+                log::warn!("computation aborted after task {} failure", task.id);
+                actor.abort();
+            }
+        }
+    }
+);
+
+#[derive(Message)]
+#[rtype(result = "()")]
+struct SetTaskState(usize, TaskState);
+actix_handler!(
+    Requestor,
+    SetTaskState,
+    |actor: &mut Requestor, msg: SetTaskState, _| {
+        // Ignore late updates of tasks finished by another copy, and updates
+        // of copies behind the one furthest along.
+        let forward = actor
+            .task_log
+            .state(msg.0)
+            .map_or(true, |current| current.can_transition_to(msg.1));
+        if actor.running.contains_key(&msg.0) && forward {
+            actor.task_log.record(msg.0, msg.1);
+        }
     }
 );
 
+#[derive(Message)]
+#[rtype(result = "bool")]
+struct IsTaskDone(usize);
+actix_handler!(
+    Requestor,
+    IsTaskDone,
+    |actor: &mut Requestor, msg: IsTaskDone, _| !actor.running.contains_key(&msg.0)
+);
+
+#[derive(Message)]
+#[rtype(result = "ProviderCache")]
+struct GetCompletedProviders;
+actix_handler!(
+    Requestor,
+    GetCompletedProviders,
+    |actor: &mut Requestor, _, _| actor.completed_providers.clone()
+);
+
 #[derive(Message)]
 #[rtype(result = "()")]
-struct FinishTask(String, Vec<String>);
+struct FinishTask {
+    activity_id: String,
+    agreement_id: String,
+    task_id: usize,
+    provider: ProviderInfo,
+    output: Vec<String>,
+    /// Downloaded files.
+    outputs: Vec<PathBuf>,
+}
 actix_handler!(
     Requestor,
     FinishTask,
     |actor: &mut Requestor, msg: FinishTask, _| {
+        let FinishTask {
+            activity_id,
+            agreement_id,
+            task_id,
+            provider,
+            output,
+            outputs,
+        } = msg;
+        let candidate = Candidate {
+            activity_id,
+            agreement_id,
+            provider,
+            result: TaskOutput {
+                output,
+                files: outputs,
+            },
+        };
+        let Candidate {
+            activity_id,
+            agreement_id,
+            provider,
+            result,
+        } = match actor.verify(task_id, candidate) {
+            Some(accepted) => accepted,
+            None => return,
+        };
+        let (output, outputs) = (result.output, result.files);
+        let context = TaskContext::new(task_id, agreement_id, Some(activity_id), &provider);
+        let running = match actor.running.remove(&task_id) {
+            Some(running) => running,
+            None => {
+                log::debug!("ignoring redundant result of task {}", task_id);
+                return;
+            }
+        };
+        // Drop duplicates which have not been dispatched yet.
+        actor.tasks.retain(|task| task.id != task_id);
+        if let ComputationState::AwaitingProviders = actor.state {
+            if actor.tasks.is_empty() {
+                actor.state = ComputationState::AwaitingCompletion;
+            }
+        }
+
+        actor.completed_providers.insert(&provider);
+        if let Some(checkpointing) = &actor.checkpointing {
+            checkpointing.remove(task_id);
+        }
+        #[cfg(feature = "progress-server")]
+        actor.publish(ProgressEvent::Provider {
+            task_id,
+            node_id: provider.node_id.to_string(),
+            name: provider.name().map(ToString::to_string),
+        });
+        actor.task_log.record(task_id, TaskState::Done);
+
+        let duration = actor.clock.elapsed(running.started);
+        actor.provider_costs.observe(&provider.node_id, duration);
+        actor.run_summary.record(TaskSummary {
+            task_id,
+            status: TaskStatus::Done,
+            provider_id: Some(provider.node_id.to_string()),
+            provider_name: provider.name().map(ToString::to_string),
+            region: provider.region().map(ToString::to_string),
+            agreement_id: Some(context.agreement_id.clone()),
+            duration_secs: Some(duration.as_secs_f64()),
+            cost: None,
+            error: None,
+            outputs,
+        });
+
         let track = &mut actor.tracker;
         track.completed += 1;
+        track.durations.push(duration);
 
         log::info!(
             "completed {} tasks out of {}",
@@ -590,11 +3064,275 @@ actix_handler!(
             track.initial
         );
 
-        if track.completed == track.initial {
+        if track.is_done() {
             actor.state = ComputationState::Finished;
         }
+        actor.settle_paused();
         if let Some(f) = &actor.on_completed {
-            f(msg.0, msg.1)
+            f(&context, output)
+        }
+    }
+);
+
+actix_handler!(
+    Requestor,
+    BudgetThresholdCrossed,
+    |actor: &mut Requestor, msg: BudgetThresholdCrossed, _| {
+        let threshold = msg.0;
+        let decisions: Vec<BudgetDecision> = actor
+            .budget_thresholds
+            .iter()
+            .filter(|(fraction, _)| *fraction == threshold.fraction)
+            .map(|(_, f)| f(&threshold))
+            .collect();
+        if decisions.contains(&BudgetDecision::Pause) && !actor.paused {
+            log::warn!(
+                "dispatching paused after spending {} GLM of {} GLM",
+                threshold.spent,
+                threshold.budget
+            );
+            actor.paused = true;
+            actor.settle_paused();
         }
     }
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn tracker(secs: &[u64]) -> ComputationTracker {
+        ComputationTracker {
+            durations: secs.iter().cloned().map(Duration::from_secs).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tracker_durations() {
+        assert_eq!(tracker(&[]).mean_duration(), None);
+        assert_eq!(tracker(&[]).median_duration(), None);
+
+        let odd = tracker(&[30, 10, 20]);
+        assert_eq!(odd.mean_duration(), Some(Duration::from_secs(20)));
+        assert_eq!(odd.median_duration(), Some(Duration::from_secs(20)));
+
+        let even = tracker(&[40, 10, 20, 100]);
+        assert_eq!(even.mean_duration(), Some(Duration::from_secs(42)));
+        assert_eq!(even.median_duration(), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_tracker_misses_deadline() {
+        let time_left = Duration::from_secs(100);
+        assert_eq!(tracker(&[]).misses_deadline(4, 2, time_left), None);
+
+        // 4 tasks of a minute on 2 providers take 2 minutes.
+        let tracker = tracker(&[50, 70]);
+        assert_eq!(tracker.misses_deadline(4, 2, time_left), Some(true));
+        // On 4 providers they make it, so nothing is duplicated.
+        assert_eq!(tracker.misses_deadline(4, 4, time_left), Some(false));
+    }
+
+    #[test]
+    fn test_manifest_roundtrip() {
+        let package = Package::Url {
+            digest: "beefdead".to_string(),
+            url: "http://example.com/image.gvmi".to_string(),
+        };
+        let requestor = Requestor::new("test", Image::GVMKit((0, 2, 4).into()), package)
+            .with_subnet("devnet")
+            .with_max_budget_glm(5)
+            .with_tasks(vec![
+                commands![run("/bin/ls", "-la")],
+                commands![upload("input.txt", "/golem/work/input.txt")],
+            ]);
+
+        let manifest = requestor.to_manifest().unwrap();
+        let json = serde_json::to_value(&manifest).unwrap();
+        assert_eq!(json["payload"]["runtime"], "vm");
+        assert_eq!(json["payload"]["image"]["type"], "url");
+        assert_eq!(json["tasks"][0][0]["run"][1], "-la");
+
+        let imported = Requestor::from_manifest(serde_json::from_value(json).unwrap()).unwrap();
+        assert_eq!(imported.subnet, "devnet");
+        assert_eq!(imported.budget, 5.into());
+        assert_eq!(imported.tasks.len(), 2);
+        assert!(imported.raw_constraints.is_empty());
+
+        // Another cycle doesn't add the pricing constraint again.
+        let manifest2 = imported.to_manifest().unwrap();
+        assert_eq!(manifest2.market.constraints, manifest.market.constraints);
+        let imported = Requestor::from_manifest(manifest2).unwrap();
+        assert!(imported.raw_constraints.is_empty());
+
+        let filtered = imported.with_filter("(golem.inf.mem.gib>=4)");
+        let manifest = filtered.to_manifest().unwrap();
+        let imported = Requestor::from_manifest(manifest.clone()).unwrap();
+        assert_eq!(imported.raw_constraints, vec!["(golem.inf.mem.gib>=4)"]);
+        let manifest2 = imported.to_manifest().unwrap();
+        assert_eq!(manifest2.market.constraints, manifest.market.constraints);
+    }
+
+    fn provider(i: u64) -> ProviderInfo {
+        ProviderInfo {
+            node_id: format!("0x{:040x}", i).parse().unwrap(),
+            properties: serde_json::json!({}),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_budget_pause_waits_for_top_up() {
+        let package = Package::Url {
+            digest: "beefdead".to_string(),
+            url: "http://example.com/image.gvmi".to_string(),
+        };
+        let requestor = Requestor::new("test", Image::GVMKit((0, 2, 4).into()), package)
+            .with_tasks(vec![
+                commands![run("/bin/ls")],
+                commands![run("/bin/ls")],
+                commands![run("/bin/ls")],
+            ])
+            .on_budget_threshold(0.5, |_| BudgetDecision::Pause);
+        let requestor = requestor.start();
+
+        let running = requestor
+            .send(TakeTask(provider(1)))
+            .await
+            .unwrap()
+            .unwrap();
+        requestor
+            .send(BudgetThresholdCrossed(BudgetThreshold {
+                fraction: 0.5,
+                spent: Glm::from(5),
+                budget: Glm::from(10),
+            }))
+            .await
+            .unwrap();
+        assert!(requestor
+            .send(TakeTask(provider(2)))
+            .await
+            .unwrap()
+            .is_err());
+
+        // The paused run outlives its last running task.
+        requestor
+            .send(FinishTask {
+                activity_id: "activity".to_string(),
+                agreement_id: "agreement".to_string(),
+                task_id: running.id,
+                provider: provider(1),
+                output: vec![],
+                outputs: vec![],
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            requestor.send(GetState).await.unwrap(),
+            ComputationState::AwaitingCompletion
+        ));
+
+        // Sent by `add_budget` once the allocation has been topped up.
+        requestor.send(ResumeDispatching).await.unwrap();
+        assert!(matches!(
+            requestor.send(GetState).await.unwrap(),
+            ComputationState::AwaitingProviders
+        ));
+        assert!(requestor.send(TakeTask(provider(2))).await.unwrap().is_ok());
+        assert!(requestor.send(TakeTask(provider(3))).await.unwrap().is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_abort_decision_cancels_running_copies() {
+        let package = Package::Url {
+            digest: "beefdead".to_string(),
+            url: "http://example.com/image.gvmi".to_string(),
+        };
+        let requestor = Requestor::new("test", Image::GVMKit((0, 2, 4).into()), package)
+            .with_tasks(vec![
+                commands![run("/bin/ls")],
+                commands![run("/bin/ls")],
+                commands![run("/bin/ls")],
+            ])
+            .on_task_failed(|_, _, _| Decision::Abort);
+        let summary = requestor.run_summary();
+        let requestor = requestor.start();
+
+        let failing = requestor
+            .send(TakeTask(provider(1)))
+            .await
+            .unwrap()
+            .unwrap();
+        let running = requestor
+            .send(TakeTask(provider(2)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!requestor.send(IsTaskDone(running.id)).await.unwrap());
+
+        let context = TaskContext::new(failing.id, "agreement".to_string(), None, &provider(1));
+        requestor
+            .send(TaskFailed(context, failing, anyhow!("failed")))
+            .await
+            .unwrap();
+
+        // The monitor of the other copy sees it done and destroys its activity.
+        assert!(requestor.send(IsTaskDone(running.id)).await.unwrap());
+        assert!(matches!(
+            requestor.send(GetState).await.unwrap(),
+            ComputationState::Finished
+        ));
+        let statuses: Vec<TaskStatus> = summary.tasks().iter().map(|task| task.status).collect();
+        assert_eq!(
+            statuses,
+            vec![TaskStatus::Aborted, TaskStatus::Aborted, TaskStatus::Failed]
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_failed_copy_counts_attempt() {
+        let package = Package::Url {
+            digest: "beefdead".to_string(),
+            url: "http://example.com/image.gvmi".to_string(),
+        };
+        let failures = Arc::new(AtomicUsize::new(0));
+        let counter = failures.clone();
+        let mut requestor = Requestor::new("test", Image::GVMKit((0, 2, 4).into()), package)
+            .with_tasks(vec![commands![run("/bin/ls")]])
+            .with_verification(Verification::new(2))
+            .on_task_failed(move |_, _, _| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Decision::Retry
+            });
+        // Done by `run`.
+        let verifier = requestor.verifier.clone().unwrap();
+        requestor.tasks = verifier.expand(std::mem::take(&mut requestor.tasks));
+        let requestor = requestor.start();
+
+        let failing = requestor
+            .send(TakeTask(provider(1)))
+            .await
+            .unwrap()
+            .unwrap();
+        requestor
+            .send(TakeTask(provider(2)))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let context = TaskContext::new(failing.id, "agreement".to_string(), None, &provider(1));
+        requestor
+            .send(TaskFailed(context, failing, anyhow!("failed")))
+            .await
+            .unwrap();
+
+        assert_eq!(failures.load(Ordering::SeqCst), 1);
+        let retried = requestor
+            .send(TakeTask(provider(3)))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(retried.attempt, 1);
+    }
+}
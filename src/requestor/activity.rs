@@ -1,9 +1,12 @@
 #![allow(dead_code)]
 
 use crate::requestor::command::{CommandList, ExeScript};
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use std::time::Duration;
 use ya_client::activity::{ActivityRequestorApi, SecureActivityRequestorApi};
-use ya_client::model::activity::{ActivityState, ActivityUsage, ExeScriptCommandResult};
+use ya_client::model::activity::{
+    ActivityState, ActivityUsage, CommandResult, ExeScriptCommandResult,
+};
 
 #[derive(Clone)]
 enum ActivityKind {
@@ -59,15 +62,20 @@ impl Activity {
     }
 
     pub async fn exec(&self) -> Result<String> {
+        self.exec_script(&self.script).await
+    }
+
+    /// Executes a batch other than the task's, e.g. a checkpoint download.
+    pub async fn exec_script(&self, script: &ExeScript) -> Result<String> {
         let batch_id = match &self.kind {
             ActivityKind::Default => {
                 self.api
                     .control()
-                    .exec(self.script.request.clone(), &self.activity_id)
+                    .exec(script.request.clone(), &self.activity_id)
                     .await?
             }
             ActivityKind::Secure(secure_api) => {
-                let cmd_vec = serde_json::from_str(&self.script.request.text)?;
+                let cmd_vec = serde_json::from_str(&script.request.text)?;
                 secure_api.exec(cmd_vec).await?
             }
         };
@@ -78,7 +86,15 @@ impl Activity {
         &self,
         batch_id: &str,
     ) -> Result<Vec<ExeScriptCommandResult>> {
-        let cmd_idx = Some(self.script.num_cmds - 1);
+        self.get_batch_results(batch_id, &self.script).await
+    }
+
+    pub async fn get_batch_results(
+        &self,
+        batch_id: &str,
+        script: &ExeScript,
+    ) -> Result<Vec<ExeScriptCommandResult>> {
+        let cmd_idx = Some(script.num_cmds - 1);
         let vec = match &self.kind {
             ActivityKind::Default => {
                 self.api
@@ -95,6 +111,34 @@ impl Activity {
         Ok(vec)
     }
 
+    /// Executes `commands` in a separate batch and waits until it finishes;
+    /// fails if any of the commands fails.
+    pub async fn run_batch(&self, commands: CommandList) -> Result<Vec<ExeScriptCommandResult>> {
+        let script = commands.into_exe_script_with(false).await?;
+        let batch_id = self.exec_script(&script).await?;
+        loop {
+            let results = match self.get_batch_results(&batch_id, &script).await {
+                Ok(results) => results,
+                // The long poll timed out while the command is still running.
+                Err(e) if is_timeout(&e) => continue,
+                Err(e) => return Err(e),
+            };
+            match results.last() {
+                Some(result) if result.is_batch_finished => {
+                    if result.result != CommandResult::Ok {
+                        return Err(anyhow!(
+                            "command {} failed: {}",
+                            result.index,
+                            result.message.clone().unwrap_or_default()
+                        ));
+                    }
+                    return Ok(results);
+                }
+                _ => tokio::time::delay_for(Duration::from_secs(1)).await,
+            }
+        }
+    }
+
     pub async fn get_state(&self) -> Result<ActivityState> {
         Ok(self.api.state().get_state(&self.activity_id).await?)
     }
@@ -103,3 +147,11 @@ impl Activity {
         Ok(self.api.state().get_usage(&self.activity_id).await?)
     }
 }
+
+/// Whether the long poll for the batch results timed out.
+fn is_timeout(e: &anyhow::Error) -> bool {
+    match e.downcast_ref::<ya_client::Error>() {
+        Some(ya_client::Error::TimeoutError { .. }) => true,
+        _ => e.to_string() == "Timeout",
+    }
+}
@@ -0,0 +1,132 @@
+use bigdecimal::BigDecimal;
+use std::sync::Arc;
+
+use crate::glm::Glm;
+
+/// Tells the `Requestor` whether to keep dispatching tasks once a budget
+/// threshold has been crossed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BudgetDecision {
+    Continue,
+    /// Dispatch no more tasks until the budget is raised with
+    /// `Requestor::add_budget`; running ones are completed meanwhile. The
+    /// run waits for it, unless aborted or timed out; tasks left are kept
+    /// as `Pending` in the state file, if any.
+    Pause,
+}
+
+/// Spending at the moment a threshold set with `Requestor::on_budget_threshold`
+/// was crossed.
+#[derive(Clone, Debug)]
+pub struct BudgetThreshold {
+    /// The crossed fraction of the budget (0.0 - 1.0).
+    pub fraction: f64,
+    /// Amount of accepted invoices.
+    pub spent: Glm,
+    /// Initial allocation.
+    pub budget: Glm,
+}
+
+pub(crate) type BudgetCallback = Arc<dyn Fn(&BudgetThreshold) -> BudgetDecision>;
+
+/// Thresholds not crossed yet, as fractions of the budget.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct BudgetWatch {
+    pending: Vec<f64>,
+}
+
+impl BudgetWatch {
+    pub fn new(thresholds: Vec<f64>) -> Self {
+        BudgetWatch {
+            pending: thresholds,
+        }
+    }
+
+    /// Removes and returns the thresholds crossed by spending `spent`.
+    pub fn crossed(&mut self, spent: &BigDecimal, budget: &BigDecimal) -> Vec<BudgetThreshold> {
+        let ratio = match (to_f64(spent), to_f64(budget)) {
+            (Some(spent), Some(budget)) if budget > 0.0 => spent / budget,
+            _ => return vec![],
+        };
+        let (crossed, pending) = self
+            .pending
+            .iter()
+            .partition(|fraction| ratio >= **fraction);
+        self.pending = pending;
+        crossed
+            .into_iter()
+            .map(|fraction| BudgetThreshold {
+                fraction,
+                spent: Glm::from(spent.clone()),
+                budget: Glm::from(budget.clone()),
+            })
+            .collect()
+    }
+}
+
+/// Max cost of a single agreement, see `Requestor::with_agreement_cap`.
+#[derive(Clone, Debug)]
+pub(crate) struct CostCap {
+    pub limit: BigDecimal,
+    /// Negotiated linear pricing coefficients; the last one is the start price.
+    pub coeffs: Option<Vec<f64>>,
+}
+
+impl CostCap {
+    /// Cost of `usage` (as reported by `get_usage`), if it exceeds the limit.
+    pub fn exceeded_by(&self, usage: &[f64]) -> Option<f64> {
+        let cost = estimate_cost(self.coeffs.as_ref()?, usage)?;
+        match cost > to_f64(&self.limit)? {
+            true => Some(cost),
+            false => None,
+        }
+    }
+}
+
+/// Cost of `usage` with linear pricing `coeffs`; the last coefficient is the start price.
+pub(crate) fn estimate_cost(coeffs: &[f64], usage: &[f64]) -> Option<f64> {
+    let (start, coeffs) = coeffs.split_last()?;
+    Some(start + coeffs.iter().zip(usage).map(|(c, u)| c * u).sum::<f64>())
+}
+
+pub(crate) fn to_f64(amount: &BigDecimal) -> Option<f64> {
+    amount.to_string().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crossed() {
+        let mut watch = BudgetWatch::new(vec![0.5, 0.8]);
+        let budget = BigDecimal::from(10);
+        assert!(watch.crossed(&BigDecimal::from(4), &budget).is_empty());
+
+        let crossed = watch.crossed(&BigDecimal::from(6), &budget);
+        assert_eq!(crossed.len(), 1);
+        assert_eq!(crossed[0].fraction, 0.5);
+        // Reported once only.
+        assert!(watch.crossed(&BigDecimal::from(7), &budget).is_empty());
+
+        let crossed = watch.crossed(&BigDecimal::from(10), &budget);
+        assert_eq!(crossed.len(), 1);
+        assert_eq!(crossed[0].fraction, 0.8);
+    }
+
+    #[test]
+    fn test_cost_cap() {
+        let cap = CostCap {
+            limit: BigDecimal::from(1),
+            // 0.01 GLM per second, 0.02 GLM per cpu second, 0.1 GLM to start.
+            coeffs: Some(vec![0.01, 0.02, 0.1]),
+        };
+        assert_eq!(cap.exceeded_by(&[10.0, 10.0]), None);
+        assert!(cap.exceeded_by(&[40.0, 40.0]).is_some());
+        let unpriced = CostCap {
+            coeffs: None,
+            ..cap
+        };
+        assert_eq!(unpriced.exceeded_by(&[40.0, 40.0]), None);
+    }
+}
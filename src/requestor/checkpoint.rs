@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::requestor::command::{Command, CommandList};
+
+/// Where guests keep their checkpoints.
+///
+/// A checkpointed task splits its work into several `run` steps, each of
+/// which leaves the task's state at this path before it exits. The
+/// requestor downloads the checkpoint after every step but the last, within
+/// the task's own batch, since exe-units run one batch at a time. A task
+/// with a single `run` step is never checkpointed.
+///
+/// On start every step should resume from this file, if present: when a
+/// task is retried on another provider, the latest checkpoint downloaded by
+/// the requestor is uploaded here before the task's commands.
+pub const CHECKPOINT_PATH: &str = "/golem/output/checkpoint";
+
+/// See `Requestor::with_checkpointing`.
+#[derive(Clone, Debug)]
+pub(crate) struct Checkpointing {
+    /// Local directory with the latest checkpoints of the tasks.
+    pub dir: PathBuf,
+}
+
+impl Checkpointing {
+    pub fn local_path(&self, task_id: usize) -> PathBuf {
+        self.dir.join(format!("task-{}.checkpoint", task_id))
+    }
+
+    fn partial_path(&self, task_id: usize, step: usize) -> PathBuf {
+        self.dir
+            .join(format!("task-{}.checkpoint-{}.partial", task_id, step))
+    }
+
+    /// Whether `path` is where a checkpoint of the task is downloaded to.
+    pub fn is_partial(&self, task_id: usize, path: &Path) -> bool {
+        let prefix = format!("task-{}.checkpoint-", task_id);
+        path.parent() == Some(self.dir.as_path())
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .map(|name| name.starts_with(&prefix) && name.ends_with(".partial"))
+                .unwrap_or(false)
+    }
+
+    /// Prepends uploading the task's checkpoint, if one has been downloaded,
+    /// and downloads the guest's checkpoint after every `run` step but the
+    /// last.
+    pub fn seed(&self, task_id: usize, commands: CommandList) -> CommandList {
+        if let Err(e) = std::fs::create_dir_all(&self.dir) {
+            log::warn!("unable to create {}: {}", self.dir.display(), e);
+        }
+        let runs = commands
+            .iter()
+            .filter(|command| matches!(command, Command::Run(_)))
+            .count();
+        let mut seeded = Vec::new();
+        let mut step = 0;
+        for command in commands.iter().cloned() {
+            let is_run = matches!(command, Command::Run(_));
+            seeded.push(command);
+            if is_run {
+                step += 1;
+                if step < runs {
+                    seeded.push(Command::Download {
+                        from: CHECKPOINT_PATH.to_string(),
+                        to: self.partial_path(task_id, step),
+                    });
+                }
+            }
+        }
+        let commands = CommandList::new(seeded);
+
+        let checkpoint = self.local_path(task_id);
+        if !checkpoint.exists() {
+            return commands;
+        }
+        log::info!(
+            "resuming task {} from checkpoint {}",
+            task_id,
+            checkpoint.display()
+        );
+        commands.prepend(Command::Upload {
+            from: checkpoint,
+            to: CHECKPOINT_PATH.to_string(),
+        })
+    }
+
+    /// Replaces the local checkpoint with a completed download.
+    pub fn store(&self, task_id: usize, partial: &Path) -> Result<()> {
+        rename(partial, &self.local_path(task_id))
+    }
+
+    /// Removes the checkpoint of a completed task.
+    pub fn remove(&self, task_id: usize) {
+        let _ = std::fs::remove_file(self.local_path(task_id));
+    }
+}
+
+fn rename(from: &Path, to: &Path) -> Result<()> {
+    std::fs::rename(from, to)
+        .with_context(|| format!("unable to move {} to {}", from.display(), to.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoints_within_batch() {
+        let checkpointing = Checkpointing {
+            dir: std::env::temp_dir().join(format!("yarapi-checkpoint-{}", std::process::id())),
+        };
+        let commands = CommandList::new(vec![
+            Command::Run(vec!["/bin/step".to_string(), "1".to_string()]),
+            Command::Run(vec!["/bin/step".to_string(), "2".to_string()]),
+            Command::Run(vec!["/bin/step".to_string(), "3".to_string()]),
+            Command::Download {
+                from: "/golem/output/result".to_string(),
+                to: "result".into(),
+            },
+        ]);
+        let seeded: Vec<Command> = checkpointing.seed(7, commands).iter().cloned().collect();
+        let checkpoints: Vec<usize> = seeded
+            .iter()
+            .enumerate()
+            .filter(|(_, command)| match command {
+                Command::Download { from, to } => {
+                    from == CHECKPOINT_PATH && checkpointing.is_partial(7, to)
+                }
+                _ => false,
+            })
+            .map(|(i, _)| i)
+            .collect();
+        let last_run = seeded
+            .iter()
+            .rposition(|command| matches!(command, Command::Run(_)))
+            .unwrap();
+        // Downloaded while the task still runs, not after the batch.
+        assert_eq!(checkpoints, vec![1, 3]);
+        assert!(checkpoints.iter().all(|i| *i < last_run));
+        let _ = std::fs::remove_dir_all(&checkpointing.dir);
+    }
+}
@@ -1,17 +1,27 @@
 use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
 use std::{
-    collections::HashSet,
+    collections::{HashMap, HashSet},
+    fs,
     iter::FromIterator,
     path::{Path, PathBuf},
 };
 use ya_client::model::activity::ExeScriptRequest;
 
+use crate::requestor::glob;
+use crate::requestor::provider::ProviderInfo;
+
+/// Start of a placeholder resolved with the provider's properties.
+const PLACEHOLDER: &str = "{provider.";
+
 /// Represents supported exe-script commands.
 ///
 /// Note that when specifying the `CommandList`, specifying
 /// `Deploy` and `Start` explicitly is optional; therefore,
 /// skipping those two is fine.
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Command {
     /// Deploy the container.
     Deploy,
@@ -31,10 +41,30 @@ pub enum Command {
         to: String,
     },
     /// Path to file(s) to download via gftp.
+    ///
+    /// A `from` pattern with wildcards, e.g. `/golem/output/*.png`, downloads
+    /// every matching file into the `to` directory, once the other commands
+    /// have completed.
     Download {
         from: String,
         to: PathBuf,
     },
+    /// Path to a tar archive to upload via gftp and unpack into the `to`
+    /// directory.
+    UploadArchive {
+        from: PathBuf,
+        to: String,
+    },
+}
+
+impl Command {
+    /// Runs `cmd` with `/bin/sh -c`, so that pipes, redirections and globs
+    /// work as in a shell; see also the `sh!` macro.
+    ///
+    /// The shell has to be available in the image, as in the VM runtime.
+    pub fn run_shell(cmd: impl Into<String>) -> Command {
+        Command::Run(vec!["/bin/sh".to_string(), "-c".to_string(), cmd.into()])
+    }
 }
 
 /// Represents a list of commands to execute at the remote node.
@@ -55,7 +85,24 @@ pub enum Command {
 ///     download("/workdir/output.txt".to_string(), "output.txt".to_string())
 /// ];
 /// ```
-#[derive(Clone)]
+///
+/// Arguments of `Run` and the remote paths and urls of the other commands
+/// may contain `{provider.<name>}` placeholders, replaced when the task is
+/// dispatched with the properties of the negotiated provider: `id`, `name`,
+/// `region`, `cores`, `threads`, `mem_gib`, `storage_gib` or any offer
+/// property, e.g. `{provider.golem.inf.cpu.architecture}`. A provider which
+/// doesn't advertise a property used by a task doesn't get the task.
+///
+/// ## Example:
+/// ```rust
+/// use yarapi::requestor::{Command, CommandList};
+///
+/// let script = CommandList::new(vec![Command::Run(vec![
+///     "/usr/bin/make".to_string(),
+///     "-j{provider.threads}".to_string(),
+/// ])]);
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct CommandList(Vec<Command>);
 
 impl CommandList {
@@ -65,18 +112,142 @@ impl CommandList {
         Self(Vec::from_iter(v))
     }
 
+    /// Packs consecutive uploads of files not larger than `threshold` bytes
+    /// into a single `UploadArchive` per destination directory.
+    ///
+    /// Every upload is a separate gftp publish and transfer, which adds up
+    /// for tasks with dozens of small inputs. Archives are kept in the
+    /// system's temporary directory and reused while the files don't change.
+    pub fn pack_uploads(self, threshold: u64) -> Result<CommandList> {
+        self.pack_uploads_in(threshold, &std::env::temp_dir().join("yarapi-uploads"))
+    }
+
+    /// Like `pack_uploads`, with the archives kept in `dir`.
+    pub fn pack_uploads_in(self, threshold: u64, dir: &Path) -> Result<CommandList> {
+        let mut commands = Vec::with_capacity(self.0.len());
+        let mut uploads = vec![];
+        for command in self.0 {
+            match command {
+                Command::Upload { from, to } if is_small_file(&from, threshold) => {
+                    uploads.push((from, to))
+                }
+                command => {
+                    commands.extend(pack_group(std::mem::take(&mut uploads), dir)?);
+                    commands.push(command);
+                }
+            }
+        }
+        commands.extend(pack_group(uploads, dir)?);
+        Ok(Self(commands))
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.0.iter()
+    }
+
+    /// Inserts `command` before the others.
+    pub(crate) fn prepend(mut self, command: Command) -> Self {
+        self.0.insert(0, command);
+        self
+    }
+
+    /// Changes the local paths the `Download` commands write to.
+    pub(crate) fn map_downloads(self, f: impl Fn(PathBuf) -> PathBuf) -> Self {
+        Self(
+            self.0
+                .into_iter()
+                .map(|command| match command {
+                    Command::Download { from, to } => Command::Download { from, to: f(to) },
+                    command => command,
+                })
+                .collect(),
+        )
+    }
+
+    /// Local paths the `Download` commands write to.
+    pub(crate) fn outputs(&self) -> Vec<PathBuf> {
+        self.0
+            .iter()
+            .filter_map(|command| match command {
+                Command::Download { to, .. } => Some(to.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Downloads with wildcards, as (pattern, local directory).
+    pub(crate) fn glob_downloads(&self) -> Vec<(String, PathBuf)> {
+        self.0
+            .iter()
+            .filter_map(|command| match command {
+                Command::Download { from, to } if glob::is_pattern(from) => {
+                    Some((from.clone(), to.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Replaces the `{provider.<name>}` placeholders with the properties
+    /// of `provider`.
+    pub(crate) fn resolve(self, provider: &ProviderInfo) -> Result<Self> {
+        let resolve_text = |text: String| resolve(&text, provider);
+        self.0
+            .into_iter()
+            .map(|command| {
+                Ok(match command {
+                    Command::Run(args) => {
+                        Command::Run(args.into_iter().map(&resolve_text).collect::<Result<_>>()?)
+                    }
+                    Command::Transfer { from, to } => Command::Transfer {
+                        from: resolve_text(from)?,
+                        to: resolve_text(to)?,
+                    },
+                    Command::Upload { from, to } => Command::Upload {
+                        from,
+                        to: resolve_text(to)?,
+                    },
+                    Command::Download { from, to } => Command::Download {
+                        from: resolve_text(from)?,
+                        to,
+                    },
+                    Command::UploadArchive { from, to } => Command::UploadArchive {
+                        from,
+                        to: resolve_text(to)?,
+                    },
+                    command => command,
+                })
+            })
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+
     pub(super) async fn into_exe_script(self) -> Result<ExeScript> {
+        self.into_exe_script_with(true).await
+    }
+
+    /// Builds the exe-script; without `init`, deploy and start are skipped,
+    /// e.g. for an activity which has already been started.
+    pub(super) async fn into_exe_script_with(self, init: bool) -> Result<ExeScript> {
         use serde_json::{json, map::Map};
 
         let mut res = vec![];
         let mut run_ind = HashSet::new();
+        let mut download_ind = HashMap::new();
+        let mut upload_ind = HashSet::new();
+        let mut upload_bytes = 0;
         // TODO verify the `CommandList` doesn't already contain `Command::Deploy` or
         // `Command::Start`.
-        for (i, cmd) in vec![Command::Deploy, Command::Start]
-            .iter()
-            .chain(self.0.iter())
-            .enumerate()
-        {
+        let init_cmds = match init {
+            true => vec![Command::Deploy, Command::Start],
+            false => vec![],
+        };
+        // Glob downloads are expanded after the batch, see `glob::download`.
+        let commands = self.0.iter().filter(|cmd| match cmd {
+            Command::Download { from, .. } => !glob::is_pattern(from),
+            _ => true,
+        });
+        for (i, cmd) in init_cmds.iter().chain(commands).enumerate() {
             res.push(match cmd {
                 Command::Deploy => json!({"deploy": {}}),
                 Command::Start => json!({"start": {"args": []}}),
@@ -94,14 +265,30 @@ impl CommandList {
                     json!({ "run": obj })
                 }
                 Command::Transfer { from, to } => json!({"transfer": { "from": from, "to": to }}),
-                Command::Upload { from, to } => serde_json::json!({ "transfer": {
-                    "from": Self::get_upload(&from).await.with_context(|| format!("upload file {}", from.display()))?,
-                    "to": format!("container:{}", to),
-                }}),
-                Command::Download { from, to } => serde_json::json!({ "transfer": {
-                    "from": format!("container:{}", from),
-                    "to": Self::get_download(&to).await?,
-                }}),
+                Command::Upload { from, to } => {
+                    upload_ind.insert(i);
+                    upload_bytes += file_size(from);
+                    serde_json::json!({ "transfer": {
+                        "from": Self::get_upload(&from).await.with_context(|| format!("upload file {}", from.display()))?,
+                        "to": format!("container:{}", to),
+                    }})
+                }
+                Command::Download { from, to } => {
+                    download_ind.insert(i, to.clone());
+                    serde_json::json!({ "transfer": {
+                        "from": format!("container:{}", from),
+                        "to": Self::get_download(&to).await?,
+                    }})
+                }
+                Command::UploadArchive { from, to } => {
+                    upload_ind.insert(i);
+                    upload_bytes += file_size(from);
+                    serde_json::json!({ "transfer": {
+                        "from": Self::get_upload(&from).await.with_context(|| format!("upload archive {}", from.display()))?,
+                        "to": format!("container:{}/", to.trim_end_matches('/')),
+                        "format": "tar",
+                    }})
+                }
             })
         }
 
@@ -109,6 +296,9 @@ impl CommandList {
             request: ExeScriptRequest::new(serde_json::to_string_pretty(&res)?),
             num_cmds: res.len(),
             run_indices: run_ind,
+            downloads: download_ind,
+            upload_indices: upload_ind,
+            upload_bytes,
         })
     }
 
@@ -132,9 +322,164 @@ impl CommandList {
     }
 }
 
+/// Replaces the `{provider.<name>}` placeholders in `text`.
+fn resolve(text: &str, provider: &ProviderInfo) -> Result<String> {
+    let mut resolved = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(PLACEHOLDER) {
+        resolved.push_str(&rest[..start]);
+        let end = start
+            + rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("unterminated placeholder in {:?}", text))?;
+        let name = &rest[start + PLACEHOLDER.len()..end];
+        let value = provider.placeholder(name).ok_or_else(|| {
+            anyhow!(
+                "provider [{:?}] doesn't advertise {{provider.{}}}",
+                provider.node_id,
+                name
+            )
+        })?;
+        resolved.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    resolved.push_str(rest);
+    Ok(resolved)
+}
+
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0)
+}
+
+fn is_small_file(path: &Path, threshold: u64) -> bool {
+    fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.len() <= threshold)
+        .unwrap_or(false)
+}
+
+/// Replaces uploads to the same directory with an archive; single files are
+/// left as they are.
+fn pack_group(uploads: Vec<(PathBuf, String)>, archives: &Path) -> Result<Vec<Command>> {
+    let mut dirs: Vec<(String, Vec<(PathBuf, String)>)> = vec![];
+    let mut commands = vec![];
+    for (from, to) in uploads {
+        let (dir, name) = match to.rfind('/') {
+            Some(i) if i + 1 < to.len() => (to[..i].to_string(), to[i + 1..].to_string()),
+            _ => {
+                commands.push(Command::Upload { from, to });
+                continue;
+            }
+        };
+        match dirs.iter_mut().find(|(d, _)| *d == dir) {
+            Some((_, files)) => files.push((from, name)),
+            None => dirs.push((dir, vec![(from, name)])),
+        }
+    }
+
+    for (dir, mut files) in dirs {
+        if files.len() == 1 {
+            let (from, name) = files.remove(0);
+            commands.push(Command::Upload {
+                from,
+                to: format!("{}/{}", dir, name),
+            });
+            continue;
+        }
+        let archive = create_archive(&files, archives)
+            .with_context(|| format!("packing uploads to {}", dir))?;
+        log::debug!(
+            "packed {} uploads to {} into {}",
+            files.len(),
+            dir,
+            archive.display()
+        );
+        commands.push(Command::UploadArchive {
+            from: archive,
+            to: dir,
+        });
+    }
+    Ok(commands)
+}
+
+/// Creates a tar archive with `files` (stored under the given names), named
+/// after the files' paths, sizes and modification times.
+fn create_archive(files: &[(PathBuf, String)], dir: &Path) -> Result<PathBuf> {
+    let mut hasher = Sha3_256::new();
+    for (from, name) in files {
+        let metadata = fs::metadata(from)?;
+        hasher.update(
+            format!(
+                "{}\0{}\0{}\0{:?}\n",
+                from.canonicalize()?.display(),
+                name,
+                metadata.len(),
+                metadata.modified().ok()
+            )
+            .as_bytes(),
+        );
+    }
+    fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{:x}.tar", hasher.finalize()));
+    if path.exists() {
+        return Ok(path);
+    }
+
+    // Written aside and renamed, so that concurrent tasks never see a partial archive.
+    let partial = path.with_extension(format!("tar.{}", std::process::id()));
+    let mut builder = tar::Builder::new(fs::File::create(&partial)?);
+    for (from, name) in files {
+        builder.append_path_with_name(from, name)?;
+    }
+    builder.into_inner()?.sync_all()?;
+    fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct ExeScript {
     pub request: ExeScriptRequest,
     pub num_cmds: usize,
     pub run_indices: HashSet<usize>,
+    /// Local paths of the downloads, by their index in the batch.
+    pub downloads: HashMap<usize, PathBuf>,
+    pub upload_indices: HashSet<usize>,
+    /// Total size of the uploaded files.
+    pub upload_bytes: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_resolve() {
+        let provider = ProviderInfo {
+            node_id: "0x0000000000000000000000000000000000000000"
+                .parse()
+                .unwrap(),
+            properties: json!({
+                "golem.inf.cpu.threads": 8,
+                "golem.inf.mem.gib": 15.5,
+                "golem.inf.cpu.architecture": "x86_64",
+            }),
+        };
+        assert_eq!(
+            resolve("-j{provider.threads} -m{provider.mem_gib}G", &provider).unwrap(),
+            "-j8 -m15.5G"
+        );
+        assert_eq!(
+            resolve("/opt/{provider.golem.inf.cpu.architecture}/bin", &provider).unwrap(),
+            "/opt/x86_64/bin"
+        );
+        // Other braces are left alone, e.g. in shell scripts.
+        assert_eq!(
+            resolve("awk '{print $1}' ${HOME}", &provider).unwrap(),
+            "awk '{print $1}' ${HOME}"
+        );
+        assert!(resolve("-j{provider.cores}", &provider).is_err());
+        assert!(resolve("-j{provider.threads", &provider).is_err());
+    }
 }
@@ -0,0 +1,202 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::glm::Glm;
+use crate::requestor::{manifest::ImageLocation, Image, Package, Requestor};
+
+/// Prefix of the environment variables overriding config file settings,
+/// e.g. `YARAPI_SUBNET` or `YARAPI_BUDGET`.
+const ENV_PREFIX: &str = "YARAPI_";
+
+/// Requestor settings read from a TOML file.
+///
+/// Every setting is optional and may be overridden with an environment
+/// variable named after it, e.g. `YARAPI_TIMEOUT_SECS=600`.
+///
+/// ## Example:
+/// ```toml
+/// name = "My Requestor"
+/// description = "Rendering frames 1-100"
+/// subnet = "community.4"
+/// budget = "5"
+/// timeout_secs = 720
+/// constraints = "(golem.inf.mem.gib>0.5)"
+/// payment_network = "rinkeby"
+/// runtime = "vm"
+/// runtime_version = "0.2.4"
+///
+/// [package]
+/// type = "file"
+/// path = "image.gvmi"
+/// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestorConfig {
+    pub name: Option<String>,
+    /// See `Requestor::with_node_name`.
+    pub node_name: Option<String>,
+    /// See `Requestor::with_description`.
+    pub description: Option<String>,
+    pub subnet: Option<String>,
+    /// Max budget in GLM.
+    pub budget: Option<String>,
+    pub timeout_secs: Option<u64>,
+    pub expiration_margin_secs: Option<u64>,
+    /// Demand constraints in LDAP filter notation.
+    pub constraints: Option<String>,
+    /// See `Requestor::with_payment_network`.
+    pub payment_network: Option<String>,
+    pub runtime: Option<String>,
+    pub runtime_version: Option<String>,
+    pub package: Option<ImageLocation>,
+}
+
+impl RequestorConfig {
+    /// Reads the config from `path` and applies the environment overrides.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("unable to read config {}", path.display()))?;
+        let config: Self = toml::from_str(&contents)
+            .with_context(|| format!("invalid config {}", path.display()))?;
+        config.with_overrides(|name| std::env::var(name).ok())
+    }
+
+    /// Overrides settings with the variables returned by `var`, which is
+    /// given names like `YARAPI_SUBNET`.
+    pub fn with_overrides(self, var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let var = |name: &str| var(&format!("{}{}", ENV_PREFIX, name));
+        let number = |name: &str| -> Result<Option<u64>> {
+            var(name)
+                .map(|value| {
+                    value
+                        .parse()
+                        .with_context(|| format!("invalid {}{}: {}", ENV_PREFIX, name, value))
+                })
+                .transpose()
+        };
+        let package = match (var("PACKAGE_PATH"), var("PACKAGE_URL"), var("PACKAGE_HASH")) {
+            (Some(path), _, _) => Some(ImageLocation::File { path: path.into() }),
+            (None, Some(url), Some(hash)) => Some(ImageLocation::Url { hash, url }),
+            (None, Some(_), None) => {
+                return Err(anyhow!(
+                    "{}PACKAGE_URL requires {}PACKAGE_HASH",
+                    ENV_PREFIX,
+                    ENV_PREFIX
+                ))
+            }
+            _ => self.package,
+        };
+
+        Ok(Self {
+            name: var("NAME").or(self.name),
+            node_name: var("NODE_NAME").or(self.node_name),
+            description: var("DESCRIPTION").or(self.description),
+            subnet: var("SUBNET").or(self.subnet),
+            budget: var("BUDGET").or(self.budget),
+            timeout_secs: number("TIMEOUT_SECS")?.or(self.timeout_secs),
+            expiration_margin_secs: number("EXPIRATION_MARGIN_SECS")?
+                .or(self.expiration_margin_secs),
+            constraints: var("CONSTRAINTS").or(self.constraints),
+            payment_network: var("PAYMENT_NETWORK").or(self.payment_network),
+            runtime: var("RUNTIME").or(self.runtime),
+            runtime_version: var("RUNTIME_VERSION").or(self.runtime_version),
+            package,
+        })
+    }
+
+    /// Creates a requestor; `runtime`, `runtime_version` and `package` are required.
+    pub fn requestor(&self) -> Result<Requestor> {
+        let runtime = self
+            .runtime
+            .as_ref()
+            .ok_or_else(|| anyhow!("runtime is not configured"))?;
+        let version = self
+            .runtime_version
+            .as_ref()
+            .ok_or_else(|| anyhow!("runtime_version is not configured"))?;
+        let version = semver::Version::parse(version)
+            .with_context(|| format!("invalid runtime version: {}", version))?;
+        let image = Image::from_runtime_name(runtime, version)
+            .ok_or_else(|| anyhow!("unsupported runtime: {}", runtime))?;
+        let package = match self.package.clone() {
+            Some(ImageLocation::File { path }) => Package::Archive(path),
+            Some(ImageLocation::Url { hash, url }) => Package::Url { digest: hash, url },
+            None => return Err(anyhow!("package is not configured")),
+        };
+        let name = self.name.clone().unwrap_or_else(|| "yarapi".to_string());
+
+        self.apply(Requestor::new(name, image, package))
+    }
+
+    /// Applies the market and payment settings to an existing requestor.
+    pub fn apply(&self, requestor: Requestor) -> Result<Requestor> {
+        let mut requestor = requestor;
+        if let Some(node_name) = &self.node_name {
+            requestor = requestor.with_node_name(node_name.clone());
+        }
+        if let Some(description) = &self.description {
+            requestor = requestor.with_description(description.clone());
+        }
+        if let Some(subnet) = &self.subnet {
+            requestor = requestor.with_subnet(subnet.clone());
+        }
+        if let Some(budget) = &self.budget {
+            let budget =
+                Glm::from_str(budget).with_context(|| format!("invalid budget: {}", budget))?;
+            requestor = requestor.with_max_budget_glm(budget);
+        }
+        if let Some(timeout) = self.timeout_secs {
+            requestor = requestor.with_timeout(Duration::from_secs(timeout));
+        }
+        if let Some(margin) = self.expiration_margin_secs {
+            requestor = requestor.with_expiration_margin(Duration::from_secs(margin));
+        }
+        if let Some(network) = &self.payment_network {
+            requestor = requestor.with_payment_network(network.clone());
+        }
+        requestor.raw_constraints.extend(self.constraints.clone());
+        Ok(requestor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides() {
+        let config: RequestorConfig = toml::from_str(
+            r#"
+            subnet = "community.4"
+            timeout_secs = 720
+
+            [package]
+            type = "url"
+            hash = "beefdead"
+            url = "http://example.com/image.gvmi"
+            "#,
+        )
+        .unwrap();
+        let config = config
+            .with_overrides(|name| match name {
+                "YARAPI_SUBNET" => Some("devnet-beta".to_string()),
+                "YARAPI_BUDGET" => Some("1.5".to_string()),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(config.subnet.as_deref(), Some("devnet-beta"));
+        assert_eq!(config.budget.as_deref(), Some("1.5"));
+        assert_eq!(config.timeout_secs, Some(720));
+        assert!(matches!(config.package, Some(ImageLocation::Url { .. })));
+
+        let invalid = RequestorConfig::default().with_overrides(|name| match name {
+            "YARAPI_TIMEOUT_SECS" => Some("soon".to_string()),
+            _ => None,
+        });
+        assert!(invalid.is_err());
+    }
+}
@@ -0,0 +1,66 @@
+/// A debit note compared with the usage sampled by the requestor.
+#[derive(Clone, Debug)]
+pub struct DebitNoteCheck {
+    pub debit_note_id: String,
+    pub agreement_id: String,
+    /// Amount claimed by the provider, in GLM.
+    pub amount_due: f64,
+    /// Cost of the latest usage sample at the negotiated prices, in GLM.
+    pub expected: f64,
+    /// The latest usage sample, as reported by `get_usage`.
+    pub usage: Vec<f64>,
+}
+
+/// Decides whether a debit note matches the requestor's own usage samples,
+/// see `Requestor::with_debit_note_verifier`.
+///
+/// Closures `Fn(&DebitNoteCheck) -> Result<(), String>` implement it too.
+pub trait DebitNoteVerifier {
+    /// Returns the reason to reject the debit note with.
+    fn verify(&self, check: &DebitNoteCheck) -> Result<(), String>;
+}
+
+impl<F: Fn(&DebitNoteCheck) -> Result<(), String>> DebitNoteVerifier for F {
+    fn verify(&self, check: &DebitNoteCheck) -> Result<(), String> {
+        self(check)
+    }
+}
+
+/// Accepts debit notes exceeding the expected amount by at most the given
+/// fraction, e.g. `DeviationTolerance(0.1)` for 10%.
+///
+/// Usage is sampled every few seconds, so the expected amount lags behind
+/// the provider's and some tolerance is needed.
+#[derive(Clone, Copy, Debug)]
+pub struct DeviationTolerance(pub f64);
+
+impl DebitNoteVerifier for DeviationTolerance {
+    fn verify(&self, check: &DebitNoteCheck) -> Result<(), String> {
+        let limit = check.expected * (1.0 + self.0);
+        match check.amount_due <= limit {
+            true => Ok(()),
+            false => Err(format!(
+                "amount due {:.6} GLM exceeds {:.6} GLM expected from usage {:?}",
+                check.amount_due, check.expected, check.usage
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deviation_tolerance() {
+        let check = DebitNoteCheck {
+            debit_note_id: "d0".to_string(),
+            agreement_id: "a0".to_string(),
+            amount_due: 1.05,
+            expected: 1.0,
+            usage: vec![60.0, 30.0],
+        };
+        assert!(DeviationTolerance(0.1).verify(&check).is_ok());
+        assert!(DeviationTolerance(0.01).verify(&check).is_err());
+    }
+}
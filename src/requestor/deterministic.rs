@@ -0,0 +1,134 @@
+use chrono::{DateTime, Utc};
+use sha3::{Digest, Sha3_256};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use ya_client::model::NodeId;
+
+/// Clock advanced only by hand, see `Requestor::with_deterministic_mode`.
+///
+/// Task durations, straggler duplication and deadlines are measured with
+/// it, so that a test decides when a task counts as slow.
+///
+/// ## Example:
+/// ```no_run
+/// # fn run(requestor: yarapi::requestor::Requestor) {
+/// use std::time::Duration;
+/// use yarapi::requestor::VirtualClock;
+///
+/// let clock = VirtualClock::new();
+/// let requestor = requestor
+///     .with_speculative_execution(0.5, 2.0)
+///     .with_deterministic_mode(42, clock.clone());
+/// // ... once the first tasks are done:
+/// clock.advance(Duration::from_secs(600));
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct VirtualClock {
+    start: Instant,
+    start_utc: DateTime<Utc>,
+    elapsed: Arc<Mutex<Duration>>,
+}
+
+impl VirtualClock {
+    pub fn new() -> Self {
+        VirtualClock {
+            start: Instant::now(),
+            start_utc: Utc::now(),
+            elapsed: Default::default(),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap() += by;
+    }
+
+    /// Time elapsed since the clock was created.
+    pub fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap()
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Source of time for the scheduling decisions.
+#[derive(Clone, Debug)]
+pub(crate) enum Clock {
+    System,
+    Virtual(VirtualClock),
+}
+
+impl Clock {
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::System => Instant::now(),
+            Clock::Virtual(clock) => clock.start + clock.elapsed(),
+        }
+    }
+
+    pub fn now_utc(&self) -> DateTime<Utc> {
+        match self {
+            Clock::System => Utc::now(),
+            Clock::Virtual(clock) => {
+                clock.start_utc
+                    + chrono::Duration::from_std(clock.elapsed())
+                        .unwrap_or_else(|_| chrono::Duration::zero())
+            }
+        }
+    }
+
+    pub fn elapsed(&self, since: Instant) -> Duration {
+        self.now().checked_duration_since(since).unwrap_or_default()
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Clock::System
+    }
+}
+
+/// Position of the provider in the order of proposals for `seed`; the same
+/// seed always gives the same order, whatever the order of the events.
+pub(crate) fn rank(seed: u64, node_id: &NodeId) -> Vec<u8> {
+    let mut hasher = Sha3_256::new();
+    hasher.update(seed.to_le_bytes());
+    hasher.update(node_id.to_string().as_bytes());
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock() {
+        let clock = VirtualClock::new();
+        let clock_ref = Clock::Virtual(clock.clone());
+        let started = clock_ref.now();
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(clock_ref.elapsed(started), Duration::from_secs(30));
+        assert_eq!(
+            clock_ref.now_utc() - clock.start_utc,
+            chrono::Duration::seconds(30)
+        );
+    }
+
+    #[test]
+    fn test_rank() {
+        let nodes: Vec<NodeId> = (1..=5)
+            .map(|i| format!("0x{:040x}", i).parse().unwrap())
+            .collect();
+        let order = |seed: u64, mut nodes: Vec<NodeId>| {
+            nodes.sort_by_cached_key(|node_id| rank(seed, node_id));
+            nodes
+        };
+        let reversed = nodes.iter().rev().cloned().collect();
+        assert_eq!(order(7, nodes.clone()), order(7, reversed));
+        assert_ne!(order(7, nodes.clone()), order(8, nodes));
+    }
+}
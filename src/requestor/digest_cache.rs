@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct CachedDigest {
+    size: u64,
+    modified: SystemTime,
+    digest: String,
+}
+
+/// Digests of package archives computed in previous runs, by canonical path.
+///
+/// An entry is valid as long as the archive's size and modification time
+/// don't change.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct DigestCache {
+    digests: HashMap<PathBuf, CachedDigest>,
+}
+
+impl DigestCache {
+    /// `$XDG_CACHE_HOME/yarapi/digests.json`, or `~/.cache/yarapi/digests.json`.
+    pub fn default_path() -> Option<PathBuf> {
+        let cache_dir = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(cache_dir.join("yarapi").join("digests.json"))
+    }
+
+    /// Loads the cache; a missing file means an empty cache.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read(path)
+            .with_context(|| format!("unable to read digest cache {}", path.display()))?;
+        Ok(serde_json::from_slice(&contents)
+            .with_context(|| format!("invalid digest cache {}", path.display()))?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("unable to create {}", dir.display()))?;
+        }
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("unable to write digest cache {}", path.display()))
+    }
+
+    pub fn get(&self, path: &Path, metadata: &Metadata) -> Option<&str> {
+        let cached = self.digests.get(path)?;
+        match Some(cached.modified) == metadata.modified().ok() && cached.size == metadata.len() {
+            true => Some(&cached.digest),
+            false => None,
+        }
+    }
+
+    pub fn insert(&mut self, path: &Path, metadata: &Metadata, digest: String) {
+        let modified = match metadata.modified() {
+            Ok(modified) => modified,
+            // Without the modification time, changes can't be detected.
+            Err(_) => return,
+        };
+        self.digests.insert(
+            path.to_path_buf(),
+            CachedDigest {
+                size: metadata.len(),
+                modified,
+                digest,
+            },
+        );
+    }
+}
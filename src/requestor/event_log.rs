@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::requestor::task_state::TaskState;
+
+/// Version of the record layout, bumped on incompatible changes.
+pub const EVENT_LOG_VERSION: u32 = 1;
+
+/// Engine lifecycle event, written by `Requestor::with_event_log`.
+///
+/// Every line is a JSON object with `version`, `timestamp` and `event`
+/// (the variant name in camelCase) followed by the fields of the variant,
+/// e.g.
+/// ```json
+/// {"version":1,"timestamp":"2021-03-01T10:00:00Z","event":"taskState","taskId":3,"state":"dispatched"}
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum LifecycleEvent {
+    #[serde(rename_all = "camelCase")]
+    ProposalReceived {
+        proposal_id: String,
+        provider_id: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    AgreementCreated {
+        agreement_id: String,
+        provider_id: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    NegotiationFailed { provider_id: String, reason: String },
+    /// A task has been dispatched, started, finished, etc.
+    #[serde(rename_all = "camelCase")]
+    TaskState { task_id: usize, state: TaskState },
+    /// Result of a single command of a task's exe-script.
+    #[serde(rename_all = "camelCase")]
+    StepResult {
+        activity_id: String,
+        task_id: usize,
+        index: usize,
+        success: bool,
+        message: Option<String>,
+    },
+    #[serde(rename_all = "camelCase")]
+    InvoiceAccepted {
+        invoice_id: String,
+        agreement_id: String,
+        amount: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    InvoiceRejected { invoice_id: String, reason: String },
+    #[serde(rename_all = "camelCase")]
+    DebitNoteRejected {
+        debit_note_id: String,
+        agreement_id: String,
+        reason: String,
+    },
+    /// The provider terminated the agreement of a running task.
+    #[serde(rename_all = "camelCase")]
+    ProviderTerminated {
+        agreement_id: String,
+        reason: Option<String>,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    version: u32,
+    timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    event: &'a LifecycleEvent,
+}
+
+/// Writes lifecycle events as JSON lines; clones share the writer.
+#[derive(Clone)]
+pub(crate) struct EventLog {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl EventLog {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        EventLog {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+        }
+    }
+
+    pub fn emit(&self, event: LifecycleEvent) {
+        let record = Record {
+            version: EVENT_LOG_VERSION,
+            timestamp: Utc::now(),
+            event: &event,
+        };
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                log::warn!("unable to serialize lifecycle event: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut writer = self.writer.lock().unwrap();
+        if let Err(e) = writer.write_all(&line).and_then(|_| writer.flush()) {
+            log::warn!("unable to write lifecycle event: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let buffer = Buffer::default();
+        let log = EventLog::new(buffer.clone());
+        log.emit(LifecycleEvent::TaskState {
+            task_id: 3,
+            state: TaskState::Dispatched,
+        });
+        log.emit(LifecycleEvent::NegotiationFailed {
+            provider_id: "0x01".to_string(),
+            reason: "price too high".to_string(),
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["version"], 1);
+        assert_eq!(lines[0]["event"], "taskState");
+        assert_eq!(lines[0]["taskId"], 3);
+        assert_eq!(lines[1]["providerId"], "0x01");
+
+        let event: LifecycleEvent = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+        assert_eq!(
+            event,
+            LifecycleEvent::TaskState {
+                task_id: 3,
+                state: TaskState::Dispatched,
+            }
+        );
+    }
+}
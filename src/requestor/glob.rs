@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::requestor::activity::Activity;
+use crate::requestor::command::{Command, CommandList};
+
+/// Whether a container path has wildcards, e.g. `/golem/output/*.png`.
+pub(crate) fn is_pattern(path: &str) -> bool {
+    path.contains(|c| c == '*' || c == '?' || c == '[')
+}
+
+/// Lists the files matching `pattern` in the container, then downloads them
+/// into the `to` directory in a separate batch.
+///
+/// Returns the local paths; a pattern matching nothing is not an error.
+pub(crate) async fn download(
+    activity: &Activity,
+    pattern: &str,
+    to: &Path,
+) -> Result<Vec<PathBuf>> {
+    // `ls` fails when nothing matches; an empty listing is enough.
+    let listing = CommandList::new(vec![Command::run_shell(format!(
+        "ls -1d {} 2>/dev/null; true",
+        pattern
+    ))]);
+    let results = activity.run_batch(listing).await?;
+    let listing = results
+        .last()
+        .and_then(|result| result.message.clone())
+        .unwrap_or_default();
+
+    let downloads = downloads(&listing, to)?;
+    if downloads.is_empty() {
+        log::warn!("no files match {}", pattern);
+        return Ok(vec![]);
+    }
+    std::fs::create_dir_all(to).with_context(|| format!("unable to create {}", to.display()))?;
+    log::info!("downloading {} files matching {}", downloads.len(), pattern);
+    let commands = CommandList::new(downloads);
+    let paths = commands.outputs();
+    let results = activity.run_batch(commands).await?;
+    if results.len() != paths.len() {
+        return Err(anyhow!(
+            "downloading files matching {} interrupted",
+            pattern
+        ));
+    }
+    Ok(paths)
+}
+
+/// Downloads of the container paths in `listing`, one per line.
+fn downloads(listing: &str, to: &Path) -> Result<Vec<Command>> {
+    listing
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|from| {
+            let name = Path::new(from)
+                .file_name()
+                .ok_or_else(|| anyhow!("invalid path in listing: {}", from))?;
+            Ok(Command::Download {
+                from: from.to_string(),
+                to: to.join(name),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_downloads() {
+        assert!(is_pattern("/golem/output/*.png"));
+        assert!(!is_pattern("/golem/output/frame.png"));
+
+        let listing = "/golem/output/frame_1.png\n/golem/output/frame_2.png\n";
+        let downloads = downloads(listing, Path::new("outputs")).unwrap();
+        assert_eq!(downloads.len(), 2);
+        match &downloads[1] {
+            Command::Download { from, to } => {
+                assert_eq!(from, "/golem/output/frame_2.png");
+                assert_eq!(to, &PathBuf::from("outputs/frame_2.png"));
+            }
+            command => panic!("unexpected command: {:?}", command),
+        }
+    }
+}
@@ -7,6 +7,9 @@ macro_rules! expand_cmd {
     (run ( $($e:expr),* )) => {{
         $crate::requestor::Command::Run(vec![ $($e.into()),* ])
     }};
+    (sh ( $e:expr )) => {
+        $crate::requestor::Command::run_shell($e)
+    };
     (transfer ( $e:expr, $f:expr )) => {
         $crate::requestor::Command::Transfer { from: $e.into(), to: $f.into() }
     };
@@ -16,6 +19,9 @@ macro_rules! expand_cmd {
     (download ( $e:expr, $f:expr )) => {
         $crate::requestor::Command::Download { from: $e.into(), to: $f.into() }
     };
+    (upload_archive ( $e:expr, $f:expr )) => {
+        $crate::requestor::Command::UploadArchive { from: $e.into(), to: $f.into() }
+    };
 }
 
 #[doc(hidden)]
@@ -50,6 +56,7 @@ macro_rules! commands_helper {
 ///      upload("some_file", "/workdir/input");
 ///      run("/bin/ls", "-la", "/workdir/input");
 ///      run("/bin/cp", "/workdir/input", "/workdir/output");
+///      sh("wc -l /workdir/input > /workdir/lines");
 ///      download("/workdir/output", "some_file_copy")
 ///  };
 ///
@@ -62,6 +69,23 @@ macro_rules! commands {
     }};
 }
 
+/// Builds a shell command with `format!` arguments, see `Command::run_shell`.
+///
+/// ## Example
+///
+/// ```no_run
+/// let frame = 3;
+/// let commands = yarapi::requestor::CommandList::new(vec![
+///     yarapi::sh!("blender -b scene.blend -f {} -o /golem/output/ | tail -n 20", frame),
+/// ]);
+/// ```
+#[macro_export]
+macro_rules! sh {
+    ( $( $arg:tt )* ) => {
+        $crate::requestor::Command::run_shell(format!( $($arg)* ))
+    };
+}
+
 macro_rules! actix_handler {
     ($actor:ty, $message:ty, $handle:expr) => {
         impl Handler<$message> for $actor {
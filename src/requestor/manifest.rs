@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::requestor::command::CommandList;
+
+pub const MANIFEST_VERSION: &str = "0.1.0";
+
+/// Job definition of a `Requestor` in yarapi's own JSON format.
+///
+/// Describes the payload, market parameters and the task list of a
+/// `Requestor`. Created with `Requestor::to_manifest` and turned back
+/// into a `Requestor` with `Requestor::from_manifest`.
+///
+/// yapapi and golem-js define jobs in code and have no serialized job
+/// format to exchange, so the manifest is read and written by yarapi only.
+///
+/// ## Example:
+/// ```json
+/// {
+///   "version": "0.1.0",
+///   "name": "My Requestor",
+///   "payload": {
+///     "runtime": "vm",
+///     "runtimeVersion": "0.2.4",
+///     "image": { "type": "url", "hash": "9a3b...", "url": "http://..." }
+///   },
+///   "market": {
+///     "subnet": "community.4",
+///     "budget": "5",
+///     "timeoutSecs": 720,
+///     "secure": false,
+///     "constraints": "(&(golem.inf.mem.gib>0.5))"
+///   },
+///   "tasks": [
+///     [ { "run": ["/bin/ls", "-la"] } ]
+///   ]
+/// }
+/// ```
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub version: String,
+    pub name: String,
+    pub payload: PayloadManifest,
+    pub market: MarketManifest,
+    pub tasks: Vec<CommandList>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PayloadManifest {
+    pub runtime: String,
+    pub runtime_version: String,
+    pub image: ImageLocation,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ImageLocation {
+    Url { hash: String, url: String },
+    File { path: PathBuf },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketManifest {
+    pub subnet: String,
+    /// Max budget in GLM.
+    pub budget: String,
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub secure: bool,
+    /// Demand constraints in LDAP filter notation.
+    #[serde(default)]
+    pub constraints: Option<String>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("unable to read manifest {}", path.display()))?;
+        Ok(serde_json::from_slice(&contents)
+            .with_context(|| format!("invalid manifest {}", path.display()))?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("unable to write manifest {}", path.display()))
+    }
+}
@@ -0,0 +1,145 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Share of the mean duration a running task is credited with at most,
+/// so that progress doesn't reach 100% before the tasks complete.
+const MAX_RUNNING_SHARE: f64 = 0.95;
+
+#[derive(Clone, Debug, Default)]
+struct MetricsState {
+    total: usize,
+    /// Completed or failed.
+    finished: usize,
+    mean_duration: Option<Duration>,
+    /// Start times of the running tasks.
+    running: Vec<Instant>,
+}
+
+/// Progress and estimated time left of a run.
+///
+/// Like `RunSummary`, it is a handle: take it before `Requestor::run` and
+/// query it while the tasks are computed. Estimates are based on the mean
+/// duration of the tasks completed so far and take the elapsed time of the
+/// running ones into account.
+///
+/// ## Example:
+/// ```no_run
+/// # async fn run(requestor: yarapi::requestor::Requestor) -> anyhow::Result<()> {
+/// let metrics = requestor.scheduler_metrics();
+/// actix_rt::spawn(async move {
+///     loop {
+///         tokio::time::delay_for(std::time::Duration::from_secs(10)).await;
+///         println!("{:.1}% done, eta {:?}", metrics.progress(), metrics.eta());
+///     }
+/// });
+/// requestor.run().await
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct SchedulerMetrics {
+    state: Arc<Mutex<MetricsState>>,
+}
+
+impl SchedulerMetrics {
+    /// Estimated time until all tasks are finished; `None` until the first
+    /// task completes.
+    pub fn eta(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        estimate_eta(
+            state.total,
+            state.finished,
+            state.mean_duration,
+            &elapsed(&state.running),
+        )
+    }
+
+    /// Percentage of the work done (0.0 - 100.0), including a part of the
+    /// running tasks proportional to their elapsed time.
+    pub fn progress(&self) -> f64 {
+        let state = self.state.lock().unwrap();
+        estimate_progress(
+            state.total,
+            state.finished,
+            state.mean_duration,
+            &elapsed(&state.running),
+        )
+    }
+
+    pub(crate) fn update(
+        &self,
+        total: usize,
+        finished: usize,
+        mean_duration: Option<Duration>,
+        running: Vec<Instant>,
+    ) {
+        *self.state.lock().unwrap() = MetricsState {
+            total,
+            finished,
+            mean_duration,
+            running,
+        };
+    }
+}
+
+fn elapsed(running: &[Instant]) -> Vec<Duration> {
+    running.iter().map(Instant::elapsed).collect()
+}
+
+fn estimate_progress(
+    total: usize,
+    finished: usize,
+    mean_duration: Option<Duration>,
+    running: &[Duration],
+) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+    let partial: f64 = match mean_duration {
+        Some(mean) if mean > Duration::from_secs(0) => running
+            .iter()
+            .map(|elapsed| (elapsed.as_secs_f64() / mean.as_secs_f64()).min(MAX_RUNNING_SHARE))
+            .sum(),
+        _ => 0.0,
+    };
+    ((finished as f64 + partial) / total as f64 * 100.0).min(100.0)
+}
+
+fn estimate_eta(
+    total: usize,
+    finished: usize,
+    mean_duration: Option<Duration>,
+    running: &[Duration],
+) -> Option<Duration> {
+    if finished >= total {
+        return Some(Duration::from_secs(0));
+    }
+    let mean = mean_duration?;
+    let pending = total.saturating_sub(finished + running.len());
+    let left_running = running
+        .iter()
+        .map(|elapsed| mean.checked_sub(*elapsed).unwrap_or_default())
+        .sum::<Duration>();
+    let workers = running.len().max(1) as u32;
+    Some((mean * pending as u32 + left_running) / workers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimates() {
+        let mean = Some(Duration::from_secs(10));
+        let running = [Duration::from_secs(5), Duration::from_secs(20)];
+        // 4 done, 2 running (halfway and overdue), 4 pending on 2 workers.
+        assert_eq!(
+            estimate_eta(10, 4, mean, &running),
+            Some(Duration::from_secs(22) + Duration::from_millis(500))
+        );
+        assert!((estimate_progress(10, 4, mean, &running) - 54.5).abs() < 1e-9);
+
+        assert_eq!(estimate_eta(10, 0, None, &running), None);
+        assert_eq!(estimate_progress(10, 0, None, &running), 0.0);
+        assert_eq!(estimate_eta(3, 3, None, &[]), Some(Duration::from_secs(0)));
+    }
+}
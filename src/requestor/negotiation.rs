@@ -0,0 +1,202 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use ya_client::model::NodeId;
+
+use crate::requestor::event_log::{EventLog, LifecycleEvent};
+
+/// Why a proposal has not been turned into a working agreement.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FailureReason {
+    /// The offer does not meet a requirement, e.g. `"cpu threads"`.
+    ConstraintMismatch(String),
+    PriceTooHigh,
+    /// The provider has not approved the agreement in time.
+    Timeout,
+    ApprovalRejected,
+    /// The provider rejected our counter-proposal.
+    RejectedByProvider,
+    /// The offer still didn't meet the requirements after `max_rounds`
+    /// counter-proposals.
+    TooManyRounds,
+    HealthCheckFailed,
+    /// The provider doesn't support the transfer protocols of the task.
+    UnsupportedTransfer,
+    Other(String),
+}
+
+impl fmt::Display for FailureReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureReason::ConstraintMismatch(category) => {
+                write!(f, "constraint mismatch ({})", category)
+            }
+            FailureReason::PriceTooHigh => write!(f, "price too high"),
+            FailureReason::Timeout => write!(f, "approval timed out"),
+            FailureReason::ApprovalRejected => write!(f, "agreement not approved"),
+            FailureReason::RejectedByProvider => write!(f, "rejected by provider"),
+            FailureReason::TooManyRounds => write!(f, "negotiation rounds exceeded"),
+            FailureReason::HealthCheckFailed => write!(f, "health check failed"),
+            FailureReason::UnsupportedTransfer => write!(f, "unsupported transfer protocols"),
+            FailureReason::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl FailureReason {
+    /// Stable identifier of the reason, sent to providers with rejections.
+    pub fn code(&self) -> &'static str {
+        match self {
+            FailureReason::ConstraintMismatch(_) => "constraint-mismatch",
+            FailureReason::PriceTooHigh => "price-too-high",
+            FailureReason::Timeout => "timeout",
+            FailureReason::ApprovalRejected => "approval-rejected",
+            FailureReason::RejectedByProvider => "rejected-by-provider",
+            FailureReason::TooManyRounds => "too-many-rounds",
+            FailureReason::HealthCheckFailed => "health-check-failed",
+            FailureReason::UnsupportedTransfer => "unsupported-transfer",
+            FailureReason::Other(_) => "other",
+        }
+    }
+}
+
+#[derive(Default)]
+struct ReportState {
+    proposals: usize,
+    agreements: usize,
+    failures: BTreeMap<FailureReason, Vec<NodeId>>,
+    event_log: Option<EventLog>,
+}
+
+/// Collects outcomes of negotiations with providers.
+///
+/// It is a handle: clones share the same data, so the report taken before
+/// `Requestor::run` can be inspected afterwards.
+///
+/// ## Example:
+/// ```no_run
+/// # async fn run(requestor: yarapi::requestor::Requestor) -> anyhow::Result<()> {
+/// let report = requestor.negotiation_report();
+/// requestor.run().await?;
+/// println!("{}", report);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct NegotiationReport {
+    state: Arc<Mutex<ReportState>>,
+}
+
+impl NegotiationReport {
+    /// Number of proposals ready for an agreement.
+    pub fn proposals(&self) -> usize {
+        self.state.lock().unwrap().proposals
+    }
+
+    /// Number of agreements made.
+    pub fn agreements(&self) -> usize {
+        self.state.lock().unwrap().agreements
+    }
+
+    /// Number of failures by reason.
+    pub fn failures(&self) -> BTreeMap<FailureReason, usize> {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .iter()
+            .map(|(reason, providers)| (reason.clone(), providers.len()))
+            .collect()
+    }
+
+    /// Providers which failed for the given reason.
+    pub fn providers(&self, reason: &FailureReason) -> Vec<NodeId> {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .get(reason)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn set_event_log(&self, event_log: EventLog) {
+        self.state.lock().unwrap().event_log = Some(event_log);
+    }
+
+    pub(crate) fn proposal(&self, node_id: NodeId, proposal_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.proposals += 1;
+        if let Some(event_log) = &state.event_log {
+            event_log.emit(LifecycleEvent::ProposalReceived {
+                proposal_id: proposal_id.to_string(),
+                provider_id: node_id.to_string(),
+            });
+        }
+    }
+
+    pub(crate) fn agreement(&self, node_id: NodeId, agreement_id: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.agreements += 1;
+        if let Some(event_log) = &state.event_log {
+            event_log.emit(LifecycleEvent::AgreementCreated {
+                agreement_id: agreement_id.to_string(),
+                provider_id: node_id.to_string(),
+            });
+        }
+    }
+
+    pub(crate) fn failure(&self, node_id: NodeId, reason: FailureReason) {
+        log::debug!("negotiation with [{:?}] failed: {}", node_id, reason);
+        let mut state = self.state.lock().unwrap();
+        if let Some(event_log) = &state.event_log {
+            event_log.emit(LifecycleEvent::NegotiationFailed {
+                provider_id: node_id.to_string(),
+                reason: reason.to_string(),
+            });
+        }
+        state.failures.entry(reason).or_default().push(node_id);
+    }
+}
+
+impl fmt::Display for NegotiationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        write!(
+            f,
+            "negotiations: {} proposals, {} agreements",
+            state.proposals, state.agreements
+        )?;
+        let mut failures: Vec<_> = state.failures.iter().collect();
+        failures.sort_by(|a, b| b.1.len().cmp(&a.1.len()));
+        for (reason, providers) in failures {
+            write!(f, "\n  {:>4} x {}", providers.len(), reason)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let report = NegotiationReport::default();
+        let node_id: NodeId = "0x0000000000000000000000000000000000000001"
+            .parse()
+            .unwrap();
+        report.proposal(node_id, "proposal-1");
+        report.proposal(node_id, "proposal-2");
+        report.agreement(node_id, "agreement-1");
+        report.failure(node_id, FailureReason::Timeout);
+        report.failure(node_id, FailureReason::RejectedByProvider);
+        report.failure(node_id, FailureReason::RejectedByProvider);
+
+        assert_eq!(report.failures()[&FailureReason::RejectedByProvider], 2);
+        assert_eq!(
+            report.to_string(),
+            "negotiations: 2 proposals, 1 agreements\n     2 x rejected by provider\n     1 x approval timed out"
+        );
+    }
+}
@@ -1,9 +1,14 @@
 use anyhow::{Context, Result};
 use sha3::{Digest, Sha3_512};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::fs;
+use tokio::io::AsyncReadExt;
 use url::Url;
 
+use crate::requestor::digest_cache::DigestCache;
+
+const HASH_CHUNK_SIZE: usize = 1024 * 1024;
+
 /// Represents a path/url to a Yagna package.
 #[derive(Debug, Clone)]
 pub enum Package {
@@ -26,8 +31,30 @@ impl Package {
     /// If the `Package` is specified as `Package::Url`, verifies the url is correct
     /// but does not re-publish the package (assumes it is already published).
     ///
-    /// In all cases, `gftp` is the assumed communication medium.
+    /// In all cases, `gftp` is the assumed communication medium. A published
+    /// archive stays available until closed with `gftp::close`, which
+    /// `Requestor::run` does once it finishes.
     pub async fn publish(&self) -> Result<(String, Url)> {
+        self.publish_with_progress(|_, _| ()).await
+    }
+
+    /// Same as `publish`, but calls `progress` with the number of bytes
+    /// hashed so far and the size of the archive.
+    pub async fn publish_with_progress(
+        &self,
+        progress: impl FnMut(u64, u64),
+    ) -> Result<(String, Url)> {
+        self.publish_with(false, progress).await
+    }
+
+    /// Digests of archives are cached (see `DigestCache::default_path`) and
+    /// reused while the archive's size and modification time don't change.
+    /// Set `force_rehash` to compute the digest anyway.
+    pub async fn publish_with(
+        &self,
+        force_rehash: bool,
+        progress: impl FnMut(u64, u64),
+    ) -> Result<(String, Url)> {
         match self {
             Self::Archive(path) => {
                 let image_path = path
@@ -42,13 +69,7 @@ impl Package {
 
                 log::info!("image published at: {}", url);
 
-                let contents = fs::read(&image_path)
-                    .await
-                    .with_context(|| format!("unable to open image {}", image_path.display()))?;
-                let digest = Sha3_512::digest(&contents);
-                let digest = format!("{:x}", digest);
-
-                log::info!("image's computed digest: {}", digest);
+                let digest = digest(&image_path, force_rehash, progress).await?;
 
                 Ok((digest, url))
             }
@@ -64,6 +85,65 @@ impl Package {
     }
 }
 
+/// Returns the cached digest of the image, or computes (and caches) it.
+async fn digest(
+    image_path: &Path,
+    force_rehash: bool,
+    progress: impl FnMut(u64, u64),
+) -> Result<String> {
+    let metadata = fs::metadata(image_path)
+        .await
+        .with_context(|| format!("unable to open image {}", image_path.display()))?;
+    let cache_path = DigestCache::default_path();
+    let mut cache = match &cache_path {
+        Some(path) => DigestCache::load(path).unwrap_or_else(|e| {
+            log::warn!("{:#}", e);
+            DigestCache::default()
+        }),
+        None => DigestCache::default(),
+    };
+
+    if !force_rehash {
+        if let Some(digest) = cache.get(image_path, &metadata) {
+            log::info!("image's cached digest: {}", digest);
+            return Ok(digest.to_string());
+        }
+    }
+
+    let digest = hash_file(image_path, progress)
+        .await
+        .with_context(|| format!("unable to hash image {}", image_path.display()))?;
+    log::info!("image's computed digest: {}", digest);
+
+    if let Some(path) = &cache_path {
+        cache.insert(image_path, &metadata, digest.clone());
+        if let Err(e) = cache.save(path) {
+            log::warn!("{:#}", e);
+        }
+    }
+    Ok(digest)
+}
+
+/// Computes the sha3 digest of the file in chunks, so that images larger
+/// than the available memory can be hashed.
+async fn hash_file(path: &Path, mut progress: impl FnMut(u64, u64)) -> Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let mut hasher = Sha3_512::new();
+    let mut buf = vec![0; HASH_CHUNK_SIZE];
+    let mut hashed = 0;
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        hashed += n as u64;
+        progress(hashed, size);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
 #[derive(Clone)]
 pub enum Image {
     Wasm(semver::Version),
@@ -72,6 +152,16 @@ pub enum Image {
 }
 
 impl Image {
+    /// Creates an `Image` from the runtime name, e.g. `"vm"`.
+    pub fn from_runtime_name(name: &str, version: semver::Version) -> Option<Self> {
+        match name {
+            "wasmtime" => Some(Image::Wasm(version)),
+            "vm" => Some(Image::GVMKit(version)),
+            "sgx" => Some(Image::Sgx(version)),
+            _ => None,
+        }
+    }
+
     pub fn runtime_name(&self) -> &'static str {
         match self {
             Image::Wasm(_) => "wasmtime",
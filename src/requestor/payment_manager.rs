@@ -3,16 +3,54 @@
 use actix::prelude::*;
 use bigdecimal::BigDecimal;
 use chrono::{DateTime, Utc};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use std::time::Duration;
 use ya_client::{model, payment::PaymentApi};
 
+use crate::glm::Glm;
+use crate::requestor::budget::{estimate_cost, to_f64, BudgetThreshold, BudgetWatch};
+use crate::requestor::debit_note::{DebitNoteCheck, DebitNoteVerifier};
+use crate::requestor::event_log::{EventLog, LifecycleEvent};
+
+/// Delay before creating an allocation for a payment platform again.
+const ALLOCATION_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Invoice accepted, waiting for an allocation on its payment platform.
+struct PendingInvoice {
+    invoice_id: String,
+    amount: BigDecimal,
+}
+
 pub struct PaymentManager {
     payment_api: PaymentApi,
-    allocation_id: String,
+    /// Allocation ids by payment platform.
+    allocations: HashMap<String, String>,
+    /// Platforms whose allocations are being created.
+    creating: HashSet<String>,
+    /// Invoices waiting for the allocations of their platforms.
+    pending_invoices: HashMap<String, Vec<PendingInvoice>>,
+    /// Acceptances in flight, by allocation id.
+    accepting: HashMap<String, usize>,
+    /// Replaced allocations, released once their acceptances finish.
+    retired: HashSet<String>,
+    /// Platform of the requestor's account, topped up by `TopUp`.
+    platform: String,
+    budget: BigDecimal,
     total_amount: BigDecimal,
     amount_paid: BigDecimal,
+    paid_by_agreement: HashMap<String, BigDecimal>,
+    budget_watch: BudgetWatch,
+    budget_alerts: Option<Recipient<BudgetThresholdCrossed>>,
     valid_agreements: HashSet<String>,
+    /// Max invoice amounts, by agreement id.
+    agreement_caps: HashMap<String, BigDecimal>,
+    debit_note_verifier: Option<Arc<dyn DebitNoteVerifier>>,
+    /// Negotiated linear pricing coefficients, by agreement id.
+    pricing: HashMap<String, Vec<f64>>,
+    /// The latest usage samples, by agreement id.
+    usage: HashMap<String, Vec<f64>>,
+    event_log: Option<EventLog>,
     last_debit_note_event: DateTime<Utc>,
     last_invoice_event: DateTime<Utc>,
 }
@@ -29,35 +67,85 @@ impl Actor for PaymentManager {
 impl PaymentManager {
     pub fn new(payment_api: PaymentApi, allocation: model::payment::Allocation) -> Self {
         let now = Utc::now();
+        let mut allocations = HashMap::new();
+        let platform = allocation.payment_platform.clone();
+        allocations.insert(allocation.payment_platform, allocation.allocation_id);
         PaymentManager {
             payment_api,
-            allocation_id: allocation.allocation_id,
+            allocations,
+            creating: Default::default(),
+            pending_invoices: Default::default(),
+            accepting: Default::default(),
+            retired: Default::default(),
+            platform,
+            budget: allocation.total_amount.clone(),
             total_amount: allocation.total_amount,
             amount_paid: 0.into(),
+            paid_by_agreement: Default::default(),
+            budget_watch: BudgetWatch::default(),
+            budget_alerts: None,
             valid_agreements: Default::default(),
+            agreement_caps: Default::default(),
+            debit_note_verifier: None,
+            pricing: Default::default(),
+            usage: Default::default(),
+            event_log: None,
             last_debit_note_event: now,
             last_invoice_event: now,
         }
     }
 
+    /// Rejects debit notes which `verifier` finds not matching the usage
+    /// sampled with `RecordUsage`. Without a verifier debit notes are ignored.
+    pub fn with_debit_note_verifier(self, verifier: Option<Arc<dyn DebitNoteVerifier>>) -> Self {
+        Self {
+            debit_note_verifier: verifier,
+            ..self
+        }
+    }
+
+    /// Records accepted and rejected invoices and debit notes.
+    pub(crate) fn with_event_log(self, event_log: Option<EventLog>) -> Self {
+        Self { event_log, ..self }
+    }
+
+    fn emit(&self, event: LifecycleEvent) {
+        if let Some(event_log) = &self.event_log {
+            event_log.emit(event);
+        }
+    }
+
     fn update_debit_notes(&mut self, ctx: &mut <PaymentManager as Actor>::Context) {
         let mut ts = self.last_debit_note_event;
         let api = self.payment_api.clone();
+        let verify = self.debit_note_verifier.is_some();
 
         let f = async move {
             let events = api
                 .get_debit_note_events(Some(&ts), Some(Duration::from_secs(60)), Some(5), None)
                 .await?;
+            let mut new_debit_notes = Vec::new();
             for event in events {
                 log::debug!("got debit note: {:?}", event);
+                if verify
+                    && event.event_type
+                        == model::payment::DebitNoteEventType::DebitNoteReceivedEvent
+                {
+                    new_debit_notes.push(api.get_debit_note(&event.debit_note_id).await?);
+                }
                 ts = event.event_date;
             }
-            Ok::<_, anyhow::Error>(ts)
+            Ok::<_, anyhow::Error>((ts, new_debit_notes))
         }
         .into_actor(self)
-        .then(|ts, this, ctx: &mut Context<Self>| {
-            match ts {
-                Ok(ts) => this.last_debit_note_event = ts,
+        .then(|result, this, ctx: &mut Context<Self>| {
+            match result {
+                Ok((ts, debit_notes)) => {
+                    this.last_debit_note_event = ts;
+                    for debit_note in debit_notes {
+                        this.verify_debit_note(debit_note);
+                    }
+                }
                 Err(e) => {
                     log::error!("debit note event error: {}", e);
                 }
@@ -99,41 +187,58 @@ impl PaymentManager {
                     Ok((ts, invoices)) => {
                         this.last_invoice_event = ts;
                         for invoice in invoices {
-                            let api = this.payment_api.clone();
+                            let cap = this.agreement_caps.remove(&invoice.agreement_id);
 
-                            if this.valid_agreements.remove(&invoice.agreement_id) {
+                            if let Some(cap) = cap.filter(|cap| &invoice.amount > cap) {
+                                this.valid_agreements.remove(&invoice.agreement_id);
+                                log::warn!(
+                                    "Rejecting invoice amounted {} GLM above the cap of {} GLM, issuer: {}",
+                                    Glm::from(invoice.amount.clone()),
+                                    Glm::from(cap.clone()),
+                                    invoice.issuer_id
+                                );
+                                this.reject_invoice(
+                                    invoice.invoice_id,
+                                    model::payment::RejectionReason::IncorrectAmount,
+                                    format!("amount exceeds the agreement cap of {} GLM", Glm::from(cap)),
+                                );
+                            } else if this.valid_agreements.remove(&invoice.agreement_id) {
                                 let invoice_id = invoice.invoice_id;
                                 log::info!(
                                     "Accepting invoice amounted {} GLM, issuer: {}",
-                                    invoice.amount,
+                                    Glm::from(invoice.amount.clone()),
                                     invoice.issuer_id
                                 );
                                 this.amount_paid += invoice.amount.clone();
-                                let acceptance = model::payment::Acceptance {
-                                    total_amount_accepted: invoice.amount.clone(),
-                                    allocation_id: this.allocation_id.clone(),
-                                };
-                                let _ = Arbiter::spawn(async move {
-                                    if let Err(e) =
-                                        api.accept_invoice(&invoice_id, &acceptance).await
-                                    {
-                                        log::error!("invoice {} accept error: {}", invoice_id, e)
-                                    }
+                                this.paid_by_agreement
+                                    .insert(invoice.agreement_id.clone(), invoice.amount.clone());
+                                this.check_budget();
+                                this.emit(LifecycleEvent::InvoiceAccepted {
+                                    invoice_id: invoice_id.clone(),
+                                    agreement_id: invoice.agreement_id.clone(),
+                                    amount: invoice.amount.to_string(),
                                 });
-                            } else {
-                                let invoice_id = invoice.invoice_id;
-
-                                let spec = model::payment::Rejection {
-                                    rejection_reason:
-                                        model::payment::RejectionReason::UnsolicitedService,
-                                    total_amount_accepted: 0.into(),
-                                    message: Some("invoice received before results".to_string()),
-                                };
-                                let _ = Arbiter::spawn(async move {
-                                    if let Err(e) = api.reject_invoice(&invoice_id, &spec).await {
-                                        log::error!("invoice: {} reject error: {}", invoice_id, e);
+                                match this.allocations.get(&invoice.payment_platform).cloned() {
+                                    Some(allocation_id) => {
+                                        this.accept_invoice(ctx, invoice_id, invoice.amount, allocation_id)
                                     }
-                                });
+                                    None => {
+                                        this.pending_invoices
+                                            .entry(invoice.payment_platform.clone())
+                                            .or_default()
+                                            .push(PendingInvoice {
+                                                invoice_id,
+                                                amount: invoice.amount,
+                                            });
+                                        this.ensure_allocation(ctx, invoice.payment_platform, None);
+                                    }
+                                }
+                            } else {
+                                this.reject_invoice(
+                                    invoice.invoice_id,
+                                    model::payment::RejectionReason::UnsolicitedService,
+                                    "invoice received before results".to_string(),
+                                );
                             }
                         }
                     }
@@ -150,10 +255,304 @@ impl PaymentManager {
 
         let _ = ctx.spawn(f);
     }
+
+    fn verify_debit_note(&self, debit_note: model::payment::DebitNote) {
+        let verifier = match &self.debit_note_verifier {
+            Some(verifier) => verifier,
+            None => return,
+        };
+        let agreement_id = debit_note.agreement_id;
+        let usage = self.usage.get(&agreement_id);
+        let expected = self
+            .pricing
+            .get(&agreement_id)
+            .zip(usage)
+            .and_then(|(coeffs, usage)| estimate_cost(coeffs, usage));
+        let (expected, amount_due) = match (expected, to_f64(&debit_note.total_amount_due)) {
+            (Some(expected), Some(amount_due)) => (expected, amount_due),
+            _ => {
+                log::debug!(
+                    "no usage samples to verify debit note {}",
+                    debit_note.debit_note_id
+                );
+                return;
+            }
+        };
+        let check = DebitNoteCheck {
+            debit_note_id: debit_note.debit_note_id,
+            agreement_id,
+            amount_due,
+            expected,
+            usage: usage.cloned().unwrap_or_default(),
+        };
+        if let Err(message) = verifier.verify(&check) {
+            log::warn!(
+                "rejecting debit note {} for agreement [{}]: {}",
+                check.debit_note_id,
+                check.agreement_id,
+                message
+            );
+            self.emit(LifecycleEvent::DebitNoteRejected {
+                debit_note_id: check.debit_note_id.clone(),
+                agreement_id: check.agreement_id.clone(),
+                reason: message.clone(),
+            });
+            let api = self.payment_api.clone();
+            let debit_note_id = check.debit_note_id;
+            let spec = model::payment::Rejection {
+                rejection_reason: model::payment::RejectionReason::IncorrectAmount,
+                total_amount_accepted: 0.into(),
+                message: Some(message),
+            };
+            let _ = Arbiter::spawn(async move {
+                if let Err(e) = api.reject_debit_note(&debit_note_id, &spec).await {
+                    log::error!("debit note: {} reject error: {}", debit_note_id, e);
+                }
+            });
+        }
+    }
+
+    fn reject_invoice(
+        &self,
+        invoice_id: String,
+        rejection_reason: model::payment::RejectionReason,
+        message: String,
+    ) {
+        self.emit(LifecycleEvent::InvoiceRejected {
+            invoice_id: invoice_id.clone(),
+            reason: message.clone(),
+        });
+        let api = self.payment_api.clone();
+        let spec = model::payment::Rejection {
+            rejection_reason,
+            total_amount_accepted: 0.into(),
+            message: Some(message),
+        };
+        let _ = Arbiter::spawn(async move {
+            if let Err(e) = api.reject_invoice(&invoice_id, &spec).await {
+                log::error!("invoice: {} reject error: {}", invoice_id, e);
+            }
+        });
+    }
+
+    /// Notifies about the budget thresholds crossed by the accepted invoices.
+    fn check_budget(&mut self) {
+        let crossed = self.budget_watch.crossed(&self.amount_paid, &self.budget);
+        for threshold in crossed {
+            log::info!(
+                "spent {} GLM, {:.0}% of the budget",
+                threshold.spent,
+                threshold.fraction * 100.0
+            );
+            if let Some(recipient) = &self.budget_alerts {
+                let _ = recipient.do_send(BudgetThresholdCrossed(threshold));
+            }
+        }
+    }
+
+    fn accept_invoice(
+        &mut self,
+        ctx: &mut <PaymentManager as Actor>::Context,
+        invoice_id: String,
+        amount: BigDecimal,
+        allocation_id: String,
+    ) {
+        *self.accepting.entry(allocation_id.clone()).or_default() += 1;
+        let api = self.payment_api.clone();
+        let acceptance = model::payment::Acceptance {
+            total_amount_accepted: amount,
+            allocation_id: allocation_id.clone(),
+        };
+        let f = async move {
+            if let Err(e) = api.accept_invoice(&invoice_id, &acceptance).await {
+                log::error!("invoice {} accept error: {}", invoice_id, e)
+            }
+        }
+        .into_actor(self)
+        .then(move |_, this, _| {
+            let done = match this.accepting.get_mut(&allocation_id) {
+                Some(count) if *count > 1 => {
+                    *count -= 1;
+                    false
+                }
+                _ => true,
+            };
+            if done {
+                this.accepting.remove(&allocation_id);
+                if this.retired.remove(&allocation_id) {
+                    this.release_allocation(allocation_id);
+                }
+            }
+            fut::ready(())
+        });
+        let _ = ctx.spawn(f);
+    }
+
+    /// Releases a replaced allocation, once the acceptances against it have
+    /// finished.
+    fn retire_allocation(&mut self, allocation_id: String) {
+        match self.accepting.contains_key(&allocation_id) {
+            true => {
+                self.retired.insert(allocation_id);
+            }
+            false => self.release_allocation(allocation_id),
+        }
+    }
+
+    fn release_allocation(&self, allocation_id: String) {
+        let api = self.payment_api.clone();
+        let _ = Arbiter::spawn(async move {
+            if let Err(e) = api.release_allocation(&allocation_id).await {
+                log::warn!("unable to release allocation {}: {}", allocation_id, e);
+            }
+        });
+    }
+
+    /// Budget not reserved by any allocation yet.
+    fn unallocated(&self) -> BigDecimal {
+        let unallocated = &self.budget - &self.total_amount;
+        match unallocated > 0.into() {
+            true => unallocated,
+            false => 0.into(),
+        }
+    }
+
+    /// Creates an allocation for a payment platform met for the first time,
+    /// unless it is already being created, and accepts the invoices waiting
+    /// for it. Retried until it succeeds.
+    ///
+    /// The allocation gets `cap` (or, without a cap, half of the budget left
+    /// for spending) out of the unallocated budget; what is missing is moved
+    /// from the allocation of the requestor's platform, which is recreated
+    /// smaller, so that the allocations never reserve more than the budget.
+    fn ensure_allocation(
+        &mut self,
+        ctx: &mut <PaymentManager as Actor>::Context,
+        platform: String,
+        cap: Option<BigDecimal>,
+    ) {
+        if self.allocations.contains_key(&platform) || !self.creating.insert(platform.clone()) {
+            return;
+        }
+        log::info!("creating allocation for payment platform {}", platform);
+        let api = self.payment_api.clone();
+        let unallocated = self.unallocated();
+        let primary_id = self.allocations.get(&self.platform).cloned();
+        let (new_platform, retry_cap) = (platform.clone(), cap.clone());
+        let f = async move {
+            let primary = match primary_id {
+                Some(allocation_id) => Some(api.get_allocation(&allocation_id).await?),
+                None => None,
+            };
+            let spare = primary
+                .as_ref()
+                .map(|primary| primary.remaining_amount.clone())
+                .unwrap_or_else(|| 0.into());
+            let wanted = cap.unwrap_or_else(|| (&unallocated + &spare) / BigDecimal::from(2));
+            let missing = &wanted - &unallocated;
+            let moved = match missing > 0.into() {
+                true if missing > spare => spare,
+                true => missing,
+                false => 0.into(),
+            };
+            let amount = match wanted < unallocated {
+                true => wanted,
+                false => unallocated,
+            } + &moved;
+            if amount <= 0.into() {
+                anyhow::bail!("no budget left to allocate");
+            }
+            let shrunk = match primary {
+                Some(primary) if moved > 0.into() => {
+                    let allocation = api
+                        .create_allocation(&model::payment::NewAllocation {
+                            address: Some(primary.address.clone()),
+                            payment_platform: Some(primary.payment_platform.clone()),
+                            total_amount: &primary.remaining_amount - &moved,
+                            timeout: None,
+                            make_deposit: false,
+                        })
+                        .await?;
+                    Some((allocation, moved))
+                }
+                _ => None,
+            };
+            let allocation = api
+                .create_allocation(&model::payment::NewAllocation {
+                    address: None,
+                    payment_platform: Some(new_platform),
+                    total_amount: amount,
+                    timeout: None,
+                    make_deposit: false,
+                })
+                .await
+                .map_err(anyhow::Error::from);
+            Ok::<_, anyhow::Error>((shrunk, allocation))
+        }
+        .into_actor(self)
+        .then(move |result, this, ctx: &mut Context<Self>| {
+            this.creating.remove(&platform);
+            let allocation = result.and_then(|(shrunk, allocation)| {
+                if let Some((primary, moved)) = shrunk {
+                    this.total_amount = &this.total_amount - &moved;
+                    let old = this
+                        .allocations
+                        .insert(primary.payment_platform, primary.allocation_id);
+                    if let Some(allocation_id) = old {
+                        this.retire_allocation(allocation_id);
+                    }
+                }
+                allocation
+            });
+            match allocation {
+                Ok(allocation) => {
+                    log::info!(
+                        "allocated {} GLM for payment platform {}",
+                        Glm::from(allocation.total_amount.clone()),
+                        platform
+                    );
+                    this.total_amount += allocation.total_amount;
+                    this.allocations
+                        .insert(platform.clone(), allocation.allocation_id.clone());
+                    for invoice in this.pending_invoices.remove(&platform).unwrap_or_default() {
+                        this.accept_invoice(
+                            ctx,
+                            invoice.invoice_id,
+                            invoice.amount,
+                            allocation.allocation_id.clone(),
+                        );
+                    }
+                }
+                Err(e) => {
+                    let pending = this.pending_invoices.get(&platform).map_or(0, Vec::len);
+                    log::warn!(
+                        "unable to create allocation for {} ({} invoice(s) waiting): {:#}; retrying in {:?}",
+                        platform,
+                        pending,
+                        e,
+                        ALLOCATION_RETRY_DELAY
+                    );
+                    ctx.run_later(ALLOCATION_RETRY_DELAY, move |this, ctx| {
+                        this.ensure_allocation(ctx, platform, retry_cap)
+                    });
+                }
+            }
+            fut::ready(())
+        });
+
+        let _ = ctx.spawn(f);
+    }
 }
 
 pub struct AcceptAgreement {
     pub agreement_id: String,
+    /// Invoices above this amount are rejected; compared with the
+    /// `BigDecimal` amounts of invoices as they come from ya-client.
+    pub cap: Option<BigDecimal>,
+    /// Negotiated linear pricing coefficients.
+    pub coeffs: Option<Vec<f64>>,
+    /// Payment platforms of the provider.
+    pub platforms: Vec<String>,
 }
 
 impl Message for AcceptAgreement {
@@ -163,7 +562,19 @@ impl Message for AcceptAgreement {
 impl Handler<AcceptAgreement> for PaymentManager {
     type Result = anyhow::Result<()>;
 
-    fn handle(&mut self, msg: AcceptAgreement, _ctx: &mut Self::Context) -> Self::Result {
+    fn handle(&mut self, msg: AcceptAgreement, ctx: &mut Self::Context) -> Self::Result {
+        // Providers not paid on the requestor's platform are paid on their first one.
+        if !msg.platforms.contains(&self.platform) {
+            if let Some(platform) = msg.platforms.first() {
+                self.ensure_allocation(ctx, platform.clone(), msg.cap.clone());
+            }
+        }
+        if let Some(cap) = msg.cap {
+            self.agreement_caps.insert(msg.agreement_id.clone(), cap);
+        }
+        if let Some(coeffs) = msg.coeffs {
+            self.pricing.insert(msg.agreement_id.clone(), coeffs);
+        }
         self.valid_agreements.insert(msg.agreement_id);
         Ok(())
     }
@@ -183,6 +594,144 @@ impl Handler<GetPending> for PaymentManager {
     }
 }
 
+/// Allocated and already paid amounts, in GLM.
+///
+/// Kept as `BigDecimal`, the type the payment manager sums amounts in;
+/// `Glm` (which has no arithmetic) is only used to display them.
+#[derive(Clone, Debug)]
+pub struct PaymentStatus {
+    pub allocated: BigDecimal,
+    pub paid: BigDecimal,
+}
+
+pub struct GetPaymentStatus;
+
+impl Message for GetPaymentStatus {
+    type Result = PaymentStatus;
+}
+
+impl Handler<GetPaymentStatus> for PaymentManager {
+    type Result = MessageResult<GetPaymentStatus>;
+
+    fn handle(&mut self, _msg: GetPaymentStatus, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(PaymentStatus {
+            allocated: self.total_amount.clone(),
+            paid: self.amount_paid.clone(),
+        })
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct BudgetThresholdCrossed(pub BudgetThreshold);
+
+/// Sends `BudgetThresholdCrossed` to `recipient` when spending crosses
+/// any of the `thresholds` (fractions of the budget).
+pub(crate) struct WatchBudget {
+    pub thresholds: Vec<f64>,
+    pub recipient: Recipient<BudgetThresholdCrossed>,
+}
+
+impl Message for WatchBudget {
+    type Result = ();
+}
+
+impl Handler<WatchBudget> for PaymentManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: WatchBudget, _ctx: &mut Self::Context) -> Self::Result {
+        self.budget_watch = BudgetWatch::new(msg.thresholds);
+        self.budget_alerts = Some(msg.recipient);
+        self.check_budget();
+    }
+}
+
+/// Amounts accepted so far, by agreement id; summed as `BigDecimal`, like
+/// `PaymentStatus`.
+pub struct GetAgreementCosts;
+
+impl Message for GetAgreementCosts {
+    type Result = HashMap<String, BigDecimal>;
+}
+
+impl Handler<GetAgreementCosts> for PaymentManager {
+    type Result = MessageResult<GetAgreementCosts>;
+
+    fn handle(&mut self, _msg: GetAgreementCosts, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.paid_by_agreement.clone())
+    }
+}
+
+/// The latest usage of the activity of an agreement, for verifying debit notes.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct RecordUsage {
+    pub agreement_id: String,
+    pub usage: Vec<f64>,
+}
+
+impl Handler<RecordUsage> for PaymentManager {
+    type Result = ();
+
+    fn handle(&mut self, msg: RecordUsage, _ctx: &mut Self::Context) -> Self::Result {
+        self.usage.insert(msg.agreement_id, msg.usage);
+    }
+}
+
+/// Adds to the budget by recreating the allocation of the requestor's
+/// platform with its remaining amount increased by the given amount.
+///
+/// The new allocation is created before the old one is released, so that
+/// invoices can be accepted all the time; the old one is released once the
+/// acceptances against it have finished.
+pub(crate) struct TopUp(pub BigDecimal);
+
+impl Message for TopUp {
+    type Result = anyhow::Result<()>;
+}
+
+impl Handler<TopUp> for PaymentManager {
+    type Result = ResponseActFuture<Self, anyhow::Result<()>>;
+
+    fn handle(&mut self, msg: TopUp, _: &mut Self::Context) -> Self::Result {
+        let api = self.payment_api.clone();
+        let allocation_id = self.allocations.get(&self.platform).cloned();
+        let amount = msg.0;
+        Box::new(
+            async move {
+                let allocation_id =
+                    allocation_id.ok_or_else(|| anyhow::anyhow!("no allocation to top up"))?;
+                let current = api.get_allocation(&allocation_id).await?;
+                let allocation = api
+                    .create_allocation(&model::payment::NewAllocation {
+                        address: Some(current.address.clone()),
+                        payment_platform: Some(current.payment_platform.clone()),
+                        total_amount: &current.remaining_amount + &amount,
+                        timeout: None,
+                        make_deposit: false,
+                    })
+                    .await?;
+                Ok::<_, anyhow::Error>((allocation, amount))
+            }
+            .into_actor(self)
+            .then(|result, this, _| {
+                let result = result.map(|(allocation, amount)| {
+                    let old = this
+                        .allocations
+                        .insert(allocation.payment_platform, allocation.allocation_id);
+                    if let Some(allocation_id) = old {
+                        this.retire_allocation(allocation_id);
+                    }
+                    this.budget += &amount;
+                    this.total_amount += amount;
+                    log::info!("budget topped up to {}", Glm::from(this.budget.clone()));
+                });
+                fut::ready(result)
+            }),
+        )
+    }
+}
+
 pub(crate) struct ReleaseAllocation;
 
 impl Message for ReleaseAllocation {
@@ -194,13 +743,13 @@ impl Handler<ReleaseAllocation> for PaymentManager {
 
     fn handle(&mut self, _: ReleaseAllocation, _: &mut Self::Context) -> Self::Result {
         let payment_api = self.payment_api.clone();
-        let allocation_id = self.allocation_id.clone();
+        let allocation_ids: Vec<String> = self.allocations.values().cloned().collect();
         Box::new(
             async move {
-                payment_api
-                    .release_allocation(&allocation_id)
-                    .await
-                    .map_err(anyhow::Error::from)
+                for allocation_id in allocation_ids {
+                    payment_api.release_allocation(&allocation_id).await?;
+                }
+                Ok(())
             }
             .into_actor(self),
         )
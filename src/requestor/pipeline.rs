@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::requestor::command::{Command, CommandList};
+use crate::requestor::task::Task;
+
+/// Assigns ids to the stages of a multi-stage computation, see
+/// `Requestor::with_pipeline`.
+///
+/// A stage can depend only on the stages created before it, so pipelines
+/// have no cycles.
+///
+/// ## Example:
+/// ```no_run
+/// # fn run(requestor: yarapi::requestor::Requestor) {
+/// use yarapi::{commands, requestor::Pipeline};
+///
+/// let mut pipeline = Pipeline::default();
+/// let train = pipeline.stage(commands![run("/bin/train", "/golem/output/model.bin")]);
+/// let evaluate = pipeline
+///     .stage(commands![run("/bin/evaluate", "/golem/input/model.bin")])
+///     .after(&train)
+///     .with_input(train.output("/golem/output/model.bin"), "/golem/input/model.bin");
+/// let requestor = requestor.with_pipeline(vec![train, evaluate], "pipeline");
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Pipeline {
+    next_id: usize,
+}
+
+impl Pipeline {
+    pub fn stage(&mut self, commands: CommandList) -> Stage {
+        let id = self.next_id;
+        self.next_id += 1;
+        Stage {
+            id,
+            commands,
+            after: vec![],
+            inputs: vec![],
+        }
+    }
+}
+
+/// A task of a pipeline, run once all the stages it depends on succeed.
+#[derive(Clone, Debug)]
+pub struct Stage {
+    id: usize,
+    commands: CommandList,
+    after: Vec<usize>,
+    inputs: Vec<(StageOutput, String)>,
+}
+
+/// A file a stage leaves in its container, e.g. `/golem/output/model.bin`.
+#[derive(Clone, Debug)]
+pub struct StageOutput {
+    stage_id: usize,
+    path: String,
+}
+
+impl Stage {
+    /// Id of the task, as in the callbacks and the run summary.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn after(mut self, stage: &Stage) -> Self {
+        if !self.after.contains(&stage.id) {
+            self.after.push(stage.id);
+        }
+        self
+    }
+
+    pub fn output(&self, path: impl Into<String>) -> StageOutput {
+        StageOutput {
+            stage_id: self.id,
+            path: path.into(),
+        }
+    }
+
+    /// Uploads an output of an earlier stage to `to` in this stage's
+    /// container, before the stage's commands; implies `after`.
+    pub fn with_input(mut self, output: StageOutput, to: impl Into<String>) -> Self {
+        if !self.after.contains(&output.stage_id) {
+            self.after.push(output.stage_id);
+        }
+        self.inputs.push((output, to.into()));
+        self
+    }
+}
+
+/// Where the requestor keeps an output passed between stages.
+fn local_path(dir: &Path, output: &StageOutput) -> PathBuf {
+    dir.join(format!("stage-{}", output.stage_id))
+        .join(output.path.trim_start_matches('/').replace('/', "_"))
+}
+
+/// Builds the tasks of the stages: every output consumed by a later stage is
+/// downloaded into `dir` after the producing stage's commands, and uploaded
+/// before the consuming stage's ones.
+pub(crate) fn into_tasks(stages: Vec<Stage>, dir: &Path) -> Vec<Task> {
+    let mut outputs: HashMap<usize, Vec<String>> = HashMap::new();
+    for stage in &stages {
+        for (output, _) in &stage.inputs {
+            let paths = outputs.entry(output.stage_id).or_default();
+            if !paths.contains(&output.path) {
+                paths.push(output.path.clone());
+            }
+        }
+    }
+    stages
+        .into_iter()
+        .map(|stage| {
+            let uploads = stage.inputs.iter().map(|(output, to)| Command::Upload {
+                from: local_path(dir, output),
+                to: to.clone(),
+            });
+            let downloads = outputs
+                .remove(&stage.id)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|path| {
+                    let output = stage.output(path);
+                    Command::Download {
+                        to: local_path(dir, &output),
+                        from: output.path,
+                    }
+                });
+            let commands = CommandList::new(
+                uploads
+                    .chain(stage.commands.iter().cloned())
+                    .chain(downloads)
+                    .collect::<Vec<_>>(),
+            );
+            Task::new(stage.id, commands).after(stage.after)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_into_tasks() {
+        let mut pipeline = Pipeline::default();
+        let a = pipeline.stage(crate::commands![run("/bin/a")]);
+        let b = pipeline
+            .stage(crate::commands![run("/bin/b")])
+            .with_input(a.output("/golem/output/a.txt"), "/golem/input/a.txt");
+
+        let tasks = into_tasks(vec![a, b], Path::new("stages"));
+        assert_eq!(tasks[1].after, vec![0]);
+        let a = tasks[0].fixed_commands().unwrap();
+        let b = tasks[1].fixed_commands().unwrap();
+        assert_eq!(
+            a.outputs(),
+            vec![PathBuf::from("stages/stage-0/golem_output_a.txt")]
+        );
+        match b.iter().next() {
+            Some(Command::Upload { from, to }) => {
+                assert_eq!(from, &PathBuf::from("stages/stage-0/golem_output_a.txt"));
+                assert_eq!(to, "/golem/input/a.txt");
+            }
+            command => panic!("unexpected command: {:?}", command),
+        }
+    }
+}
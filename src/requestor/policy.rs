@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use ya_client::model::NodeId;
+
+use crate::properties::{com, usage};
+use crate::requestor::budget::estimate_cost;
+use crate::requestor::provider::ProviderInfo;
+
+/// How long a provider counts as available after its last proposal.
+const OFFER_TTL: Duration = Duration::from_secs(300);
+
+/// Which providers get the tasks, see `Requestor::with_scheduling_policy`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedulingPolicy {
+    /// Every provider whose proposal matches gets a task.
+    FirstAvailable,
+    /// Only the cheapest providers get tasks, as many as it takes to finish
+    /// the remaining ones by the deadline (see `Requestor::with_deadline`).
+    ///
+    /// The cost of a task on a provider is estimated from its negotiated
+    /// prices and the durations of its completed tasks (or of all the
+    /// completed tasks, for a new provider), assuming a task keeps one CPU
+    /// thread busy. Until the first task completes, or without a deadline,
+    /// it dispatches as `FirstAvailable`.
+    CheapestWithinDeadline,
+}
+
+impl Default for SchedulingPolicy {
+    fn default() -> Self {
+        SchedulingPolicy::FirstAvailable
+    }
+}
+
+/// Prices and throughput of the providers seen in a run.
+#[derive(Clone, Default)]
+pub(crate) struct ProviderCosts {
+    /// Estimated cost of a task and when the provider last proposed.
+    offers: HashMap<NodeId, (f64, Instant)>,
+    /// Total duration and number of the tasks completed by the provider.
+    durations: HashMap<NodeId, (Duration, u32)>,
+}
+
+impl ProviderCosts {
+    pub fn observe(&mut self, node_id: &NodeId, duration: Duration) {
+        let (total, count) = self
+            .durations
+            .entry(*node_id)
+            .or_insert((Duration::from_secs(0), 0));
+        *total += duration;
+        *count += 1;
+    }
+
+    fn mean_duration(&self, node_id: &NodeId) -> Option<Duration> {
+        let (total, count) = self.durations.get(node_id)?;
+        Some(*total / (*count).max(1))
+    }
+
+    /// Whether `provider` is among the cheapest providers needed to run
+    /// `remaining` tasks within `time_left`, given the `mean_duration` of
+    /// a task.
+    pub fn admits(
+        &mut self,
+        provider: &ProviderInfo,
+        remaining: usize,
+        time_left: Option<Duration>,
+        mean_duration: Option<Duration>,
+        now: Instant,
+    ) -> bool {
+        let mean_duration = match mean_duration {
+            Some(mean_duration) => mean_duration,
+            None => return true,
+        };
+        let duration = self
+            .mean_duration(&provider.node_id)
+            .unwrap_or(mean_duration);
+        let cost = match task_cost(provider, duration) {
+            Some(cost) => cost,
+            None => return true,
+        };
+        self.offers
+            .retain(|_, (_, seen)| now.saturating_duration_since(*seen) < OFFER_TTL);
+        self.offers.insert(provider.node_id, (cost, now));
+
+        let time_left = match time_left {
+            Some(time_left) if time_left > Duration::from_secs(0) => time_left,
+            _ => return true,
+        };
+        let needed = (remaining as f64 * mean_duration.as_secs_f64() / time_left.as_secs_f64())
+            .ceil()
+            .max(1.0) as usize;
+        let cheaper = self
+            .offers
+            .iter()
+            .filter(|(node_id, (other, _))| **node_id != provider.node_id && *other < cost)
+            .count();
+        cheaper < needed
+    }
+}
+
+/// Cost of a task taking `duration` on `provider`, with its linear pricing.
+fn task_cost(provider: &ProviderInfo, duration: Duration) -> Option<f64> {
+    let coeffs = provider.linear_coeffs()?;
+    let secs = duration.as_secs_f64();
+    let usage: Vec<f64> = provider
+        .property(com::USAGE_VECTOR)
+        .and_then(serde_json::Value::as_array)?
+        .iter()
+        .map(|name| match name.as_str() {
+            Some(usage::DURATION_SEC) | Some(usage::CPU_SEC) => secs,
+            _ => 0.0,
+        })
+        .collect();
+    estimate_cost(&coeffs, &usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn provider(i: u64, price_per_sec: f64) -> ProviderInfo {
+        ProviderInfo {
+            node_id: format!("0x{:040x}", i).parse().unwrap(),
+            properties: json!({
+                "golem.com.usage.vector": ["golem.usage.duration_sec", "golem.usage.cpu_sec"],
+                "golem.com.pricing.model.linear.coeffs": [price_per_sec, 0.0, 0.0],
+            }),
+        }
+    }
+
+    #[test]
+    fn test_cheapest_within_deadline() {
+        let mut costs = ProviderCosts::default();
+        let now = Instant::now();
+        let mean = Some(Duration::from_secs(60));
+        let time_left = Some(Duration::from_secs(120));
+        let (cheap, medium, expensive) = (provider(1, 0.1), provider(2, 0.2), provider(3, 0.3));
+
+        // 4 tasks of a minute in 2 minutes take 2 providers.
+        assert!(costs.admits(&cheap, 4, time_left, mean, now));
+        assert!(costs.admits(&medium, 4, time_left, mean, now));
+        assert!(!costs.admits(&expensive, 4, time_left, mean, now));
+        assert!(costs.admits(&expensive, 6, time_left, mean, now));
+
+        // A slow provider costs more per task.
+        costs.observe(&cheap.node_id, Duration::from_secs(600));
+        assert!(!costs.admits(&cheap, 4, time_left, mean, now));
+
+        // Without throughput data there is nothing to compare.
+        assert!(costs.admits(&expensive, 4, time_left, None, now));
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Context, Result};
+use futures::channel::mpsc;
+use futures::lock::Mutex;
+use futures::prelude::*;
+use std::cell::Cell;
+
+use crate::requestor::command::CommandList;
+use crate::rest::activity::{batch_outputs, Activity, ExeScriptCommand};
+
+/// Activities kept deployed and started between batches.
+///
+/// Deploying an image takes much longer than most commands, so iterative
+/// workflows (e.g. parameter sweeps) can run every `CommandList` on an
+/// already started activity instead.
+///
+/// Activities are handed out in turn; one which fails a batch is removed
+/// from the pool, as its state is unknown. Activities created by a `Session`
+/// are destroyed when dropped with the pool.
+///
+/// ## Example:
+/// ```no_run
+/// # async fn sweep(session: yarapi::rest::Session, agreement: yarapi::rest::Agreement) -> anyhow::Result<()> {
+/// use yarapi::{commands, requestor::ActivityPool};
+///
+/// let pool = ActivityPool::new();
+/// pool.add(session.create_activity(&agreement).await?).await?;
+/// for param in 0..10 {
+///     let outputs = pool
+///         .run(commands![run("/bin/echo", param.to_string())])
+///         .await?;
+///     println!("{} => {:?}", param, outputs);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct ActivityPool<A: Activity> {
+    tx: mpsc::UnboundedSender<A>,
+    rx: Mutex<mpsc::UnboundedReceiver<A>>,
+    size: Cell<usize>,
+}
+
+impl<A: Activity> ActivityPool<A> {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded();
+        ActivityPool {
+            tx,
+            rx: Mutex::new(rx),
+            size: Cell::new(0),
+        }
+    }
+
+    /// Number of activities in the pool, including the busy ones.
+    pub fn len(&self) -> usize {
+        self.size.get()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Deploys and starts `activity`, then adds it to the pool.
+    pub async fn add(&self, activity: A) -> Result<()> {
+        let batch = activity
+            .exec(vec![
+                ExeScriptCommand::Deploy {},
+                ExeScriptCommand::Start { args: vec![] },
+            ])
+            .await?;
+        batch_outputs(&batch)
+            .await
+            .with_context(|| format!("failed to start activity [{}]", activity.id()))?;
+        self.size.set(self.size.get() + 1);
+        self.release(activity);
+        Ok(())
+    }
+
+    /// Runs `commands` (without deploy and start) on the next idle activity,
+    /// waiting for one if all are busy. Returns the outputs of all the steps.
+    pub async fn run(&self, commands: CommandList) -> Result<Vec<String>> {
+        if self.is_empty() {
+            return Err(anyhow!("activity pool is empty"));
+        }
+        let script = commands.into_exe_script_with(false).await?;
+        let activity = self
+            .rx
+            .lock()
+            .await
+            .next()
+            .await
+            .ok_or_else(|| anyhow!("activity pool is closed"))?;
+
+        let result = async {
+            let batch = activity.exec_raw(&script.request.text).await?;
+            batch_outputs(&batch).await
+        }
+        .await;
+        match &result {
+            Ok(_) => self.release(activity),
+            Err(e) => {
+                log::warn!(
+                    "removing activity [{}] from the pool: {:?}",
+                    activity.id(),
+                    e
+                );
+                self.size.set(self.size.get() - 1);
+            }
+        }
+        result
+    }
+
+    fn release(&self, activity: A) {
+        // The receiver lives as long as the pool.
+        let _ = self.tx.unbounded_send(activity);
+    }
+}
+
+impl<A: Activity> Default for ActivityPool<A> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
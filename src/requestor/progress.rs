@@ -0,0 +1,201 @@
+//! Live progress of a run over HTTP, enabled with the `progress-server` feature.
+//!
+//! * `GET /status` returns the current state of tasks, providers and payments,
+//!   with the progress percentage and ETA, as JSON,
+//! * `GET /events` streams `ProgressEvent`s as server-sent events.
+//!
+//! ## Example:
+//! ```js
+//! const events = new EventSource("http://127.0.0.1:8080/events");
+//! events.onmessage = (e) => console.log(JSON.parse(e.data));
+//! ```
+use actix::Addr;
+use actix_web::{dev::Server, web, App, HttpResponse, HttpServer};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use futures::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::glm::Glm;
+use crate::requestor::payment_manager::{GetPaymentStatus, PaymentManager};
+use crate::requestor::task_state::{StateSnapshot, TaskEvent};
+
+const MAX_LOGS: usize = 100;
+
+/// Event streamed to the `/events` subscribers.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ProgressEvent {
+    Task(TaskEvent),
+    /// A task has been computed by the provider.
+    #[serde(rename_all = "camelCase")]
+    Provider {
+        task_id: usize,
+        node_id: String,
+        name: Option<String>,
+    },
+    Log {
+        message: String,
+        timestamp: DateTime<Utc>,
+    },
+    /// Estimates of `SchedulerMetrics`.
+    #[serde(rename_all = "camelCase")]
+    Progress {
+        percent: f64,
+        eta_secs: Option<f64>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ProviderEntry {
+    node_id: String,
+    name: Option<String>,
+}
+
+#[derive(Default)]
+struct HubState {
+    tasks: StateSnapshot,
+    providers: BTreeMap<usize, ProviderEntry>,
+    logs: VecDeque<ProgressEvent>,
+    percent: f64,
+    eta_secs: Option<f64>,
+    subscribers: Vec<mpsc::UnboundedSender<web::Bytes>>,
+}
+
+/// Keeps the latest progress and fans events out to the subscribers.
+#[derive(Clone, Default)]
+pub(crate) struct ProgressHub {
+    state: Arc<Mutex<HubState>>,
+}
+
+impl ProgressHub {
+    /// Sets the initial task states, e.g. loaded from a state file.
+    pub fn seed(&self, tasks: StateSnapshot) {
+        self.state.lock().unwrap().tasks = tasks;
+    }
+
+    pub fn publish(&self, event: ProgressEvent) {
+        let data = match serde_json::to_string(&event) {
+            Ok(data) => web::Bytes::from(format!("data: {}\n\n", data)),
+            Err(e) => {
+                log::warn!("unable to serialize progress event: {}", e);
+                return;
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        match &event {
+            ProgressEvent::Task(event) => {
+                state.tasks.tasks.insert(event.task_id, event.state);
+            }
+            ProgressEvent::Provider {
+                task_id,
+                node_id,
+                name,
+            } => {
+                let entry = ProviderEntry {
+                    node_id: node_id.clone(),
+                    name: name.clone(),
+                };
+                state.providers.insert(*task_id, entry);
+            }
+            ProgressEvent::Log { .. } => {
+                if state.logs.len() == MAX_LOGS {
+                    state.logs.pop_front();
+                }
+                state.logs.push_back(event.clone());
+            }
+            ProgressEvent::Progress { percent, eta_secs } => {
+                state.percent = *percent;
+                state.eta_secs = *eta_secs;
+            }
+        }
+        state
+            .subscribers
+            .retain(|tx| tx.unbounded_send(data.clone()).is_ok());
+    }
+
+    fn subscribe(&self) -> mpsc::UnboundedReceiver<web::Bytes> {
+        let (tx, rx) = mpsc::unbounded();
+        self.state.lock().unwrap().subscribers.push(tx);
+        rx
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ProgressServer {
+    pub addr: SocketAddr,
+    pub hub: ProgressHub,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Status {
+    tasks: BTreeMap<usize, crate::requestor::TaskState>,
+    providers: BTreeMap<usize, ProviderEntry>,
+    logs: Vec<ProgressEvent>,
+    percent: f64,
+    eta_secs: Option<f64>,
+    allocated: String,
+    paid: String,
+}
+
+struct ServerData {
+    hub: ProgressHub,
+    payment_manager: Addr<PaymentManager>,
+}
+
+async fn status(data: web::Data<ServerData>) -> HttpResponse {
+    let payments = match data.payment_manager.send(GetPaymentStatus).await {
+        Ok(payments) => payments,
+        Err(e) => return HttpResponse::InternalServerError().body(e.to_string()),
+    };
+    let status = {
+        let state = data.hub.state.lock().unwrap();
+        Status {
+            tasks: state.tasks.tasks.clone(),
+            providers: state.providers.clone(),
+            logs: state.logs.iter().cloned().collect(),
+            percent: state.percent,
+            eta_secs: state.eta_secs,
+            allocated: Glm::from(payments.allocated).to_string(),
+            paid: Glm::from(payments.paid).to_string(),
+        }
+    };
+    HttpResponse::Ok().json(status)
+}
+
+async fn events(data: web::Data<ServerData>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(data.hub.subscribe().map(Ok::<_, actix_web::Error>))
+}
+
+/// Starts the HTTP server; it runs until stopped.
+pub(crate) fn serve(
+    server: &ProgressServer,
+    payment_manager: Addr<PaymentManager>,
+) -> Result<Server> {
+    let data = web::Data::new(ServerData {
+        hub: server.hub.clone(),
+        payment_manager,
+    });
+    let http_server = HttpServer::new(move || {
+        App::new()
+            .app_data(data.clone())
+            .route("/status", web::get().to(status))
+            .route("/events", web::get().to(events))
+    })
+    .workers(1)
+    .bind(server.addr)
+    .with_context(|| format!("unable to bind progress server to {}", server.addr))?
+    .run();
+    log::info!("serving progress on http://{}", server.addr);
+    Ok(http_server)
+}
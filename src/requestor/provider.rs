@@ -0,0 +1,219 @@
+use serde_json::Value;
+use ya_client::model::{market::proposal::Proposal, NodeId};
+
+use crate::properties::{self, activity, com, inf, node, runtime};
+use crate::rest::Offer;
+
+/// Properties of the provider a task has been negotiated with.
+///
+/// Passed to task factories (see `Requestor::with_task_factory`), so that
+/// command lists can be tailored to the provider's resources.
+#[derive(Clone, Debug)]
+pub struct ProviderInfo {
+    pub node_id: NodeId,
+    /// Raw offer properties, as sent by the provider.
+    pub properties: Value,
+}
+
+impl ProviderInfo {
+    pub(crate) fn from_proposal(proposal: &Proposal) -> Self {
+        ProviderInfo {
+            node_id: proposal.issuer_id,
+            properties: proposal.properties.clone(),
+        }
+    }
+
+    /// Provider of an offer found with `Market::scan`.
+    pub fn from_offer(offer: &Offer) -> Self {
+        ProviderInfo {
+            node_id: offer.node_id,
+            properties: offer.properties.clone(),
+        }
+    }
+
+    /// Looks up an offer property, e.g. `"golem.inf.cpu.threads"`.
+    ///
+    /// Both flat (`{"golem.inf.cpu.threads": 4}`) and nested
+    /// (`{"golem": {"inf": {"cpu": {"threads": 4}}}}`) layouts are supported.
+    pub fn property(&self, key: &str) -> Option<&Value> {
+        properties::get(&self.properties, key)
+    }
+
+    /// Provider's node name (`golem.node.id.name`).
+    pub fn name(&self) -> Option<&str> {
+        self.property(node::NAME).and_then(Value::as_str)
+    }
+
+    /// Region the provider reports to be in, e.g. `"EU"` (`golem.node.geo.region`).
+    pub fn region(&self) -> Option<&str> {
+        self.property(node::GEO_REGION).and_then(Value::as_str)
+    }
+
+    /// Transfer protocols of the exe-unit, e.g. `["gftp", "http", "https"]`
+    /// (`golem.activity.caps.transfer.protocol`); `None` if not advertised.
+    pub fn transfer_protocols(&self) -> Option<Vec<String>> {
+        let protocols = self
+            .property(activity::CAPS_TRANSFER_PROTOCOL)?
+            .as_array()?
+            .iter()
+            .filter_map(|protocol| protocol.as_str().map(str::to_lowercase))
+            .collect();
+        Some(protocols)
+    }
+
+    /// Payment platforms the provider accepts, e.g. `["erc20-rinkeby-tglm"]`
+    /// (`golem.com.payment.platform.<platform>.address`).
+    pub fn payment_platforms(&self) -> Vec<String> {
+        let prefix = format!("{}.", com::PAYMENT_PLATFORM);
+        let mut platforms: Vec<String> = self
+            .properties
+            .as_object()
+            .map(|properties| {
+                properties
+                    .keys()
+                    .filter_map(|key| key.strip_prefix(&prefix)?.strip_suffix(".address"))
+                    .map(ToString::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let nested = format!("/{}", com::PAYMENT_PLATFORM.replace('.', "/"));
+        if let Some(nested) = self.properties.pointer(&nested).and_then(Value::as_object) {
+            platforms.extend(nested.keys().cloned());
+        }
+        platforms.sort();
+        platforms.dedup();
+        platforms
+    }
+
+    /// Capabilities of the provider's runtime, e.g. `["vpn", "inet"]`
+    /// (`golem.runtime.capabilities`).
+    pub fn runtime_capabilities(&self) -> Vec<String> {
+        self.property(runtime::CAPABILITIES)
+            .and_then(Value::as_array)
+            .map(|capabilities| {
+                capabilities
+                    .iter()
+                    .filter_map(|c| c.as_str().map(ToString::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Number of CPU threads (`golem.inf.cpu.threads`).
+    pub fn cpu_threads(&self) -> Option<u64> {
+        self.property(inf::CPU_THREADS).and_then(Value::as_u64)
+    }
+
+    /// Number of CPU cores (`golem.inf.cpu.cores`).
+    pub fn cpu_cores(&self) -> Option<u64> {
+        self.property(inf::CPU_CORES).and_then(Value::as_u64)
+    }
+
+    /// CPU architecture, e.g. `"x86_64"` (`golem.inf.cpu.architecture`).
+    pub fn cpu_architecture(&self) -> Option<&str> {
+        self.property(inf::CPU_ARCHITECTURE).and_then(Value::as_str)
+    }
+
+    /// CPU brand string (`golem.inf.cpu.brand`).
+    pub fn cpu_brand(&self) -> Option<&str> {
+        self.property(inf::CPU_BRAND).and_then(Value::as_str)
+    }
+
+    /// Memory available in GiB (`golem.inf.mem.gib`).
+    pub fn mem_gib(&self) -> Option<f64> {
+        self.property(inf::MEM_GIB).and_then(Value::as_f64)
+    }
+
+    /// Storage available in GiB (`golem.inf.storage.gib`).
+    pub fn storage_gib(&self) -> Option<f64> {
+        self.property(inf::STORAGE_GIB).and_then(Value::as_f64)
+    }
+
+    /// Version of the provider's runtime (`golem.runtime.version`).
+    pub fn runtime_version(&self) -> Option<&str> {
+        self.property(runtime::VERSION).and_then(Value::as_str)
+    }
+
+    /// Linear pricing coefficients (`golem.com.pricing.model.linear.coeffs`).
+    pub fn linear_coeffs(&self) -> Option<Vec<f64>> {
+        self.property(com::PRICING_LINEAR_COEFFS)
+            .and_then(Value::as_array)
+            .and_then(|coeffs| coeffs.iter().map(Value::as_f64).collect())
+    }
+
+    /// Value of the `{provider.<name>}` placeholder in commands, see
+    /// `CommandList`; `name` is a short name or an offer property.
+    pub(crate) fn placeholder(&self, name: &str) -> Option<String> {
+        match name {
+            "id" => Some(self.node_id.to_string()),
+            "name" => self.name().map(ToString::to_string),
+            "region" => self.region().map(ToString::to_string),
+            "cores" => self.cpu_cores().map(|cores| cores.to_string()),
+            "threads" => self.cpu_threads().map(|threads| threads.to_string()),
+            "mem_gib" => self.mem_gib().map(|gib| gib.to_string()),
+            "storage_gib" => self.storage_gib().map(|gib| gib.to_string()),
+            key => match self.property(key)? {
+                Value::String(value) => Some(value.clone()),
+                Value::Number(value) => Some(value.to_string()),
+                Value::Bool(value) => Some(value.to_string()),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn provider(properties: Value) -> ProviderInfo {
+        ProviderInfo {
+            node_id: "0x0000000000000000000000000000000000000000"
+                .parse()
+                .unwrap(),
+            properties,
+        }
+    }
+
+    #[test]
+    fn test_flat_properties() {
+        let info = provider(json!({
+            "golem.inf.cpu.threads": 4,
+            "golem.inf.mem.gib": 2.5,
+            "golem.node.id.name": "node",
+            "golem.node.geo.region": "EU",
+        }));
+        assert_eq!(info.cpu_threads(), Some(4));
+        assert_eq!(info.mem_gib(), Some(2.5));
+        assert_eq!(info.name(), Some("node"));
+        assert_eq!(info.region(), Some("EU"));
+        assert_eq!(info.storage_gib(), None);
+        assert!(info.payment_platforms().is_empty());
+    }
+
+    #[test]
+    fn test_nested_properties() {
+        let info = provider(json!({
+            "golem": {"inf": {"cpu": {"threads": 8}}, "runtime": {"version": "0.2.4"}}
+        }));
+        assert_eq!(info.cpu_threads(), Some(8));
+        assert_eq!(info.runtime_version(), Some("0.2.4"));
+    }
+
+    #[test]
+    fn test_payment_platforms() {
+        let info = provider(json!({
+            "golem.com.payment.platform.zksync-rinkeby-tglm.address": "0x01",
+            "golem.com.payment.platform.erc20-rinkeby-tglm.address": "0x01",
+        }));
+        assert_eq!(
+            info.payment_platforms(),
+            vec!["erc20-rinkeby-tglm", "zksync-rinkeby-tglm"]
+        );
+        let info = provider(json!({
+            "golem": {"com": {"payment": {"platform": {"erc20-mainnet-glm": {"address": "0x01"}}}}}
+        }));
+        assert_eq!(info.payment_platforms(), vec!["erc20-mainnet-glm"]);
+    }
+}
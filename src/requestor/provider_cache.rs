@@ -0,0 +1,177 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    path::Path,
+    time::{Duration, Instant},
+};
+use ya_client::model::{market::proposal::Proposal, NodeId};
+
+use crate::requestor::provider::ProviderInfo;
+
+/// Provider which successfully completed a task in a previous run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CachedProvider {
+    pub node_id: NodeId,
+    pub name: Option<String>,
+    /// Negotiated linear pricing coefficients.
+    pub coeffs: Option<Vec<f64>>,
+}
+
+impl CachedProvider {
+    fn new(provider: &ProviderInfo) -> Self {
+        CachedProvider {
+            node_id: provider.node_id,
+            name: provider.name().map(ToString::to_string),
+            coeffs: provider.linear_coeffs(),
+        }
+    }
+}
+
+/// Set of providers exported at the end of a run.
+///
+/// Feed it into the next run with `Requestor::with_preferred_providers`
+/// to negotiate with the known-good providers first.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProviderCache {
+    pub created: DateTime<Utc>,
+    pub providers: Vec<CachedProvider>,
+    /// Providers whose results disagreed with the majority, see
+    /// `Requestor::with_verification`; they are not dispatched tasks to.
+    #[serde(default)]
+    pub flagged: Vec<CachedProvider>,
+}
+
+impl Default for ProviderCache {
+    fn default() -> Self {
+        ProviderCache {
+            created: Utc::now(),
+            providers: vec![],
+            flagged: vec![],
+        }
+    }
+}
+
+impl ProviderCache {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("unable to read provider cache {}", path.display()))?;
+        Ok(serde_json::from_slice(&contents)
+            .with_context(|| format!("invalid provider cache {}", path.display()))?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("unable to write provider cache {}", path.display()))
+    }
+
+    /// Whether the cache has been created no longer than `max_age` ago.
+    pub fn is_fresh(&self, max_age: Duration) -> bool {
+        match chrono::Duration::from_std(max_age) {
+            Ok(max_age) => self.created + max_age >= Utc::now(),
+            Err(_) => true,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, provider: &ProviderInfo) {
+        if self.is_flagged(&provider.node_id)
+            || self.providers.iter().any(|p| p.node_id == provider.node_id)
+        {
+            return;
+        }
+        self.providers.push(CachedProvider::new(provider));
+    }
+
+    pub fn is_flagged(&self, node_id: &NodeId) -> bool {
+        self.flagged.iter().any(|p| &p.node_id == node_id)
+    }
+
+    /// Moves the provider to the `flagged` ones.
+    pub(crate) fn flag(&mut self, provider: &ProviderInfo) {
+        self.providers.retain(|p| p.node_id != provider.node_id);
+        if !self.is_flagged(&provider.node_id) {
+            self.flagged.push(CachedProvider::new(provider));
+        }
+    }
+}
+
+/// Restricts negotiations to the preferred providers for a limited time.
+///
+/// Proposals of the other providers are postponed until the window closes.
+#[derive(Clone)]
+pub(crate) struct PreferredProviders {
+    providers: HashSet<NodeId>,
+    until: Instant,
+    postponed: Vec<Proposal>,
+}
+
+impl PreferredProviders {
+    pub fn new(cache: &ProviderCache, window: Duration) -> Self {
+        PreferredProviders {
+            providers: cache.providers.iter().map(|p| p.node_id).collect(),
+            until: Instant::now() + window,
+            postponed: vec![],
+        }
+    }
+
+    pub fn allows(&self, node_id: &NodeId) -> bool {
+        self.providers.is_empty() || Instant::now() > self.until || self.providers.contains(node_id)
+    }
+
+    /// Keeps the proposal of a provider not allowed yet.
+    pub fn postpone(&mut self, proposal: Proposal) {
+        self.postponed.push(proposal);
+    }
+
+    /// Postponed proposals, once the window has closed.
+    pub fn release(&mut self) -> Vec<Proposal> {
+        match Instant::now() > self.until {
+            true => std::mem::take(&mut self.postponed),
+            false => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proposal(node_id: &NodeId) -> Proposal {
+        serde_json::from_value(serde_json::json!({
+            "properties": {},
+            "constraints": "",
+            "proposalId": "proposal",
+            "issuerId": node_id,
+            "state": "Initial",
+            "timestamp": Utc::now(),
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_postponed_proposals_released() {
+        let node_id = |i: u64| -> NodeId { format!("0x{:040x}", i).parse().unwrap() };
+        let cache = ProviderCache {
+            providers: vec![CachedProvider {
+                node_id: node_id(1),
+                name: None,
+                coeffs: None,
+            }],
+            ..ProviderCache::default()
+        };
+        let mut preferred = PreferredProviders::new(&cache, Duration::from_secs(60));
+        assert!(preferred.allows(&node_id(1)));
+        assert!(!preferred.allows(&node_id(2)));
+        preferred.postpone(proposal(&node_id(2)));
+        assert!(preferred.release().is_empty());
+
+        // No preferred provider showed up within the window.
+        preferred.until = Instant::now() - Duration::from_secs(1);
+        assert!(preferred.allows(&node_id(2)));
+        let released = preferred.release();
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].issuer_id, node_id(2));
+        assert!(preferred.release().is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+//! Helpers for rendering frame ranges, enabled with the `render` feature.
+//!
+//! ## Example:
+//! ```no_run
+//! use yarapi::requestor::render::RenderJob;
+//!
+//! let job = RenderJob::new("scene.blend", 1..=100, "output/frame_####.png")
+//!     .with_frames_per_task(10);
+//! let tasks = job.tasks();
+//! ```
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+use crate::requestor::command::{Command, CommandList};
+
+const REMOTE_SCENE_DIR: &str = "/golem/resources";
+const REMOTE_OUTPUT_DIR: &str = "/golem/output";
+/// Name of the rendered frames on the provider, as given to Blender's `-o`;
+/// Blender replaces the `#`s with the frame number and adds the extension.
+const REMOTE_OUTPUT_PATTERN: &str = "frame_####";
+
+/// Splits rendering of a frame range into tasks.
+///
+/// The local output path is built from `output_pattern`, in which a run of
+/// `#` characters is replaced with the zero-padded frame number (as in
+/// Blender), e.g. `frame_####.png` becomes `frame_0007.png`.
+#[derive(Clone, Debug)]
+pub struct RenderJob {
+    scene: PathBuf,
+    frames: RangeInclusive<u32>,
+    step: u32,
+    frames_per_task: usize,
+    output_pattern: String,
+    command: Vec<String>,
+}
+
+impl RenderJob {
+    pub fn new(
+        scene: impl Into<PathBuf>,
+        frames: RangeInclusive<u32>,
+        output_pattern: impl Into<String>,
+    ) -> Self {
+        RenderJob {
+            scene: scene.into(),
+            frames,
+            step: 1,
+            frames_per_task: 1,
+            output_pattern: output_pattern.into(),
+            command: vec![
+                "blender".into(),
+                "-b".into(),
+                "{scene}".into(),
+                "-o".into(),
+                "{output}".into(),
+                "-F".into(),
+                "PNG".into(),
+                "-f".into(),
+                "{frame}".into(),
+            ],
+        }
+    }
+
+    /// Renders every `step`-th frame of the range.
+    pub fn with_step(self, step: u32) -> Self {
+        Self {
+            step: step.max(1),
+            ..self
+        }
+    }
+
+    /// Number of frames rendered by a single task.
+    pub fn with_frames_per_task(self, frames_per_task: usize) -> Self {
+        Self {
+            frames_per_task: frames_per_task.max(1),
+            ..self
+        }
+    }
+
+    /// Sets the command rendering a single frame.
+    ///
+    /// `{scene}`, `{frame}` and `{output}` are replaced with the remote scene
+    /// path, the frame number and the remote output pattern respectively.
+    /// As with Blender's `-o`, the frame is expected at the output pattern
+    /// with its `#`s replaced by the padded frame number and with the
+    /// extension of the local output pattern (`.png` by default).
+    /// By default Blender is run in background mode.
+    pub fn with_command(self, command: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            command: command.into_iter().map(Into::into).collect(),
+            ..self
+        }
+    }
+
+    /// Numbers of frames to render.
+    pub fn frames(&self) -> Vec<u32> {
+        self.frames.clone().step_by(self.step as usize).collect()
+    }
+
+    /// Local path of the rendered frame.
+    pub fn output_path(&self, frame: u32) -> PathBuf {
+        PathBuf::from(expand_pattern(&self.output_pattern, frame))
+    }
+
+    /// Path Blender renders `frame` to on the provider.
+    fn remote_output(&self, frame: u32) -> String {
+        let extension = Path::new(&self.output_pattern)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_string())
+            .unwrap_or_else(|| "png".to_string());
+        format!(
+            "{}/{}.{}",
+            REMOTE_OUTPUT_DIR,
+            expand_pattern(REMOTE_OUTPUT_PATTERN, frame),
+            extension
+        )
+    }
+
+    /// Local paths of all the rendered frames, in frame order.
+    pub fn outputs(&self) -> Vec<PathBuf> {
+        self.frames()
+            .into_iter()
+            .map(|frame| self.output_path(frame))
+            .collect()
+    }
+
+    /// Builds a `CommandList` for every chunk of frames.
+    pub fn tasks(&self) -> Vec<CommandList> {
+        let scene = format!("{}/{}", REMOTE_SCENE_DIR, file_name(&self.scene));
+        self.frames()
+            .chunks(self.frames_per_task)
+            .map(|chunk| {
+                let mut commands = vec![Command::Upload {
+                    from: self.scene.clone(),
+                    to: scene.clone(),
+                }];
+                let pattern = format!("{}/{}", REMOTE_OUTPUT_DIR, REMOTE_OUTPUT_PATTERN);
+                for frame in chunk {
+                    commands.push(Command::Run(
+                        self.command
+                            .iter()
+                            .map(|arg| {
+                                arg.replace("{scene}", &scene)
+                                    .replace("{frame}", &frame.to_string())
+                                    .replace("{output}", &pattern)
+                            })
+                            .collect(),
+                    ));
+                    commands.push(Command::Download {
+                        from: self.remote_output(*frame),
+                        to: self.output_path(*frame),
+                    });
+                }
+                CommandList::new(commands)
+            })
+            .collect()
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Replaces the last run of `#` with the zero-padded frame number.
+/// Appends the frame number if there is no `#` in the pattern.
+fn expand_pattern(pattern: &str, frame: u32) -> String {
+    match pattern.rfind('#') {
+        Some(end) => {
+            let start = pattern[..end].trim_end_matches('#').len();
+            let width = end + 1 - start;
+            format!(
+                "{}{:0width$}{}",
+                &pattern[..start],
+                frame,
+                &pattern[end + 1..],
+                width = width
+            )
+        }
+        None => format!("{}{}", pattern, frame),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_pattern() {
+        assert_eq!(
+            expand_pattern("out/frame_####.png", 7),
+            "out/frame_0007.png"
+        );
+        assert_eq!(expand_pattern("frame_#.png", 123), "frame_123.png");
+        assert_eq!(expand_pattern("frame_", 5), "frame_5");
+    }
+
+    #[test]
+    fn test_chunks() {
+        let job = RenderJob::new("scene.blend", 1..=10, "frame_##.png")
+            .with_step(2)
+            .with_frames_per_task(2);
+
+        assert_eq!(job.frames(), vec![1, 3, 5, 7, 9]);
+        assert_eq!(job.outputs()[4], PathBuf::from("frame_09.png"));
+        assert_eq!(job.tasks().len(), 3);
+    }
+
+    #[test]
+    fn test_download_matches_blender_output() {
+        let job = RenderJob::new("scene.blend", 7..=7, "out/frame_####.png");
+        let tasks = job.tasks();
+        let commands: Vec<&Command> = tasks[0].iter().collect();
+        let (argv, from, to) = match commands.as_slice() {
+            [Command::Upload { .. }, Command::Run(argv), Command::Download { from, to }] => {
+                (argv, from, to)
+            }
+            commands => panic!("unexpected commands: {:?}", commands),
+        };
+        let output = &argv[argv.iter().position(|arg| arg == "-o").unwrap() + 1];
+        assert_eq!(output, "/golem/output/frame_####");
+        // Blender fills in the frame number and adds the extension of `-F PNG`.
+        assert_eq!(from, &format!("{}.png", expand_pattern(output, 7)));
+        assert_eq!(from, "/golem/output/frame_0007.png");
+        assert_eq!(to, &PathBuf::from("out/frame_0007.png"));
+    }
+}
@@ -0,0 +1,170 @@
+use ya_client::model::NodeId;
+
+use crate::properties::{inf, node, runtime};
+use crate::requestor::provider::ProviderInfo;
+
+/// Minimal capabilities of providers, set with `Requestor::with_min_cpu_threads` etc.
+///
+/// Numeric thresholds and exact matches are sent to the market as constraints.
+/// As offers are not guaranteed to honor them (e.g. a property may be missing
+/// in the offer), proposals are also checked before an agreement is made,
+/// together with the requirements which can't be expressed as LDAP filters.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Requirements {
+    pub min_cpu_threads: Option<u64>,
+    pub min_cpu_cores: Option<u64>,
+    pub cpu_architecture: Option<String>,
+    /// Case-insensitive substring of `golem.inf.cpu.brand`.
+    pub cpu_brand: Option<String>,
+    /// The only provider to negotiate with.
+    pub node_id: Option<NodeId>,
+    /// Accepted values of `golem.node.geo.region`; empty accepts any region.
+    pub regions: Vec<String>,
+    /// Required entries of `golem.runtime.capabilities`, e.g. `"vpn"`.
+    pub capabilities: Vec<String>,
+}
+
+impl Requirements {
+    /// LDAP filters for the requirements which can be checked by the market.
+    pub fn constraints(&self) -> Vec<String> {
+        let mut constraints = vec![];
+        if let Some(threads) = self.min_cpu_threads {
+            constraints.push(format!("({}>={})", inf::CPU_THREADS, threads));
+        }
+        if let Some(cores) = self.min_cpu_cores {
+            constraints.push(format!("({}>={})", inf::CPU_CORES, cores));
+        }
+        if let Some(architecture) = &self.cpu_architecture {
+            constraints.push(format!("({}={})", inf::CPU_ARCHITECTURE, architecture));
+        }
+        match self.regions.as_slice() {
+            [] => (),
+            [region] => constraints.push(format!("({}={})", node::GEO_REGION, region)),
+            regions => {
+                let alternatives: String = regions
+                    .iter()
+                    .map(|region| format!("({}={})", node::GEO_REGION, region))
+                    .collect();
+                constraints.push(format!("(|{})", alternatives));
+            }
+        }
+        for capability in &self.capabilities {
+            constraints.push(format!("({}={})", runtime::CAPABILITIES, capability));
+        }
+        constraints
+    }
+
+    /// Checks whether proposals from the node should be considered at all.
+    pub fn allows_node(&self, node_id: &NodeId) -> bool {
+        self.node_id
+            .as_ref()
+            .map(|id| id == node_id)
+            .unwrap_or(true)
+    }
+
+    /// Checks the provider's offer; returns the name of the first unmet requirement.
+    pub fn check(&self, provider: &ProviderInfo) -> Result<(), &'static str> {
+        if !self.allows_node(&provider.node_id) {
+            return Err("provider");
+        }
+        if let Some(threads) = self.min_cpu_threads {
+            if provider.cpu_threads().unwrap_or(0) < threads {
+                return Err("cpu threads");
+            }
+        }
+        if let Some(cores) = self.min_cpu_cores {
+            if provider.cpu_cores().unwrap_or(0) < cores {
+                return Err("cpu cores");
+            }
+        }
+        if let Some(architecture) = &self.cpu_architecture {
+            if provider.cpu_architecture() != Some(architecture.as_str()) {
+                return Err("cpu architecture");
+            }
+        }
+        if let Some(brand) = &self.cpu_brand {
+            let matches = provider
+                .cpu_brand()
+                .map(|b| b.to_lowercase().contains(&brand.to_lowercase()))
+                .unwrap_or(false);
+            if !matches {
+                return Err("cpu brand");
+            }
+        }
+        if !self.regions.is_empty() {
+            let matches = provider
+                .region()
+                .map(|r| {
+                    self.regions
+                        .iter()
+                        .any(|region| region.eq_ignore_ascii_case(r))
+                })
+                .unwrap_or(false);
+            if !matches {
+                return Err("region");
+            }
+        }
+        if !self.capabilities.is_empty() {
+            let offered = provider.runtime_capabilities();
+            if !self.capabilities.iter().all(|c| offered.contains(c)) {
+                return Err("runtime capabilities");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_check() {
+        let provider = ProviderInfo {
+            node_id: "0x0000000000000000000000000000000000000000"
+                .parse()
+                .unwrap(),
+            properties: json!({
+                "golem.inf.cpu.threads": 8,
+                "golem.inf.cpu.architecture": "x86_64",
+                "golem.inf.cpu.brand": "Intel(R) Core(TM) i7-8550U CPU @ 1.80GHz",
+            }),
+        };
+        let requirements = Requirements {
+            min_cpu_threads: Some(4),
+            cpu_architecture: Some("x86_64".into()),
+            cpu_brand: Some("intel".into()),
+            ..Default::default()
+        };
+        assert_eq!(requirements.check(&provider), Ok(()));
+        assert_eq!(requirements.constraints().len(), 2);
+
+        let requirements = Requirements {
+            min_cpu_cores: Some(4),
+            ..requirements
+        };
+        assert_eq!(requirements.check(&provider), Err("cpu cores"));
+
+        let requirements = Requirements {
+            regions: vec!["EU".into(), "NA".into()],
+            ..Default::default()
+        };
+        assert_eq!(requirements.check(&provider), Err("region"));
+        assert_eq!(
+            requirements.constraints(),
+            vec!["(|(golem.node.geo.region=EU)(golem.node.geo.region=NA))"]
+        );
+
+        let requirements = Requirements {
+            capabilities: vec!["vpn".into()],
+            ..Default::default()
+        };
+        assert_eq!(requirements.check(&provider), Err("runtime capabilities"));
+        let provider = ProviderInfo {
+            properties: json!({ "golem.runtime.capabilities": ["inet", "vpn"] }),
+            ..provider
+        };
+        assert_eq!(requirements.check(&provider), Ok(()));
+    }
+}
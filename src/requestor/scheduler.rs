@@ -0,0 +1,243 @@
+use futures::channel::oneshot;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Shares the daemon between several `Requestor`s run in one process.
+///
+/// Limits how many agreements are negotiated and how many activities run
+/// at a time across all the requestors using the scheduler. A free slot goes
+/// to the waiting requestor using the fewest slots relative to its priority,
+/// so a requestor with priority 2 gets about twice the slots of one with
+/// priority 1 while both have work.
+///
+/// ## Example:
+/// ```no_run
+/// # fn run(render: yarapi::requestor::Requestor, stats: yarapi::requestor::Requestor) {
+/// use yarapi::requestor::SharedScheduler;
+///
+/// let scheduler = SharedScheduler::new(4, 16);
+/// let render = render.with_scheduler(&scheduler, 2);
+/// let stats = stats.with_scheduler(&scheduler, 1);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SharedScheduler {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SlotKind {
+    Negotiation,
+    Dispatch,
+}
+
+struct State {
+    /// Priorities of the registered requestors.
+    engines: HashMap<usize, u32>,
+    next_engine: usize,
+    negotiation: Pool,
+    dispatch: Pool,
+}
+
+impl State {
+    fn pool(&mut self, kind: SlotKind) -> &mut Pool {
+        match kind {
+            SlotKind::Negotiation => &mut self.negotiation,
+            SlotKind::Dispatch => &mut self.dispatch,
+        }
+    }
+
+    /// Hands free slots over to the waiting requestors.
+    fn grant(&mut self, kind: SlotKind) {
+        let engines = self.engines.clone();
+        let pool = self.pool(kind);
+        while pool.used() < pool.capacity {
+            let index = match pick(&pool.waiters, &pool.in_use, &engines) {
+                Some(index) => index,
+                None => break,
+            };
+            let waiter = match pool.waiters.remove(index) {
+                Some(waiter) => waiter,
+                None => break,
+            };
+            // A waiter which gave up has dropped its receiver.
+            if waiter.tx.send(()).is_ok() {
+                *pool.in_use.entry(waiter.engine).or_default() += 1;
+            }
+        }
+    }
+}
+
+struct Pool {
+    capacity: usize,
+    /// Slots in use by requestor.
+    in_use: HashMap<usize, usize>,
+    waiters: VecDeque<Waiter>,
+}
+
+impl Pool {
+    fn new(capacity: usize) -> Self {
+        Pool {
+            capacity: capacity.max(1),
+            in_use: HashMap::new(),
+            waiters: VecDeque::new(),
+        }
+    }
+
+    fn used(&self) -> usize {
+        self.in_use.values().sum()
+    }
+}
+
+struct Waiter {
+    engine: usize,
+    tx: oneshot::Sender<()>,
+}
+
+/// The waiter whose requestor uses the fewest slots per unit of priority;
+/// the earliest one on ties.
+fn pick(
+    waiters: &VecDeque<Waiter>,
+    in_use: &HashMap<usize, usize>,
+    priorities: &HashMap<usize, u32>,
+) -> Option<usize> {
+    let share = |engine: &usize| {
+        let used = in_use.get(engine).copied().unwrap_or_default() as f64;
+        let priority = priorities.get(engine).copied().unwrap_or(1).max(1) as f64;
+        used / priority
+    };
+    waiters
+        .iter()
+        .enumerate()
+        .fold(None, |best: Option<(usize, f64)>, (index, waiter)| {
+            let share = share(&waiter.engine);
+            match best {
+                Some((_, best_share)) if best_share <= share => best,
+                _ => Some((index, share)),
+            }
+        })
+        .map(|(index, _)| index)
+}
+
+impl SharedScheduler {
+    /// Allows `negotiation_slots` agreements negotiated and `dispatch_slots`
+    /// activities at a time.
+    pub fn new(negotiation_slots: usize, dispatch_slots: usize) -> Self {
+        SharedScheduler {
+            state: Arc::new(Mutex::new(State {
+                engines: HashMap::new(),
+                next_engine: 0,
+                negotiation: Pool::new(negotiation_slots),
+                dispatch: Pool::new(dispatch_slots),
+            })),
+        }
+    }
+
+    pub(crate) fn register(&self, priority: u32) -> SchedulerEngine {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_engine;
+        state.next_engine += 1;
+        state.engines.insert(id, priority.max(1));
+        SchedulerEngine {
+            scheduler: self.clone(),
+            id,
+        }
+    }
+}
+
+/// A requestor registered with a `SharedScheduler`.
+#[derive(Clone)]
+pub(crate) struct SchedulerEngine {
+    scheduler: SharedScheduler,
+    id: usize,
+}
+
+impl SchedulerEngine {
+    /// Waits for a slot; it is released when the returned `Slot` is dropped.
+    pub async fn acquire(&self, kind: SlotKind) -> Slot {
+        let rx = {
+            let mut state = self.scheduler.state.lock().unwrap();
+            let (tx, rx) = oneshot::channel();
+            state.pool(kind).waiters.push_back(Waiter {
+                engine: self.id,
+                tx,
+            });
+            state.grant(kind);
+            rx
+        };
+        // Created before waiting, so that a slot granted to a cancelled
+        // waiter is released too.
+        let mut slot = Slot {
+            engine: self.clone(),
+            kind,
+            pending: Some(rx),
+        };
+        if let Some(rx) = &mut slot.pending {
+            let _ = rx.await;
+        }
+        slot.pending = None;
+        slot
+    }
+}
+
+pub(crate) struct Slot {
+    engine: SchedulerEngine,
+    kind: SlotKind,
+    /// Set until the slot is granted.
+    pending: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.pending.take() {
+            if rx.try_recv() != Ok(Some(())) {
+                return;
+            }
+        }
+        let mut state = match self.engine.scheduler.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        let pool = state.pool(self.kind);
+        if let Some(used) = pool.in_use.get_mut(&self.engine.id) {
+            *used = used.saturating_sub(1);
+        }
+        state.grant(self.kind);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick() {
+        let waiters: VecDeque<Waiter> = vec![0, 0, 1]
+            .into_iter()
+            .map(|engine| Waiter {
+                engine,
+                tx: oneshot::channel().0,
+            })
+            .collect();
+        let priorities = vec![(0, 2), (1, 1)].into_iter().collect();
+
+        // Engine 0 uses 2 slots at priority 2, engine 1 uses 2 at priority 1.
+        let in_use = vec![(0, 2), (1, 2)].into_iter().collect();
+        assert_eq!(pick(&waiters, &in_use, &priorities), Some(0));
+
+        let in_use = vec![(0, 4), (1, 1)].into_iter().collect();
+        assert_eq!(pick(&waiters, &in_use, &priorities), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let scheduler = SharedScheduler::new(1, 1);
+        let engine = scheduler.register(1);
+        let slot = engine.acquire(SlotKind::Dispatch).await;
+        let waiting = engine.acquire(SlotKind::Dispatch);
+        futures::pin_mut!(waiting);
+        assert!(futures::poll!(&mut waiting).is_pending());
+        drop(slot);
+        let _slot = waiting.await;
+    }
+}
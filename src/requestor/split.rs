@@ -0,0 +1,271 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::requestor::command::{Command, CommandList};
+
+const REMOTE_INPUT_DIR: &str = "/golem/work";
+const REMOTE_OUTPUT_DIR: &str = "/golem/output";
+
+static SPLIT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Splits `file` into parts of about `chunk_size` bytes.
+///
+/// Parts are cut at line boundaries, so a part may be longer than `chunk_size`
+/// by up to one line. Returns paths of the parts, written to a new temporary
+/// directory, which the caller removes when done. An empty file has no parts
+/// and leaves no directory behind.
+pub fn split_input(file: &Path, chunk_size: usize) -> Result<Vec<PathBuf>> {
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "input".to_string());
+    let dir = create_split_dir(&name)?;
+    let parts = write_parts(file, chunk_size, &dir, &name);
+    if !matches!(&parts, Ok(parts) if !parts.is_empty()) {
+        if let Err(e) = fs::remove_dir_all(&dir) {
+            log::warn!("unable to remove {}: {}", dir.display(), e);
+        }
+    }
+    parts
+}
+
+fn write_parts(file: &Path, chunk_size: usize, dir: &Path, name: &str) -> Result<Vec<PathBuf>> {
+    let mut reader = BufReader::new(
+        File::open(file).with_context(|| format!("unable to open {}", file.display()))?,
+    );
+    let mut parts = vec![];
+    let mut line = vec![];
+    loop {
+        let path = dir.join(format!("{}.part-{:04}", name, parts.len()));
+        let mut writer = BufWriter::new(File::create(&path)?);
+        let mut written = 0;
+        while written < chunk_size.max(1) {
+            line.clear();
+            match reader.read_until(b'\n', &mut line)? {
+                0 => break,
+                n => {
+                    writer.write_all(&line)?;
+                    written += n;
+                }
+            }
+        }
+        writer.flush()?;
+
+        if written == 0 {
+            drop(writer);
+            fs::remove_file(&path)?;
+            break;
+        }
+        parts.push(path);
+    }
+    Ok(parts)
+}
+
+/// Creates a directory for the parts, not used by any other call.
+fn create_split_dir(name: &str) -> Result<PathBuf> {
+    loop {
+        let dir = std::env::temp_dir().join(format!(
+            "yarapi-split-{}-{}-{}",
+            name,
+            std::process::id(),
+            SPLIT_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            // Left over by an earlier process with the same pid.
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("unable to create {}", dir.display())),
+        }
+    }
+}
+
+/// Concatenates `parts` into `output`, in order.
+pub fn join_outputs(parts: &[PathBuf], output: &Path) -> Result<()> {
+    let mut writer = BufWriter::new(
+        File::create(output).with_context(|| format!("unable to create {}", output.display()))?,
+    );
+    for part in parts {
+        let mut reader =
+            File::open(part).with_context(|| format!("unable to open {}", part.display()))?;
+        let mut buf = vec![];
+        reader.read_to_end(&mut buf)?;
+        writer.write_all(&buf)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Runs the same command on every part of a split input file.
+///
+/// The parts and the results are kept in a temporary directory, removed
+/// when the job is dropped.
+///
+/// ## Example:
+/// ```no_run
+/// use yarapi::requestor::SplitJob;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let job = SplitJob::new("data.csv".as_ref(), 1 << 20, vec!["/bin/sort", "{input}", "-o", "{output}"])?;
+/// let tasks = job.tasks();
+/// // ... run the tasks with a `Requestor` ...
+/// job.join("sorted.csv".as_ref())?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SplitJob {
+    dir: Option<PathBuf>,
+    parts: Vec<PathBuf>,
+    outputs: Vec<PathBuf>,
+    command: Vec<String>,
+}
+
+impl SplitJob {
+    /// Splits `input` (see `split_input`). In `command`, `{input}` and `{output}`
+    /// are replaced with the remote paths of a part and of its result.
+    pub fn new(
+        input: &Path,
+        chunk_size: usize,
+        command: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Result<Self> {
+        let parts = split_input(input, chunk_size)?;
+        let outputs = parts
+            .iter()
+            .map(|part| part.with_extension(format!("{}.out", extension(part))))
+            .collect();
+        Ok(SplitJob {
+            dir: parts
+                .first()
+                .and_then(|part| part.parent())
+                .map(Path::to_path_buf),
+            parts,
+            outputs,
+            command: command.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    pub fn parts(&self) -> &[PathBuf] {
+        &self.parts
+    }
+
+    /// Local paths of the results, one per part.
+    pub fn outputs(&self) -> &[PathBuf] {
+        &self.outputs
+    }
+
+    /// Builds a `CommandList` for every part.
+    pub fn tasks(&self) -> Vec<CommandList> {
+        self.parts
+            .iter()
+            .zip(self.outputs.iter())
+            .enumerate()
+            .map(|(i, (part, output))| {
+                let remote_input = format!("{}/input-{}", REMOTE_INPUT_DIR, i);
+                let remote_output = format!("{}/output-{}", REMOTE_OUTPUT_DIR, i);
+                CommandList::new(vec![
+                    Command::Upload {
+                        from: part.clone(),
+                        to: remote_input.clone(),
+                    },
+                    Command::Run(
+                        self.command
+                            .iter()
+                            .map(|arg| {
+                                arg.replace("{input}", &remote_input)
+                                    .replace("{output}", &remote_output)
+                            })
+                            .collect(),
+                    ),
+                    Command::Download {
+                        from: remote_output,
+                        to: output.clone(),
+                    },
+                ])
+            })
+            .collect()
+    }
+
+    /// Concatenates the results into `output`.
+    pub fn join(&self, output: &Path) -> Result<()> {
+        join_outputs(&self.outputs, output)
+    }
+
+    /// Merges the results with a user-provided function.
+    pub fn merge<T>(&self, merge: impl FnOnce(&[PathBuf]) -> Result<T>) -> Result<T> {
+        merge(&self.outputs)
+    }
+}
+
+impl Drop for SplitJob {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.dir {
+            if let Err(e) = fs::remove_dir_all(dir) {
+                log::warn!("unable to remove {}: {}", dir.display(), e);
+            }
+        }
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_join() {
+        let dir = std::env::temp_dir().join(format!("yarapi-split-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("input.txt");
+        let contents = (0..100)
+            .map(|i| format!("line {}\n", i))
+            .collect::<String>();
+        fs::write(&input, &contents).unwrap();
+
+        let parts = split_input(&input, 64).unwrap();
+        assert!(parts.len() > 1);
+        let other = split_input(&input, 64).unwrap();
+        assert_ne!(parts[0].parent(), other[0].parent());
+        for part in &parts {
+            assert!(fs::read_to_string(part).unwrap().ends_with('\n'));
+        }
+
+        let output = dir.join("output.txt");
+        join_outputs(&parts, &output).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), contents);
+
+        let job = SplitJob::new(&input, 64, vec!["/bin/cat", "{input}"]).unwrap();
+        let job_dir = job.parts()[0].parent().unwrap().to_path_buf();
+        assert!(job_dir.exists());
+        drop(job);
+        assert!(!job_dir.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(parts[0].parent().unwrap());
+        let _ = fs::remove_dir_all(other[0].parent().unwrap());
+    }
+
+    #[test]
+    fn test_split_empty() {
+        let name = format!("yarapi-split-empty-{}.txt", std::process::id());
+        let input = std::env::temp_dir().join(&name);
+        fs::write(&input, "").unwrap();
+
+        let job = SplitJob::new(&input, 64, vec!["/bin/cat", "{input}"]).unwrap();
+        assert!(job.tasks().is_empty());
+        let prefix = format!("yarapi-split-{}-", name);
+        let leaked = fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_name().to_string_lossy().starts_with(&prefix));
+        assert!(!leaked);
+
+        fs::remove_file(&input).unwrap();
+    }
+}
@@ -0,0 +1,175 @@
+use anyhow::Result;
+use bigdecimal::BigDecimal;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::glm::Glm;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskStatus {
+    Done,
+    Failed,
+    /// Queued or running when the run was aborted, see `RequestorHandle::abort`.
+    Aborted,
+}
+
+/// Outcome of a single task.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSummary {
+    pub task_id: usize,
+    pub status: TaskStatus,
+    pub provider_id: Option<String>,
+    pub provider_name: Option<String>,
+    /// Region reported by the provider.
+    pub region: Option<String>,
+    pub agreement_id: Option<String>,
+    /// Time from taking the task to its completion.
+    pub duration_secs: Option<f64>,
+    /// Amount paid for the agreement, in GLM.
+    pub cost: Option<String>,
+    pub error: Option<String>,
+    /// Local paths of the downloaded files.
+    pub outputs: Vec<PathBuf>,
+}
+
+impl TaskSummary {
+    pub(crate) fn failed(task_id: usize, error: String) -> Self {
+        TaskSummary {
+            task_id,
+            status: TaskStatus::Failed,
+            provider_id: None,
+            provider_name: None,
+            region: None,
+            agreement_id: None,
+            duration_secs: None,
+            cost: None,
+            error: Some(error),
+            outputs: vec![],
+        }
+    }
+    pub(crate) fn aborted(task_id: usize) -> Self {
+        TaskSummary {
+            status: TaskStatus::Aborted,
+            error: None,
+            ..Self::failed(task_id, String::new())
+        }
+    }
+}
+
+/// Per-task outcomes of a run, for spreadsheets or data pipelines.
+///
+/// Like `NegotiationReport`, it is a handle: take it before `Requestor::run`
+/// and export it afterwards. Costs are filled in once the invoices have
+/// been accepted, at the end of the run.
+///
+/// ## Example:
+/// ```no_run
+/// # async fn run(requestor: yarapi::requestor::Requestor) -> anyhow::Result<()> {
+/// let summary = requestor.run_summary();
+/// requestor.run().await?;
+/// std::fs::write("summary.csv", summary.to_csv())?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct RunSummary {
+    tasks: Arc<Mutex<BTreeMap<usize, TaskSummary>>>,
+}
+
+impl RunSummary {
+    /// Summaries of the finished tasks, by task id.
+    pub fn tasks(&self) -> Vec<TaskSummary> {
+        self.tasks.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(&self.tasks())?)
+    }
+
+    /// One row per task; output paths are separated with `;`.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "task_id,status,provider_id,provider_name,region,agreement_id,duration_secs,cost,error,outputs\n",
+        );
+        for task in self.tasks() {
+            let outputs = task
+                .outputs
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(";");
+            let fields = [
+                task.task_id.to_string(),
+                format!("{:?}", task.status),
+                task.provider_id.unwrap_or_default(),
+                task.provider_name.unwrap_or_default(),
+                task.region.unwrap_or_default(),
+                task.agreement_id.unwrap_or_default(),
+                task.duration_secs
+                    .map(|secs| format!("{:.3}", secs))
+                    .unwrap_or_default(),
+                task.cost.unwrap_or_default(),
+                task.error.unwrap_or_default(),
+                outputs,
+            ];
+            let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            csv.push_str(&row.join(","));
+            csv.push('\n');
+        }
+        csv
+    }
+
+    pub(crate) fn record(&self, summary: TaskSummary) {
+        self.tasks.lock().unwrap().insert(summary.task_id, summary);
+    }
+
+    /// Sets task costs from the amounts paid by agreement.
+    pub(crate) fn set_costs(&self, paid: &HashMap<String, BigDecimal>) {
+        for task in self.tasks.lock().unwrap().values_mut() {
+            let amount = task.agreement_id.as_ref().and_then(|id| paid.get(id));
+            if let Some(amount) = amount {
+                task.cost = Some(Glm::from(amount.clone()).to_string());
+            }
+        }
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    match field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_csv() {
+        let summary = RunSummary::default();
+        summary.record(TaskSummary::failed(1, "exit code 1, \"boom\"".to_string()));
+        summary.record(TaskSummary {
+            status: TaskStatus::Done,
+            region: Some("EU".to_string()),
+            agreement_id: Some("a0".to_string()),
+            duration_secs: Some(1.5),
+            outputs: vec!["out/0.txt".into(), "out/1.txt".into()],
+            error: None,
+            ..TaskSummary::failed(0, String::new())
+        });
+        let mut paid = HashMap::new();
+        paid.insert("a0".to_string(), BigDecimal::from(2));
+        summary.set_costs(&paid);
+
+        let csv = summary.to_csv();
+        let rows: Vec<&str> = csv.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[1], "0,Done,,,EU,a0,1.500,2,,out/0.txt;out/1.txt");
+        assert_eq!(rows[2], "1,Failed,,,,,,,\"exit code 1, \"\"boom\"\"\",");
+    }
+}
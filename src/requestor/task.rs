@@ -0,0 +1,144 @@
+use crate::requestor::{command::CommandList, provider::ProviderInfo};
+use std::{sync::Arc, time::Instant};
+use ya_client::model::NodeId;
+
+/// Tells the `Requestor` how to proceed after a task has failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Put the task back in the queue; it will be dispatched to the next provider.
+    Retry,
+    /// Drop the task and carry on with the remaining ones.
+    Skip,
+    /// Stop the whole computation.
+    Abort,
+}
+
+/// Identifies an attempt to compute a task, passed to the `Requestor`
+/// callbacks, e.g. to correlate results with payments and logs.
+#[derive(Clone, Debug)]
+pub struct TaskContext {
+    pub task_id: usize,
+    pub agreement_id: String,
+    /// `None` if the activity could not be created.
+    pub activity_id: Option<String>,
+    pub provider_id: NodeId,
+    pub provider_name: Option<String>,
+}
+
+impl TaskContext {
+    pub(crate) fn new(
+        task_id: usize,
+        agreement_id: String,
+        activity_id: Option<String>,
+        provider: &ProviderInfo,
+    ) -> Self {
+        TaskContext {
+            task_id,
+            agreement_id,
+            activity_id,
+            provider_id: provider.node_id,
+            provider_name: provider.name().map(ToString::to_string),
+        }
+    }
+
+    /// The activity id or, without an activity, the agreement id.
+    pub fn attempt_id(&self) -> &str {
+        self.activity_id.as_deref().unwrap_or(&self.agreement_id)
+    }
+}
+
+/// Kept by the `Requestor`, as it needn't be `Send`; tasks sent to the
+/// negotiations carry the commands it has built.
+pub(crate) type TaskFactory = Arc<dyn Fn(&ProviderInfo) -> CommandList>;
+
+#[derive(Clone)]
+pub(crate) enum TaskCommands {
+    Fixed(CommandList),
+    /// Commands built by a `TaskFactory` once the provider is known; `None`
+    /// until the task is taken.
+    Factory(Option<CommandList>),
+}
+
+/// A single unit of work tracked by the `Requestor`.
+#[derive(Clone)]
+pub(crate) struct Task {
+    pub id: usize,
+    commands: TaskCommands,
+    /// Number of failed attempts so far.
+    pub attempt: usize,
+    /// Number of providers which failed to start an activity for this task.
+    pub activity_failures: usize,
+    /// Tasks which have to be done before this one is dispatched.
+    pub after: Vec<usize>,
+}
+
+impl Task {
+    pub fn new(id: usize, commands: CommandList) -> Self {
+        Self::with_commands(id, TaskCommands::Fixed(commands))
+    }
+
+    pub fn with_factory(id: usize) -> Self {
+        Self::with_commands(id, TaskCommands::Factory(None))
+    }
+
+    fn with_commands(id: usize, commands: TaskCommands) -> Self {
+        Task {
+            id,
+            commands,
+            attempt: 0,
+            activity_failures: 0,
+            after: vec![],
+        }
+    }
+
+    pub fn after(self, after: Vec<usize>) -> Self {
+        Self { after, ..self }
+    }
+
+    /// Returns commands of the task unless they are built by a factory.
+    pub fn fixed_commands(&self) -> Option<&CommandList> {
+        match &self.commands {
+            TaskCommands::Fixed(commands) => Some(commands),
+            TaskCommands::Factory(_) => None,
+        }
+    }
+
+    /// Sets commands built by the factory of the task for a provider.
+    pub fn build(&mut self, commands: CommandList) {
+        self.commands = TaskCommands::Factory(Some(commands));
+    }
+
+    /// Returns commands to execute on the provider the task was taken by.
+    pub fn commands(&self) -> anyhow::Result<CommandList> {
+        match &self.commands {
+            TaskCommands::Fixed(commands) | TaskCommands::Factory(Some(commands)) => {
+                Ok(commands.clone())
+            }
+            TaskCommands::Factory(None) => Err(anyhow::anyhow!(
+                "commands of task {} are not built",
+                self.id
+            )),
+        }
+    }
+}
+
+/// A task dispatched to at least one provider.
+pub(crate) struct RunningTask {
+    pub task: Task,
+    pub started: Instant,
+    /// Number of activities currently computing this task.
+    pub copies: usize,
+    /// Whether a duplicate of this task has already been scheduled.
+    pub duplicated: bool,
+}
+
+impl RunningTask {
+    pub fn new(task: Task, started: Instant) -> Self {
+        RunningTask {
+            task,
+            started,
+            copies: 1,
+            duplicated: false,
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Lifecycle of a single task.
+///
+/// `Pending → Negotiating → Dispatched → Running → Downloading → Done`;
+/// any non-final state may go to `Failed`, or back to `Pending` when
+/// the task is retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TaskState {
+    /// Waiting for a provider.
+    Pending,
+    /// Agreement with a provider is being made.
+    Negotiating,
+    /// Agreement made; the activity is being created.
+    Dispatched,
+    /// Commands are being executed.
+    Running,
+    /// Outputs are being downloaded.
+    Downloading,
+    Done,
+    Failed,
+}
+
+impl TaskState {
+    pub fn is_final(&self) -> bool {
+        matches!(self, TaskState::Done | TaskState::Failed)
+    }
+
+    pub fn can_transition_to(&self, next: TaskState) -> bool {
+        use TaskState::*;
+        match (self, next) {
+            (Pending, Negotiating)
+            | (Negotiating, Dispatched)
+            | (Dispatched, Running)
+            | (Running, Downloading)
+            | (Running, Done)
+            | (Downloading, Done) => true,
+            (state, Pending) | (state, Failed) => !state.is_final(),
+            _ => false,
+        }
+    }
+}
+
+/// Change of the state of a task.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskEvent {
+    pub task_id: usize,
+    pub state: TaskState,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// States of all the tasks, built by replaying `TaskEvent`s.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StateSnapshot {
+    pub tasks: BTreeMap<usize, TaskState>,
+}
+
+impl StateSnapshot {
+    /// Replays the events persisted with `Requestor::with_state_file`.
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(TaskLog::load(path)?.snapshot())
+    }
+
+    pub fn state(&self, task_id: usize) -> Option<TaskState> {
+        self.tasks.get(&task_id).cloned()
+    }
+
+    /// Number of tasks in the given state.
+    pub fn count(&self, state: TaskState) -> usize {
+        self.tasks.values().filter(|s| **s == state).count()
+    }
+}
+
+/// Append-only log of task events, optionally persisted as JSON lines.
+#[derive(Clone, Default)]
+pub(crate) struct TaskLog {
+    /// Latest state of every task, as replayed from the events.
+    states: HashMap<usize, TaskState>,
+    path: Option<PathBuf>,
+    listeners: Vec<Arc<dyn Fn(&TaskEvent) + Send + Sync>>,
+}
+
+impl TaskLog {
+    /// Loads events from `path`; new events will be appended there.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut states = HashMap::new();
+        if path.exists() {
+            let file = std::fs::File::open(path)
+                .with_context(|| format!("unable to open task log {}", path.display()))?;
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let event: TaskEvent = serde_json::from_str(&line)
+                    .with_context(|| format!("invalid task log {}", path.display()))?;
+                states.insert(event.task_id, event.state);
+            }
+        }
+        Ok(TaskLog {
+            states,
+            path: Some(path.to_path_buf()),
+            listeners: vec![],
+        })
+    }
+
+    /// Calls `listener` on every newly recorded event.
+    pub fn add_listener(&mut self, listener: impl Fn(&TaskEvent) + Send + Sync + 'static) {
+        self.listeners.push(Arc::new(listener));
+    }
+
+    pub fn record(&mut self, task_id: usize, state: TaskState) {
+        match self.state(task_id) {
+            Some(current) if current == state => return,
+            Some(current) if !current.can_transition_to(state) => {
+                log::debug!(
+                    "task {}: unexpected transition {:?} -> {:?}",
+                    task_id,
+                    current,
+                    state
+                )
+            }
+            _ => (),
+        }
+        let event = TaskEvent {
+            task_id,
+            state,
+            timestamp: Utc::now(),
+        };
+        if let Some(path) = &self.path {
+            if let Err(e) = append(path, &event) {
+                log::warn!("unable to persist task event: {:?}", e);
+            }
+        }
+        for listener in &self.listeners {
+            listener(&event);
+        }
+        self.states.insert(task_id, state);
+    }
+
+    pub fn state(&self, task_id: usize) -> Option<TaskState> {
+        self.states.get(&task_id).cloned()
+    }
+
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            tasks: self
+                .states
+                .iter()
+                .map(|(id, state)| (*id, *state))
+                .collect(),
+        }
+    }
+}
+
+fn append(path: &Path, event: &TaskEvent) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut line = serde_json::to_vec(event)?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions() {
+        assert!(TaskState::Pending.can_transition_to(TaskState::Negotiating));
+        assert!(TaskState::Running.can_transition_to(TaskState::Pending));
+        assert!(!TaskState::Done.can_transition_to(TaskState::Pending));
+        assert!(!TaskState::Pending.can_transition_to(TaskState::Done));
+    }
+
+    #[test]
+    fn test_replay() {
+        let path = std::env::temp_dir().join(format!("yarapi-task-log-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut log = TaskLog::load(&path).unwrap();
+        log.record(0, TaskState::Pending);
+        log.record(1, TaskState::Pending);
+        log.record(0, TaskState::Negotiating);
+        log.record(0, TaskState::Dispatched);
+        log.record(0, TaskState::Running);
+        log.record(0, TaskState::Done);
+
+        let snapshot = StateSnapshot::load(&path).unwrap();
+        assert_eq!(snapshot.state(0), Some(TaskState::Done));
+        assert_eq!(snapshot.state(1), Some(TaskState::Pending));
+        assert_eq!(snapshot.count(TaskState::Pending), 1);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,77 @@
+use opentelemetry::trace::{SpanContext, TraceContextExt, Tracer};
+use opentelemetry::{global, global::BoxedSpan, Context, KeyValue};
+use ya_client::model::NodeId;
+
+/// Name of the tracer, as given to the global tracer provider.
+const TRACER: &str = "yarapi";
+
+/// Spans of a run, see `Requestor::with_telemetry`.
+///
+/// The run has a root span; agreements and activities get child spans in
+/// the same trace, tagged with their ids.
+#[derive(Clone)]
+pub(crate) struct Telemetry {
+    root: SpanContext,
+}
+
+/// Span ended when dropped.
+pub(crate) struct SpanGuard(BoxedSpan);
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        use opentelemetry::trace::Span;
+        self.0.end();
+    }
+}
+
+impl Telemetry {
+    /// Starts the root span of the run of requestor `name`.
+    pub fn start(name: &str) -> (Self, SpanGuard) {
+        use opentelemetry::trace::Span;
+        let tracer = global::tracer(TRACER);
+        let span = tracer
+            .span_builder("yarapi.run")
+            .with_attributes(vec![KeyValue::new("yarapi.requestor", name.to_string())])
+            .start(&tracer);
+        let root = span.span_context().clone();
+        (Telemetry { root }, SpanGuard(span))
+    }
+
+    /// Id of the trace, as hex; put in the demand as `srv::TRACE_ID`.
+    pub fn trace_id(&self) -> String {
+        format!("{:032x}", self.root.trace_id().to_u128())
+    }
+
+    fn span(&self, name: &'static str, attributes: Vec<KeyValue>) -> SpanGuard {
+        let tracer = global::tracer(TRACER);
+        let parent = Context::new().with_remote_span_context(self.root.clone());
+        SpanGuard(
+            tracer
+                .span_builder(name)
+                .with_parent_context(parent)
+                .with_attributes(attributes)
+                .start(&tracer),
+        )
+    }
+
+    pub fn agreement(&self, agreement_id: &str, node_id: &NodeId) -> SpanGuard {
+        self.span(
+            "yarapi.agreement",
+            vec![
+                KeyValue::new("golem.agreement.id", agreement_id.to_string()),
+                KeyValue::new("golem.provider.id", node_id.to_string()),
+            ],
+        )
+    }
+
+    pub fn activity(&self, activity_id: &str, agreement_id: &str, task_id: usize) -> SpanGuard {
+        self.span(
+            "yarapi.activity",
+            vec![
+                KeyValue::new("golem.activity.id", activity_id.to_string()),
+                KeyValue::new("golem.agreement.id", agreement_id.to_string()),
+                KeyValue::new("yarapi.task.id", task_id as i64),
+            ],
+        )
+    }
+}
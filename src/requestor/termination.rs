@@ -0,0 +1,186 @@
+use actix::prelude::*;
+use chrono::Utc;
+use futures::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time;
+use ya_client::market::MarketRequestorApi;
+
+use crate::requestor::event_log::{EventLog, LifecycleEvent};
+use crate::rest::{agreement_events, AgreementEvent, Party};
+
+/// How often the watcher checks that the requestor still runs.
+const LIVENESS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Error of a task whose provider terminated the agreement while the task
+/// was running, e.g. on shutdown.
+///
+/// Passed to `Requestor::on_task_failed`; the task is computed again on
+/// another provider unless the callback decides otherwise.
+///
+/// ## Example:
+/// ```no_run
+/// # fn run(requestor: yarapi::requestor::Requestor) {
+/// use yarapi::requestor::{Decision, ProviderTerminated};
+///
+/// let requestor = requestor.on_task_failed(|_context, error, attempt| {
+///     match error.downcast_ref::<ProviderTerminated>() {
+///         Some(_) => Decision::Retry,
+///         None if attempt < 3 => Decision::Retry,
+///         None => Decision::Skip,
+///     }
+/// });
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProviderTerminated {
+    pub agreement_id: String,
+    /// Message of the reason given by the provider.
+    pub reason: Option<String>,
+}
+
+impl fmt::Display for ProviderTerminated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "provider terminated agreement [{}]: {}",
+            self.agreement_id,
+            self.reason.as_deref().unwrap_or("no reason given")
+        )
+    }
+}
+
+impl std::error::Error for ProviderTerminated {}
+
+/// Agreements of the running tasks, and the terminations reported for them.
+#[derive(Clone, Default)]
+pub(crate) struct Terminations {
+    agreements: Arc<Mutex<HashMap<String, Option<ProviderTerminated>>>>,
+}
+
+impl Terminations {
+    /// Watches `agreement_id` until the returned guard is dropped.
+    pub fn track(&self, agreement_id: &str) -> TrackGuard {
+        self.agreements
+            .lock()
+            .unwrap()
+            .insert(agreement_id.to_string(), None);
+        TrackGuard {
+            terminations: self.clone(),
+            agreement_id: agreement_id.to_string(),
+        }
+    }
+
+    pub fn get(&self, agreement_id: &str) -> Option<ProviderTerminated> {
+        self.agreements
+            .lock()
+            .unwrap()
+            .get(agreement_id)
+            .cloned()
+            .flatten()
+    }
+
+    /// Records the termination; false if the agreement is not watched.
+    fn terminated(&self, termination: ProviderTerminated) -> bool {
+        match self
+            .agreements
+            .lock()
+            .unwrap()
+            .get_mut(&termination.agreement_id)
+        {
+            Some(entry) => {
+                *entry = Some(termination);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub(crate) struct TrackGuard {
+    terminations: Terminations,
+    agreement_id: String,
+}
+
+impl Drop for TrackGuard {
+    fn drop(&mut self) {
+        if let Ok(mut agreements) = self.terminations.agreements.lock() {
+            agreements.remove(&self.agreement_id);
+        }
+    }
+}
+
+/// Collects agreement events while `requestor` runs, and records
+/// terminations of the watched agreements.
+///
+/// The requestor stops watching an agreement before terminating it itself,
+/// so the terminations recorded come from the providers.
+pub(crate) async fn watch<A: Actor>(
+    terminations: Terminations,
+    market_api: MarketRequestorApi,
+    event_log: Option<EventLog>,
+    requestor: Addr<A>,
+) {
+    let events = agreement_events(market_api, Utc::now(), None);
+    futures::pin_mut!(events);
+    while requestor.connected() {
+        // A pending poll is kept by the stream, so no event is lost.
+        let event = match time::timeout(LIVENESS_INTERVAL, events.next()).await {
+            Ok(Some(Ok(event))) => event,
+            Ok(Some(Err(e))) => {
+                log::warn!("no longer watching agreement terminations: {:#}", e);
+                return;
+            }
+            Ok(None) => return,
+            Err(_) => continue,
+        };
+        if let AgreementEvent::Terminated {
+            agreement_id,
+            reason,
+            by,
+            ..
+        } = event
+        {
+            if by == Some(Party::Requestor) {
+                continue;
+            }
+            let termination = ProviderTerminated {
+                agreement_id,
+                reason,
+            };
+            if !terminations.terminated(termination.clone()) {
+                continue;
+            }
+            log::warn!("{}", termination);
+            if let Some(event_log) = &event_log {
+                event_log.emit(LifecycleEvent::ProviderTerminated {
+                    agreement_id: termination.agreement_id,
+                    reason: termination.reason,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track() {
+        let terminations = Terminations::default();
+        let guard = terminations.track("a");
+        let termination = ProviderTerminated {
+            agreement_id: "a".to_string(),
+            reason: Some("shutdown".to_string()),
+        };
+        assert!(terminations.terminated(termination.clone()));
+        assert_eq!(terminations.get("a"), Some(termination.clone()));
+
+        drop(guard);
+        assert!(!terminations.terminated(termination));
+        assert_eq!(terminations.get("a"), None);
+    }
+}
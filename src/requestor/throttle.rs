@@ -0,0 +1,166 @@
+use futures::channel::oneshot;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limits the uploads to providers, see `Requestor::with_transfer_limits`.
+///
+/// Providers download the task's inputs from the requestor, so uploads to
+/// many providers at once can saturate the uplink and make market polling
+/// and approvals time out. A batch with uploads waits for a free transfer
+/// slot, which is held until the provider reports its uploads done.
+///
+/// With `with_max_rate`, batches start no more often than their inputs can
+/// be sent at that rate; gftp itself is not throttled, so it is an average
+/// over the batches rather than a hard cap.
+///
+/// ## Example:
+/// ```no_run
+/// # fn run(render: yarapi::requestor::Requestor, stats: yarapi::requestor::Requestor) {
+/// use yarapi::requestor::TransferLimits;
+///
+/// let limits = TransferLimits::new(4).with_max_rate(10 * 1024 * 1024);
+/// let render = render.with_transfer_limits(&limits);
+/// let stats = stats.with_transfer_limits(&limits);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct TransferLimits {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    max_concurrent: usize,
+    in_use: usize,
+    waiters: VecDeque<oneshot::Sender<()>>,
+    /// Bytes per second.
+    max_rate: Option<u64>,
+    /// When the next batch may start without exceeding `max_rate`.
+    next_start: Option<Instant>,
+}
+
+impl State {
+    fn grant(&mut self) {
+        while self.in_use < self.max_concurrent {
+            match self.waiters.pop_front() {
+                // A waiter which gave up has dropped its receiver.
+                Some(tx) => {
+                    if tx.send(()).is_ok() {
+                        self.in_use += 1;
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Reserves time for sending `bytes`; returns when the batch may start.
+    fn schedule(&mut self, bytes: u64, now: Instant) -> Option<Instant> {
+        let rate = self.max_rate?;
+        let start = self.next_start.map(|next| next.max(now)).unwrap_or(now);
+        self.next_start = Some(start + Duration::from_secs_f64(bytes as f64 / rate.max(1) as f64));
+        Some(start)
+    }
+}
+
+impl TransferLimits {
+    /// Allows uploads to `max_concurrent` providers at a time.
+    pub fn new(max_concurrent: usize) -> Self {
+        TransferLimits {
+            state: Arc::new(Mutex::new(State {
+                max_concurrent: max_concurrent.max(1),
+                in_use: 0,
+                waiters: VecDeque::new(),
+                max_rate: None,
+                next_start: None,
+            })),
+        }
+    }
+
+    /// Spreads the batches so that uploads average `bytes_per_sec`.
+    pub fn with_max_rate(self, bytes_per_sec: u64) -> Self {
+        self.state.lock().unwrap().max_rate = Some(bytes_per_sec);
+        self
+    }
+
+    /// Waits for a transfer slot for a batch uploading `bytes`; it is
+    /// released when the returned permit is dropped.
+    pub(crate) async fn acquire(&self, bytes: u64) -> TransferPermit {
+        let rx = {
+            let mut state = self.state.lock().unwrap();
+            let (tx, rx) = oneshot::channel();
+            state.waiters.push_back(tx);
+            state.grant();
+            rx
+        };
+        // Created before waiting, so that a slot granted to a cancelled
+        // waiter is released too.
+        let mut permit = TransferPermit {
+            limits: self.clone(),
+            pending: Some(rx),
+        };
+        if let Some(rx) = &mut permit.pending {
+            let _ = rx.await;
+        }
+        permit.pending = None;
+
+        let start = self.state.lock().unwrap().schedule(bytes, Instant::now());
+        if let Some(start) = start {
+            let delay = start.saturating_duration_since(Instant::now());
+            if delay > Duration::from_secs(0) {
+                log::debug!("delaying uploads of {} bytes by {:?}", bytes, delay);
+                tokio::time::delay_for(delay).await;
+            }
+        }
+        permit
+    }
+}
+
+pub(crate) struct TransferPermit {
+    limits: TransferLimits,
+    /// Set until the slot is granted.
+    pending: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for TransferPermit {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.pending.take() {
+            if rx.try_recv() != Ok(Some(())) {
+                return;
+            }
+        }
+        let mut state = match self.limits.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        state.in_use = state.in_use.saturating_sub(1);
+        state.grant();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        let limits = TransferLimits::new(2).with_max_rate(1000);
+        let mut state = limits.state.lock().unwrap();
+        let now = Instant::now();
+        assert_eq!(state.schedule(2000, now), Some(now));
+        assert_eq!(state.schedule(500, now), Some(now + Duration::from_secs(2)));
+        let later = now + Duration::from_secs(10);
+        assert_eq!(state.schedule(500, later), Some(later));
+    }
+
+    #[tokio::test]
+    async fn test_acquire() {
+        let limits = TransferLimits::new(1);
+        let permit = limits.acquire(0).await;
+        let waiting = limits.acquire(0);
+        futures::pin_mut!(waiting);
+        assert!(futures::poll!(&mut waiting).is_pending());
+        drop(permit);
+        let _permit = waiting.await;
+    }
+}
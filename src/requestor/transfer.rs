@@ -0,0 +1,112 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::requestor::command::{Command, CommandList};
+use crate::requestor::provider::ProviderInfo;
+
+/// Some protocol a task's transfers need is not supported by the provider;
+/// the task is dispatched to another one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsupportedTransfer {
+    /// Protocols the commands need, e.g. `["gftp", "https"]`.
+    pub required: Vec<String>,
+    /// `golem.activity.caps.transfer.protocol` of the offer.
+    pub supported: Vec<String>,
+}
+
+impl fmt::Display for UnsupportedTransfer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "provider supports transfers via {} only, task needs {}",
+            self.supported.join(", "),
+            self.required.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedTransfer {}
+
+/// Protocols the transfers of `commands` use.
+///
+/// Uploads and downloads of local files go through gftp; `Transfer`
+/// commands use the scheme of their non-container url.
+fn required_protocols(commands: &CommandList) -> BTreeSet<String> {
+    commands
+        .iter()
+        .filter_map(|command| match command {
+            Command::Upload { .. } | Command::Download { .. } | Command::UploadArchive { .. } => {
+                Some("gftp".to_string())
+            }
+            Command::Transfer { from, to } => [from, to]
+                .iter()
+                .filter_map(|url| url.split("://").next().filter(|_| url.contains("://")))
+                .map(str::to_lowercase)
+                .next(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Checks that the provider supports the protocols of the task's transfers.
+///
+/// There is no protocol to pick: local files are served only via gftp, and
+/// `Transfer` commands use the urls they were given. Providers lacking one
+/// of these are skipped instead.
+///
+/// Offers without `golem.activity.caps.transfer.protocol` are accepted, as
+/// older providers don't advertise it.
+pub(crate) fn check(
+    commands: &CommandList,
+    provider: &ProviderInfo,
+) -> Result<(), UnsupportedTransfer> {
+    let supported = match provider.transfer_protocols() {
+        Some(supported) => supported,
+        None => return Ok(()),
+    };
+    let required = required_protocols(commands);
+    if required.iter().all(|protocol| supported.contains(protocol)) {
+        return Ok(());
+    }
+    Err(UnsupportedTransfer {
+        required: required.into_iter().collect(),
+        supported,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::properties::activity;
+
+    fn provider(protocols: &[&str]) -> ProviderInfo {
+        ProviderInfo {
+            node_id: "0x0000000000000000000000000000000000000001"
+                .parse()
+                .unwrap(),
+            properties: serde_json::json!({ activity::CAPS_TRANSFER_PROTOCOL: protocols }),
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let commands = CommandList::new(vec![
+            Command::Transfer {
+                from: "https://example.com/input.zip".to_string(),
+                to: "container:/golem/input/input.zip".to_string(),
+            },
+            Command::Download {
+                from: "/golem/output/out.txt".to_string(),
+                to: "out.txt".into(),
+            },
+        ]);
+        assert!(check(&commands, &provider(&["gftp", "https", "http"])).is_ok());
+        assert_eq!(
+            check(&commands, &provider(&["gftp"])),
+            Err(UnsupportedTransfer {
+                required: vec!["gftp".to_string(), "https".to_string()],
+                supported: vec!["gftp".to_string()],
+            })
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use anyhow::{Context, Result};
+use sha3::{Digest, Sha3_256};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use ya_client::model::NodeId;
+
+use crate::requestor::{command::CommandList, provider::ProviderInfo, task::Task};
+
+type Comparator = Arc<dyn Fn(&TaskOutput, &TaskOutput) -> bool + Send + Sync>;
+
+/// Result of one copy of a task, compared against the other copies.
+#[derive(Clone, Debug)]
+pub struct TaskOutput {
+    /// Outputs of the `run` commands.
+    pub output: Vec<String>,
+    /// Files downloaded by this copy, in the order of the `download`
+    /// commands; kept apart from the other copies until a result is chosen.
+    pub files: Vec<PathBuf>,
+}
+
+/// Redundant computation of every task, see `Requestor::with_verification`.
+#[derive(Clone)]
+pub struct Verification {
+    copies: usize,
+    comparator: Option<Comparator>,
+}
+
+impl Verification {
+    /// Computes every task on `copies` distinct providers; outputs and
+    /// downloaded files are compared byte by byte.
+    pub fn new(copies: usize) -> Self {
+        Verification {
+            copies: copies.max(1),
+            comparator: None,
+        }
+    }
+
+    /// Compares the results with `comparator` instead, e.g. to allow for
+    /// timestamps or floating point differences.
+    pub fn with_comparator<F>(self, comparator: F) -> Self
+    where
+        F: Fn(&TaskOutput, &TaskOutput) -> bool + Send + Sync + 'static,
+    {
+        Self {
+            comparator: Some(Arc::new(comparator)),
+            ..self
+        }
+    }
+
+    fn equal(&self, a: &TaskOutput, b: &TaskOutput) -> bool {
+        match &self.comparator {
+            Some(comparator) => comparator(a, b),
+            None => match (digest(a), digest(b)) {
+                (Ok(a), Ok(b)) => a == b,
+                (a, b) => {
+                    for e in a.err().into_iter().chain(b.err()) {
+                        log::warn!("unable to compare results: {:#}", e);
+                    }
+                    false
+                }
+            },
+        }
+    }
+}
+
+/// A finished copy of a task.
+#[derive(Clone)]
+pub(crate) struct Candidate {
+    pub activity_id: String,
+    pub agreement_id: String,
+    pub provider: ProviderInfo,
+    pub result: TaskOutput,
+}
+
+pub(crate) enum Verdict {
+    /// Not all the copies have finished yet.
+    Pending,
+    /// The majority agreed; the files of the `winner` are in place.
+    Accepted {
+        winner: Candidate,
+        disagreeing: Vec<ProviderInfo>,
+    },
+    /// No result was returned by the majority of the copies.
+    Inconclusive,
+}
+
+/// Tracks the copies of the tasks under verification.
+#[derive(Clone)]
+pub(crate) struct Verifier {
+    config: Verification,
+    /// Providers each task has been dispatched to.
+    assigned: HashMap<usize, HashSet<NodeId>>,
+    candidates: HashMap<usize, Vec<Candidate>>,
+}
+
+impl Verifier {
+    pub fn new(config: Verification) -> Self {
+        Verifier {
+            config,
+            assigned: HashMap::new(),
+            candidates: HashMap::new(),
+        }
+    }
+
+    /// Queues every task once per copy.
+    pub fn expand(&self, tasks: Vec<Task>) -> Vec<Task> {
+        tasks
+            .into_iter()
+            .flat_map(|task| std::iter::repeat(task).take(self.config.copies))
+            .collect()
+    }
+
+    /// Whether `node_id` hasn't been dispatched a copy of the task yet.
+    pub fn allows(&self, task_id: usize, node_id: &NodeId) -> bool {
+        self.assigned
+            .get(&task_id)
+            .map(|nodes| !nodes.contains(node_id))
+            .unwrap_or(true)
+    }
+
+    pub fn assign(&mut self, task_id: usize, node_id: &NodeId) {
+        self.assigned.entry(task_id).or_default().insert(*node_id);
+    }
+
+    /// Records a finished copy and, once all the copies have finished,
+    /// compares their results.
+    pub fn add(&mut self, task_id: usize, candidate: Candidate) -> Verdict {
+        let candidates = self.candidates.entry(task_id).or_default();
+        candidates.push(candidate);
+        if candidates.len() < self.config.copies {
+            log::info!(
+                "task {}: {} of {} results to compare",
+                task_id,
+                candidates.len(),
+                self.config.copies
+            );
+            return Verdict::Pending;
+        }
+        let candidates = self.candidates.remove(&task_id).unwrap_or_default();
+        self.assigned.remove(&task_id);
+
+        let groups = group(&candidates, |a, b| self.config.equal(&a.result, &b.result));
+        let majority = groups
+            .iter()
+            .max_by_key(|group| group.len())
+            .filter(|group| group.len() * 2 > candidates.len())
+            .cloned();
+        let (winner, disagreeing) = match majority {
+            Some(majority) => {
+                let winner = majority[0];
+                let disagreeing = (0..candidates.len())
+                    .filter(|i| !majority.contains(i))
+                    .map(|i| candidates[i].provider.clone())
+                    .collect();
+                (winner, disagreeing)
+            }
+            None => {
+                log::warn!(
+                    "task {}: no majority among {} results",
+                    task_id,
+                    candidates.len()
+                );
+                discard(&candidates, None);
+                return Verdict::Inconclusive;
+            }
+        };
+        discard(&candidates, Some(winner));
+        let mut winner = candidates
+            .into_iter()
+            .nth(winner)
+            .expect("winner is a candidate");
+        if let Err(e) = restore(&mut winner) {
+            log::warn!("task {}: {:#}", task_id, e);
+        }
+        Verdict::Accepted {
+            winner,
+            disagreeing,
+        }
+    }
+}
+
+/// Where a copy computed by `node_id` downloads `path` to.
+pub(crate) fn copy_path(path: &Path, node_id: &NodeId) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}", node_id));
+    path.with_file_name(name)
+}
+
+/// Redirects the downloads, so that the copies don't overwrite each other.
+pub(crate) fn redirect_downloads(commands: CommandList, node_id: &NodeId) -> CommandList {
+    commands.map_downloads(|to| copy_path(&to, node_id))
+}
+
+/// Moves the files of the accepted copy to the paths of the task.
+fn restore(winner: &mut Candidate) -> Result<()> {
+    let suffix = format!(".{}", winner.provider.node_id);
+    for file in winner.result.files.iter_mut() {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        let original = file.with_file_name(name.trim_end_matches(&suffix));
+        remove(&original);
+        fs::rename(&file, &original).with_context(|| {
+            format!(
+                "unable to move {} to {}",
+                file.display(),
+                original.display()
+            )
+        })?;
+        *file = original;
+    }
+    Ok(())
+}
+
+/// Removes the files of all the copies but the `keep` one.
+fn discard(candidates: &[Candidate], keep: Option<usize>) {
+    for (i, candidate) in candidates.iter().enumerate() {
+        if Some(i) != keep {
+            candidate.result.files.iter().for_each(|file| remove(file));
+        }
+    }
+}
+
+fn remove(path: &Path) {
+    let _ = match path.is_dir() {
+        true => fs::remove_dir_all(path),
+        false => fs::remove_file(path),
+    };
+}
+
+/// Groups indices of equal items; every group is compared by its first item.
+fn group<T>(items: &[T], equal: impl Fn(&T, &T) -> bool) -> Vec<Vec<usize>> {
+    let mut groups: Vec<Vec<usize>> = vec![];
+    for (i, item) in items.iter().enumerate() {
+        match groups
+            .iter_mut()
+            .find(|group| equal(&items[group[0]], item))
+        {
+            Some(group) => group.push(i),
+            None => groups.push(vec![i]),
+        }
+    }
+    groups
+}
+
+/// Hash of the outputs and the contents of the files; directories are
+/// hashed recursively, by sorted entry names.
+fn digest(result: &TaskOutput) -> Result<Vec<u8>> {
+    let mut hasher = Sha3_256::new();
+    for output in &result.output {
+        hasher.update(output.as_bytes());
+        hasher.update(b"\0");
+    }
+    for file in &result.files {
+        digest_path(&mut hasher, file)?;
+    }
+    Ok(hasher.finalize().to_vec())
+}
+
+fn digest_path(hasher: &mut Sha3_256, path: &Path) -> Result<()> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)
+            .with_context(|| format!("unable to list {}", path.display()))?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort();
+        for entry in entries {
+            hasher.update(
+                entry
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .as_bytes(),
+            );
+            digest_path(hasher, &entry)?;
+        }
+    } else {
+        let contents =
+            fs::read(path).with_context(|| format!("unable to read {}", path.display()))?;
+        hasher.update(&contents);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group() {
+        let results = ["a", "b", "a", "c", "a"];
+        let groups = group(&results, |a, b| a == b);
+        assert_eq!(groups, vec![vec![0, 2, 4], vec![1], vec![3]]);
+    }
+
+    #[test]
+    fn test_copy_path() {
+        let node_id: NodeId = "0x0000000000000000000000000000000000000001"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            copy_path(Path::new("out/frame.png"), &node_id),
+            PathBuf::from("out/frame.png.0x0000000000000000000000000000000000000001")
+        );
+    }
+}
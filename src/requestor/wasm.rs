@@ -0,0 +1,138 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+
+use crate::requestor::command::{Command, CommandList};
+
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// `manifest.json` of a WASM package, listing its modules' entry points.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WasmManifest {
+    pub id: String,
+    pub name: String,
+    pub entry_points: Vec<WasmEntryPoint>,
+    #[serde(default)]
+    pub mount_points: Vec<serde_json::Value>,
+}
+
+/// Entry point of a WASM package; `run(id, args...)` starts the module at
+/// `wasm_path`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct WasmEntryPoint {
+    pub id: String,
+    pub wasm_path: String,
+}
+
+impl WasmManifest {
+    /// Reads the manifest of a WASM package (a zip archive) and checks that
+    /// the modules of all its entry points are there.
+    pub fn read(path: &Path) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("unable to open {}", path.display()))?;
+        Self::from_archive(file).with_context(|| format!("invalid WASM package {}", path.display()))
+    }
+
+    fn from_archive(reader: impl Read + Seek) -> Result<Self> {
+        let mut archive = zip::ZipArchive::new(reader)?;
+        let manifest: WasmManifest = {
+            let file = archive
+                .by_name(MANIFEST_FILE)
+                .with_context(|| format!("no {}", MANIFEST_FILE))?;
+            serde_json::from_reader(file).with_context(|| format!("invalid {}", MANIFEST_FILE))?
+        };
+        for entry_point in &manifest.entry_points {
+            if archive.by_name(&entry_point.wasm_path).is_err() {
+                bail!(
+                    "no module {} of entry point {}",
+                    entry_point.wasm_path,
+                    entry_point.id
+                );
+            }
+        }
+        Ok(manifest)
+    }
+
+    pub fn entry_point(&self, id: &str) -> Option<&WasmEntryPoint> {
+        self.entry_points
+            .iter()
+            .find(|entry_point| entry_point.id == id)
+    }
+
+    /// Checks that the `run` commands start entry points of the package.
+    pub fn validate(&self, commands: &CommandList) -> Result<()> {
+        for command in commands.iter() {
+            let entry_point = match command {
+                Command::Run(args) => args.first(),
+                _ => continue,
+            };
+            let entry_point = entry_point.ok_or_else(|| anyhow!("run without entry point"))?;
+            if self.entry_point(entry_point).is_none() {
+                let available: Vec<&str> =
+                    self.entry_points.iter().map(|e| e.id.as_str()).collect();
+                bail!(
+                    "no entry point {} in package {} (available: {})",
+                    entry_point,
+                    self.name,
+                    available.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    fn package(manifest: &str, modules: &[&str]) -> Cursor<Vec<u8>> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(vec![]));
+        let options = zip::write::FileOptions::default();
+        zip.start_file(MANIFEST_FILE, options).unwrap();
+        zip.write_all(manifest.as_bytes()).unwrap();
+        for module in modules {
+            zip.start_file(*module, options).unwrap();
+            zip.write_all(b"\0asm").unwrap();
+        }
+        let mut archive = zip.finish().unwrap();
+        archive.set_position(0);
+        archive
+    }
+
+    #[test]
+    fn test_manifest() {
+        let manifest = r#"{
+            "id": "9a3b5d67-b5f0-4f0b-9a9f-7cb3d1e1e1a0",
+            "name": "tools",
+            "entry-points": [
+                { "id": "compress", "wasm-path": "compress.wasm" },
+                { "id": "hash", "wasm-path": "hash.wasm" }
+            ],
+            "mount-points": [{ "rw": "workdir" }]
+        }"#;
+        let manifest =
+            WasmManifest::from_archive(package(manifest, &["compress.wasm", "hash.wasm"])).unwrap();
+        assert_eq!(manifest.entry_points.len(), 2);
+
+        assert!(manifest
+            .validate(&crate::commands![run("hash", "/workdir/input")])
+            .is_ok());
+        let error = manifest
+            .validate(&crate::commands![run("main", "/workdir/input")])
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "no entry point main in package tools (available: compress, hash)"
+        );
+
+        let manifest = r#"{ "id": "1", "name": "broken",
+            "entry-points": [{ "id": "main", "wasm-path": "main.wasm" }] }"#;
+        assert!(WasmManifest::from_archive(package(manifest, &[])).is_err());
+    }
+}
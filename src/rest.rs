@@ -1,51 +1,409 @@
 pub mod activity;
+mod agreement_event;
 mod async_drop;
+mod audit;
+mod batches;
+mod client;
+mod daemon;
 mod market;
+mod payment;
+mod responder;
 pub mod streaming;
+mod tags;
+mod usage;
+mod workdir;
 
-pub use activity::{Activity, Credentials, Event as BatchEvent, ExeScriptCommand, RunningBatch};
+pub use activity::{
+    execute_on_all, Activity, BatchInfo, BatchStatus, Credentials, Event as BatchEvent,
+    ExeScriptCommand, ExecOptions, JsonTransferOptions, RunningBatch, StepOutput,
+};
+pub use agreement_event::{AgreementEvent, Party};
+pub use audit::AuditRecord;
+pub use client::ClientConfig;
+pub use daemon::{Capability, DaemonInfo};
+pub use payment::Payment;
+pub use responder::{PaymentPlatforms, Responder};
+pub use usage::{UsageSample, UsageVector};
+pub use workdir::{CleanupPolicy, Workdir};
+pub use ya_client::model::payment::{Allocation, DebitNoteEvent, InvoiceEvent};
 pub use ya_client::web::{WebClient, WebClientBuilder};
 
 use futures::prelude::*;
-pub use market::{Agreement, Market, Proposal, Subscription, SubscriptionId};
+pub(crate) use market::{agreement_events, is_subscription_expired, reject_reason};
+pub use market::{Agreement, Approval, Market, Offer, Proposal, Subscription, SubscriptionId};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tags::TagRegistry;
+use url::Url;
+use ya_client::model::NodeId;
 
+/// Connection to yagna on behalf of one identity.
+///
+/// The identity is given by the app key the client authenticates with;
+/// several sessions with different app keys can be used side by side.
+///
+/// Clones share the published files, tags and daemon info. A session is not
+/// `Send`: the HTTP client of ya-client and the cleanups queued by its
+/// activities and subscriptions are bound to the thread the session was
+/// created on. Use `handle` to get a session on another thread; `with`
+/// also waits for the cleanups of the sessions made from handles.
+#[derive(Clone)]
 pub struct Session {
     client: WebClient,
     drop_list: async_drop::DropList,
+    /// Set for sessions created with `with_app_key`, see `handle`.
+    app_key: Option<String>,
+    identity: Option<NodeId>,
+    shared: Shared,
+    config: ClientConfig,
+    workdir: Option<Workdir>,
+    max_concurrent_batches: usize,
+}
+
+/// State shared by the clones of a session and the sessions made from its
+/// handle.
+#[derive(Clone, Default)]
+struct Shared {
+    published: Arc<Mutex<Vec<Url>>>,
+    tags: Arc<Mutex<TagRegistry>>,
+    daemon_info: Arc<Mutex<Option<DaemonInfo>>>,
+    /// Cleanup queues of the sessions, one per thread, see `Session::with`.
+    drop_lists: Arc<Mutex<Vec<async_drop::DropFlusher>>>,
+}
+
+impl Shared {
+    fn add_drop_list(&self, drop_list: &async_drop::DropList) {
+        let mut drop_lists = self.drop_lists.lock().unwrap();
+        drop_lists.retain(|flusher| !flusher.is_closed());
+        drop_lists.push(drop_list.flusher());
+    }
+}
+
+/// `Send + Sync` handle of a `Session`, e.g. for the worker threads of a
+/// multi-threaded tokio runtime.
+///
+/// ## Example:
+/// ```no_run
+/// # fn run(session: yarapi::rest::Session) {
+/// let handle = session.handle().expect("session with an app key");
+/// std::thread::spawn(move || {
+///     let mut sys = actix_rt::System::new("worker");
+///     sys.block_on(async move {
+///         let session = handle.session();
+///         // ...
+///     });
+/// });
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct SessionHandle {
+    app_key: String,
+    identity: Option<NodeId>,
+    shared: Shared,
+    config: ClientConfig,
+    workdir: Option<Workdir>,
+    max_concurrent_batches: usize,
+}
+
+impl SessionHandle {
+    /// Session on the current thread, sharing the published files, tags and
+    /// daemon info with the original one; must be called within a local
+    /// task set, e.g. an actix system.
+    pub fn session(&self) -> Session {
+        let session = Session {
+            app_key: Some(self.app_key.clone()),
+            identity: self.identity.clone(),
+            shared: self.shared.clone(),
+            config: self.config.clone(),
+            workdir: self.workdir.clone(),
+            max_concurrent_batches: self.max_concurrent_batches,
+            ..Session::with_client(WebClient::builder().auth_token(&self.app_key).build())
+        };
+        session.shared.add_drop_list(&session.drop_list);
+        session
+    }
 }
 
 impl Session {
     pub fn with_client(client: WebClient) -> Self {
-        let drop_list = Default::default();
-        Session { client, drop_list }
+        let drop_list = async_drop::DropList::default();
+        let shared = Shared::default();
+        shared.add_drop_list(&drop_list);
+        Session {
+            client,
+            drop_list,
+            app_key: None,
+            identity: None,
+            shared,
+            config: ClientConfig::default(),
+            workdir: None,
+            max_concurrent_batches: 1,
+        }
+    }
+
+    /// Session authenticated with `app_key`; yagna's address is taken from
+    /// the usual environment variables (e.g. `YAGNA_API_URL`).
+    pub fn with_app_key(app_key: &str) -> Self {
+        Session {
+            app_key: Some(app_key.to_string()),
+            ..Self::with_client(WebClient::builder().auth_token(app_key).build())
+        }
+    }
+
+    /// Handle for making sessions on other threads; `None` for sessions
+    /// created with `with_client`.
+    pub fn handle(&self) -> Option<SessionHandle> {
+        Some(SessionHandle {
+            app_key: self.app_key.clone()?,
+            identity: self.identity.clone(),
+            shared: self.shared.clone(),
+            config: self.config.clone(),
+            workdir: self.workdir.clone(),
+            max_concurrent_batches: self.max_concurrent_batches,
+        })
+    }
+
+    /// Sets the node id of the app key's identity, which selects the
+    /// requestor account used by `payment()` for allocations.
+    pub fn with_identity(self, identity: NodeId) -> Self {
+        Session {
+            identity: Some(identity),
+            ..self
+        }
+    }
+
+    /// Sets timeouts and retries of the REST calls made by the markets and
+    /// payments of this session, see `ClientConfig`.
+    pub fn with_client_config(self, config: ClientConfig) -> Self {
+        Session { config, ..self }
+    }
+
+    /// Keeps the local temporary files of the session's activities in
+    /// `workdir`, which is cleaned up when `with` returns; a run interrupted
+    /// with ctrl-c counts as failed.
+    pub fn with_workdir(self, workdir: Workdir) -> Self {
+        Session {
+            workdir: Some(workdir),
+            ..self
+        }
+    }
+
+    /// Allows up to `max` batches running at once on each activity created
+    /// by this session; 1 by default.
+    ///
+    /// Raise it only for exe-units which run batches concurrently, each
+    /// under its own batch id; others queue or reject the extra batches.
+    /// `Activity::exec` fails when the limit is reached, see
+    /// `Activity::running_batches`.
+    pub fn with_max_concurrent_batches(self, max: usize) -> Self {
+        Session {
+            max_concurrent_batches: max.max(1),
+            ..self
+        }
+    }
+
+    /// Keeps the tags of agreements and activities in the JSON file at
+    /// `path`, so that they can be looked up by another run.
+    pub fn with_tag_store(self, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let tags = TagRegistry::load(path.as_ref())?;
+        *self.shared.tags.lock().unwrap() = tags;
+        Ok(self)
+    }
+
+    pub fn identity(&self) -> Option<&NodeId> {
+        self.identity.as_ref()
+    }
+
+    pub fn workdir(&self) -> Option<&Workdir> {
+        self.workdir.as_ref()
+    }
+
+    /// Version of the yagna daemon; queried once per session.
+    pub async fn daemon_info(&self) -> anyhow::Result<DaemonInfo> {
+        if let Some(info) = self.shared.daemon_info.lock().unwrap().clone() {
+            return Ok(info);
+        }
+        let info = self
+            .config
+            .read("query daemon version", || daemon::query(&self.client))
+            .await?;
+        log::debug!("connected to yagna {}", info.name);
+        *self.shared.daemon_info.lock().unwrap() = Some(info.clone());
+        Ok(info)
+    }
+
+    /// Fails with a clear error if the daemon doesn't support `capability`,
+    /// e.g. before starting a run which depends on it.
+    pub async fn require(&self, capability: Capability) -> anyhow::Result<()> {
+        self.daemon_info().await?.require(capability)
     }
 
     pub fn market(&self) -> anyhow::Result<Market> {
-        Market::new(self.client.clone(), self.drop_list.clone())
+        Market::new(
+            self.client.clone(),
+            self.drop_list.clone(),
+            self.config.clone(),
+        )
+    }
+
+    pub fn payment(&self) -> anyhow::Result<Payment> {
+        Payment::new(
+            self.client.clone(),
+            self.identity.clone(),
+            self.config.clone(),
+        )
+    }
+
+    /// Publishes a file via gftp until the session ends (see `with`).
+    pub async fn publish(&self, path: &Path) -> anyhow::Result<Url> {
+        let url = gftp::publish(path).await?;
+        self.shared.published.lock().unwrap().push(url.clone());
+        Ok(url)
+    }
+
+    /// Urls of the files published with `publish`, which are still available.
+    pub fn published_files(&self) -> Vec<Url> {
+        self.shared.published.lock().unwrap().clone()
+    }
+
+    /// Attaches `tag`, e.g. a job name or task id, to the agreement and
+    /// its activities.
+    pub fn tag_agreement(&self, agreement: &market::Agreement, tag: impl Into<String>) {
+        self.shared
+            .tags
+            .lock()
+            .unwrap()
+            .tag_agreement(agreement.id(), tag.into());
+    }
+
+    /// Attaches `tag` to an activity created by this session.
+    pub fn tag_activity(&self, activity: &impl Activity, tag: impl Into<String>) {
+        self.shared
+            .tags
+            .lock()
+            .unwrap()
+            .tag_activity(activity.id(), tag.into());
+    }
+
+    pub fn agreement_tags(&self, agreement_id: &str) -> Vec<String> {
+        self.shared
+            .tags
+            .lock()
+            .unwrap()
+            .agreement_tags(agreement_id)
+    }
+
+    /// Tags of the activity, including the tags of its agreement.
+    pub fn activity_tags(&self, activity_id: &str) -> Vec<String> {
+        self.shared.tags.lock().unwrap().activity_tags(activity_id)
+    }
+
+    /// Ids of the agreements tagged with `tag`.
+    pub fn find_agreements(&self, tag: &str) -> Vec<String> {
+        self.shared.tags.lock().unwrap().find_agreements(tag)
+    }
+
+    /// Streams the events of the agreements of this session, i.e. those
+    /// tagged or with activities created, which happened after `since`; see
+    /// `Market::agreement_events_stream`.
+    pub fn agreement_events(
+        &self,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> anyhow::Result<impl Stream<Item = anyhow::Result<AgreementEvent>>> {
+        let tags = self.shared.tags.clone();
+        Ok(
+            market::agreement_events(self.client.interface()?, since, None).try_filter(
+                move |event| {
+                    let agreement_ids = tags.lock().unwrap().agreement_ids();
+                    future::ready(agreement_ids.iter().any(|id| id == event.agreement_id()))
+                },
+            ),
+        )
+    }
+
+    /// Ids of the activities tagged with `tag`, directly or via their agreements.
+    pub fn find_activities(&self, tag: &str) -> Vec<String> {
+        self.shared.tags.lock().unwrap().find_activities(tag)
+    }
+
+    /// Writes an `AuditRecord` of every agreement with activities created
+    /// or tags attached in this session to `dir`, one file per agreement.
+    ///
+    /// Returns the paths of the records.
+    pub async fn export_audit(&self, dir: impl AsRef<Path>) -> anyhow::Result<Vec<PathBuf>> {
+        let market = self.market()?;
+        let payment = self.payment()?;
+        let agreement_ids = self.shared.tags.lock().unwrap().agreement_ids();
+        let mut paths = vec![];
+        for agreement_id in agreement_ids {
+            let record = market
+                .agreement(agreement_id)
+                .export_audit_record(&payment)
+                .await?;
+            paths.push(record.write_to(dir.as_ref())?);
+        }
+        Ok(paths)
     }
 
     pub async fn create_activity(
         &self,
         agreement: &market::Agreement,
     ) -> anyhow::Result<activity::DefaultActivity> {
-        activity::DefaultActivity::create(
+        let mut activity = activity::DefaultActivity::create(
             self.client.interface()?,
             agreement.id(),
             Some(self.drop_list.clone()),
         )
-        .await
+        .await?;
+        activity.workdir = self.workdir.clone();
+        activity.batches = batches::BatchTracker::new(self.max_concurrent_batches);
+        activity.tags = Some(self.shared.tags.clone());
+        self.shared
+            .tags
+            .lock()
+            .unwrap()
+            .add_activity(activity.id(), agreement.id());
+        Ok(activity)
+    }
+
+    /// Activity created earlier, e.g. by a previous run with the same tag
+    /// store, to continue its work; see `DefaultActivity::attach_to_batch`.
+    ///
+    /// Like a created activity, it is destroyed when the session ends.
+    pub async fn attach_activity(
+        &self,
+        activity_id: &str,
+    ) -> anyhow::Result<activity::DefaultActivity> {
+        let mut activity = activity::DefaultActivity::attach(
+            self.client.interface()?,
+            activity_id,
+            Some(self.drop_list.clone()),
+        )
+        .await?;
+        activity.workdir = self.workdir.clone();
+        activity.batches = batches::BatchTracker::new(self.max_concurrent_batches);
+        activity.tags = Some(self.shared.tags.clone());
+        Ok(activity)
     }
 
     pub async fn create_secure_activity(
         &self,
         agreement: &market::Agreement,
     ) -> anyhow::Result<activity::SgxActivity> {
-        activity::SgxActivity::create(
+        self.require(Capability::SecureActivities).await?;
+        let mut activity = activity::SgxActivity::create(
             self.client.interface()?,
             agreement.id(),
             self.drop_list.clone().into(),
         )
-        .await
+        .await?;
+        activity.batches = batches::BatchTracker::new(self.max_concurrent_batches);
+        self.shared
+            .tags
+            .lock()
+            .unwrap()
+            .add_activity(activity.id(), agreement.id());
+        Ok(activity)
     }
 
     pub async fn with<F: Future>(&self, work: F) -> Option<F::Output> {
@@ -60,7 +418,31 @@ impl Session {
                 future::Either::Right(_) => None,
             }
         };
+        for url in self.shared.published.lock().unwrap().drain(..) {
+            self.drop_list.async_drop(async move {
+                gftp::close(&url).await?;
+                Ok(())
+            });
+        }
         self.drop_list.flush().await;
+        let drop_lists = self.shared.drop_lists.lock().unwrap().clone();
+        for drop_list in drop_lists {
+            drop_list.flush().await;
+        }
+        if let Some(workdir) = &self.workdir {
+            workdir.cleanup(result.is_some());
+        }
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SessionHandle>();
+    }
+}
@@ -1,29 +1,232 @@
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
 
 use crate::rest::async_drop::{CancelableDropList, DropList};
+use crate::rest::batches::{BatchGuard, BatchTracker};
+use crate::rest::market::{classify, ErrorKind};
+use crate::rest::tags::TagRegistry;
+use crate::rest::usage::{self, UsageSample, UsageVector};
+use crate::rest::workdir::{self, Workdir};
+use crate::util::{retry_if, RetryPolicy};
 use futures::future::LocalBoxFuture;
 use futures::prelude::*;
 use futures::stream::LocalBoxStream;
 use futures::{FutureExt, StreamExt};
-use std::sync::Arc;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use ya_client::activity::ActivityRequestorApi;
 pub use ya_client::activity::SecureActivityRequestorApi;
 pub use ya_client::model::activity::Credentials;
 pub use ya_client::model::activity::ExeScriptCommand;
-use ya_client::model::activity::ExeScriptRequest;
 use ya_client::model::activity::{CommandResult, ExeScriptCommandResult};
+use ya_client::model::activity::{ExeScriptRequest, State};
 
+/// Result of a single batch step.
+///
+/// `timestamp` is the time reported by the provider when the step finished.
+/// `duration` is the time since the previous step finished, so it is `None`
+/// for the first step of a batch (and if the provider's clock went back).
+///
+/// `return_code` is the exit code of a `run` step; the polled results don't
+/// carry it, so it is 0 for a successful step and read from the exe-unit's
+/// message for a failed one (`None` if the message doesn't tell).
 #[derive(Debug)]
 pub enum Event {
     StepSuccess {
+        index: usize,
         command: ExeScriptCommand,
         output: String,
+        return_code: Option<i32>,
+        timestamp: DateTime<Utc>,
+        duration: Option<Duration>,
     },
     StepFailed {
+        index: usize,
         message: String,
+        return_code: Option<i32>,
+        timestamp: DateTime<Utc>,
+        duration: Option<Duration>,
     },
 }
 
+impl Event {
+    pub fn index(&self) -> usize {
+        match self {
+            Event::StepSuccess { index, .. } | Event::StepFailed { index, .. } => *index,
+        }
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        match self {
+            Event::StepSuccess { timestamp, .. } | Event::StepFailed { timestamp, .. } => {
+                *timestamp
+            }
+        }
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        match self {
+            Event::StepSuccess { duration, .. } | Event::StepFailed { duration, .. } => *duration,
+        }
+    }
+
+    pub fn return_code(&self) -> Option<i32> {
+        match self {
+            Event::StepSuccess { return_code, .. } | Event::StepFailed { return_code, .. } => {
+                *return_code
+            }
+        }
+    }
+}
+
+/// Exit code in a message of a failed `run` step, e.g.
+/// "ExeScript command exited with code 2".
+fn parse_return_code(message: &str) -> Option<i32> {
+    const PREFIX: &str = "exited with code ";
+    let start = message.find(PREFIX)? + PREFIX.len();
+    let code = &message[start..];
+    let end = code
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || (i == 0 && c == '-')))
+        .map(|(i, _)| i)
+        .unwrap_or_else(|| code.len());
+    code[..end].parse().ok()
+}
+
+/// Output of a single batch step, see `DefaultActivity::execute_commands_with`.
+#[derive(Clone, Debug)]
+pub struct StepOutput {
+    pub index: usize,
+    /// Output of a successful step, or the error message of a failed one.
+    pub output: String,
+    pub return_code: Option<i32>,
+}
+
+/// Batch sent to an activity, see `Activity::batches`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchInfo {
+    pub batch_id: String,
+    pub status: BatchStatus,
+}
+
+/// Status of a batch as reported by the daemon.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BatchStatus {
+    /// Still running, with the first `steps_done` steps finished.
+    Running {
+        steps_done: usize,
+    },
+    Finished,
+    Failed {
+        index: usize,
+        message: String,
+    },
+    /// The daemon doesn't know the batch, e.g. it was restarted.
+    Unknown,
+}
+
+impl BatchStatus {
+    fn from_results(results: &[ExeScriptCommandResult]) -> Self {
+        if let Some(failed) = results
+            .iter()
+            .find(|r| matches!(r.result, CommandResult::Error))
+        {
+            return BatchStatus::Failed {
+                index: failed.index as usize,
+                message: failed.message.clone().unwrap_or_default(),
+            };
+        }
+        if results.iter().any(|r| r.is_batch_finished) {
+            return BatchStatus::Finished;
+        }
+        BatchStatus::Running {
+            steps_done: results.len(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self, BatchStatus::Running { .. })
+    }
+}
+
+/// How the outputs of a batch are collected.
+#[derive(Clone, Debug)]
+pub struct ExecOptions {
+    fail_on_nonzero: bool,
+}
+
+impl Default for ExecOptions {
+    fn default() -> Self {
+        ExecOptions {
+            fail_on_nonzero: true,
+        }
+    }
+}
+
+impl ExecOptions {
+    /// With `false`, a `run` step exiting with a non-zero code is returned
+    /// with its code instead of failing the collection, e.g. for `grep` or
+    /// `diff`.
+    ///
+    /// The exe-unit still stops the batch at such a step, so the following
+    /// steps don't run; put the command last, or in a batch of its own.
+    pub fn fail_on_nonzero(self, fail_on_nonzero: bool) -> Self {
+        Self {
+            fail_on_nonzero,
+            ..self
+        }
+    }
+}
+
+/// Controls the lifecycle of files used by `send_json` and `download_json`.
+#[derive(Clone, Debug)]
+pub struct JsonTransferOptions {
+    /// Remove the local temporary file once the transfer is done.
+    pub cleanup_local: bool,
+    /// Remove the file from the container once it has been downloaded.
+    pub cleanup_remote: bool,
+}
+
+impl Default for JsonTransferOptions {
+    fn default() -> Self {
+        JsonTransferOptions {
+            cleanup_local: true,
+            cleanup_remote: false,
+        }
+    }
+}
+
+/// Local temporary file, removed on drop unless kept.
+struct TempFile {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempFile {
+    fn new(keep: bool, workdir: Option<&Workdir>) -> Self {
+        let path = workdir::temp_path(workdir, "json");
+        TempFile { path, keep }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.keep {
+            if let Err(e) = std::fs::remove_file(&self.path) {
+                log::debug!("unable to remove {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
+
+/// An activity on a provider, running exe-script batches.
+///
+/// Exe-units run one batch at a time unless they support more, so `exec`
+/// fails while the previous batch is still running; allow more at once with
+/// `Session::with_max_concurrent_batches`. A batch counts as running until
+/// it and all the streams of its `events` are dropped.
 pub trait Activity {
     type RunningBatch: RunningBatch;
 
@@ -34,9 +237,57 @@ pub trait Activity {
         commands: Vec<ExeScriptCommand>,
     ) -> future::LocalBoxFuture<'static, Result<Self::RunningBatch>>;
 
+    /// Executes an exe-script given as JSON text, e.g. generated by an external tool.
+    ///
+    /// The text must be a JSON array of command objects.
+    fn exec_raw(&self, json: &str) -> future::LocalBoxFuture<'static, Result<Self::RunningBatch>> {
+        match parse_exe_script(json) {
+            Ok(commands) => self.exec(commands),
+            Err(e) => future::err(e).boxed_local(),
+        }
+    }
+
     fn credentials(&self) -> Option<Credentials>;
 
+    /// Ids of the batches of this activity which are still running.
+    fn running_batches(&self) -> Vec<String>;
+
     fn destroy(&self) -> future::LocalBoxFuture<'static, Result<()>>;
+
+    /// Batches sent to this activity and their statuses, in no particular
+    /// order, e.g. to resume waiting for the running ones after a restart
+    /// (see `DefaultActivity::attach_to_batch`).
+    fn batches(&self) -> LocalBoxFuture<'static, Result<Vec<BatchInfo>>> {
+        future::err(anyhow!("batch history is not supported")).boxed_local()
+    }
+
+    /// Samples the usage counters every `interval`, e.g. for live cost plots.
+    ///
+    /// Counters are named after `usage_vector`, see `UsageVector::from_agreement`.
+    fn usage_stream(
+        &self,
+        interval: Duration,
+        usage_vector: UsageVector,
+    ) -> LocalBoxStream<'static, Result<UsageSample>> {
+        let _ = (interval, usage_vector);
+        stream::once(future::err(anyhow!("usage is not supported"))).boxed_local()
+    }
+}
+
+/// Checks that `json` is an array of command objects, and parses it.
+fn parse_exe_script(json: &str) -> Result<Vec<ExeScriptCommand>> {
+    let value: serde_json::Value =
+        serde_json::from_str(json).context("exe-script is not valid JSON")?;
+    let commands = value
+        .as_array()
+        .ok_or_else(|| anyhow!("exe-script must be a JSON array"))?;
+    if commands.is_empty() {
+        bail!("exe-script is empty");
+    }
+    if let Some(i) = commands.iter().position(|command| !command.is_object()) {
+        bail!("exe-script command {} is not an object", i);
+    }
+    serde_json::from_value(value).context("invalid exe-script command")
 }
 
 pub trait RunningBatch {
@@ -46,10 +297,103 @@ pub trait RunningBatch {
     fn events(&self) -> stream::LocalBoxStream<'static, Result<Event>>;
 }
 
+/// Collects outputs of all the batch steps; fails on the first failed step.
+pub(crate) async fn batch_outputs(batch: &impl RunningBatch) -> Result<Vec<String>> {
+    Ok(batch_results(batch, &ExecOptions::default())
+        .await?
+        .into_iter()
+        .map(|step| step.output)
+        .collect())
+}
+
+/// Collects outputs and exit codes of all the batch steps; fails on the first
+/// failed step, unless `options` allow its exit code.
+pub(crate) async fn batch_results(
+    batch: &impl RunningBatch,
+    options: &ExecOptions,
+) -> Result<Vec<StepOutput>> {
+    let fail_on_nonzero = options.fail_on_nonzero;
+    batch
+        .events()
+        .and_then(move |event| {
+            log::debug!("Event: {:?}", event);
+            match event {
+                Event::StepFailed {
+                    index,
+                    message,
+                    return_code: Some(code),
+                    ..
+                } if !fail_on_nonzero => {
+                    log::debug!("Command [{}] exited with code {}.", index, code);
+                    future::ok(StepOutput {
+                        index,
+                        output: message,
+                        return_code: Some(code),
+                    })
+                }
+                Event::StepFailed { message, .. } => {
+                    future::err::<StepOutput, anyhow::Error>(anyhow!("Step failed: {}", message))
+                }
+                Event::StepSuccess {
+                    index,
+                    command,
+                    output,
+                    return_code,
+                    ..
+                } => {
+                    log::debug!("Command [{:?}] finished.", command);
+                    log::debug!("Command result:\n {}", output);
+                    future::ok(StepOutput {
+                        index,
+                        output,
+                        return_code,
+                    })
+                }
+            }
+        })
+        .try_collect()
+        .await
+}
+
+/// Executes `commands` on every activity, running at most `max_parallel`
+/// batches at a time.
+///
+/// Returns `(activity_id, result)` pairs in the order of `activities`;
+/// a failure on one activity does not affect the others.
+pub async fn execute_on_all<A: Activity>(
+    activities: &[A],
+    commands: Vec<ExeScriptCommand>,
+    max_parallel: usize,
+) -> Vec<(String, Result<Vec<String>>)> {
+    stream::iter(activities)
+        .map(|activity| {
+            let activity_id = activity.id().to_string();
+            let batch = activity.exec(commands.clone());
+            async move {
+                let result = match batch.await {
+                    Ok(batch) => batch_outputs(&batch).await,
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = &result {
+                    log::warn!("batch on activity [{}] failed: {:?}", activity_id, e);
+                }
+                (activity_id, result)
+            }
+        })
+        .buffered(max_parallel.max(1))
+        .collect()
+        .await
+}
+
 pub struct DefaultActivity {
     pub(crate) api: ActivityRequestorApi,
     activity_id: String,
     drop_list: Option<DropList>,
+    /// Where the files of JSON transfers go, see `Session::with_workdir`.
+    pub(crate) workdir: Option<Workdir>,
+    pub(crate) batches: BatchTracker,
+    /// Where the commands of the batches are kept for `attach_to_batch`.
+    pub(crate) tags: Option<Arc<Mutex<TagRegistry>>>,
 }
 
 impl DefaultActivity {
@@ -69,6 +413,81 @@ impl DefaultActivity {
             api,
             activity_id,
             drop_list,
+            workdir: None,
+            batches: BatchTracker::default(),
+            tags: None,
+        })
+    }
+
+    /// Activity `activity_id` created earlier, e.g. by a previous run;
+    /// fails if it doesn't exist or is terminated.
+    pub(crate) async fn attach(
+        api: ActivityRequestorApi,
+        activity_id: &str,
+        drop_list: Option<DropList>,
+    ) -> Result<Self> {
+        let state = api
+            .state()
+            .get_state(activity_id)
+            .await
+            .with_context(|| format!("failed to query activity {:?}", activity_id))?;
+        if state.state.0 == State::Terminated {
+            bail!("activity {:?} is terminated", activity_id);
+        }
+        Ok(Self {
+            api,
+            activity_id: activity_id.to_string(),
+            drop_list,
+            workdir: None,
+            batches: BatchTracker::default(),
+            tags: None,
+        })
+    }
+
+    /// Batch `batch_id` sent earlier to this activity, e.g. by a previous
+    /// run, so that its `events` can be awaited again.
+    ///
+    /// The daemon only reports the results of the commands, so they are
+    /// recovered from the batches sent by sessions sharing the tag store
+    /// (see `Session::with_tag_store`); fails for other batches. Takes a
+    /// slot of `Session::with_max_concurrent_batches` like `exec`.
+    ///
+    /// ## Example:
+    /// ```no_run
+    /// use yarapi::rest::{RunningBatch, Session};
+    /// use futures::prelude::*;
+    ///
+    /// # async fn run(activity_id: &str, batch_id: &str) -> anyhow::Result<()> {
+    /// let session = Session::with_app_key("app-key").with_tag_store("tags.json")?;
+    /// let activity = session.attach_activity(activity_id).await?;
+    /// let batch = activity.attach_to_batch(batch_id)?;
+    /// let events: Vec<_> = batch.events().try_collect().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn attach_to_batch(&self, batch_id: &str) -> Result<DefaultBatch> {
+        let commands = self
+            .tags
+            .as_ref()
+            .and_then(|tags| {
+                tags.lock()
+                    .unwrap()
+                    .batch_commands(&self.activity_id, batch_id)
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "commands of batch {:?} of activity {:?} are unknown; it must be sent by a session with the same tag store",
+                    batch_id,
+                    self.activity_id
+                )
+            })?;
+        let guard = self.batches.reserve(&self.activity_id)?;
+        Ok(DefaultBatch {
+            api: self.api.clone(),
+            activity_id: self.activity_id.clone(),
+            guard: guard.started(batch_id),
+            batch_id: batch_id.to_string(),
+            commands: commands.into(),
         })
     }
 
@@ -77,23 +496,146 @@ impl DefaultActivity {
         commands: Vec<ExeScriptCommand>,
     ) -> anyhow::Result<Vec<String>> {
         let batch = self.exec(commands).await?;
-        batch
-            .events()
-            .and_then(|event| {
-                log::debug!("Event: {:?}", event);
-                match event {
-                    Event::StepFailed { message } => {
-                        future::err::<String, anyhow::Error>(anyhow!("Step failed: {}", message))
-                    }
-                    Event::StepSuccess { command, output } => {
-                        log::debug!("Command [{:?}] finished.", command);
-                        log::debug!("Command result:\n {}", output);
-                        future::ok(output)
-                    }
+        batch_outputs(&batch).await
+    }
+
+    /// Like `execute_commands`, with the exit codes of the steps.
+    ///
+    /// ## Example:
+    /// ```no_run
+    /// # async fn run(activity: yarapi::rest::activity::DefaultActivity) -> anyhow::Result<()> {
+    /// use yarapi::rest::{ExecOptions, ExeScriptCommand};
+    ///
+    /// let steps = activity
+    ///     .execute_commands_with(
+    ///         vec![ExeScriptCommand::Run {
+    ///             entry_point: "/bin/grep".to_string(),
+    ///             args: vec!["-q".to_string(), "ERROR".to_string(), "/golem/output/log.txt".to_string()],
+    ///             capture: None,
+    ///         }],
+    ///         &ExecOptions::default().fail_on_nonzero(false),
+    ///     )
+    ///     .await?;
+    /// let found = steps[0].return_code == Some(0);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn execute_commands_with(
+        &self,
+        commands: Vec<ExeScriptCommand>,
+        options: &ExecOptions,
+    ) -> anyhow::Result<Vec<StepOutput>> {
+        let batch = self.exec(commands).await?;
+        batch_results(&batch, options).await
+    }
+
+    /// Downloads all files from `remote_dir` in the container to `local_dir`.
+    ///
+    /// Files are listed with `ls`, so the runtime has to provide it (as the VM
+    /// runtime does). Subdirectories are skipped. Returns paths of the
+    /// downloaded files.
+    pub async fn collect_outputs(
+        &self,
+        remote_dir: &str,
+        local_dir: &Path,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let remote_dir = remote_dir.trim_end_matches('/');
+        let listing = self
+            .execute_commands(vec![ExeScriptCommand::Run {
+                entry_point: "/bin/ls".to_string(),
+                args: vec!["-1p".to_string(), remote_dir.to_string()],
+                capture: None,
+            }])
+            .await
+            .with_context(|| format!("failed to list {}", remote_dir))?;
+        let files = listing
+            .iter()
+            .flat_map(|output| output.lines())
+            .map(str::trim)
+            .filter(|name| !name.is_empty() && !name.ends_with('/'))
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            return Ok(vec![]);
+        }
+
+        tokio::fs::create_dir_all(local_dir)
+            .await
+            .with_context(|| format!("unable to create {}", local_dir.display()))?;
+        let mut commands = vec![];
+        let mut paths = vec![];
+        for name in files {
+            let path = local_dir.join(&name);
+            let url = gftp::open_for_upload(&path).await?;
+            commands.push(serde_json::from_value(serde_json::json!({
+                "transfer": {
+                    "from": format!("container:{}/{}", remote_dir, name),
+                    "to": url.to_string(),
                 }
-            })
-            .try_collect()
+            }))?);
+            paths.push(path);
+        }
+        self.execute_commands(commands)
+            .await
+            .with_context(|| format!("failed to download files from {}", remote_dir))?;
+
+        Ok(paths)
+    }
+
+    /// Serializes `value` to JSON and uploads it to `remote_path` in the container.
+    pub async fn send_json(
+        &self,
+        value: &impl Serialize,
+        remote_path: &str,
+        options: &JsonTransferOptions,
+    ) -> anyhow::Result<()> {
+        let file = TempFile::new(!options.cleanup_local, self.workdir.as_ref());
+        tokio::fs::write(&file.path, serde_json::to_vec(value)?).await?;
+        let url = gftp::publish(&file.path).await?;
+
+        let command = serde_json::from_value(serde_json::json!({
+            "transfer": {
+                "from": url.to_string(),
+                "to": format!("container:{}", remote_path),
+            }
+        }))?;
+        let result = self.execute_commands(vec![command]).await;
+        if let Err(e) = gftp::close(&url).await {
+            log::warn!("unable to unpublish {}: {}", url, e);
+        }
+        result.with_context(|| format!("failed to send json to {}", remote_path))?;
+        Ok(())
+    }
+
+    /// Downloads `remote_path` from the container and deserializes it from JSON.
+    pub async fn download_json<T: DeserializeOwned>(
+        &self,
+        remote_path: &str,
+        options: &JsonTransferOptions,
+    ) -> anyhow::Result<T> {
+        let file = TempFile::new(!options.cleanup_local, self.workdir.as_ref());
+        let url = gftp::open_for_upload(&file.path).await?;
+
+        let mut commands = vec![serde_json::from_value(serde_json::json!({
+            "transfer": {
+                "from": format!("container:{}", remote_path),
+                "to": url.to_string(),
+            }
+        }))?];
+        if options.cleanup_remote {
+            commands.push(ExeScriptCommand::Run {
+                entry_point: "/bin/rm".to_string(),
+                args: vec!["-f".to_string(), remote_path.to_string()],
+                capture: None,
+            });
+        }
+        self.execute_commands(commands)
             .await
+            .with_context(|| format!("failed to download json from {}", remote_path))?;
+
+        let contents = tokio::fs::read(&file.path).await?;
+        Ok(serde_json::from_slice(&contents)
+            .with_context(|| format!("invalid json in {}", remote_path))?)
     }
 }
 
@@ -102,12 +644,16 @@ impl Drop for DefaultActivity {
         if let Some(ref drop_list) = self.drop_list {
             let api = self.api.clone();
             let id = self.activity_id.clone();
+            let tags = self.tags.clone();
             drop_list.async_drop(async move {
                 api.control()
                     .destroy_activity(&id)
                     .await
                     .with_context(|| format!("Failed to auto destroy Activity: {:?}", id))?;
                 log::debug!(target:"yarapi::drop", "Activity {:?} destroyed", id);
+                if let Some(tags) = tags {
+                    tags.lock().unwrap().remove_batches(&id);
+                }
                 Ok(())
             })
         }
@@ -127,17 +673,57 @@ impl Activity for DefaultActivity {
     ) -> future::LocalBoxFuture<'static, Result<Self::RunningBatch>> {
         let api = self.api.clone();
         let activity_id = self.activity_id.clone();
+        let guard = self.batches.reserve(&activity_id);
+        let tags = self.tags.clone();
 
         async move {
+            let guard = guard?;
             let request = ExeScriptRequest {
                 text: serde_json::to_string(&commands)?,
             };
 
             let batch_id = api.control().exec(request, &activity_id).await?;
+            if let Some(tags) = tags {
+                tags.lock()
+                    .unwrap()
+                    .add_batch(&activity_id, &batch_id, &commands);
+            }
+
+            Ok(DefaultBatch {
+                api,
+                activity_id,
+                guard: guard.started(&batch_id),
+                batch_id,
+                commands: commands.into(),
+            })
+        }
+        .boxed_local()
+    }
+
+    /// Sends `json` as is, so that fields unknown to `ExeScriptCommand`
+    /// are passed to the exe-unit.
+    fn exec_raw(&self, json: &str) -> LocalBoxFuture<'static, Result<Self::RunningBatch>> {
+        let api = self.api.clone();
+        let activity_id = self.activity_id.clone();
+        let text = json.to_string();
+        let guard = self.batches.reserve(&activity_id);
+        let tags = self.tags.clone();
+
+        async move {
+            let guard = guard?;
+            let commands = parse_exe_script(&text)?;
+            let request = ExeScriptRequest { text };
+            let batch_id = api.control().exec(request, &activity_id).await?;
+            if let Some(tags) = tags {
+                tags.lock()
+                    .unwrap()
+                    .add_batch(&activity_id, &batch_id, &commands);
+            }
 
             Ok(DefaultBatch {
                 api,
                 activity_id,
+                guard: guard.started(&batch_id),
                 batch_id,
                 commands: commands.into(),
             })
@@ -149,41 +735,100 @@ impl Activity for DefaultActivity {
         None
     }
 
+    fn running_batches(&self) -> Vec<String> {
+        self.batches.running()
+    }
+
+    /// The daemon can't list the batches of an activity, so these are the
+    /// batches sent by sessions sharing the tag store (see
+    /// `Session::with_tag_store`), with their statuses queried from the
+    /// daemon.
+    fn batches(&self) -> LocalBoxFuture<'static, Result<Vec<BatchInfo>>> {
+        let api = self.api.clone();
+        let activity_id = self.activity_id.clone();
+        let batch_ids = match &self.tags {
+            Some(tags) => tags.lock().unwrap().batch_ids(&activity_id),
+            None => vec![],
+        };
+        async move {
+            let mut batches = vec![];
+            for batch_id in batch_ids {
+                let status = match api
+                    .control()
+                    .get_exec_batch_results(&activity_id, &batch_id, None, None)
+                    .await
+                {
+                    Ok(results) => BatchStatus::from_results(&results),
+                    Err(e) => {
+                        log::debug!("no results of batch [{}]: {}", batch_id, e);
+                        BatchStatus::Unknown
+                    }
+                };
+                batches.push(BatchInfo { batch_id, status });
+            }
+            Ok(batches)
+        }
+        .boxed_local()
+    }
+
     fn destroy(&self) -> LocalBoxFuture<'static, Result<()>> {
         let api = self.api.clone();
         let activity_id = self.activity_id.clone();
+        let tags = self.tags.clone();
         async move {
             api.control()
                 .destroy_activity(&activity_id)
                 .await
-                .with_context(|| format!("failed to destroy activity: {:?}", activity_id))
+                .with_context(|| format!("failed to destroy activity: {:?}", activity_id))?;
+            if let Some(tags) = tags {
+                tags.lock().unwrap().remove_batches(&activity_id);
+            }
+            Ok(())
         }
         .boxed_local()
     }
+
+    fn usage_stream(
+        &self,
+        interval: Duration,
+        usage_vector: UsageVector,
+    ) -> LocalBoxStream<'static, Result<UsageSample>> {
+        usage::usage_stream(
+            self.api.clone(),
+            self.activity_id.clone(),
+            interval,
+            usage_vector,
+        )
+    }
 }
 
 pub struct DefaultBatch {
     pub(crate) api: ActivityRequestorApi,
     pub(crate) activity_id: String,
+    pub(crate) guard: Arc<BatchGuard>,
     batch_id: String,
     commands: Arc<[ExeScriptCommand]>,
 }
 
+/// Polls the results of a batch; the batch counts as running while the
+/// stream holds `guard`.
 fn generate_events<Generator, GResult>(
     generator: Generator,
     commands: Arc<[ExeScriptCommand]>,
+    guard: Arc<BatchGuard>,
 ) -> impl Stream<Item = Result<Event>>
 where
     Generator: FnMut(Option<usize>) -> GResult,
     GResult: Future<Output = Result<Vec<ExeScriptCommandResult>>>,
 {
     stream::try_unfold(
-        (generator, commands, None, false),
-        |(mut generator, commands, command_index, finish)| async move {
+        (generator, commands, None, None, false),
+        |(mut generator, commands, command_index, mut last_timestamp, finish)| async move {
             if finish {
                 return Ok(None);
             }
-            let result = generator(command_index).await?;
+            let mut result = generator(command_index).await?;
+            result.sort_by_key(|r| r.index);
 
             let last_index = result
                 .iter()
@@ -191,38 +836,62 @@ where
                 .max()
                 .or(command_index);
             let is_last = result.iter().any(|r| r.is_batch_finished);
-            let events = {
-                let commands = commands.clone();
-                result
-                    .into_iter()
-                    .filter(move |s| Some(s.index as usize) >= command_index)
-                    .map(move |step| {
-                        let command: &ExeScriptCommand =
-                            commands.get(step.index as usize).ok_or_else(|| {
-                                anyhow::anyhow!(
-                                    "invalid command response with index: {}",
-                                    step.index
-                                )
-                            })?;
-                        match step.result {
-                            CommandResult::Ok => Ok(Event::StepSuccess {
-                                command: command.clone(),
-                                output: step.message.unwrap_or_default(),
-                            }),
-                            CommandResult::Error => Ok(Event::StepFailed {
-                                message: step.message.unwrap_or_default(),
-                            }),
-                        }
-                    })
-            };
+            let mut events = vec![];
+            for step in result {
+                let index = step.index as usize;
+                if Some(index) < command_index {
+                    continue;
+                }
+                let command: &ExeScriptCommand = match commands.get(index) {
+                    Some(command) => command,
+                    None => {
+                        events.push(Err(anyhow!(
+                            "invalid command response with index: {}",
+                            index
+                        )));
+                        continue;
+                    }
+                };
+                let is_run = matches!(command, ExeScriptCommand::Run { .. });
+                let timestamp = DateTime::<Utc>::from_utc(step.event_date, Utc);
+                let duration = last_timestamp.and_then(|last| (timestamp - last).to_std().ok());
+                last_timestamp = Some(timestamp);
+                events.push(match step.result {
+                    CommandResult::Ok => Ok(Event::StepSuccess {
+                        index,
+                        command: command.clone(),
+                        output: step.message.unwrap_or_default(),
+                        return_code: if is_run { Some(0) } else { None },
+                        timestamp,
+                        duration,
+                    }),
+                    CommandResult::Error => {
+                        let message = step.message.unwrap_or_default();
+                        Ok(Event::StepFailed {
+                            index,
+                            return_code: if is_run {
+                                parse_return_code(&message)
+                            } else {
+                                None
+                            },
+                            message,
+                            timestamp,
+                            duration,
+                        })
+                    }
+                });
+            }
 
             Ok::<_, anyhow::Error>(Some((
                 stream::iter(events),
-                (generator, commands, last_index, is_last),
+                (generator, commands, last_index, last_timestamp, is_last),
             )))
         },
     )
     .try_flatten()
+    .inspect(move |_| {
+        let _ = &guard;
+    })
 }
 
 impl RunningBatch for DefaultBatch {
@@ -254,6 +923,7 @@ impl RunningBatch for DefaultBatch {
                 }
             },
             commands,
+            self.guard.clone(),
         )
         .boxed_local()
     }
@@ -264,6 +934,7 @@ pub struct SgxActivity {
     api: ActivityRequestorApi,
     activity_id: String,
     drop_list: CancelableDropList,
+    pub(crate) batches: BatchTracker,
 }
 
 impl Drop for SgxActivity {
@@ -289,13 +960,23 @@ impl SgxActivity {
         agreement_id: &str,
         drop_list: CancelableDropList,
     ) -> Result<Self> {
-        let secure_api = api
-            .control()
-            .create_secure_activity(agreement_id)
-            .await
-            .with_context(|| {
-                format!("failed to create activity for agreement {:?}", agreement_id)
-            })?;
+        // Only retried if yagna wasn't reached, so no activity was created.
+        let secure_api = retry_if(
+            &RetryPolicy::default(),
+            || {
+                let api = api.clone();
+                let agreement_id = agreement_id.to_string();
+                async move {
+                    api.control()
+                        .create_secure_activity(&agreement_id)
+                        .await
+                        .map_err(anyhow::Error::from)
+                }
+            },
+            |e| classify(e) == ErrorKind::Connection,
+        )
+        .await
+        .with_context(|| format!("failed to create activity for agreement {:?}", agreement_id))?;
         let activity_id = secure_api.activity_id();
 
         Ok(Self {
@@ -303,6 +984,7 @@ impl SgxActivity {
             secure_api,
             activity_id,
             drop_list,
+            batches: BatchTracker::default(),
         })
     }
 }
@@ -319,11 +1001,14 @@ impl Activity for SgxActivity {
         commands: Vec<ExeScriptCommand>,
     ) -> LocalBoxFuture<'static, Result<Self::RunningBatch>> {
         let api = self.secure_api.clone();
+        let guard = self.batches.reserve(&self.activity_id);
         async move {
+            let guard = guard?;
             let batch_commands = commands.clone().into();
             let batch_id = api.exec(commands).await?;
             Ok(SgxBatch {
                 api,
+                guard: guard.started(&batch_id),
                 batch_id,
                 commands: batch_commands,
             })
@@ -335,6 +1020,10 @@ impl Activity for SgxActivity {
         Some(self.secure_api.proof())
     }
 
+    fn running_batches(&self) -> Vec<String> {
+        self.batches.running()
+    }
+
     fn destroy(&self) -> LocalBoxFuture<'static, Result<()>> {
         let api = self.api.clone();
         let activity_id = self.activity_id.clone();
@@ -346,10 +1035,24 @@ impl Activity for SgxActivity {
         }
         .boxed_local()
     }
+
+    fn usage_stream(
+        &self,
+        interval: Duration,
+        usage_vector: UsageVector,
+    ) -> LocalBoxStream<'static, Result<UsageSample>> {
+        usage::usage_stream(
+            self.api.clone(),
+            self.activity_id.clone(),
+            interval,
+            usage_vector,
+        )
+    }
 }
 
 pub struct SgxBatch {
     api: SecureActivityRequestorApi,
+    guard: Arc<BatchGuard>,
     batch_id: String,
     commands: Arc<[ExeScriptCommand]>,
 }
@@ -372,18 +1075,39 @@ impl RunningBatch for SgxBatch {
                 let api = api.clone();
                 let batch_id = batch_id.clone();
                 async move {
-                    loop {
-                        match api.get_exec_batch_results(&batch_id, Some(10.0), idx).await {
-                            Ok(v) => return Ok(v),
-                            Err(ya_client::Error::TimeoutError { .. }) => (),
-                            Err(ya_client::Error::InternalError(ref msg)) if msg == "Timeout" => (),
-                            Err(e) => return Err(e.into()),
-                        }
-                    }
+                    // The long poll times out while the step is running.
+                    let policy = RetryPolicy::new(u32::MAX)
+                        .with_backoff(Duration::from_secs(0), Duration::from_secs(0));
+                    Ok(retry_if(
+                        &policy,
+                        || api.get_exec_batch_results(&batch_id, Some(10.0), idx),
+                        |e| match e {
+                            ya_client::Error::TimeoutError { .. } => true,
+                            ya_client::Error::InternalError(msg) => msg == "Timeout",
+                            _ => false,
+                        },
+                    )
+                    .await?)
                 }
             },
             self.commands.clone(),
+            self.guard.clone(),
         )
         .boxed_local()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_return_code() {
+        assert_eq!(
+            parse_return_code("ExeScript command exited with code 2"),
+            Some(2)
+        );
+        assert_eq!(parse_return_code("exited with code -9: killed"), Some(-9));
+        assert_eq!(parse_return_code("transfer failed"), None);
+    }
+}
@@ -0,0 +1,172 @@
+use chrono::{DateTime, Utc};
+use ya_client::model::market::{AgreementOperationEvent, Reason};
+
+/// Side of an agreement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Party {
+    Requestor,
+    Provider,
+}
+
+/// Event of an agreement's lifecycle, see `Market::agreement_events_stream`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AgreementEvent {
+    /// The provider approved the agreement.
+    Approved {
+        agreement_id: String,
+        date: DateTime<Utc>,
+    },
+    /// The provider rejected the agreement.
+    Rejected {
+        agreement_id: String,
+        date: DateTime<Utc>,
+        reason: Option<String>,
+    },
+    /// The requestor cancelled the agreement before it was approved.
+    Cancelled {
+        agreement_id: String,
+        date: DateTime<Utc>,
+        reason: Option<String>,
+    },
+    /// Either side terminated the agreement; `by` is `None` if the market
+    /// doesn't tell which.
+    Terminated {
+        agreement_id: String,
+        date: DateTime<Utc>,
+        by: Option<Party>,
+        reason: Option<String>,
+    },
+}
+
+impl AgreementEvent {
+    pub fn agreement_id(&self) -> &str {
+        match self {
+            AgreementEvent::Approved { agreement_id, .. }
+            | AgreementEvent::Rejected { agreement_id, .. }
+            | AgreementEvent::Cancelled { agreement_id, .. }
+            | AgreementEvent::Terminated { agreement_id, .. } => agreement_id,
+        }
+    }
+
+    pub fn date(&self) -> DateTime<Utc> {
+        match self {
+            AgreementEvent::Approved { date, .. }
+            | AgreementEvent::Rejected { date, .. }
+            | AgreementEvent::Cancelled { date, .. }
+            | AgreementEvent::Terminated { date, .. } => *date,
+        }
+    }
+
+    /// Whether the agreement can't be used after the event.
+    pub fn is_terminal(&self) -> bool {
+        !matches!(self, AgreementEvent::Approved { .. })
+    }
+
+    /// Reason given for rejecting, cancelling or terminating the agreement.
+    pub fn termination_reason(&self) -> Option<&str> {
+        match self {
+            AgreementEvent::Approved { .. } => None,
+            AgreementEvent::Rejected { reason, .. }
+            | AgreementEvent::Cancelled { reason, .. }
+            | AgreementEvent::Terminated { reason, .. } => reason.as_deref(),
+        }
+    }
+
+    /// Side which caused the event.
+    pub fn by_whom(&self) -> Option<Party> {
+        match self {
+            AgreementEvent::Approved { .. } | AgreementEvent::Rejected { .. } => {
+                Some(Party::Provider)
+            }
+            AgreementEvent::Cancelled { .. } => Some(Party::Requestor),
+            AgreementEvent::Terminated { by, .. } => *by,
+        }
+    }
+}
+
+fn message(reason: Option<Reason>) -> Option<String> {
+    reason.map(|reason| reason.message)
+}
+
+/// Reads the terminator from the event's JSON, so that it doesn't depend on
+/// the model's type of the field.
+fn terminator(event: &AgreementOperationEvent) -> Option<Party> {
+    let value = serde_json::to_value(event).ok()?;
+    match value.get("terminator")?.as_str()?.to_lowercase().as_str() {
+        "requestor" => Some(Party::Requestor),
+        "provider" => Some(Party::Provider),
+        _ => None,
+    }
+}
+
+impl From<AgreementOperationEvent> for AgreementEvent {
+    fn from(event: AgreementOperationEvent) -> Self {
+        let by = terminator(&event);
+        match event {
+            AgreementOperationEvent::AgreementApprovedEvent {
+                agreement_id,
+                event_date,
+                ..
+            } => AgreementEvent::Approved {
+                agreement_id,
+                date: event_date,
+            },
+            AgreementOperationEvent::AgreementRejectedEvent {
+                agreement_id,
+                event_date,
+                reason,
+                ..
+            } => AgreementEvent::Rejected {
+                agreement_id,
+                date: event_date,
+                reason: message(reason),
+            },
+            AgreementOperationEvent::AgreementCancelledEvent {
+                agreement_id,
+                event_date,
+                reason,
+                ..
+            } => AgreementEvent::Cancelled {
+                agreement_id,
+                date: event_date,
+                reason: message(reason),
+            },
+            AgreementOperationEvent::AgreementTerminatedEvent {
+                agreement_id,
+                event_date,
+                reason,
+                ..
+            } => AgreementEvent::Terminated {
+                agreement_id,
+                date: event_date,
+                by,
+                reason: message(reason),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_helpers() {
+        let date = Utc::now();
+        let approved = AgreementEvent::Approved {
+            agreement_id: "a".to_string(),
+            date,
+        };
+        let terminated = AgreementEvent::Terminated {
+            agreement_id: "a".to_string(),
+            date,
+            by: Some(Party::Provider),
+            reason: Some("shutdown".to_string()),
+        };
+        assert!(!approved.is_terminal());
+        assert_eq!(approved.termination_reason(), None);
+        assert!(terminated.is_terminal());
+        assert_eq!(terminated.termination_reason(), Some("shutdown"));
+        assert_eq!(terminated.by_whom(), Some(Party::Provider));
+    }
+}
@@ -25,6 +25,38 @@ impl DropList {
             let _ = rx.await;
         }
     }
+
+    /// `Send` handle flushing this list from other threads; must be called
+    /// within a local task set.
+    pub fn flusher(&self) -> DropFlusher {
+        let (tx, mut rx) = mpsc::unbounded::<oneshot::Sender<()>>();
+        let sender = self.0.clone();
+        // Queued behind the pending actions, so the flush waits for them.
+        tokio::task::spawn_local(async move {
+            while let Some(resp) = rx.next().await {
+                let _ = sender.unbounded_send(Command::Sync(resp));
+            }
+        });
+        DropFlusher(tx)
+    }
+}
+
+/// Flushes a `DropList` living on another thread, see `DropList::flusher`.
+#[derive(Clone)]
+pub struct DropFlusher(mpsc::UnboundedSender<oneshot::Sender<()>>);
+
+impl DropFlusher {
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self.0.unbounded_send(tx).is_ok() {
+            let _ = rx.await;
+        }
+    }
+
+    /// Whether the thread of the list is gone.
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
 }
 
 impl Default for DropList {
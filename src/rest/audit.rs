@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha3::{Digest, Sha3_256};
+use std::path::{Path, PathBuf};
+
+use crate::rest::{Agreement, Payment};
+
+/// Agreement with its payments, for archiving, see `Agreement::export_audit_record`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    pub agreement_id: String,
+    pub exported_at: DateTime<Utc>,
+    /// The agreement as returned by yagna: demand, offer, timestamps and
+    /// the signatures of both parties, if any.
+    pub agreement: serde_json::Value,
+    /// Hex-encoded SHA3-256 of `agreement` serialized as JSON.
+    pub content_digest: String,
+    pub invoices: Vec<serde_json::Value>,
+    pub debit_notes: Vec<serde_json::Value>,
+}
+
+impl AuditRecord {
+    /// Whether `agreement` hasn't been modified since the export.
+    pub fn verify_digest(&self) -> Result<bool> {
+        Ok(digest(&self.agreement)? == self.content_digest)
+    }
+
+    /// Writes the record to `<dir>/<agreement id>.json`.
+    pub fn write_to(&self, dir: &Path) -> Result<PathBuf> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("unable to create {}", dir.display()))?;
+        let path = dir.join(format!("{}.json", self.agreement_id));
+        std::fs::write(&path, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("unable to write audit record {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+impl Agreement {
+    /// Collects the agreement content with its invoices and debit notes.
+    pub async fn export_audit_record(&self, payment: &Payment) -> Result<AuditRecord> {
+        let agreement_id = self.id().to_string();
+        let agreement = serde_json::to_value(self.content().await?)?;
+        let invoices = payment.invoices(&agreement_id).await?;
+        let debit_notes = payment.debit_notes(&agreement_id).await?;
+        Ok(AuditRecord {
+            content_digest: digest(&agreement)?,
+            agreement_id,
+            exported_at: Utc::now(),
+            agreement,
+            invoices: invoices
+                .into_iter()
+                .map(serde_json::to_value)
+                .collect::<Result<_, _>>()?,
+            debit_notes: debit_notes
+                .into_iter()
+                .map(serde_json::to_value)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+fn digest(agreement: &serde_json::Value) -> Result<String> {
+    Ok(format!(
+        "{:x}",
+        Sha3_256::digest(&serde_json::to_vec(agreement)?)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_digest() {
+        let agreement = serde_json::json!({"agreementId": "a1", "state": "Approved"});
+        let mut record = AuditRecord {
+            agreement_id: "a1".to_string(),
+            exported_at: Utc::now(),
+            content_digest: digest(&agreement).unwrap(),
+            agreement,
+            invoices: vec![],
+            debit_notes: vec![],
+        };
+        assert!(record.verify_digest().unwrap());
+
+        record.agreement["state"] = "Terminated".into();
+        assert!(!record.verify_digest().unwrap());
+    }
+}
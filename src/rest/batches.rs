@@ -0,0 +1,114 @@
+use anyhow::{bail, Result};
+use std::sync::{Arc, Mutex};
+
+/// Batches running on one activity, limited by
+/// `Session::with_max_concurrent_batches`.
+#[derive(Clone)]
+pub(crate) struct BatchTracker {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    max_concurrent: usize,
+    /// Slots taken, also by batches still being sent to the exe-unit.
+    in_use: usize,
+    running: Vec<String>,
+}
+
+impl BatchTracker {
+    pub fn new(max_concurrent: usize) -> Self {
+        BatchTracker {
+            state: Arc::new(Mutex::new(State {
+                max_concurrent: max_concurrent.max(1),
+                in_use: 0,
+                running: vec![],
+            })),
+        }
+    }
+
+    /// Takes a slot for a new batch, released when the returned guard is
+    /// dropped; fails if all slots are taken.
+    pub fn reserve(&self, activity_id: &str) -> Result<BatchGuard> {
+        let mut state = self.state.lock().unwrap();
+        if state.in_use >= state.max_concurrent {
+            bail!(
+                "activity [{}] already runs {} batch(es) {:?}; raise Session::with_max_concurrent_batches to run more at once",
+                activity_id,
+                state.in_use,
+                state.running
+            );
+        }
+        state.in_use += 1;
+        Ok(BatchGuard {
+            tracker: self.clone(),
+            batch_id: None,
+        })
+    }
+
+    pub fn running(&self) -> Vec<String> {
+        self.state.lock().unwrap().running.clone()
+    }
+}
+
+impl Default for BatchTracker {
+    fn default() -> Self {
+        BatchTracker::new(1)
+    }
+}
+
+/// Slot of a batch; shared by the batch and its event streams, so that the
+/// batch counts as running until all of them are dropped.
+pub(crate) struct BatchGuard {
+    tracker: BatchTracker,
+    batch_id: Option<String>,
+}
+
+impl BatchGuard {
+    pub fn started(mut self, batch_id: &str) -> Arc<Self> {
+        self.tracker
+            .state
+            .lock()
+            .unwrap()
+            .running
+            .push(batch_id.to_string());
+        self.batch_id = Some(batch_id.to_string());
+        Arc::new(self)
+    }
+}
+
+impl Drop for BatchGuard {
+    fn drop(&mut self) {
+        let mut state = match self.tracker.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        state.in_use = state.in_use.saturating_sub(1);
+        if let Some(batch_id) = &self.batch_id {
+            state.running.retain(|id| id != batch_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reserve() {
+        let tracker = BatchTracker::new(2);
+        let first = tracker.reserve("a").unwrap().started("b1");
+        let pending = tracker.reserve("a").unwrap();
+        assert!(tracker.reserve("a").is_err());
+        assert_eq!(tracker.running(), vec!["b1".to_string()]);
+
+        drop(pending);
+        let second = tracker.reserve("a").unwrap().started("b2");
+        let events = first.clone();
+        drop(first);
+        assert_eq!(tracker.running(), vec!["b1".to_string(), "b2".to_string()]);
+        drop(events);
+        assert_eq!(tracker.running(), vec!["b2".to_string()]);
+        drop(second);
+        assert!(tracker.running().is_empty());
+    }
+}
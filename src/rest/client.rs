@@ -0,0 +1,117 @@
+use anyhow::{anyhow, Context, Result};
+use futures::prelude::*;
+use std::time::Duration;
+
+use crate::rest::market::{classify, ErrorKind};
+use crate::util::{retry_if, RetryPolicy};
+
+/// Timeouts and retries of the REST calls, see `Session::with_client_config`.
+///
+/// Queries (e.g. getting an agreement) are retried, as they can be repeated
+/// safely; calls changing state (e.g. creating an agreement) only time out.
+/// Long polls, like collecting market events, have their own timeouts.
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+    /// How many times a failed query is retried; 4xx responses are not.
+    pub read_retries: u32,
+    /// Delay before the first retry; doubled for every next one.
+    pub retry_backoff: Duration,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            read_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(60),
+            read_retries: 3,
+            retry_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Runs an idempotent query, retrying it on failures and timeouts.
+    pub(crate) async fn read<T, E, F, Fut>(&self, what: &str, mut call: F) -> Result<T>
+    where
+        E: Into<anyhow::Error>,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let policy = RetryPolicy {
+            max_retries: self.read_retries,
+            initial_backoff: self.retry_backoff,
+            ..RetryPolicy::default()
+        };
+        let timeout = self.read_timeout;
+        retry_if(
+            &policy,
+            || {
+                let call = call();
+                async move {
+                    match tokio::time::timeout(timeout, call).await {
+                        Ok(result) => result.map_err(Into::into),
+                        Err(_) => Err(anyhow!("timed out after {:?}", timeout)),
+                    }
+                }
+            },
+            |e| classify(e) != ErrorKind::Client,
+        )
+        .await
+        .with_context(|| format!("{} failed", what))
+    }
+
+    /// Runs a call changing state, which is not retried.
+    pub(crate) async fn write<T, E>(
+        &self,
+        what: &str,
+        call: impl Future<Output = Result<T, E>>,
+    ) -> Result<T>
+    where
+        E: Into<anyhow::Error>,
+    {
+        match tokio::time::timeout(self.write_timeout, call).await {
+            Ok(result) => result
+                .map_err(Into::into)
+                .with_context(|| format!("{} failed", what)),
+            Err(_) => Err(anyhow!("{} timed out after {:?}", what, self.write_timeout)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[tokio::test]
+    async fn test_read_retries() {
+        let config = ClientConfig {
+            retry_backoff: Duration::from_millis(1),
+            ..ClientConfig::default()
+        };
+        let calls = Cell::new(0);
+        let result = config
+            .read("query", || {
+                calls.set(calls.get() + 1);
+                future::ready(match calls.get() {
+                    1 => Err(anyhow!("error sending request: connection reset")),
+                    _ => Ok(calls.get()),
+                })
+            })
+            .await;
+        assert_eq!(result.unwrap(), 2);
+
+        // Only 4xx responses of yagna are final, not numbers in messages.
+        calls.set(0);
+        let result: Result<()> = config
+            .read("query", || {
+                calls.set(calls.get() + 1);
+                future::ready(Err(anyhow!("agreement 0x404ab1: no response")))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), config.read_retries as usize + 1);
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{anyhow, Context, Result};
+use semver::Version;
+use serde::Deserialize;
+use std::fmt;
+use ya_client::web::WebClient;
+
+const DEFAULT_API_URL: &str = "http://127.0.0.1:7465";
+
+/// Daemon features which not every yagna version supports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    /// Streaming of the command output, see `rest::streaming`.
+    Streaming,
+    /// Activities in a Trusted Execution Environment.
+    SecureActivities,
+    Vpn,
+}
+
+impl Capability {
+    /// The first yagna version supporting the capability.
+    fn min_version(&self) -> Version {
+        match self {
+            Capability::SecureActivities => Version::new(0, 5, 0),
+            Capability::Streaming => Version::new(0, 6, 0),
+            Capability::Vpn => Version::new(0, 8, 0),
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Capability::Streaming => write!(f, "streaming"),
+            Capability::SecureActivities => write!(f, "secure activities"),
+            Capability::Vpn => write!(f, "VPN"),
+        }
+    }
+}
+
+/// Version of the yagna daemon, see `Session::daemon_info`.
+#[derive(Clone, Debug)]
+pub struct DaemonInfo {
+    pub version: Version,
+    /// Version name reported by the daemon, e.g. with the git commit.
+    pub name: String,
+}
+
+impl DaemonInfo {
+    pub fn supports(&self, capability: Capability) -> bool {
+        // Pre-releases of the first supporting version count as supporting.
+        let version = Version::new(self.version.major, self.version.minor, self.version.patch);
+        version >= capability.min_version()
+    }
+
+    /// Fails with e.g. "yagna 0.5.0 doesn't support streaming".
+    pub fn require(&self, capability: Capability) -> Result<()> {
+        match self.supports(capability) {
+            true => Ok(()),
+            false => Err(anyhow!(
+                "yagna {} doesn't support {} (requires {} or newer)",
+                self.version,
+                capability,
+                capability.min_version()
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct VersionInfo {
+    current: Release,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    version: String,
+    name: String,
+}
+
+/// Queries the daemon's version endpoint.
+pub(crate) async fn query(client: &WebClient) -> Result<DaemonInfo> {
+    let api_url = std::env::var("YAGNA_API_URL").unwrap_or_else(|_| DEFAULT_API_URL.to_string());
+    let url = format!("{}/version/get", api_url.trim_end_matches('/'));
+    let info: VersionInfo = client
+        .get(&url)
+        .send()
+        .json()
+        .await
+        .with_context(|| format!("unable to query yagna version at {}", url))?;
+    parse(info.current)
+}
+
+fn parse(release: Release) -> Result<DaemonInfo> {
+    let version = Version::parse(release.version.trim_start_matches('v'))
+        .with_context(|| format!("invalid yagna version: {}", release.version))?;
+    Ok(DaemonInfo {
+        version,
+        name: release.name,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capabilities() {
+        let info = parse(Release {
+            version: "0.6.0-rc2".to_string(),
+            name: "v0.6.0-rc2 (1a2b3c4d 2021-02-18 build #120)".to_string(),
+        })
+        .unwrap();
+        assert!(info.supports(Capability::Streaming));
+        assert!(info.require(Capability::SecureActivities).is_ok());
+        assert_eq!(
+            info.require(Capability::Vpn).unwrap_err().to_string(),
+            "yagna 0.6.0-rc2 doesn't support VPN (requires 0.8.0 or newer)"
+        );
+    }
+}
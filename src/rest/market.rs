@@ -2,16 +2,38 @@ use anyhow::{anyhow, bail, Context};
 use chrono::{DateTime, Utc};
 use futures::prelude::*;
 use futures::TryStreamExt;
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
 
+use crate::properties::{self, node, srv};
+use crate::rest::agreement_event::AgreementEvent;
 use crate::rest::async_drop::{CancelableDropList, DropList};
+use crate::rest::{ClientConfig, Responder};
+use crate::util::{retry_if, RetryPolicy};
 use ya_client::market::MarketRequestorApi;
 use ya_client::model::market::NewDemand;
-use ya_client::model::market::{AgreementProposal, RequestorEvent};
+use ya_client::model::market::{AgreementProposal, Reason, RequestorEvent};
 use ya_client::model::NodeId;
 use ya_client::web::WebClient;
 
+/// Consecutive collect failures after which `collect_proposals` gives up.
+pub(crate) const MAX_CONSECUTIVE_FAILURES: u32 = 10;
+pub(crate) const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+pub(crate) const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const DEFAULT_APPROVAL_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_MAX_CONCURRENT_COUNTERS: usize = 8;
+/// Time after which a provider countered by `negotiated_proposals` may be
+/// countered again.
+const COUNTERED_TTL: Duration = Duration::from_secs(600);
+/// Agreements `Subscription::agreements` waits for approval of at once.
+const MAX_CONCURRENT_NEGOTIATIONS: usize = 4;
+/// Long-polling timeout of `agreement_events_stream`, in seconds.
+const AGREEMENT_EVENTS_TIMEOUT: f32 = 10.0;
+
 #[derive(Clone)]
 pub struct SubscriptionId(String);
 
@@ -30,14 +52,46 @@ impl AsRef<str> for SubscriptionId {
 pub struct Market {
     api: MarketRequestorApi,
     drop_list: DropList,
+    config: ClientConfig,
+    node_name: Option<String>,
+    description: Option<String>,
 }
 
 impl Market {
-    pub(crate) fn new(client: WebClient, drop_list: DropList) -> anyhow::Result<Self> {
+    pub(crate) fn new(
+        client: WebClient,
+        drop_list: DropList,
+        config: ClientConfig,
+    ) -> anyhow::Result<Self> {
         let api = client.interface()?;
-        Ok(Self { api, drop_list })
+        Ok(Self {
+            api,
+            drop_list,
+            config,
+            node_name: None,
+            description: None,
+        })
+    }
+
+    /// Name (`golem.node.id.name`) put into demands which don't set one.
+    pub fn with_node_name(self, node_name: impl Into<String>) -> Self {
+        Self {
+            node_name: Some(node_name.into()),
+            ..self
+        }
     }
 
+    /// Job description (`golem.node.id.description`) put into demands which
+    /// don't set one.
+    pub fn with_description(self, description: impl Into<String>) -> Self {
+        Self {
+            description: Some(description.into()),
+            ..self
+        }
+    }
+
+    /// Subscribes a demand; properties given in `props` override the ones
+    /// set with `with_node_name` and `with_description`.
     pub async fn subscribe(
         &self,
         props: &serde_json::Value,
@@ -47,12 +101,27 @@ impl Market {
         self.subscribe_demand(demand).await
     }
 
-    pub async fn subscribe_demand(&self, demand: NewDemand) -> anyhow::Result<Subscription> {
-        let subscription_id = self.api.subscribe(&demand).await?;
+    pub async fn subscribe_demand(&self, mut demand: NewDemand) -> anyhow::Result<Subscription> {
+        if let Some(node_name) = &self.node_name {
+            properties::set_default(&mut demand.properties, node::NAME, node_name.as_str());
+        }
+        if let Some(description) = &self.description {
+            properties::set_default(
+                &mut demand.properties,
+                node::DESCRIPTION,
+                description.as_str(),
+            );
+        }
+        let subscription_id = self
+            .config
+            .write("subscribe", self.api.subscribe(&demand))
+            .await?;
         Ok(Subscription::new(
             self.api.clone(),
             subscription_id.into(),
+            Some(demand),
             self.drop_list.clone().into(),
+            self.config.clone(),
         ))
     }
 
@@ -63,30 +132,321 @@ impl Market {
         Ok(Subscription::new(
             self.api.clone(),
             subscription_id,
+            None,
             CancelableDropList::new(),
+            self.config.clone(),
         ))
     }
 
+    /// Collects offers matching the demand for `duration`, without negotiating.
+    ///
+    /// Returns the latest offer of every provider. The demand is unsubscribed
+    /// once the scan is over.
+    pub async fn scan(
+        &self,
+        props: &serde_json::Value,
+        constraints: &str,
+        duration: Duration,
+    ) -> anyhow::Result<Vec<Offer>> {
+        let subscription = self.subscribe(props, constraints).await?;
+        let proposals = subscription
+            .proposals()
+            .take_until(tokio::time::delay_for(duration));
+        futures::pin_mut!(proposals);
+
+        let mut offers = HashMap::new();
+        while let Some(proposal) = proposals.try_next().await? {
+            let offer = Offer {
+                node_id: proposal.issuer_id(),
+                proposal_id: proposal.proposal_id,
+                properties: proposal.data.properties,
+                constraints: proposal.data.constraints,
+            };
+            offers.insert(offer.node_id, offer);
+        }
+        Ok(offers.into_iter().map(|(_, offer)| offer).collect())
+    }
+
+    /// Agreement made earlier, e.g. by another process.
+    ///
+    /// As with `subscription`, it is not terminated on drop.
+    pub fn agreement(&self, agreement_id: impl Into<String>) -> Agreement {
+        Agreement::new(
+            self.api.clone(),
+            agreement_id.into(),
+            CancelableDropList::new(),
+            self.config.clone(),
+        )
+    }
+
+    /// Makes a follow-up agreement with the provider of `agreement`, valid
+    /// until `expiration`, e.g. for a task which needs more time than agreed.
+    ///
+    /// The market can't amend an agreement, so the demand of `agreement` is
+    /// republished with the new expiration and the provider's next offer is
+    /// countered. Activities can't be moved between agreements: `agreement`
+    /// stays in force until it is terminated, so its activity can finish the
+    /// current step before the work moves to the follow-up agreement.
+    pub async fn renegotiate(
+        &self,
+        agreement: &Agreement,
+        expiration: DateTime<Utc>,
+        timeout: Duration,
+    ) -> anyhow::Result<Agreement> {
+        let content = agreement.content().await?;
+        let provider_id = content.offer.provider_id;
+        let mut properties = content.demand.properties;
+        properties::set(
+            &mut properties,
+            srv::EXPIRATION,
+            expiration.timestamp_millis(),
+        );
+        let demand = NewDemand::new(properties, content.demand.constraints);
+        let subscription = self.subscribe_demand(demand.clone()).await?;
+
+        let negotiation = async {
+            let mut proposals = subscription.collect_proposals();
+            while let Some(proposal) = proposals.recv().await {
+                let proposal = proposal?;
+                if proposal.issuer_id() != provider_id {
+                    continue;
+                }
+                if !proposal.is_response() {
+                    proposal
+                        .counter_proposal(&demand.properties, &demand.constraints)
+                        .await?;
+                    continue;
+                }
+                return match negotiate(proposal, expiration, subscription.approval_timeout).await? {
+                    Ok(agreement) => Ok(agreement),
+                    Err(approval) => bail!("follow-up agreement not approved: {:?}", approval),
+                };
+            }
+            bail!("proposal stream closed")
+        };
+        tokio::time::timeout(timeout, negotiation)
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "no follow-up agreement with [{}] within {:?}",
+                    provider_id,
+                    timeout
+                )
+            })?
+            .with_context(|| format!("renegotiating agreement [{}]", agreement.id()))
+    }
+
+    /// Lists the demands currently subscribed by this identity, e.g. by
+    /// another process.
+    ///
+    /// As with `subscription`, these are not unsubscribed on drop.
     pub fn subscriptions(&self) -> impl Stream<Item = anyhow::Result<Subscription>> {
-        stream::empty()
+        let api = self.api.clone();
+        let config = self.config.clone();
+        async move {
+            let demands = config.read("get demands", || api.get_demands()).await?;
+            Ok::<_, anyhow::Error>(stream::iter(demands.into_iter().map(move |demand| {
+                let new_demand = NewDemand::new(demand.properties, demand.constraints);
+                Ok(Subscription::new(
+                    api.clone(),
+                    demand.demand_id.into(),
+                    Some(new_demand),
+                    CancelableDropList::new(),
+                    config.clone(),
+                ))
+            })))
+        }
+        .try_flatten_stream()
+    }
+
+    /// Streams the events of this identity's agreements (approvals,
+    /// rejections, cancellations and terminations) which happened after
+    /// `since`, for live monitoring of agreement lifecycles.
+    ///
+    /// The market is long-polled with the date of the last event as the
+    /// cursor, so no event is yielded twice. Failed polls are retried with
+    /// exponential backoff; the stream ends with an error on a client error
+    /// (4xx) or after `MAX_CONSECUTIVE_FAILURES` failures in a row.
+    /// `app_session_id` limits the events to agreements created with it.
+    ///
+    /// ## Example:
+    /// ```no_run
+    /// # async fn run(market: yarapi::rest::Market) -> anyhow::Result<()> {
+    /// use futures::prelude::*;
+    ///
+    /// let mut events = market.agreement_events_stream(chrono::Utc::now(), None).boxed_local();
+    /// while let Some(event) = events.try_next().await? {
+    ///     println!("{:?}", event);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn agreement_events_stream(
+        &self,
+        since: DateTime<Utc>,
+        app_session_id: Option<String>,
+    ) -> impl Stream<Item = anyhow::Result<AgreementEvent>> {
+        agreement_events(self.api.clone(), since, app_session_id)
     }
 }
 
+pub(crate) fn agreement_events(
+    api: MarketRequestorApi,
+    since: DateTime<Utc>,
+    app_session_id: Option<String>,
+) -> impl Stream<Item = anyhow::Result<AgreementEvent>> {
+    let policy =
+        RetryPolicy::new(MAX_CONSECUTIVE_FAILURES).with_backoff(INITIAL_BACKOFF, MAX_BACKOFF);
+    stream::try_unfold(since, move |after| {
+        let (api, policy, app_session_id) = (api.clone(), policy.clone(), app_session_id.clone());
+        async move {
+            let events = retry_if(
+                &policy,
+                || {
+                    api.collect_agreement_events(
+                        Some(AGREEMENT_EVENTS_TIMEOUT),
+                        Some(&after),
+                        None,
+                        app_session_id.clone(),
+                    )
+                    .map_err(anyhow::Error::from)
+                },
+                |e| classify(e) != ErrorKind::Client,
+            )
+            .await
+            .context("collecting agreement events failed")?;
+            let events: Vec<AgreementEvent> = events.into_iter().map(Into::into).collect();
+            let after = events
+                .iter()
+                .map(AgreementEvent::date)
+                .fold(after, |after, date| after.max(date));
+            Ok::<_, anyhow::Error>(Some((stream::iter(events.into_iter().map(Ok)), after)))
+        }
+    })
+    .try_flatten()
+}
+
 #[derive(Clone)]
 pub struct Subscription {
     inner: Arc<SubscriptionInner>,
+    approval_timeout: Duration,
+    max_concurrent_counters: usize,
+    responder: Option<Arc<dyn Responder>>,
 }
 
 struct SubscriptionInner {
-    id: SubscriptionId,
+    id: RwLock<SubscriptionId>,
+    /// Demand to republish when the subscription expires.
+    demand: RwLock<Option<NewDemand>>,
+    keep_alive: RwLock<Option<KeepAlive>>,
     api: MarketRequestorApi,
     drop_list: CancelableDropList,
+    config: ClientConfig,
+}
+
+/// See `Subscription::with_keep_alive`.
+#[derive(Clone, Copy)]
+struct KeepAlive {
+    lifetime: Duration,
+    refresh_at: Instant,
+}
+
+impl SubscriptionInner {
+    fn id(&self) -> SubscriptionId {
+        self.id.read().unwrap().clone()
+    }
+
+    /// Collects market events, resubscribing the demand if the subscription has expired.
+    async fn collect(&self) -> anyhow::Result<Vec<RequestorEvent>> {
+        self.refresh_if_due().await?;
+        let id = self.id();
+        match self
+            .api
+            .collect(id.as_ref(), Some(30f32), Some(15i32))
+            .await
+        {
+            Ok(items) => Ok(items),
+            Err(e) if is_subscription_expired(&e) => match self.demand() {
+                Some(demand) => {
+                    let new_id = self.api.subscribe(&demand).await?;
+                    log::info!(
+                        "Subscription {} expired; resubscribed as {}",
+                        id.as_ref(),
+                        new_id
+                    );
+                    *self.id.write().unwrap() = new_id.into();
+                    Ok(vec![])
+                }
+                None => Err(e.into()),
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn demand(&self) -> Option<NewDemand> {
+        self.demand.read().unwrap().clone()
+    }
+
+    /// Republishes the demand with a new expiration once the keep-alive
+    /// period is over. The new demand is subscribed before the old one is
+    /// unsubscribed, so no proposals are missed.
+    async fn refresh_if_due(&self) -> anyhow::Result<()> {
+        let keep_alive = match *self.keep_alive.read().unwrap() {
+            Some(keep_alive) if keep_alive.refresh_at <= Instant::now() => keep_alive,
+            _ => return Ok(()),
+        };
+        let mut demand = match self.demand() {
+            Some(demand) => demand,
+            None => return Ok(()),
+        };
+        let expiration = Utc::now() + chrono::Duration::from_std(keep_alive.lifetime)?;
+        if let Some(props) = demand.properties.as_object_mut() {
+            props.insert(
+                srv::EXPIRATION.to_string(),
+                expiration.timestamp_millis().into(),
+            );
+        }
+
+        let new_id = self.api.subscribe(&demand).await?;
+        let old_id = std::mem::replace(&mut *self.id.write().unwrap(), new_id.clone().into());
+        *self.demand.write().unwrap() = Some(demand);
+        *self.keep_alive.write().unwrap() = Some(KeepAlive {
+            refresh_at: Instant::now() + keep_alive.lifetime / 2,
+            ..keep_alive
+        });
+        log::info!(
+            "Subscription {} refreshed as {}; expires at {}",
+            old_id.as_ref(),
+            new_id,
+            expiration
+        );
+        if let Err(e) = self.api.unsubscribe(old_id.as_ref()).await {
+            log::debug!("Failed to unsubscribe {}. Error: {}", old_id.as_ref(), e);
+        }
+        Ok(())
+    }
+}
+
+/// Checks whether the market no longer knows the subscription.
+pub(crate) fn is_subscription_expired(e: &ya_client::Error) -> bool {
+    let msg = e.to_string().to_lowercase();
+    msg.contains("404") || msg.contains("410") || msg.contains("expired")
+}
+
+/// Reason sent to the provider along with a rejection; `code` lets the
+/// provider's software tell the reasons apart, e.g. `"price-too-high"`.
+pub(crate) fn reject_reason(code: &str, message: &str) -> Reason {
+    serde_json::from_value(serde_json::json!({
+        "message": message,
+        "code": code,
+    }))
+    .unwrap_or_else(|_| Reason::new(message))
 }
 
 impl Drop for SubscriptionInner {
     fn drop(&mut self) {
         let api = self.api.clone();
-        let id = self.id.0.clone();
+        let id = self.id().0;
         self.drop_list.async_drop(async move {
             let _ = api.unsubscribe(&id).await?;
             log::debug!(target:"yarapi::drop", "Subscription {:?} destroyed", id);
@@ -96,21 +456,81 @@ impl Drop for SubscriptionInner {
 }
 
 impl Subscription {
-    fn new(api: MarketRequestorApi, id: SubscriptionId, drop_list: CancelableDropList) -> Self {
-        let inner = Arc::new(SubscriptionInner { api, id, drop_list });
-        Subscription { inner }
+    fn new(
+        api: MarketRequestorApi,
+        id: SubscriptionId,
+        demand: Option<NewDemand>,
+        drop_list: CancelableDropList,
+        config: ClientConfig,
+    ) -> Self {
+        let inner = Arc::new(SubscriptionInner {
+            api,
+            id: RwLock::new(id),
+            demand: RwLock::new(demand),
+            keep_alive: RwLock::new(None),
+            drop_list,
+            config,
+        });
+        Subscription {
+            inner,
+            approval_timeout: DEFAULT_APPROVAL_TIMEOUT,
+            max_concurrent_counters: DEFAULT_MAX_CONCURRENT_COUNTERS,
+            responder: None,
+        }
     }
 
-    pub fn id(&self) -> &SubscriptionId {
-        &self.inner.id
+    /// Sets how long `negotiate_agreements` waits for a provider to approve
+    /// an agreement before moving on to the next proposal.
+    pub fn with_approval_timeout(self, approval_timeout: Duration) -> Self {
+        Self {
+            approval_timeout,
+            ..self
+        }
+    }
+
+    /// Keeps a long-lived subscription matching offers.
+    ///
+    /// Every `lifetime / 2` the demand is republished with
+    /// `golem.srv.comp.expiration` set `lifetime` ahead and the previous
+    /// subscription is unsubscribed. Streams returned by `proposals` and
+    /// `collect_proposals` continue from the new subscription.
+    ///
+    /// Has no effect on subscriptions without a known demand (see `Market::subscription`).
+    pub fn with_keep_alive(self, lifetime: Duration) -> Self {
+        *self.inner.keep_alive.write().unwrap() = Some(KeepAlive {
+            lifetime,
+            refresh_at: Instant::now() + lifetime / 2,
+        });
+        self
+    }
+
+    /// Sets how many counter-proposals `negotiated_proposals` sends at once.
+    pub fn with_max_concurrent_counters(self, max_concurrent_counters: usize) -> Self {
+        Self {
+            max_concurrent_counters: max_concurrent_counters.max(1),
+            ..self
+        }
+    }
+
+    /// Adjusts the properties of every counter-proposal sent by
+    /// `negotiated_proposals` to the offer it responds to, e.g. with
+    /// `PaymentPlatforms`.
+    pub fn with_responder(self, responder: impl Responder + 'static) -> Self {
+        Self {
+            responder: Some(Arc::new(responder)),
+            ..self
+        }
+    }
+
+    /// Current id of the subscription; it changes when an expired subscription
+    /// is republished.
+    pub fn id(&self) -> SubscriptionId {
+        self.inner.id()
     }
 
     pub fn proposals(&self) -> impl Stream<Item = anyhow::Result<Proposal>> {
         stream::try_unfold(self.inner.clone(), move |subscription| async move {
-            let items = subscription
-                .api
-                .collect(subscription.id.as_ref(), Some(30f32), Some(15i32))
-                .await?;
+            let items = subscription.collect().await?;
             {
                 let subscription_iter = subscription.clone();
                 Ok::<_, anyhow::Error>(Some((
@@ -132,66 +552,251 @@ impl Subscription {
         .try_flatten()
     }
 
-    pub fn collect_proposals(&self) -> mpsc::Receiver<Proposal> {
+    /// Streams proposals from the market.
+    ///
+    /// Failed collects are retried with exponential backoff. The stream ends
+    /// with an error on a client error (4xx) or after
+    /// `MAX_CONSECUTIVE_FAILURES` failures in a row.
+    pub fn collect_proposals(&self) -> mpsc::Receiver<anyhow::Result<Proposal>> {
         let (sender, receiver) = mpsc::channel(20);
         tokio::task::spawn_local(proposals_collector(self.inner.clone(), sender));
         receiver
     }
 
+    /// Counters offers with `demand` and yields the providers' responses.
+    ///
+    /// Each provider is countered once per `COUNTERED_TTL`; further offers
+    /// from the same issuer are skipped meanwhile. At most `max_concurrent_counters` counter-proposals are
+    /// in flight at a time (see `with_max_concurrent_counters`).
+    ///
     /// TODO: We shouldn't pass Demand here, but we don't store initial Demand in subscription,
     ///       so we have no choice. Rethink this design.
-    pub fn negotiated_proposals(&self, demand: NewDemand) -> mpsc::Receiver<Proposal> {
-        let (mut sender, receiver) = mpsc::channel(20);
-        let mut proposals = self.collect_proposals();
+    pub fn negotiated_proposals(
+        &self,
+        demand: NewDemand,
+    ) -> mpsc::Receiver<anyhow::Result<Proposal>> {
+        let (sender, receiver) = mpsc::channel(20);
+        let proposals = self.collect_proposals();
+        let max_concurrent_counters = self.max_concurrent_counters;
+        let responder = self.responder.clone();
 
         tokio::task::spawn_local(async move {
-            while let Some(proposal) = proposals.recv().await {
-                if proposal.is_response() {
-                    if let Err(_) = sender.send(proposal).await {
-                        // Probably no one is listening for these events anymore.
-                        return;
-                    };
-                } else {
-                    proposal
-                        .counter_proposal(&demand.properties, &demand.constraints)
-                        .await
-                        .map_err(|e| log::warn!("Failed to counter Proposal. Error: {}", e))
-                        .ok();
-                }
+            let countered = RefCell::new(Countered::default());
+            let (countered, demand, responder) = (&countered, &demand, &responder);
+            let result = proposals
+                .try_for_each_concurrent(max_concurrent_counters, |proposal| {
+                    let mut sender = sender.clone();
+                    async move {
+                        if proposal.is_response() {
+                            if let Err(_) = sender.send(Ok(proposal)).await {
+                                // Probably no one is listening for these events anymore.
+                                bail!("proposal receiver closed");
+                            }
+                            return Ok(());
+                        }
+                        let now = Instant::now();
+                        if !countered.borrow_mut().insert(proposal.issuer_id(), now) {
+                            log::debug!(
+                                "Provider [{}] already countered; skipping Proposal [{}]",
+                                proposal.issuer_id(),
+                                proposal.id()
+                            );
+                            return Ok(());
+                        }
+                        let mut properties = demand.properties.clone();
+                        if let Some(responder) = responder {
+                            responder.respond(&proposal, &mut properties);
+                        }
+                        if let Err(e) = proposal
+                            .counter_proposal(&properties, &demand.constraints)
+                            .await
+                        {
+                            log::warn!("Failed to counter Proposal. Error: {}", e);
+                            // Allow countering the provider's next offer.
+                            countered.borrow_mut().remove(&proposal.issuer_id());
+                        }
+                        Ok(())
+                    }
+                })
+                .await;
+            if let Err(e) = result {
+                let mut sender = sender;
+                let _ = sender.send(Err(e)).await;
             }
         });
         receiver
     }
 
+    /// Yields agreements valid until `deadline` as soon as they are
+    /// approved, up to `limit` of them.
+    ///
+    /// Unlike `negotiate_agreements`, the first agreement can be used while
+    /// the next ones are still negotiated, though never more of them than
+    /// agreements are missing. Providers which rejected an agreement are
+    /// skipped afterwards.
+    ///
+    /// ## Example:
+    /// ```no_run
+    /// # use futures::prelude::*;
+    /// # async fn run(subscription: yarapi::rest::Subscription, demand: ya_client::model::market::NewDemand) -> anyhow::Result<()> {
+    /// let deadline = chrono::Utc::now() + chrono::Duration::minutes(15);
+    /// let agreements = subscription.agreements(demand, 3, deadline);
+    /// futures::pin_mut!(agreements);
+    /// while let Some(agreement) = agreements.try_next().await? {
+    ///     println!("agreement {} approved", agreement.id());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn agreements(
+        &self,
+        demand: NewDemand,
+        limit: usize,
+        deadline: DateTime<Utc>,
+    ) -> impl Stream<Item = anyhow::Result<Agreement>> {
+        let approval_timeout = self.approval_timeout;
+        let refused = Rc::new(RefCell::new(HashSet::new()));
+        // A permit per agreement still needed, held during the negotiation:
+        // negotiations still running when the stream ends are dropped, and
+        // the agreements they would get approved would be left behind.
+        let needed = Rc::new(tokio::sync::Semaphore::new(limit));
+        self.negotiated_proposals(demand)
+            .map(move |proposal| {
+                let (refused, needed) = (refused.clone(), needed.clone());
+                async move {
+                    let proposal = match proposal {
+                        Ok(proposal) => proposal,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    let permit = needed.acquire().await;
+                    let issuer_id = proposal.issuer_id();
+                    if refused.borrow().contains(&issuer_id) {
+                        return None;
+                    }
+                    match negotiate(proposal, deadline, approval_timeout).await {
+                        Ok(Ok(agreement)) => {
+                            permit.forget();
+                            Some(Ok(agreement))
+                        }
+                        Ok(Err(approval)) => {
+                            log::info!(
+                                "Agreement with [{}] not approved: {:?}; trying next proposal",
+                                issuer_id,
+                                approval
+                            );
+                            if let Approval::Rejected(_) | Approval::Cancelled(_) = approval {
+                                refused.borrow_mut().insert(issuer_id);
+                            }
+                            None
+                        }
+                        Err(e) => {
+                            log::warn!("Negotiating Agreement failed. {}", e);
+                            None
+                        }
+                    }
+                }
+            })
+            .buffer_unordered(MAX_CONCURRENT_NEGOTIATIONS)
+            .filter_map(future::ready)
+            .take(limit)
+    }
+
+    /// Negotiates up to `num_agreements` agreements valid until `deadline`.
+    ///
+    /// With `time_budget` set, returns the agreements made so far once the
+    /// budget is spent, so fewer than `num_agreements` may be returned.
     pub async fn negotiate_agreements(
         &self,
         demand: NewDemand,
         num_agreements: usize,
         deadline: DateTime<Utc>,
+        time_budget: Option<Duration>,
     ) -> anyhow::Result<Vec<Agreement>> {
         let mut agreements = vec![];
+        // Providers which refused an agreement; their next proposals are skipped.
+        let mut refused = HashSet::new();
         let mut proposals = self.negotiated_proposals(demand);
+        let until = time_budget.map(|budget| Instant::now() + budget);
 
         while agreements.len() < num_agreements {
-            if let Some(proposal) = proposals.recv().await {
-                match negotiate_agreement(proposal, deadline).await {
-                    Ok(agreement) => agreements.push(agreement),
-                    Err(e) => log::warn!("Negotiating Agreement failed. {}", e),
+            let proposal = match within(until, proposals.recv()).await {
+                Some(proposal) => proposal,
+                None => break,
+            };
+            match proposal {
+                Some(Ok(proposal)) => {
+                    let issuer_id = proposal.issuer_id();
+                    if refused.contains(&issuer_id) {
+                        continue;
+                    }
+                    let negotiation = negotiate(proposal, deadline, self.approval_timeout);
+                    let result = match within(until, negotiation).await {
+                        Some(result) => result,
+                        None => break,
+                    };
+                    match result {
+                        Ok(Ok(agreement)) => agreements.push(agreement),
+                        Ok(Err(approval)) => {
+                            log::info!(
+                                "Agreement with [{}] not approved: {:?}; trying next proposal",
+                                issuer_id,
+                                approval
+                            );
+                            if let Approval::Rejected(_) | Approval::Cancelled(_) = approval {
+                                refused.insert(issuer_id);
+                            }
+                        }
+                        Err(e) => log::warn!("Negotiating Agreement failed. {}", e),
+                    }
                 }
+                Some(Err(e)) => return Err(e),
+                None => bail!("Proposal stream closed"),
             }
         }
 
+        if agreements.len() < num_agreements {
+            log::warn!(
+                "Time budget exceeded; negotiated {} of {} agreements",
+                agreements.len(),
+                num_agreements
+            );
+        }
         Ok(agreements)
     }
 }
 
+/// Runs `f` until `until`; `None` means it didn't finish in time.
+async fn within<F: Future>(until: Option<Instant>, f: F) -> Option<F::Output> {
+    match until {
+        Some(until) => {
+            let remaining = until.saturating_duration_since(Instant::now());
+            tokio::time::timeout(remaining, f).await.ok()
+        }
+        None => Some(f.await),
+    }
+}
+
 pub async fn negotiate_agreement(
     proposal: Proposal,
     deadline: DateTime<Utc>,
 ) -> anyhow::Result<Agreement> {
+    match negotiate(proposal, deadline, DEFAULT_APPROVAL_TIMEOUT).await? {
+        Ok(agreement) => Ok(agreement),
+        Err(approval) => bail!("Waiting for approval failed. {:?}", approval),
+    }
+}
+
+/// Creates and confirms an agreement; an agreement which is not approved
+/// is returned as `Ok(Err(..))`.
+async fn negotiate(
+    proposal: Proposal,
+    deadline: DateTime<Utc>,
+    approval_timeout: Duration,
+) -> anyhow::Result<Result<Agreement, Approval>> {
     let agreement = proposal.create_agreement(deadline).await?;
-    if let Err(e) = agreement.confirm().await {
-        bail!("Waiting for approval failed. {}", e)
+    match agreement.confirm_with_timeout(approval_timeout).await? {
+        Approval::Approved => (),
+        approval => return Ok(Err(approval)),
     }
 
     // TODO: Use AgreementView.
@@ -200,29 +805,84 @@ pub async fn negotiate_agreement(
         .await?
         .offer
         .properties
-        .pointer("/golem.node.id.name")
+        .pointer(&format!("/{}", node::NAME))
         .map(|value| value.as_str().map(|name| name.to_string()))
         .flatten()
         .ok_or(anyhow!("Can't find node name in Agreement"))?;
 
     log::info!("Created agreement [{}] with '{}'", agreement.id(), name);
-    return Ok(agreement);
+    Ok(Ok(agreement))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ErrorKind {
+    /// yagna is not reachable.
+    Connection,
+    /// The request has been refused (4xx); retrying won't help.
+    Client,
+    Other,
+}
+
+/// Classifies `e` by the `ya_client::Error` or `io::Error` in its chain;
+/// other errors are `Other`.
+pub(crate) fn classify(e: &anyhow::Error) -> ErrorKind {
+    for cause in e.chain() {
+        if let Some(e) = cause.downcast_ref::<ya_client::Error>() {
+            return match e {
+                ya_client::Error::SendRequestError { .. } => ErrorKind::Connection,
+                ya_client::Error::HttpError { code, .. } if code.is_client_error() => {
+                    ErrorKind::Client
+                }
+                _ => ErrorKind::Other,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            return match e.kind() {
+                std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::NotConnected => ErrorKind::Connection,
+                _ => ErrorKind::Other,
+            };
+        }
+    }
+    ErrorKind::Other
 }
 
 async fn proposals_collector(
     subscription: Arc<SubscriptionInner>,
-    mut sender: mpsc::Sender<Proposal>,
+    mut sender: mpsc::Sender<anyhow::Result<Proposal>>,
 ) {
-    let id = subscription.id.clone();
+    let policy =
+        RetryPolicy::new(MAX_CONSECUTIVE_FAILURES).with_backoff(INITIAL_BACKOFF, MAX_BACKOFF);
+    let mut failures = 0;
     loop {
-        let items = match subscription
-            .api
-            .collect(id.as_ref(), Some(30f32), Some(15i32))
-            .await
-        {
-            Ok(items) => items,
+        let items = match subscription.collect().await {
+            Ok(items) => {
+                failures = 0;
+                items
+            }
             Err(e) => {
-                log::debug!("Failed to collect proposals. Error: {}", e);
+                failures += 1;
+                let kind = classify(&e);
+                if kind == ErrorKind::Client || failures >= MAX_CONSECUTIVE_FAILURES {
+                    log::error!("Failed to collect proposals; giving up. Error: {}", e);
+                    let _ = sender
+                        .send(Err(e.context(format!(
+                            "collecting proposals failed ({} times in a row)",
+                            failures
+                        ))))
+                        .await;
+                    return;
+                }
+                let backoff = policy.delay(failures);
+                log::warn!(
+                    "Failed to collect proposals ({:?}); retrying in {:?}. Error: {}",
+                    kind,
+                    backoff,
+                    e
+                );
+                tokio::time::delay_for(backoff).await;
                 continue;
             }
         };
@@ -243,7 +903,7 @@ async fn proposals_collector(
                         proposal.state()
                     );
 
-                    if let Err(_) = sender.send(proposal).await {
+                    if let Err(_) = sender.send(Ok(proposal)).await {
                         // Probably no one is listening for these events anymore.
                         return;
                     }
@@ -254,6 +914,39 @@ async fn proposals_collector(
     }
 }
 
+/// Providers countered recently, forgotten after `COUNTERED_TTL`.
+#[derive(Default)]
+struct Countered(HashMap<NodeId, Instant>);
+
+impl Countered {
+    /// Returns false if `node_id` has been countered within the TTL.
+    fn insert(&mut self, node_id: NodeId, now: Instant) -> bool {
+        self.0
+            .retain(|_, countered| now.duration_since(*countered) < COUNTERED_TTL);
+        match self.0.contains_key(&node_id) {
+            true => false,
+            false => {
+                self.0.insert(node_id, now);
+                true
+            }
+        }
+    }
+
+    fn remove(&mut self, node_id: &NodeId) {
+        self.0.remove(node_id);
+    }
+}
+
+/// Offer of a provider, as seen by `Market::scan`.
+#[derive(Clone, Debug)]
+pub struct Offer {
+    pub node_id: NodeId,
+    pub proposal_id: String,
+    /// Raw offer properties, e.g. pricing and capabilities.
+    pub properties: serde_json::Value,
+    pub constraints: String,
+}
+
 pub struct Proposal {
     subscription: Arc<SubscriptionInner>,
     proposal_id: String,
@@ -274,11 +967,18 @@ impl Proposal {
             properties: props.clone(),
             constraints: constraints.to_string(),
         };
-        Ok(self
-            .subscription
-            .api
-            .counter_proposal(&proposal, self.subscription.id.as_ref(), &self.proposal_id)
-            .await?)
+        let subscription_id = self.subscription.id();
+        self.subscription
+            .config
+            .write(
+                "counter proposal",
+                self.subscription.api.counter_proposal(
+                    &proposal,
+                    subscription_id.as_ref(),
+                    &self.proposal_id,
+                ),
+            )
+            .await
     }
 
     pub fn state(&self) -> ya_client::model::market::proposal::State {
@@ -290,13 +990,28 @@ impl Proposal {
     }
 
     pub async fn reject_proposal(&self) -> anyhow::Result<()> {
+        self.reject(None).await
+    }
+
+    /// Rejects the proposal, telling the provider why; `code` is a stable
+    /// identifier of the reason, e.g. `"price-too-high"`, and `message`
+    /// a human-readable explanation.
+    pub async fn reject_with_reason(&self, code: &str, message: &str) -> anyhow::Result<()> {
+        self.reject(Some(reject_reason(code, message))).await
+    }
+
+    async fn reject(&self, reason: Option<Reason>) -> anyhow::Result<()> {
+        let subscription_id = self.subscription.id();
         let _ = self
             .subscription
-            .api
-            .reject_proposal(
-                self.subscription.id.as_ref(),
-                self.proposal_id.as_str(),
-                &None,
+            .config
+            .write(
+                "reject proposal",
+                self.subscription.api.reject_proposal(
+                    subscription_id.as_ref(),
+                    self.proposal_id.as_str(),
+                    &reason,
+                ),
             )
             .await?;
         Ok(())
@@ -307,12 +1022,20 @@ impl Proposal {
             proposal_id: self.proposal_id,
             valid_to: deadline,
         };
-        let agreement_id = self.subscription.api.create_agreement(&ap).await?;
+        let agreement_id = self
+            .subscription
+            .config
+            .write(
+                "create agreement",
+                self.subscription.api.create_agreement(&ap),
+            )
+            .await?;
         // TODO
         Ok(Agreement::new(
             self.subscription.api.clone(),
             agreement_id,
             CancelableDropList::new(),
+            self.subscription.config.clone(),
         ))
     }
 
@@ -323,6 +1046,37 @@ impl Proposal {
     pub fn issuer_id(&self) -> NodeId {
         self.data.issuer_id.clone()
     }
+
+    /// Region the provider reports to be in (`golem.node.geo.region`).
+    pub fn region(&self) -> Option<&str> {
+        properties::get(self.props(), node::GEO_REGION).and_then(serde_json::Value::as_str)
+    }
+}
+
+/// Provider's decision on an agreement.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Approval {
+    Approved,
+    Rejected(String),
+    Cancelled(String),
+    /// No decision within the approval timeout.
+    Timeout,
+}
+
+impl Approval {
+    /// Recognizes the outcomes reported by `wait_for_approval` as errors.
+    fn from_error(msg: &str) -> Option<Self> {
+        let lower = msg.to_lowercase();
+        if lower.contains("reject") {
+            Some(Approval::Rejected(msg.to_string()))
+        } else if lower.contains("cancel") {
+            Some(Approval::Cancelled(msg.to_string()))
+        } else if lower.contains("timeout") || lower.contains("408") {
+            Some(Approval::Timeout)
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -334,6 +1088,7 @@ struct AgreementInner {
     agreement_id: String,
     api: MarketRequestorApi,
     drop_list: CancelableDropList,
+    config: ClientConfig,
 }
 
 impl Drop for AgreementInner {
@@ -351,20 +1106,46 @@ impl Drop for AgreementInner {
 }
 
 impl Agreement {
-    fn new(api: MarketRequestorApi, agreement_id: String, drop_list: CancelableDropList) -> Self {
+    fn new(
+        api: MarketRequestorApi,
+        agreement_id: String,
+        drop_list: CancelableDropList,
+        config: ClientConfig,
+    ) -> Self {
         let inner = Arc::new(AgreementInner {
             api,
             agreement_id,
             drop_list,
+            config,
         });
         Self { inner }
     }
 
     pub async fn confirm(&self) -> anyhow::Result<()> {
+        match self.confirm_with_timeout(DEFAULT_APPROVAL_TIMEOUT).await? {
+            Approval::Approved => Ok(()),
+            approval => bail!(
+                "agreement_id={} not approved: {:?}",
+                self.inner.agreement_id,
+                approval
+            ),
+        }
+    }
+
+    /// Confirms the agreement and waits up to `timeout` for the provider's decision.
+    ///
+    /// Errors are returned only when the outcome is unknown, e.g. when yagna
+    /// is not reachable.
+    pub async fn confirm_with_timeout(&self, timeout: Duration) -> anyhow::Result<Approval> {
         let _ = self
             .inner
-            .api
-            .confirm_agreement(&self.inner.agreement_id, None)
+            .config
+            .write(
+                "confirm agreement",
+                self.inner
+                    .api
+                    .confirm_agreement(&self.inner.agreement_id, None),
+            )
             .await
             .with_context(|| {
                 format!(
@@ -372,30 +1153,78 @@ impl Agreement {
                     self.inner.agreement_id
                 )
             })?;
-        let _ = self
+        match self
             .inner
             .api
-            .wait_for_approval(&self.inner.agreement_id, Some(15.0))
+            .wait_for_approval(&self.inner.agreement_id, Some(timeout.as_secs_f32()))
             .await
-            .with_context(|| {
-                format!(
+        {
+            Ok(_) => Ok(Approval::Approved),
+            Err(e) => Approval::from_error(&e.to_string()).ok_or_else(|| {
+                anyhow::Error::from(e).context(format!(
                     "error while wait_for_approval agreement_id={}",
                     self.inner.agreement_id
-                )
-            })?;
-
-        Ok(())
+                ))
+            }),
+        }
     }
 
     pub async fn content(&self) -> anyhow::Result<ya_client::model::market::Agreement> {
-        Ok(self
-            .inner
-            .api
-            .get_agreement(&self.inner.agreement_id)
-            .await?)
+        self.inner
+            .config
+            .read("get agreement", || {
+                self.inner.api.get_agreement(&self.inner.agreement_id)
+            })
+            .await
     }
 
     pub fn id(&self) -> &str {
         &self.inner.agreement_id
     }
+
+    /// Names of the usage counters agreed with the provider.
+    pub async fn usage_vector(&self) -> anyhow::Result<crate::rest::UsageVector> {
+        let content = serde_json::to_value(self.content().await?)?;
+        Ok(crate::rest::UsageVector::from_agreement(&content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_countered_expire() {
+        let node_id: NodeId = "0x0000000000000000000000000000000000000001"
+            .parse()
+            .unwrap();
+        let now = Instant::now();
+        let mut countered = Countered::default();
+        assert!(countered.insert(node_id, now));
+        assert!(!countered.insert(node_id, now + Duration::from_secs(1)));
+        assert!(countered.insert(node_id, now + COUNTERED_TTL));
+        assert_eq!(countered.0.len(), 1);
+    }
+
+    #[test]
+    fn test_classify() {
+        let e = anyhow::Error::from(std::io::Error::from(std::io::ErrorKind::ConnectionRefused))
+            .context("collecting events failed");
+        assert_eq!(classify(&e), ErrorKind::Connection);
+        // Numbers in ids are not status codes.
+        let e = anyhow!("agreement 0x404ab1 failed: connection lost");
+        assert_eq!(classify(&e), ErrorKind::Other);
+        let e = anyhow::Error::from(ya_client::Error::InternalError("Timeout".to_string()));
+        assert_eq!(classify(&e), ErrorKind::Other);
+    }
+
+    #[test]
+    fn test_approval_from_error() {
+        assert_eq!(
+            Approval::from_error("Agreement rejected by provider"),
+            Some(Approval::Rejected("Agreement rejected by provider".into()))
+        );
+        assert_eq!(Approval::from_error("Timeout"), Some(Approval::Timeout));
+        assert_eq!(Approval::from_error("connection refused"), None);
+    }
 }
@@ -0,0 +1,207 @@
+use anyhow::{anyhow, Context};
+use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
+use futures::prelude::*;
+use std::time::Duration;
+
+use ya_client::model::payment::{
+    Allocation, DebitNote, DebitNoteEvent, Invoice, InvoiceEvent, NewAllocation,
+};
+use ya_client::model::NodeId;
+use ya_client::payment::PaymentApi;
+use ya_client::web::WebClient;
+
+use crate::rest::market::{
+    classify, ErrorKind, INITIAL_BACKOFF, MAX_BACKOFF, MAX_CONSECUTIVE_FAILURES,
+};
+use crate::rest::ClientConfig;
+use crate::util::{retry_if, RetryPolicy};
+
+const EVENTS_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_EVENTS: i32 = 15;
+
+pub struct Payment {
+    api: PaymentApi,
+    identity: Option<NodeId>,
+    config: ClientConfig,
+}
+
+impl Payment {
+    pub(crate) fn new(
+        client: WebClient,
+        identity: Option<NodeId>,
+        config: ClientConfig,
+    ) -> anyhow::Result<Self> {
+        let api = client.interface()?;
+        Ok(Self {
+            api,
+            identity,
+            config,
+        })
+    }
+
+    /// Allocates `amount` GLM from the session identity's requestor account,
+    /// or from yagna's default account if the identity is not set.
+    pub async fn create_allocation(
+        &self,
+        amount: impl Into<BigDecimal>,
+    ) -> anyhow::Result<Allocation> {
+        let (address, payment_platform) = match &self.identity {
+            Some(identity) => {
+                let address = identity.to_string();
+                let account = self
+                    .config
+                    .read("get requestor accounts", || {
+                        self.api.get_requestor_accounts()
+                    })
+                    .await?
+                    .into_iter()
+                    .find(|account| account.address.eq_ignore_ascii_case(&address))
+                    .ok_or_else(|| anyhow!("no requestor account for identity {}", address))?;
+                (Some(account.address), Some(account.platform))
+            }
+            None => (None, None),
+        };
+        let new_allocation = NewAllocation {
+            address,
+            payment_platform,
+            total_amount: amount.into(),
+            timeout: None,
+            make_deposit: false,
+        };
+        self.config
+            .write(
+                "create allocation",
+                self.api.create_allocation(&new_allocation),
+            )
+            .await
+    }
+
+    /// Lists allocations of this app key (of the session identity, if set),
+    /// including the ones left behind by interrupted runs.
+    pub async fn allocations(&self) -> anyhow::Result<Vec<Allocation>> {
+        let allocations = self
+            .config
+            .read("get allocations", || {
+                self.api.get_allocations::<Utc>(None, None)
+            })
+            .await?;
+        Ok(match &self.identity {
+            Some(identity) => {
+                let address = identity.to_string();
+                allocations
+                    .into_iter()
+                    .filter(|allocation| allocation.address.eq_ignore_ascii_case(&address))
+                    .collect()
+            }
+            None => allocations,
+        })
+    }
+
+    pub async fn release_allocation(&self, allocation_id: &str) -> anyhow::Result<()> {
+        self.config
+            .write(
+                "release allocation",
+                self.api.release_allocation(allocation_id),
+            )
+            .await
+    }
+
+    /// Invoices received for the agreement.
+    pub async fn invoices(&self, agreement_id: &str) -> anyhow::Result<Vec<Invoice>> {
+        let invoices = self
+            .config
+            .read("get invoices", || self.api.get_invoices::<Utc>(None, None))
+            .await?;
+        Ok(invoices
+            .into_iter()
+            .filter(|invoice| invoice.agreement_id == agreement_id)
+            .collect())
+    }
+
+    /// Debit notes received for the agreement.
+    pub async fn debit_notes(&self, agreement_id: &str) -> anyhow::Result<Vec<DebitNote>> {
+        let debit_notes = self
+            .config
+            .read("get debit notes", || {
+                self.api.get_debit_notes::<Utc>(None, None)
+            })
+            .await?;
+        Ok(debit_notes
+            .into_iter()
+            .filter(|debit_note| debit_note.agreement_id == agreement_id)
+            .collect())
+    }
+
+    /// Streams invoice events which happened after `since`.
+    ///
+    /// Failed polls are retried with exponential backoff; the stream ends
+    /// with an error on a client error (4xx) or after
+    /// `MAX_CONSECUTIVE_FAILURES` failures in a row.
+    pub fn invoice_events(
+        &self,
+        since: DateTime<Utc>,
+    ) -> impl Stream<Item = anyhow::Result<InvoiceEvent>> {
+        stream::try_unfold((self.api.clone(), since), move |(api, since)| async move {
+            let events = retry_if(
+                &events_policy(),
+                || {
+                    api.get_invoice_events(
+                        Some(&since),
+                        Some(EVENTS_TIMEOUT),
+                        Some(MAX_EVENTS),
+                        None,
+                    )
+                    .map_err(anyhow::Error::from)
+                },
+                |e| classify(e) != ErrorKind::Client,
+            )
+            .await
+            .context("collecting invoice events failed")?;
+            let since = events.last().map(|e| e.event_date).unwrap_or(since);
+            Ok::<_, anyhow::Error>(Some((
+                stream::iter(events.into_iter().map(Ok)),
+                (api, since),
+            )))
+        })
+        .try_flatten()
+    }
+
+    /// Streams debit note events which happened after `since`.
+    ///
+    /// Failed polls are retried with exponential backoff; the stream ends
+    /// with an error on a client error (4xx) or after
+    /// `MAX_CONSECUTIVE_FAILURES` failures in a row.
+    pub fn debit_note_events(
+        &self,
+        since: DateTime<Utc>,
+    ) -> impl Stream<Item = anyhow::Result<DebitNoteEvent>> {
+        stream::try_unfold((self.api.clone(), since), move |(api, since)| async move {
+            let events = retry_if(
+                &events_policy(),
+                || {
+                    api.get_debit_note_events(
+                        Some(&since),
+                        Some(EVENTS_TIMEOUT),
+                        Some(MAX_EVENTS),
+                        None,
+                    )
+                    .map_err(anyhow::Error::from)
+                },
+                |e| classify(e) != ErrorKind::Client,
+            )
+            .await
+            .context("collecting debit note events failed")?;
+            let since = events.last().map(|e| e.event_date).unwrap_or(since);
+            Ok::<_, anyhow::Error>(Some((
+                stream::iter(events.into_iter().map(Ok)),
+                (api, since),
+            )))
+        })
+        .try_flatten()
+    }
+}
+
+fn events_policy() -> RetryPolicy {
+    RetryPolicy::new(MAX_CONSECUTIVE_FAILURES).with_backoff(INITIAL_BACKOFF, MAX_BACKOFF)
+}
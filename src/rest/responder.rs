@@ -0,0 +1,110 @@
+use serde_json::Value;
+
+use crate::properties::{self, com};
+use crate::rest::Proposal;
+
+/// Adjusts the properties of a counter-proposal to the offer it responds to,
+/// see `Subscription::with_responder`.
+///
+/// Some providers require properties which depend on their offer, e.g. the
+/// payment platform chosen out of the ones they support.
+pub trait Responder: Send + Sync {
+    fn respond(&self, offer: &Proposal, properties: &mut Value);
+}
+
+impl<F: Fn(&Proposal, &mut Value) + Send + Sync> Responder for F {
+    fn respond(&self, offer: &Proposal, properties: &mut Value) {
+        self(offer, properties)
+    }
+}
+
+/// Chooses the first of the requestor's payment platforms the offer supports,
+/// and sets `golem.com.payment.chosen-platform` with the platform's address.
+///
+/// ## Example:
+/// ```no_run
+/// # fn run(subscription: yarapi::rest::Subscription) {
+/// use yarapi::rest::PaymentPlatforms;
+///
+/// let subscription = subscription.with_responder(
+///     PaymentPlatforms::default()
+///         .with_platform("erc20-rinkeby-tglm", "0x1234...")
+///         .with_platform("zksync-rinkeby-tglm", "0x1234..."),
+/// );
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PaymentPlatforms {
+    /// Addresses by platform, in the order of preference.
+    platforms: Vec<(String, String)>,
+}
+
+impl PaymentPlatforms {
+    pub fn with_platform(
+        mut self,
+        platform: impl Into<String>,
+        address: impl Into<String>,
+    ) -> Self {
+        self.platforms.push((platform.into(), address.into()));
+        self
+    }
+
+    /// The first platform with an address in the `offer` properties.
+    fn choose(&self, offer: &Value) -> Option<&(String, String)> {
+        self.platforms.iter().find(|(platform, _)| {
+            properties::get(offer, &com::payment_platform_address(platform)).is_some()
+        })
+    }
+
+    fn apply(&self, offer: &Value, properties: &mut Value) {
+        let (platform, address) = match self.choose(offer) {
+            Some(choice) => choice,
+            None => {
+                log::debug!("offer supports none of the payment platforms");
+                return;
+            }
+        };
+        if let Some(properties) = properties.as_object_mut() {
+            properties.insert(
+                com::PAYMENT_CHOSEN_PLATFORM.to_string(),
+                platform.as_str().into(),
+            );
+            properties.insert(
+                com::payment_platform_address(platform),
+                address.as_str().into(),
+            );
+        }
+    }
+}
+
+impl Responder for PaymentPlatforms {
+    fn respond(&self, offer: &Proposal, properties: &mut Value) {
+        self.apply(offer.props(), properties)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payment_platforms() {
+        let offer = serde_json::json!({
+            "golem.com.payment.platform.zksync-rinkeby-tglm.address": "0xprovider",
+        });
+        let platforms = PaymentPlatforms::default()
+            .with_platform("erc20-rinkeby-tglm", "0xerc20")
+            .with_platform("zksync-rinkeby-tglm", "0xzksync");
+        let mut properties = serde_json::json!({ "golem.node.id.name": "requestor" });
+        platforms.apply(&offer, &mut properties);
+
+        assert_eq!(
+            properties[com::PAYMENT_CHOSEN_PLATFORM],
+            "zksync-rinkeby-tglm"
+        );
+        assert_eq!(
+            properties["golem.com.payment.platform.zksync-rinkeby-tglm.address"],
+            "0xzksync"
+        );
+    }
+}
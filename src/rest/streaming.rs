@@ -2,12 +2,18 @@ mod batch;
 mod capture_messages;
 mod forward_to_file;
 mod forward_to_std;
+pub mod framing;
+mod interactive;
 mod messaging;
+mod record;
 mod result_stream;
+pub mod testing;
 
 pub use batch::{StreamingActivity, StreamingBatch};
+pub use interactive::InteractiveSession;
+pub use record::replay_from_file;
 pub use result_stream::ResultStream;
 
 pub use ya_client::model::activity::{CommandOutput, RuntimeEvent, RuntimeEventKind};
 
-pub use messaging::{send_to_guest, ExeUnitMessage};
+pub use messaging::{send_to_guest, send_to_guest_with, ExeUnitMessage, MessageEncoding};
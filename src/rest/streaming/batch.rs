@@ -4,6 +4,7 @@ use futures::FutureExt;
 use std::sync::Arc;
 
 use crate::rest::activity::DefaultActivity;
+use crate::rest::batches::BatchGuard;
 use crate::rest::{Activity, RunningBatch};
 
 use ya_client::activity::ActivityRequestorApi;
@@ -16,6 +17,7 @@ pub struct StreamingBatch {
     activity_id: String,
     batch_id: String,
     commands: Arc<[ExeScriptCommand]>,
+    _guard: Arc<BatchGuard>,
 }
 
 pub trait StreamingActivity {
@@ -85,6 +87,7 @@ impl StreamingActivity for DefaultActivity {
                 commands: Arc::from(batch.commands()),
                 api: batch.api,
                 activity_id: batch.activity_id,
+                _guard: batch.guard,
             })
         }
         .boxed_local()
@@ -9,11 +9,13 @@ use tokio::sync::mpsc;
 
 use ya_client::model::activity::{CommandOutput, RuntimeEvent, RuntimeEventKind};
 
-use super::messaging::ExeUnitMessage;
+use super::framing::{Frame, FrameDecoder, FrameEncoder};
+use super::messaging::{decode_message, ExeUnitMessage, MessageEncoding};
 
 struct MessageProcessor<MessageType: ExeUnitMessage> {
     notifier: mpsc::UnboundedSender<MessageType>,
-    buffer: Vec<u8>,
+    decoder: FrameDecoder,
+    encoding: MessageEncoding,
 }
 
 #[pin_project]
@@ -32,72 +34,49 @@ where
     pub(crate) fn new(
         stream: St,
         notifier: mpsc::UnboundedSender<MessageType>,
+        encoding: MessageEncoding,
     ) -> CaptureMessages<St, MessageType> {
         CaptureMessages {
             stream,
-            processor: MessageProcessor {
-                notifier,
-                buffer: vec![],
-            },
+            processor: MessageProcessor::new(notifier).with_encoding(encoding),
         }
     }
 }
 
 impl<MessageType: ExeUnitMessage> MessageProcessor<MessageType> {
+    fn new(notifier: mpsc::UnboundedSender<MessageType>) -> Self {
+        MessageProcessor {
+            notifier,
+            decoder: FrameDecoder::new(),
+            encoding: MessageEncoding::default(),
+        }
+    }
+
+    fn with_encoding(self, encoding: MessageEncoding) -> Self {
+        Self { encoding, ..self }
+    }
+
     /// Consumes part of output related to message, passing further rest of characters.
     /// Returns None in case, when whole output was consumed.
     pub(crate) fn consume_message(&mut self, output: CommandOutput) -> Option<CommandOutput> {
-        let mut output = match &output {
+        let output = match &output {
             CommandOutput::Str(output) => output.as_bytes(),
             CommandOutput::Bin(output) => output.as_slice(),
         };
 
         let mut leftovers = vec![];
-
-        while !output.is_empty() {
-            // If we have something in buffer, we are looking for end of message sign.
-            // Otherwise we are looking for beginning of message.
-            if self.buffer.len() > 0 {
-                output = match output.iter().position(|byte| *byte == 0x03 as u8) {
-                    Some(idx) => {
-                        self.buffer.extend(output[..idx].iter());
-                        match self.deserialize_message(&self.buffer) {
-                            Ok(msg) => {
-                                // TODO: How should we handle failed send here?
-                                self.notifier.send(msg).ok();
-                            }
-                            Err(_) => {
-                                // If we can't deserialize message, we should return it back
-                                // to output. Maybe there is next MessageProcessor, that can deserialize it.
-                                self.buffer[0] = 0x02 as u8;
-                                self.buffer.push(0x03 as u8);
-                                leftovers.extend(self.buffer.iter());
-                            }
-                        }
-                        self.buffer.clear();
-                        &output[idx + 1..]
-                    }
-                    // Copy all bytes. We will try to find end in next chunks of output.
-                    None => {
-                        self.buffer.extend(output.iter());
-                        &[]
-                    }
-                }
-            } else {
-                output = match output.iter().position(|byte| *byte == 0x02 as u8) {
-                    Some(idx) => {
-                        leftovers.extend(output[0..idx].iter());
-                        // Adding space to buffer. Next loop iteration will enter different branch
-                        // and space doesn't matter when deserializing.
-                        self.buffer.push(' ' as u8);
-                        &output[idx + 1..]
-                    }
-                    // No message start. Copy all to output.
-                    None => {
-                        leftovers.extend(output.iter());
-                        &[]
+        for frame in self.decoder.feed(output) {
+            match frame {
+                Frame::Data(data) => leftovers.extend(data),
+                Frame::Message(payload) => match self.deserialize_message(&payload) {
+                    Ok(msg) => {
+                        // TODO: How should we handle failed send here?
+                        self.notifier.send(msg).ok();
                     }
-                }
+                    // If we can't deserialize message, we should return it back
+                    // to output. Maybe there is next MessageProcessor, that can deserialize it.
+                    Err(_) => leftovers.extend(FrameEncoder::encode(&payload)),
+                },
             }
         }
 
@@ -108,7 +87,7 @@ impl<MessageType: ExeUnitMessage> MessageProcessor<MessageType> {
     }
 
     fn deserialize_message(&self, message: &[u8]) -> anyhow::Result<MessageType> {
-        Ok(serde_json::from_slice::<MessageType>(message)?)
+        decode_message(message, self.encoding)
     }
 }
 
@@ -179,7 +158,7 @@ where
 mod tests {
     use super::*;
 
-    use crate::rest::streaming::messaging::encode_message;
+    use crate::rest::streaming::messaging::{encode_message, encode_message_with};
     use serde::{Deserialize, Serialize};
 
     #[derive(Serialize, Deserialize)]
@@ -190,6 +169,26 @@ mod tests {
 
     impl ExeUnitMessage for Messages {}
 
+    #[tokio::test]
+    async fn test_messaging_cbor_encoding() {
+        let msg = encode_message_with(
+            &Messages::Info("\u{2}\u{3}".to_string()),
+            MessageEncoding::Cbor,
+        )
+        .unwrap();
+        assert!(!msg[1..msg.len() - 1]
+            .iter()
+            .any(|b| *b == 0x02 || *b == 0x03));
+
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Messages>();
+        let mut processor = MessageProcessor::new(sender).with_encoding(MessageEncoding::Cbor);
+        assert!(processor.consume_message(CommandOutput::Bin(msg)).is_none());
+        match receiver.recv().await {
+            Some(Messages::Info(value)) => assert_eq!(value, "\u{2}\u{3}"),
+            _ => panic!("Expected Messages::Info"),
+        };
+    }
+
     #[tokio::test]
     async fn test_messaging_only_messages_single_output() {
         let msg1 = encode_message(&Messages::Progress(0.2)).unwrap();
@@ -200,10 +199,7 @@ mod tests {
 
         let (sender, mut receiver) = mpsc::unbounded_channel::<Messages>();
 
-        let mut processor = MessageProcessor {
-            notifier: sender,
-            buffer: vec![],
-        };
+        let mut processor = MessageProcessor::new(sender);
 
         if let Some(_) = processor.consume_message(output) {
             panic!("Should consume whole output");
@@ -243,10 +239,7 @@ mod tests {
 
         let (sender, mut receiver) = mpsc::unbounded_channel::<Messages>();
 
-        let mut processor = MessageProcessor {
-            notifier: sender,
-            buffer: vec![],
-        };
+        let mut processor = MessageProcessor::new(sender);
 
         match processor.consume_message(output) {
             Some(output) => match output {
@@ -295,10 +288,7 @@ mod tests {
             .collect::<Vec<CommandOutput>>();
 
         let (sender, mut receiver) = mpsc::unbounded_channel::<Messages>();
-        let mut processor = MessageProcessor {
-            notifier: sender,
-            buffer: vec![],
-        };
+        let mut processor = MessageProcessor::new(sender);
 
         let remaining = outputs
             .into_iter()
@@ -357,10 +347,7 @@ mod tests {
             .collect::<Vec<CommandOutput>>();
 
         let (sender, mut receiver) = mpsc::unbounded_channel::<Messages>();
-        let mut processor = MessageProcessor {
-            notifier: sender,
-            buffer: vec![],
-        };
+        let mut processor = MessageProcessor::new(sender);
 
         let remaining = outputs
             .into_iter()
@@ -422,10 +409,7 @@ mod tests {
             .collect::<Vec<CommandOutput>>();
 
         let (sender, mut receiver) = mpsc::unbounded_channel::<Messages>();
-        let mut processor = MessageProcessor {
-            notifier: sender,
-            buffer: vec![],
-        };
+        let mut processor = MessageProcessor::new(sender);
 
         let remaining = outputs
             .into_iter()
@@ -0,0 +1,151 @@
+//! STX/ETX framing of messages interleaved with other output, as used by
+//! `send_to_guest` and `capture_messages`.
+//!
+//! The codec works on plain bytes, so the framing can be reused over other
+//! transports, e.g. files or sockets.
+//!
+//! ## Example:
+//! ```
+//! use yarapi::rest::streaming::framing::{Frame, FrameDecoder, FrameEncoder};
+//!
+//! let mut output = b"log line\n".to_vec();
+//! output.extend(FrameEncoder::encode(br#"{"progress":0.5}"#));
+//!
+//! let mut decoder = FrameDecoder::new();
+//! let mut frames = decoder.feed(&output[..12]);
+//! frames.extend(decoder.feed(&output[12..]));
+//! assert_eq!(
+//!     frames,
+//!     vec![
+//!         Frame::Data(b"log line\n".to_vec()),
+//!         Frame::Message(br#"{"progress":0.5}"#.to_vec()),
+//!     ]
+//! );
+//! ```
+use serde::Serialize;
+
+/// Start of a message.
+pub const STX: u8 = 0x02;
+/// End of a message.
+pub const ETX: u8 = 0x03;
+
+pub struct FrameEncoder;
+
+impl FrameEncoder {
+    /// Wraps `payload` in STX/ETX. The payload must not contain these bytes,
+    /// which holds for JSON.
+    pub fn encode(payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(payload.len() + 2);
+        frame.push(STX);
+        frame.extend_from_slice(payload);
+        frame.push(ETX);
+        frame
+    }
+
+    /// Encodes `msg` as a JSON frame.
+    pub fn encode_json(msg: &impl Serialize) -> anyhow::Result<Vec<u8>> {
+        Ok(Self::encode(&serde_json::to_vec(msg)?))
+    }
+}
+
+/// Part of the decoded byte stream.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Frame {
+    /// Bytes outside of messages.
+    Data(Vec<u8>),
+    /// Payload of a complete message, without STX/ETX.
+    Message(Vec<u8>),
+}
+
+/// Incremental decoder; messages may be split across any number of `feed` calls.
+#[derive(Clone, Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    in_message: bool,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the next chunk of the stream.
+    ///
+    /// Data between messages is returned as soon as it's fed; an incomplete
+    /// message is buffered until its end arrives.
+    pub fn feed(&mut self, mut bytes: &[u8]) -> Vec<Frame> {
+        let mut frames = vec![];
+        let mut data = vec![];
+
+        while !bytes.is_empty() {
+            if self.in_message {
+                bytes = match bytes.iter().position(|byte| *byte == ETX) {
+                    Some(idx) => {
+                        self.buffer.extend_from_slice(&bytes[..idx]);
+                        if !data.is_empty() {
+                            frames.push(Frame::Data(std::mem::take(&mut data)));
+                        }
+                        frames.push(Frame::Message(std::mem::take(&mut self.buffer)));
+                        self.in_message = false;
+                        &bytes[idx + 1..]
+                    }
+                    None => {
+                        self.buffer.extend_from_slice(bytes);
+                        &[]
+                    }
+                }
+            } else {
+                bytes = match bytes.iter().position(|byte| *byte == STX) {
+                    Some(idx) => {
+                        data.extend_from_slice(&bytes[..idx]);
+                        self.in_message = true;
+                        &bytes[idx + 1..]
+                    }
+                    None => {
+                        data.extend_from_slice(bytes);
+                        &[]
+                    }
+                }
+            }
+        }
+
+        if !data.is_empty() {
+            frames.push(Frame::Data(data));
+        }
+        frames
+    }
+
+    /// Whether a message has been started but not finished yet.
+    pub fn is_partial(&self) -> bool {
+        self.in_message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_by_byte() {
+        let mut stream = b"a".to_vec();
+        stream.extend(FrameEncoder::encode(b"msg1"));
+        stream.extend(b"b");
+        stream.extend(FrameEncoder::encode(b"msg2"));
+
+        let mut decoder = FrameDecoder::new();
+        let frames: Vec<Frame> = stream
+            .iter()
+            .flat_map(|byte| decoder.feed(std::slice::from_ref(byte)))
+            .collect();
+        assert_eq!(
+            frames,
+            vec![
+                Frame::Data(b"a".to_vec()),
+                Frame::Message(b"msg1".to_vec()),
+                Frame::Data(b"b".to_vec()),
+                Frame::Message(b"msg2".to_vec()),
+            ]
+        );
+        assert!(!decoder.is_partial());
+    }
+}
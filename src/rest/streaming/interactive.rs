@@ -0,0 +1,138 @@
+use anyhow::Result;
+use futures::channel::mpsc;
+use futures::prelude::*;
+use std::io::{self, BufRead, Write};
+
+use super::batch::StreamingActivity;
+use super::result_stream::ResultStream;
+
+use ya_client::model::activity::RuntimeEventKind;
+
+/// Runs commands typed in the local terminal on the provider, line by line.
+///
+/// This is not a remote shell: there is no PTY and the terminal is not put
+/// in raw mode, so programs which read stdin or need a terminal (editors,
+/// pagers, `top`) don't work. The exe-unit can't attach a terminal to a
+/// running process (the activity API has no stdin channel), so every line
+/// is run as a separate batch with `shell -c <line>`, and its output is
+/// streamed back. Shell state (e.g. the working directory) is not kept between lines;
+/// use `with_workdir` to set the directory all the commands start in.
+///
+/// ## Example:
+/// ```no_run
+/// # async fn debug(activity: yarapi::rest::activity::DefaultActivity) -> anyhow::Result<()> {
+/// use yarapi::rest::streaming::InteractiveSession;
+///
+/// InteractiveSession::new(&activity).run().await
+/// # }
+/// ```
+pub struct InteractiveSession<'a, A: StreamingActivity> {
+    activity: &'a A,
+    shell: String,
+    workdir: Option<String>,
+}
+
+impl<'a, A: StreamingActivity> InteractiveSession<'a, A> {
+    pub fn new(activity: &'a A) -> Self {
+        InteractiveSession {
+            activity,
+            shell: "/bin/sh".to_string(),
+            workdir: None,
+        }
+    }
+
+    pub fn with_shell(self, shell: impl Into<String>) -> Self {
+        Self {
+            shell: shell.into(),
+            ..self
+        }
+    }
+
+    pub fn with_workdir(self, workdir: impl Into<String>) -> Self {
+        Self {
+            workdir: Some(workdir.into()),
+            ..self
+        }
+    }
+
+    /// Reads commands from stdin until `exit` or end of input.
+    pub async fn run(&self) -> Result<()> {
+        let mut lines = stdin_lines();
+        prompt();
+        while let Some(line) = lines.next().await {
+            let line = line.trim().to_string();
+            match line.as_str() {
+                "" => (),
+                "exit" => break,
+                _ => {
+                    if let Err(e) = self.execute(line).await {
+                        log::error!("command failed: {:#}", e);
+                    }
+                }
+            }
+            prompt();
+        }
+        Ok(())
+    }
+
+    async fn execute(&self, line: String) -> Result<()> {
+        let line = match &self.workdir {
+            Some(workdir) => format!("cd {} && {}", quote(workdir), line),
+            None => line,
+        };
+        let batch = self
+            .activity
+            .run_streaming(&self.shell, vec!["-c".to_string(), line])
+            .await?;
+        batch
+            .stream()
+            .await?
+            .forward_to_std()
+            .take_while(|event| {
+                future::ready(!matches!(event.kind, RuntimeEventKind::Finished { .. }))
+            })
+            .for_each(|_| future::ready(()))
+            .await;
+        batch.wait_for_finish().await
+    }
+}
+
+/// Quotes `arg` for a POSIX shell.
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+fn prompt() {
+    print!("> ");
+    let _ = io::stdout().flush();
+}
+
+/// Reads stdin lines on a separate thread, so the event loop is not blocked.
+fn stdin_lines() -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded();
+    std::thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if tx.unbounded_send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("/golem/work"), "'/golem/work'");
+        assert_eq!(quote("/tmp/a b; rm -rf /"), "'/tmp/a b; rm -rf /'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+    }
+}
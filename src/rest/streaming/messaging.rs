@@ -2,20 +2,63 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::io::{self, Write};
 
+use super::framing::FrameEncoder;
+
 pub trait ExeUnitMessage: Serialize + DeserializeOwned + Send + Sync {}
 
+/// Encoding of message payloads; both sides of a channel must use the same one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageEncoding {
+    Json,
+    /// CBOR wrapped in base64, for messages carrying binary blobs
+    /// (e.g. `#[serde(with = "serde_bytes")]` fields). Base64 never
+    /// contains the framing bytes and keeps the output printable.
+    Cbor,
+}
+
+impl Default for MessageEncoding {
+    fn default() -> Self {
+        MessageEncoding::Json
+    }
+}
+
 pub fn encode_message(msg: &impl ExeUnitMessage) -> anyhow::Result<Vec<u8>> {
-    let mut data = serde_json::to_vec(msg)?;
+    encode_message_with(msg, MessageEncoding::Json)
+}
 
-    // Add control characters
-    data.insert(0, 0x02 as u8);
-    data.push(0x03 as u8);
+pub fn encode_message_with(
+    msg: &impl ExeUnitMessage,
+    encoding: MessageEncoding,
+) -> anyhow::Result<Vec<u8>> {
+    match encoding {
+        MessageEncoding::Json => FrameEncoder::encode_json(msg),
+        MessageEncoding::Cbor => {
+            let payload = base64::encode(serde_cbor::to_vec(msg)?);
+            Ok(FrameEncoder::encode(payload.as_bytes()))
+        }
+    }
+}
 
-    Ok(data)
+/// Decodes a frame payload (without STX/ETX).
+pub fn decode_message<MessageType: ExeUnitMessage>(
+    payload: &[u8],
+    encoding: MessageEncoding,
+) -> anyhow::Result<MessageType> {
+    Ok(match encoding {
+        MessageEncoding::Json => serde_json::from_slice(payload)?,
+        MessageEncoding::Cbor => serde_cbor::from_slice(&base64::decode(payload)?)?,
+    })
 }
 
 pub fn send_to_guest(msg: &impl ExeUnitMessage) -> anyhow::Result<()> {
-    let data = encode_message(msg)?;
+    send_to_guest_with(msg, MessageEncoding::Json)
+}
+
+pub fn send_to_guest_with(
+    msg: &impl ExeUnitMessage,
+    encoding: MessageEncoding,
+) -> anyhow::Result<()> {
+    let data = encode_message_with(msg, encoding)?;
 
     // Write atomically to stdout.
     let mut stdout = io::stdout();
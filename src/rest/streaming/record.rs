@@ -0,0 +1,135 @@
+use core::pin::Pin;
+use futures::prelude::*;
+use futures_core::ready;
+use futures_core::stream::FusedStream;
+use futures_core::task::{Context, Poll};
+#[cfg(feature = "sink")]
+use futures_sink::Sink;
+use pin_project::pin_project;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use ya_client::model::activity::RuntimeEvent;
+
+/// Line of a recording: an event and the time since the recording started.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RecordedEvent {
+    elapsed_ms: u64,
+    event: RuntimeEvent,
+}
+
+/// Stream for the [`record_to_file`](super::ResultStream::record_to_file) method.
+#[pin_project]
+#[must_use = "streams do nothing unless polled"]
+pub struct RecordToFile<St> {
+    #[pin]
+    stream: St,
+    file: BufWriter<File>,
+    started: Instant,
+}
+
+impl<St> RecordToFile<St> {
+    pub(crate) fn new(stream: St, path: &Path) -> anyhow::Result<RecordToFile<St>> {
+        let file = BufWriter::new(File::create(path)?);
+        Ok(RecordToFile {
+            stream,
+            file,
+            started: Instant::now(),
+        })
+    }
+}
+
+impl<St> FusedStream for RecordToFile<St>
+where
+    St: FusedStream<Item = RuntimeEvent>,
+{
+    fn is_terminated(&self) -> bool {
+        self.stream.is_terminated()
+    }
+}
+
+impl<St> Stream for RecordToFile<St>
+where
+    St: Stream<Item = RuntimeEvent>,
+{
+    type Item = RuntimeEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        let res = ready!(this.stream.as_mut().poll_next(cx));
+
+        match res {
+            Some(event) => {
+                let recorded = RecordedEvent {
+                    elapsed_ms: this.started.elapsed().as_millis() as u64,
+                    event,
+                };
+                if let Err(e) = write_event(&mut this.file, &recorded) {
+                    log::warn!("unable to record event: {}", e);
+                }
+                Poll::Ready(Some(recorded.event))
+            }
+            None => {
+                let _ = this.file.flush();
+                Poll::Ready(None)
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.stream.size_hint()
+    }
+}
+
+// Forwarding impl of Sink from the underlying stream
+#[cfg(feature = "sink")]
+impl<St, Item> Sink<Item> for RecordToFile<St>
+where
+    St: Stream + Sink<Item>,
+{
+    type Error = St::Error;
+
+    delegate_sink!(stream, Item);
+}
+
+fn write_event(file: &mut impl Write, recorded: &RecordedEvent) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut *file, recorded)?;
+    file.write_all(b"\n")?;
+    // Keep the recording usable if the process gets killed.
+    file.flush()?;
+    Ok(())
+}
+
+/// Replays events recorded with [`record_to_file`](super::ResultStream::record_to_file).
+///
+/// With `speed` set, events are delayed as they were recorded (`2.0` replays
+/// twice as fast); with `None` they are all available immediately.
+pub fn replay_from_file(
+    path: &Path,
+    speed: Option<f64>,
+) -> anyhow::Result<impl Stream<Item = RuntimeEvent>> {
+    let mut events = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        events.push(serde_json::from_str::<RecordedEvent>(&line)?);
+    }
+
+    let started = Instant::now();
+    Ok(stream::iter(events).then(move |recorded| async move {
+        if let Some(speed) = speed.filter(|speed| *speed > 0.0) {
+            let at = Duration::from_secs_f64(recorded.elapsed_ms as f64 / 1000.0 / speed);
+            let elapsed = started.elapsed();
+            if at > elapsed {
+                tokio::time::delay_for(at - elapsed).await;
+            }
+        }
+        recorded.event
+    }))
+}
@@ -5,7 +5,8 @@ use tokio::sync::mpsc;
 use super::capture_messages::CaptureMessages;
 use super::forward_to_file::ForwardToFile;
 use super::forward_to_std::ForwardStd;
-use super::messaging::ExeUnitMessage;
+use super::messaging::{ExeUnitMessage, MessageEncoding};
+use super::record::RecordToFile;
 
 use ya_client::model::activity::RuntimeEvent;
 
@@ -27,6 +28,15 @@ pub trait ResultStream: Stream {
         ForwardToFile::new(self, stdout, stderr)
     }
 
+    /// Records events with their timing to a file, to be replayed with
+    /// `replay_from_file`. Function doesn't consume events.
+    fn record_to_file(self, path: &Path) -> anyhow::Result<RecordToFile<Self>>
+    where
+        Self: Sized,
+    {
+        RecordToFile::new(self, path)
+    }
+
     fn capture_messages<MessageType: ExeUnitMessage>(
         self,
         notifier: mpsc::UnboundedSender<MessageType>,
@@ -34,7 +44,20 @@ pub trait ResultStream: Stream {
     where
         Self: Sized,
     {
-        CaptureMessages::new(self, notifier)
+        CaptureMessages::new(self, notifier, MessageEncoding::Json)
+    }
+
+    /// Like `capture_messages`, for messages sent with another encoding,
+    /// see `send_to_guest_with`.
+    fn capture_messages_with<MessageType: ExeUnitMessage>(
+        self,
+        notifier: mpsc::UnboundedSender<MessageType>,
+        encoding: MessageEncoding,
+    ) -> CaptureMessages<Self, MessageType>
+    where
+        Self: Sized,
+    {
+        CaptureMessages::new(self, notifier, encoding)
     }
 }
 
@@ -0,0 +1,214 @@
+//! Synthetic `RuntimeEvent` streams, for unit-testing `ResultStream`
+//! combinators without a provider.
+//!
+//! ## Example:
+//! ```
+//! use futures::prelude::*;
+//! use yarapi::rest::streaming::testing::EventStreamBuilder;
+//!
+//! # futures::executor::block_on(async {
+//! let events: Vec<_> = EventStreamBuilder::new("batch")
+//!     .started()
+//!     .stdout_chunked("Hello world\n", 4)
+//!     .stderr("warning\n")
+//!     .finished(0)
+//!     .build()
+//!     .collect()
+//!     .await;
+//! assert_eq!(events.len(), 6);
+//! # });
+//! ```
+use chrono::Utc;
+use futures::prelude::*;
+
+use ya_client::model::activity::{CommandOutput, ExeScriptCommand, RuntimeEvent, RuntimeEventKind};
+
+use super::messaging::{encode_message, ExeUnitMessage};
+
+/// Builds a sequence of events of a single batch.
+///
+/// Events get the current time as timestamps and the command index set
+/// with `index` (0 by default).
+#[derive(Clone, Debug)]
+pub struct EventStreamBuilder {
+    batch_id: String,
+    index: usize,
+    events: Vec<RuntimeEvent>,
+}
+
+impl EventStreamBuilder {
+    pub fn new(batch_id: impl Into<String>) -> Self {
+        EventStreamBuilder {
+            batch_id: batch_id.into(),
+            index: 0,
+            events: vec![],
+        }
+    }
+
+    /// Sets the command index of the events added next.
+    pub fn index(self, index: usize) -> Self {
+        Self { index, ..self }
+    }
+
+    /// Adds an event of any kind.
+    pub fn event(mut self, kind: RuntimeEventKind) -> Self {
+        self.events.push(RuntimeEvent {
+            batch_id: self.batch_id.clone(),
+            index: self.index,
+            timestamp: Utc::now().naive_utc(),
+            kind,
+        });
+        self
+    }
+
+    /// Adds the start of a `run` command.
+    pub fn started(self) -> Self {
+        self.event(RuntimeEventKind::Started {
+            command: ExeScriptCommand::Run {
+                entry_point: "/bin/sh".to_string(),
+                args: vec![],
+                capture: None,
+            },
+        })
+    }
+
+    pub fn finished(self, return_code: i32) -> Self {
+        self.event(RuntimeEventKind::Finished {
+            return_code,
+            message: None,
+        })
+    }
+
+    /// Adds `output` as a single stdout event.
+    pub fn stdout(self, output: impl AsRef<[u8]>) -> Self {
+        self.event(RuntimeEventKind::StdOut(bin(output.as_ref())))
+    }
+
+    pub fn stderr(self, output: impl AsRef<[u8]>) -> Self {
+        self.event(RuntimeEventKind::StdErr(bin(output.as_ref())))
+    }
+
+    /// Adds `output` as stdout events of at most `chunk_size` bytes each.
+    pub fn stdout_chunked(self, output: impl AsRef<[u8]>, chunk_size: usize) -> Self {
+        chunks(output.as_ref(), chunk_size).fold(self, |builder, chunk| builder.stdout(chunk))
+    }
+
+    pub fn stderr_chunked(self, output: impl AsRef<[u8]>, chunk_size: usize) -> Self {
+        chunks(output.as_ref(), chunk_size).fold(self, |builder, chunk| builder.stderr(chunk))
+    }
+
+    /// Adds stdout and stderr split into `chunk_size` chunks, alternately.
+    pub fn interleaved(
+        self,
+        stdout: impl AsRef<[u8]>,
+        stderr: impl AsRef<[u8]>,
+        chunk_size: usize,
+    ) -> Self {
+        let mut stdout = chunks(stdout.as_ref(), chunk_size);
+        let mut stderr = chunks(stderr.as_ref(), chunk_size);
+        let mut builder = self;
+        loop {
+            match (stdout.next(), stderr.next()) {
+                (None, None) => return builder,
+                (out, err) => {
+                    if let Some(out) = out {
+                        builder = builder.stdout(out);
+                    }
+                    if let Some(err) = err {
+                        builder = builder.stderr(err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds a message framed as by `send_to_guest`, as a single stdout event.
+    pub fn message(self, msg: &impl ExeUnitMessage) -> anyhow::Result<Self> {
+        Ok(self.stdout(encode_message(msg)?))
+    }
+
+    /// Adds a framed message split into stdout events at the `splits` offsets,
+    /// e.g. to check that partial frames are buffered.
+    pub fn message_split(
+        self,
+        msg: &impl ExeUnitMessage,
+        splits: &[usize],
+    ) -> anyhow::Result<Self> {
+        let frame = encode_message(msg)?;
+        let mut builder = self;
+        let mut start = 0;
+        for split in splits.iter().map(|split| (*split).min(frame.len())) {
+            if split > start {
+                builder = builder.stdout(&frame[start..split]);
+                start = split;
+            }
+        }
+        Ok(builder.stdout(&frame[start..]))
+    }
+
+    pub fn events(self) -> Vec<RuntimeEvent> {
+        self.events
+    }
+
+    /// Stream of the events, all available immediately.
+    pub fn build(self) -> impl Stream<Item = RuntimeEvent> {
+        stream::iter(self.events)
+    }
+}
+
+/// Concatenated stdout of `events`, e.g. to compare with the expected output
+/// of a combinator.
+pub fn collect_stdout(events: &[RuntimeEvent]) -> Vec<u8> {
+    events
+        .iter()
+        .filter_map(|event| match &event.kind {
+            RuntimeEventKind::StdOut(output) => Some(bytes(output)),
+            _ => None,
+        })
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+pub fn collect_stderr(events: &[RuntimeEvent]) -> Vec<u8> {
+    events
+        .iter()
+        .filter_map(|event| match &event.kind {
+            RuntimeEventKind::StdErr(output) => Some(bytes(output)),
+            _ => None,
+        })
+        .flatten()
+        .cloned()
+        .collect()
+}
+
+fn bytes(output: &CommandOutput) -> &[u8] {
+    match output {
+        CommandOutput::Str(text) => text.as_bytes(),
+        CommandOutput::Bin(data) => data.as_slice(),
+    }
+}
+
+fn bin(output: &[u8]) -> CommandOutput {
+    CommandOutput::Bin(output.to_vec())
+}
+
+fn chunks(output: &[u8], chunk_size: usize) -> std::slice::Chunks<'_, u8> {
+    output.chunks(chunk_size.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleaved() {
+        let events = EventStreamBuilder::new("batch")
+            .interleaved("abcdef", "xy", 2)
+            .events();
+        assert_eq!(events.len(), 4);
+        assert_eq!(collect_stdout(&events), b"abcdef");
+        assert_eq!(collect_stderr(&events), b"xy");
+        assert!(matches!(events[1].kind, RuntimeEventKind::StdErr(_)));
+    }
+}
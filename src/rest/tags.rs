@@ -0,0 +1,335 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use ya_client::model::activity::ExeScriptCommand;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ActivityEntry {
+    agreement_id: String,
+    tags: BTreeSet<String>,
+    /// Commands of the batches sent to the activity, by batch id; saved to
+    /// the batch log of the activity rather than with the tags.
+    #[serde(default, skip_serializing)]
+    batches: BTreeMap<String, Vec<ExeScriptCommand>>,
+}
+
+/// Line of the batch log of an activity.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchRecord {
+    batch_id: String,
+    commands: Vec<ExeScriptCommand>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Tags {
+    /// Tags by agreement id.
+    agreements: BTreeMap<String, BTreeSet<String>>,
+    /// Activities by activity id.
+    activities: BTreeMap<String, ActivityEntry>,
+}
+
+/// User tags of agreements and activities, see `Session::tag_agreement`.
+///
+/// Activities inherit the tags of their agreements. Batches sent to an
+/// activity are appended to its log in the `<path>.batches` directory, and
+/// forgotten once the activity is destroyed.
+#[derive(Default)]
+pub(crate) struct TagRegistry {
+    tags: Tags,
+    /// JSON file the tags are saved to after every change.
+    path: Option<PathBuf>,
+}
+
+impl TagRegistry {
+    /// Loads tags from `path`, if it exists; changes will be saved there.
+    pub fn load(path: &Path) -> Result<Self> {
+        let tags = match path.exists() {
+            true => {
+                let content = std::fs::read(path)
+                    .with_context(|| format!("unable to read tags from {}", path.display()))?;
+                serde_json::from_slice(&content)
+                    .with_context(|| format!("invalid tags file {}", path.display()))?
+            }
+            false => Tags::default(),
+        };
+        let mut registry = TagRegistry {
+            tags,
+            path: Some(path.to_path_buf()),
+        };
+        for (activity_id, entry) in registry.tags.activities.iter_mut() {
+            let log_path = batch_log(path, activity_id);
+            if !log_path.exists() {
+                continue;
+            }
+            let content = fs::read_to_string(&log_path)
+                .with_context(|| format!("unable to read batches from {}", log_path.display()))?;
+            // A line cut short by a crash is skipped.
+            for record in content
+                .lines()
+                .filter_map(|line| serde_json::from_str::<BatchRecord>(line).ok())
+            {
+                entry.batches.insert(record.batch_id, record.commands);
+            }
+        }
+        Ok(registry)
+    }
+
+    pub fn tag_agreement(&mut self, agreement_id: &str, tag: String) {
+        self.tags
+            .agreements
+            .entry(agreement_id.to_string())
+            .or_default()
+            .insert(tag);
+        self.save();
+    }
+
+    pub fn add_activity(&mut self, activity_id: &str, agreement_id: &str) {
+        self.tags
+            .activities
+            .entry(activity_id.to_string())
+            .or_default()
+            .agreement_id = agreement_id.to_string();
+        self.save();
+    }
+
+    pub fn tag_activity(&mut self, activity_id: &str, tag: String) {
+        self.tags
+            .activities
+            .entry(activity_id.to_string())
+            .or_default()
+            .tags
+            .insert(tag);
+        self.save();
+    }
+
+    pub fn add_batch(&mut self, activity_id: &str, batch_id: &str, commands: &[ExeScriptCommand]) {
+        self.tags
+            .activities
+            .entry(activity_id.to_string())
+            .or_default()
+            .batches
+            .insert(batch_id.to_string(), commands.to_vec());
+        let log_path = match &self.path {
+            Some(path) => batch_log(path, activity_id),
+            None => return,
+        };
+        let record = BatchRecord {
+            batch_id: batch_id.to_string(),
+            commands: commands.to_vec(),
+        };
+        let result = serde_json::to_string(&record)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| {
+                if let Some(dir) = log_path.parent() {
+                    fs::create_dir_all(dir)?;
+                }
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&log_path)?;
+                Ok(writeln!(file, "{}", line)?)
+            });
+        if let Err(e) = result {
+            log::warn!("unable to save batch to {}: {}", log_path.display(), e);
+        }
+    }
+
+    /// Forgets the batches of a destroyed activity, which can't be attached
+    /// to anymore; its tags are kept.
+    pub fn remove_batches(&mut self, activity_id: &str) {
+        if let Some(entry) = self.tags.activities.get_mut(activity_id) {
+            entry.batches.clear();
+        }
+        if let Some(path) = &self.path {
+            let log_path = batch_log(path, activity_id);
+            if let Err(e) = fs::remove_file(&log_path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("unable to remove {}: {}", log_path.display(), e);
+                }
+            }
+        }
+    }
+
+    pub fn batch_commands(
+        &self,
+        activity_id: &str,
+        batch_id: &str,
+    ) -> Option<Vec<ExeScriptCommand>> {
+        self.tags
+            .activities
+            .get(activity_id)?
+            .batches
+            .get(batch_id)
+            .cloned()
+    }
+
+    /// Ids of the batches sent to the activity, see `add_batch`.
+    pub fn batch_ids(&self, activity_id: &str) -> Vec<String> {
+        self.tags
+            .activities
+            .get(activity_id)
+            .map(|entry| entry.batches.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    pub fn agreement_tags(&self, agreement_id: &str) -> Vec<String> {
+        self.tags
+            .agreements
+            .get(agreement_id)
+            .map(|tags| tags.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Own tags of the activity, followed by the tags of its agreement.
+    pub fn activity_tags(&self, activity_id: &str) -> Vec<String> {
+        let entry = match self.tags.activities.get(activity_id) {
+            Some(entry) => entry,
+            None => return vec![],
+        };
+        let mut tags: Vec<String> = entry.tags.iter().cloned().collect();
+        tags.extend(
+            self.agreement_tags(&entry.agreement_id)
+                .into_iter()
+                .filter(|tag| !entry.tags.contains(tag)),
+        );
+        tags
+    }
+
+    /// Ids of the agreements tagged or with activities created.
+    pub fn agreement_ids(&self) -> Vec<String> {
+        let ids: BTreeSet<String> = self
+            .tags
+            .agreements
+            .keys()
+            .cloned()
+            .chain(
+                self.tags
+                    .activities
+                    .values()
+                    .map(|entry| entry.agreement_id.clone()),
+            )
+            .filter(|agreement_id| !agreement_id.is_empty())
+            .collect();
+        ids.into_iter().collect()
+    }
+
+    pub fn find_agreements(&self, tag: &str) -> Vec<String> {
+        self.tags
+            .agreements
+            .iter()
+            .filter(|(_, tags)| tags.contains(tag))
+            .map(|(agreement_id, _)| agreement_id.clone())
+            .collect()
+    }
+
+    pub fn find_activities(&self, tag: &str) -> Vec<String> {
+        self.tags
+            .activities
+            .iter()
+            .filter(|(_, entry)| {
+                entry.tags.contains(tag)
+                    || self
+                        .tags
+                        .agreements
+                        .get(&entry.agreement_id)
+                        .map(|tags| tags.contains(tag))
+                        .unwrap_or(false)
+            })
+            .map(|(activity_id, _)| activity_id.clone())
+            .collect()
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let result = serde_json::to_vec_pretty(&self.tags)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(std::fs::write(path, content)?));
+        if let Err(e) = result {
+            log::warn!("unable to save tags to {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn batch_log(path: &Path, activity_id: &str) -> PathBuf {
+    path.with_extension("batches")
+        .join(format!("{}.jsonl", activity_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let mut registry = TagRegistry::default();
+        registry.tag_agreement("agreement-1", "job:render".to_string());
+        registry.tag_agreement("agreement-2", "job:encode".to_string());
+        registry.add_activity("activity-1", "agreement-1");
+        registry.add_activity("activity-2", "agreement-2");
+        registry.tag_activity("activity-2", "task:7".to_string());
+
+        assert_eq!(registry.find_agreements("job:render"), vec!["agreement-1"]);
+        assert_eq!(registry.find_activities("job:render"), vec!["activity-1"]);
+        assert_eq!(registry.find_activities("task:7"), vec!["activity-2"]);
+        assert!(registry.find_activities("task:8").is_empty());
+        assert_eq!(
+            registry.activity_tags("activity-2"),
+            vec!["task:7", "job:encode"]
+        );
+    }
+
+    #[test]
+    fn test_batch_commands() {
+        let mut registry = TagRegistry::default();
+        registry.add_activity("activity-1", "agreement-1");
+        let commands = vec![ExeScriptCommand::Start { args: vec![] }];
+        registry.add_batch("activity-1", "batch-1", &commands);
+
+        let tags: Tags =
+            serde_json::from_value(serde_json::to_value(&registry.tags).unwrap()).unwrap();
+        assert_eq!(tags.activities["activity-1"].batches["batch-1"], commands);
+        assert_eq!(registry.batch_commands("activity-1", "batch-2"), None);
+        assert_eq!(registry.batch_ids("activity-1"), vec!["batch-1"]);
+
+        // Files saved before batches were kept still load.
+        let tags: Tags = serde_json::from_str(
+            r#"{"agreements": {}, "activities": {"activity-1": {"agreementId": "agreement-1", "tags": []}}}"#,
+        )
+        .unwrap();
+        assert!(tags.activities["activity-1"].batches.is_empty());
+    }
+
+    #[test]
+    fn test_batch_log() {
+        let path = std::env::temp_dir().join(format!("yarapi-tags-{}.json", std::process::id()));
+        let commands = vec![ExeScriptCommand::Start { args: vec![] }];
+        let mut registry = TagRegistry::load(&path).unwrap();
+        registry.add_activity("activity-1", "agreement-1");
+        registry.add_batch("activity-1", "batch-1", &commands);
+        registry.add_batch("activity-1", "batch-2", &commands);
+
+        // Batches aren't rewritten with the tags.
+        let saved: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert!(saved["activities"]["activity-1"].get("batches").is_none());
+        let mut registry = TagRegistry::load(&path).unwrap();
+        assert_eq!(registry.batch_ids("activity-1"), vec!["batch-1", "batch-2"]);
+
+        registry.remove_batches("activity-1");
+        assert!(!batch_log(&path, "activity-1").exists());
+        let registry = TagRegistry::load(&path).unwrap();
+        assert!(registry.batch_ids("activity-1").is_empty());
+        assert_eq!(registry.agreement_ids(), vec!["agreement-1"]);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_dir_all(path.with_extension("batches")).unwrap();
+    }
+}
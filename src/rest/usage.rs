@@ -0,0 +1,108 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::prelude::*;
+use futures::stream::LocalBoxStream;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use ya_client::activity::ActivityRequestorApi;
+
+use crate::properties::{self, com, usage};
+
+/// Names of the usage counters, in the order reported by the activity.
+///
+/// Taken from the `golem.com.usage.vector` property of the offer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UsageVector(pub Vec<String>);
+
+impl UsageVector {
+    /// Reads the usage vector from an agreement (or offer) serialized as JSON.
+    pub fn from_agreement(agreement: &serde_json::Value) -> Self {
+        let offer = agreement.pointer("/offer/properties").unwrap_or(agreement);
+        let names = properties::get(offer, com::USAGE_VECTOR)
+            .and_then(serde_json::Value::as_array)
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| name.as_str().map(ToString::to_string))
+                    .collect()
+            })
+            .unwrap_or_default();
+        UsageVector(names)
+    }
+
+    pub fn sample(&self, timestamp: DateTime<Utc>, values: &[f64]) -> UsageSample {
+        let counters: BTreeMap<String, f64> = values
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                let name = self
+                    .0
+                    .get(i)
+                    .cloned()
+                    .unwrap_or_else(|| format!("counter.{}", i));
+                (name, *value)
+            })
+            .collect();
+        UsageSample {
+            timestamp,
+            duration_secs: counters.get(usage::DURATION_SEC).copied(),
+            cpu_secs: counters.get(usage::CPU_SEC).copied(),
+            counters,
+        }
+    }
+}
+
+/// Usage of an activity at a point in time, see `Activity::usage_stream`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageSample {
+    pub timestamp: DateTime<Utc>,
+    /// `golem.usage.duration_sec`, if reported.
+    pub duration_secs: Option<f64>,
+    /// `golem.usage.cpu_sec`, if reported.
+    pub cpu_secs: Option<f64>,
+    /// All the counters by name; unnamed ones as `counter.<index>`.
+    pub counters: BTreeMap<String, f64>,
+}
+
+/// Polls the activity usage every `interval`; the first sample is taken
+/// right away.
+pub(crate) fn usage_stream(
+    api: ActivityRequestorApi,
+    activity_id: String,
+    interval: Duration,
+    usage_vector: UsageVector,
+) -> LocalBoxStream<'static, Result<UsageSample>> {
+    stream::try_unfold(
+        (api, activity_id, usage_vector, true),
+        move |(api, activity_id, usage_vector, first)| async move {
+            if !first {
+                tokio::time::delay_for(interval).await;
+            }
+            let usage = api.state().get_usage(&activity_id).await?;
+            let sample = usage_vector.sample(Utc::now(), &usage.current_usage.unwrap_or_default());
+            Ok(Some((sample, (api, activity_id, usage_vector, false))))
+        },
+    )
+    .boxed_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        let agreement = serde_json::json!({
+            "offer": { "properties": {
+                "golem.com.usage.vector": ["golem.usage.cpu_sec", "golem.usage.duration_sec"]
+            }}
+        });
+        let usage_vector = UsageVector::from_agreement(&agreement);
+        let sample = usage_vector.sample(Utc::now(), &[12.5, 30.0, 1.0]);
+        assert_eq!(sample.cpu_secs, Some(12.5));
+        assert_eq!(sample.duration_secs, Some(30.0));
+        assert_eq!(sample.counters["counter.2"], 1.0);
+    }
+}
@@ -0,0 +1,116 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// When a `Workdir` is removed at the end of a run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CleanupPolicy {
+    Always,
+    /// Keeps the files of a failed or interrupted run for inspection.
+    KeepOnFailure,
+    Never,
+}
+
+/// Directory for the local temporary files of a session or a requestor:
+/// files of JSON transfers, packed uploads and checkpoints.
+///
+/// Without one, these files go to the system's temporary directory and are
+/// never removed as a whole.
+///
+/// ## Example:
+/// ```no_run
+/// use yarapi::rest::{CleanupPolicy, Workdir};
+///
+/// let workdir = Workdir::new("runs/render").with_cleanup(CleanupPolicy::KeepOnFailure);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Workdir {
+    path: PathBuf,
+    cleanup: CleanupPolicy,
+}
+
+impl Workdir {
+    /// Files are kept on failure, see `with_cleanup`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Workdir {
+            path: path.into(),
+            cleanup: CleanupPolicy::KeepOnFailure,
+        }
+    }
+
+    pub fn with_cleanup(self, cleanup: CleanupPolicy) -> Self {
+        Self { cleanup, ..self }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Creates the subdirectory `name`, if missing.
+    pub fn dir(&self, name: &str) -> Result<PathBuf> {
+        let dir = self.path.join(name);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("unable to create {}", dir.display()))?;
+        Ok(dir)
+    }
+
+    /// Resolves a relative `path` against the working directory.
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        match path.is_relative() {
+            true => self.path.join(path),
+            false => path.to_path_buf(),
+        }
+    }
+
+    /// Removes the directory according to the cleanup policy.
+    pub fn cleanup(&self, success: bool) {
+        let remove = match self.cleanup {
+            CleanupPolicy::Always => true,
+            CleanupPolicy::KeepOnFailure => success,
+            CleanupPolicy::Never => false,
+        };
+        if !remove {
+            log::info!("keeping working directory {}", self.path.display());
+            return;
+        }
+        match std::fs::remove_dir_all(&self.path) {
+            Ok(()) => log::debug!("removed working directory {}", self.path.display()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => log::warn!("unable to remove {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// Unique path for a temporary file, in `workdir` if given.
+pub(crate) fn temp_path(workdir: Option<&Workdir>, extension: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = format!(
+        "yarapi-{}-{}.{}",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        extension
+    );
+    match workdir.and_then(|workdir| workdir.dir("tmp").ok()) {
+        Some(dir) => dir.join(name),
+        None => std::env::temp_dir().join(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cleanup() {
+        let path = std::env::temp_dir().join(format!("yarapi-workdir-{}", std::process::id()));
+        let workdir = Workdir::new(&path);
+        let file = temp_path(Some(&workdir), "json");
+        assert!(file.starts_with(&path));
+        std::fs::write(&file, b"{}").unwrap();
+
+        workdir.cleanup(false);
+        assert!(file.exists());
+        workdir.cleanup(true);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,179 @@
+//! Helpers shared by the REST and requestor APIs, for use in applications too.
+
+use futures::prelude::*;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Exponential backoff between the attempts of `retry`.
+///
+/// The delay before the retry `n` (counting from 1) is
+/// `initial_backoff * multiplier^(n - 1)`, capped at `max_backoff`, and then
+/// spread by up to `jitter` of its length either way, so that clients
+/// failing together don't retry together.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub multiplier: f64,
+    /// Fraction of the delay, from 0 to 1.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.1,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        RetryPolicy {
+            max_retries,
+            ..RetryPolicy::default()
+        }
+    }
+
+    pub fn with_backoff(self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            initial_backoff,
+            max_backoff,
+            ..self
+        }
+    }
+
+    pub fn with_jitter(self, jitter: f64) -> Self {
+        Self {
+            jitter: jitter.max(0.0).min(1.0),
+            ..self
+        }
+    }
+
+    /// Delay before the retry `attempt` (counting from 1), without jitter.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let secs = self.initial_backoff.as_secs_f64() * self.multiplier.max(1.0).powi(exponent);
+        match secs < self.max_backoff.as_secs_f64() {
+            true => Duration::from_secs_f64(secs),
+            false => self.max_backoff,
+        }
+    }
+
+    /// Delay before the retry `attempt`, with jitter.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let spread = (random() * 2.0 - 1.0) * self.jitter;
+        self.backoff(attempt).mul_f64(1.0 + spread)
+    }
+}
+
+/// Runs `op` until it succeeds or the retries of `policy` are used up;
+/// returns the last error then.
+///
+/// ## Example:
+/// ```no_run
+/// # async fn run() -> anyhow::Result<()> {
+/// use yarapi::util::{retry, RetryPolicy};
+///
+/// let body = retry(&RetryPolicy::new(5), || async {
+///     std::fs::read_to_string("/mnt/shared/input.txt")
+/// })
+/// .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn retry<T, E, F, Fut>(policy: &RetryPolicy, op: F) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    retry_if(policy, op, |_| true).await
+}
+
+/// Like `retry`, but gives up at once on errors for which `retryable`
+/// returns false, e.g. on client errors of a REST API.
+pub async fn retry_if<T, E, F, Fut>(
+    policy: &RetryPolicy,
+    mut op: F,
+    retryable: impl Fn(&E) -> bool,
+) -> Result<T, E>
+where
+    E: std::fmt::Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut attempt = 0;
+    loop {
+        let error = match op().await {
+            Ok(result) => return Ok(result),
+            Err(e) => e,
+        };
+        if attempt >= policy.max_retries || !retryable(&error) {
+            return Err(error);
+        }
+        attempt += 1;
+        let delay = policy.delay(attempt);
+        log::debug!("failed: {}; retry {} in {:?}", error, attempt, delay);
+        tokio::time::delay_for(delay).await;
+    }
+}
+
+/// Number in [0, 1), random enough for jitter.
+fn random() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new(10)
+            .with_backoff(Duration::from_secs(1), Duration::from_secs(10))
+            .with_jitter(0.5);
+        assert_eq!(policy.backoff(1), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(4));
+        assert_eq!(policy.backoff(8), Duration::from_secs(10));
+        for _ in 0..100 {
+            let delay = policy.delay(2);
+            assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(3));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let policy =
+            RetryPolicy::new(2).with_backoff(Duration::from_millis(1), Duration::from_millis(1));
+        let calls = Cell::new(0);
+        let result: Result<(), String> = retry(&policy, || {
+            calls.set(calls.get() + 1);
+            future::ready(Err(format!("attempt {}", calls.get())))
+        })
+        .await;
+        assert_eq!(result, Err("attempt 3".to_string()));
+
+        calls.set(0);
+        let result: Result<(), String> = retry_if(
+            &policy,
+            || {
+                calls.set(calls.get() + 1);
+                future::ready(Err("fatal".to_string()))
+            },
+            |e| e != "fatal",
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls.get(), 1);
+    }
+}